@@ -0,0 +1,16 @@
+-- 市場データ（1分ごとのレートと出来高）
+-- 既存環境でも適用できるように IF NOT EXISTS を付ける
+CREATE TABLE IF NOT EXISTS markets (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    pair VARCHAR(32) NOT NULL,
+    store_rate_avg DOUBLE NOT NULL,
+    ex_rate_sell DOUBLE NOT NULL,
+    ex_rate_buy DOUBLE NOT NULL,
+    ex_volume_sell DOUBLE NOT NULL,
+    ex_volume_buy DOUBLE NOT NULL,
+    recorded_at DATETIME NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    INDEX idx_markets_pair_recorded_at (pair, recorded_at)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
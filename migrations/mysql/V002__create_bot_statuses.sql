@@ -0,0 +1,13 @@
+-- ボットの状態（ON DUPLICATE KEY UPDATE で更新するため一意キーが必要）
+CREATE TABLE IF NOT EXISTS bot_statuses (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    bot_name VARCHAR(64) NOT NULL,
+    pair VARCHAR(32) NOT NULL,
+    type VARCHAR(64) NOT NULL,
+    value DOUBLE NOT NULL,
+    memo VARCHAR(255) NOT NULL DEFAULT '',
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_bot_statuses_bot_name_pair_type (bot_name, pair, type)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
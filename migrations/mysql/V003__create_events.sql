@@ -0,0 +1,12 @@
+-- 売買イベント（event_type 0:買い, 1:売り）
+CREATE TABLE IF NOT EXISTS events (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    pair VARCHAR(32) NOT NULL,
+    event_type TINYINT UNSIGNED NOT NULL,
+    memo TEXT NOT NULL,
+    recorded_at DATETIME NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    INDEX idx_events_pair_recorded_at (pair, recorded_at)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
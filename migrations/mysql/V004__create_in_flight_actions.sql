@@ -0,0 +1,11 @@
+-- 実行中のアクション（複数ステップのアクションを途中で失敗しても再開できるよう、ボット毎に進捗を記録する）
+CREATE TABLE IF NOT EXISTS in_flight_actions (
+    bot_name VARCHAR(64) NOT NULL,
+    pair VARCHAR(32) NOT NULL,
+    action_type VARCHAR(32) NOT NULL,
+    state TEXT NOT NULL,
+    attempts INT UNSIGNED NOT NULL DEFAULT 0,
+    updated_at DATETIME NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (bot_name)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
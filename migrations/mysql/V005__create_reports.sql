@@ -0,0 +1,22 @@
+-- 定期レポート（期間毎, 取引ペア毎と全体 pair='all'）
+CREATE TABLE IF NOT EXISTS reports (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    bot_name VARCHAR(64) NOT NULL,
+    period VARCHAR(16) NOT NULL,
+    pair VARCHAR(32) NOT NULL,
+    begin_at DATETIME NOT NULL,
+    end_at DATETIME NOT NULL,
+    trade_count INT UNSIGNED NOT NULL,
+    realized_pnl DOUBLE NOT NULL,
+    fee DOUBLE NOT NULL,
+    win_count INT UNSIGNED NOT NULL,
+    loss_count INT UNSIGNED NOT NULL,
+    open_amount DOUBLE NOT NULL,
+    unrealized_pnl DOUBLE NOT NULL,
+    total_jpy DOUBLE NOT NULL,
+    total_jpy_change DOUBLE NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_reports_bot_name_period_pair_end_at (bot_name, period, pair, end_at)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
@@ -0,0 +1,15 @@
+-- 市場データ（1分ごとのレートと出来高）
+-- 日時は UTC の 'YYYY-MM-DD HH:MM:SS' 形式の文字列で保存する
+CREATE TABLE IF NOT EXISTS markets (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    pair TEXT NOT NULL,
+    store_rate_avg REAL NOT NULL,
+    ex_rate_sell REAL NOT NULL,
+    ex_rate_buy REAL NOT NULL,
+    ex_volume_sell REAL NOT NULL,
+    ex_volume_buy REAL NOT NULL,
+    recorded_at TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS idx_markets_pair_recorded_at ON markets (pair, recorded_at);
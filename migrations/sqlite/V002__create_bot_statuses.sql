@@ -0,0 +1,12 @@
+-- ボットの状態（ON CONFLICT で更新するため一意制約が必要）
+CREATE TABLE IF NOT EXISTS bot_statuses (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    bot_name TEXT NOT NULL,
+    pair TEXT NOT NULL,
+    type TEXT NOT NULL,
+    value REAL NOT NULL,
+    memo TEXT NOT NULL DEFAULT '',
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (bot_name, pair, type)
+);
@@ -0,0 +1,11 @@
+-- 売買イベント（event_type 0:買い, 1:売り）
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    pair TEXT NOT NULL,
+    event_type INTEGER NOT NULL,
+    memo TEXT NOT NULL,
+    recorded_at TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS idx_events_pair_recorded_at ON events (pair, recorded_at);
@@ -0,0 +1,10 @@
+-- 実行中のアクション（複数ステップのアクションを途中で失敗しても再開できるよう、ボット毎に進捗を記録する）
+CREATE TABLE IF NOT EXISTS in_flight_actions (
+    bot_name TEXT NOT NULL PRIMARY KEY,
+    pair TEXT NOT NULL,
+    action_type TEXT NOT NULL,
+    state TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    updated_at TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,21 @@
+-- 定期レポート（期間毎, 取引ペア毎と全体 pair='all'）
+CREATE TABLE IF NOT EXISTS reports (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    bot_name TEXT NOT NULL,
+    period TEXT NOT NULL,
+    pair TEXT NOT NULL,
+    begin_at TEXT NOT NULL,
+    end_at TEXT NOT NULL,
+    trade_count INTEGER NOT NULL,
+    realized_pnl REAL NOT NULL,
+    fee REAL NOT NULL,
+    win_count INTEGER NOT NULL,
+    loss_count INTEGER NOT NULL,
+    open_amount REAL NOT NULL,
+    unrealized_pnl REAL NOT NULL,
+    total_jpy REAL NOT NULL,
+    total_jpy_change REAL NOT NULL,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (bot_name, period, pair, end_at)
+);
@@ -1,13 +1,27 @@
 use crate::env_logger::Builder;
-use chrono::Utc;
+use chrono::{FixedOffset, Utc};
 use trading_bot_rust::bot::action::ActionBehavior;
 use trading_bot_rust::bot::base::Bot;
-use trading_bot_rust::config::Config;
+use trading_bot_rust::bot::control::BotControl;
+use trading_bot_rust::bot::report::{ReportPeriod, Reporter};
+use trading_bot_rust::bot::scheduler::{CronSchedule, Scheduler, Task};
+use trading_bot_rust::bot::shutdown::Shutdown;
+use trading_bot_rust::config::{Config, DbType};
+use trading_bot_rust::error::MyResult;
+use trading_bot_rust::monitor::metrics;
+use trading_bot_rust::monitor::server::MonitorServer;
+use trading_bot_rust::notifier::base::Notifier;
+use trading_bot_rust::notifier::router::Router;
+use trading_bot_rust::notifier::throttle::Throttle;
+use trading_bot_rust::slack::server::CommandServer;
 use trading_bot_rust::strategy::base::StrategyType;
-use trading_bot_rust::{coincheck, mysql, slack, strategy};
+use trading_bot_rust::{coincheck, mysql, sqlite, strategy};
 
 use env_logger;
-use log::{error, info};
+use log::{error, info, warn};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
 
 #[tokio::main]
 async fn main() {
@@ -24,9 +38,10 @@ async fn main() {
     }
 
     let coincheck_cli: coincheck::client::DefaultClient;
-    match coincheck::client::DefaultClient::new(
+    match coincheck::client::DefaultClient::with_options(
         &config.exchange_access_key,
         &config.exchange_secret_key,
+        &coincheck::client::ClientOptions::from_config(&config),
     ) {
         Ok(cli) => {
             coincheck_cli = cli;
@@ -37,16 +52,10 @@ async fn main() {
         }
     }
 
-    let mysql_cli: mysql::client::DefaultClient;
-    match mysql::client::DefaultClient::new(
-        &config.db_user_name,
-        &config.db_password,
-        &config.db_host,
-        config.db_port,
-        &config.db_name,
-    ) {
-        Ok(cli) => {
-            mysql_cli = cli;
+    let notifier: Throttle<Router>;
+    match Router::from_config(&config) {
+        Ok(v) => {
+            notifier = Throttle::from_config(v, &config);
         }
         Err(err) => {
             error!("{}", err);
@@ -54,53 +63,237 @@ async fn main() {
         }
     }
 
-    let slack_cli: slack::client::DefaultClient;
-    match slack::client::DefaultClient::new(&config.slack_url) {
-        Ok(cli) => {
-            slack_cli = cli;
-        }
-        Err(err) => {
-            error!("{}", err);
-            return;
-        }
+    match config.db_type {
+        DbType::Mysql => match new_mysql_client(&config) {
+            Ok(cli) => run(&config, &coincheck_cli, &cli, &notifier).await,
+            Err(err) => {
+                error!("{}", err);
+            }
+        },
+        DbType::Sqlite => match new_sqlite_client(&config) {
+            Ok(cli) => run(&config, &coincheck_cli, &cli, &notifier).await,
+            Err(err) => {
+                error!("{}", err);
+            }
+        },
     }
+}
 
+// MySQLも起動時にスキーマを最新化する（未適用だと新しいテーブルを使うアクションが失敗するため）
+fn new_mysql_client(config: &Config) -> MyResult<mysql::client::DefaultClient> {
+    let cli = mysql::client::DefaultClient::new(
+        &config.db_user_name,
+        &config.db_password,
+        &config.db_host,
+        config.db_port,
+        &config.db_name,
+    )?;
+    for v in cli.migrate()? {
+        info!("applied migration version:{}", v);
+    }
+    Ok(cli)
+}
+
+// SQLiteはローカル用途のため起動時にスキーマを最新化する
+fn new_sqlite_client(config: &Config) -> MyResult<sqlite::client::DefaultClient> {
+    let cli = sqlite::client::DefaultClient::new(&config.db_path)?;
+    cli.migrate()?;
+    Ok(cli)
+}
+
+async fn run<T, U, V>(config: &Config, coincheck_cli: &V, mysql_cli: &U, notifier: &T)
+where
+    T: Notifier + std::marker::Sync,
+    U: mysql::client::Client,
+    V: coincheck::client::Client + std::marker::Sync,
+{
     info!("===========================================");
     info!("bot_name   : {}", config.bot_name);
     info!("pair       : {}", config.target_pair);
-    info!("interval   : {}sec", config.interval_sec);
+    info!(
+        "interval   : {}sec ({:?})",
+        config.interval_sec, config.overrun_policy
+    );
     info!("rate period: {}min", config.rate_period_minutes);
     info!("demo mode  : {}", config.demo_mode);
+    info!("exchange   : {}", config.exchange_base_url);
+    info!("db type    : {:?}", config.db_type);
+    info!("monitor    : {:?}", config.monitor_addr);
     info!("===========================================");
 
+    let mut shutdown = match Shutdown::listen() {
+        Ok(v) => v,
+        Err(err) => {
+            error!("{}", err);
+            return;
+        }
+    };
+
+    let control = Arc::new(BotControl::new(config.demo_mode));
+    if let (Some(addr), Some(secret)) = (&config.slack_command_addr, &config.slack_signing_secret) {
+        let addr: SocketAddr = match addr.parse() {
+            Ok(v) => v,
+            Err(err) => {
+                error!("invalid slack command addr {}, {}", addr, err);
+                return;
+            }
+        };
+        let server = CommandServer::new(config, secret, control.clone());
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(err) = server.serve(&addr, shutdown).await {
+                error!("{:?}", err);
+            }
+        });
+    }
+    if let Some(addr) = &config.monitor_addr {
+        let addr: SocketAddr = match addr.parse() {
+            Ok(v) => v,
+            Err(err) => {
+                error!("invalid monitor addr {}, {}", addr, err);
+                return;
+            }
+        };
+        let server = MonitorServer::new(config, control.clone(), metrics::shared());
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(err) = server.serve(&addr, shutdown).await {
+                error!("{:?}", err);
+            }
+        });
+    }
+
+    let offset = match FixedOffset::east_opt(config.schedule_utc_offset_hours * 3600) {
+        Some(v) => v,
+        None => {
+            error!(
+                "invalid schedule utc offset, {}",
+                config.schedule_utc_offset_hours
+            );
+            return;
+        }
+    };
+    let mut scheduler = Scheduler::new(
+        config.interval_sec,
+        config.overrun_policy,
+        offset,
+        &Utc::now(),
+    );
+    for (period, expr) in [
+        (ReportPeriod::Daily, &config.report_daily_cron),
+        (ReportPeriod::Weekly, &config.report_weekly_cron),
+    ] {
+        if let Some(expr) = expr {
+            match CronSchedule::parse(expr) {
+                Ok(schedule) => scheduler.add_job(&period.job_name(), schedule, &Utc::now()),
+                Err(err) => {
+                    error!("invalid {} report cron, {}", period.name(), err);
+                    return;
+                }
+            }
+        }
+    }
+
+    // Slack のコマンドで変更された設定は、取引の合間に反映する
+    let mut config = config.clone();
+
+    // 取引の途中で終了しないよう、終了の要求は取引の合間に確認する
+    while let Some(task) = scheduler.next(&mut shutdown).await {
+        config.demo_mode = control.demo_mode();
+        run_task(
+            &config,
+            coincheck_cli,
+            mysql_cli,
+            notifier,
+            &control,
+            Some(task),
+        )
+        .await;
+        // まとめた通知, 再送待ちの通知を送る
+        if let Err(err) = notifier.flush(&Utc::now(), false).await {
+            warn!("failed to flush notifications, {}", err);
+        }
+    }
+    run_task(&config, coincheck_cli, mysql_cli, notifier, &control, None).await;
+    if let Err(err) = notifier.flush(&Utc::now(), true).await {
+        error!("failed to flush notifications, {}", err);
+    }
+}
+
+// task が None の場合は終了処理を行う
+async fn run_task<T, U, V>(
+    config: &Config,
+    coincheck_cli: &V,
+    mysql_cli: &U,
+    notifier: &T,
+    control: &BotControl,
+    task: Option<Task>,
+) where
+    T: Notifier + std::marker::Sync,
+    U: mysql::client::Client,
+    V: coincheck::client::Client + std::marker::Sync,
+{
     let strategy_type = StrategyType::Scalping;
     let strategy = match strategy_type {
-        StrategyType::Scalping => strategy::scalping::ScalpingStrategy { config: &config },
+        StrategyType::Scalping => strategy::scalping::ScalpingStrategy { config },
     };
 
     let action_behavior = ActionBehavior {
-        config: &config,
-        slack_client: &slack_cli,
-        mysql_client: &mysql_cli,
-        coincheck_client: &coincheck_cli,
+        config,
+        notifier,
+        mysql_client: mysql_cli,
+        coincheck_client: coincheck_cli,
     };
 
     let bot = Bot {
-        config: &config,
-        coincheck_client: &coincheck_cli,
-        mysql_client: &mysql_cli,
-        slack_client: &slack_cli,
+        config,
+        coincheck_client: coincheck_cli,
+        mysql_client: mysql_cli,
+        notifier,
         strategy: &strategy,
         action_behavior: &action_behavior,
     };
 
-    loop {
-        let now = Utc::now();
-        if let Err(err) = bot.trade(&now).await {
-            error!("{:?}", err);
+    match task {
+        Some(Task::Trade(_)) => {
+            for id in control.take_loss_cut_requests() {
+                if let Err(err) = bot.loss_cut_order(id).await {
+                    error!("{:?}", err);
+                }
+            }
+            if control.is_paused() {
+                info!("skip trade (paused)");
+                return;
+            }
+            let now = Utc::now();
+            let started = Instant::now();
+            let result = bot.trade(&now).await;
+            control.record_trade(&now, result.as_ref().err().map(|e| e.to_string()));
+            metrics::shared().record_cycle(&now, started.elapsed().as_secs_f64(), result.is_ok());
+            if let Err(err) = result {
+                error!("{:?}", err);
+            }
         }
-        if let Err(err) = bot.wait() {
-            error!("{:?}", err);
+        Some(Task::Job(name, scheduled_at)) => match ReportPeriod::from_job_name(&name) {
+            Some(period) => {
+                let reporter = Reporter {
+                    config,
+                    notifier,
+                    mysql_client: mysql_cli,
+                    coincheck_client: coincheck_cli,
+                };
+                if let Err(err) = reporter.report(period, &scheduled_at).await {
+                    error!("{:?}", err);
+                }
+            }
+            None => {
+                warn!("unknown job {}", name);
+            }
+        },
+        None => {
+            if let Err(err) = bot.shutdown(&Utc::now()).await {
+                error!("{:?}", err);
+            }
         }
     }
 }
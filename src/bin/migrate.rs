@@ -0,0 +1,114 @@
+use crate::env_logger::Builder;
+use serde::Deserialize;
+use trading_bot_rust::config::DbType;
+use trading_bot_rust::error::MyResult;
+use trading_bot_rust::{mysql, sqlite};
+
+use env_logger;
+use log::{error, info};
+
+// マイグレーションに必要な設定（configs/db.env）
+#[derive(Deserialize, Debug)]
+struct MigrateConfig {
+    #[serde(default)]
+    db_type: DbType,
+    #[serde(default = "default_db_path")]
+    db_path: String,
+    #[serde(default)]
+    db_host: String,
+    #[serde(default)]
+    db_port: u16,
+    #[serde(default)]
+    db_name: String,
+    #[serde(default)]
+    db_user_name: String,
+    #[serde(default)]
+    db_password: String,
+}
+
+fn default_db_path() -> String {
+    "trading_bot.sqlite3".to_owned()
+}
+
+// DBごとのマイグレーション操作
+enum Migrator {
+    Mysql(mysql::client::DefaultClient),
+    Sqlite(sqlite::client::DefaultClient),
+}
+
+impl Migrator {
+    fn new(config: &MigrateConfig) -> MyResult<Migrator> {
+        match config.db_type {
+            DbType::Mysql => Ok(Migrator::Mysql(mysql::client::DefaultClient::new(
+                &config.db_user_name,
+                &config.db_password,
+                &config.db_host,
+                config.db_port,
+                &config.db_name,
+            )?)),
+            DbType::Sqlite => Ok(Migrator::Sqlite(sqlite::client::DefaultClient::new(
+                &config.db_path,
+            )?)),
+        }
+    }
+
+    fn migrate(&self) -> MyResult<Vec<u32>> {
+        match self {
+            Migrator::Mysql(c) => c.migrate(),
+            Migrator::Sqlite(c) => c.migrate(),
+        }
+    }
+
+    fn schema_version(&self) -> MyResult<u32> {
+        match self {
+            Migrator::Mysql(c) => c.schema_version(),
+            Migrator::Sqlite(c) => c.schema_version(),
+        }
+    }
+
+    fn latest_version(&self) -> u32 {
+        let migrations = match self {
+            Migrator::Mysql(_) => &mysql::migration::MIGRATIONS,
+            Migrator::Sqlite(_) => &sqlite::migration::MIGRATIONS,
+        };
+        migrations.iter().map(|m| m.version).max().unwrap_or(0)
+    }
+}
+
+// 使い方: migrate [up|status]
+fn main() {
+    let mut builder = Builder::from_default_env();
+    builder.format_module_path(false).init();
+
+    let command = std::env::args().nth(1).unwrap_or_else(|| "up".to_owned());
+    if let Err(err) = real_main(&command) {
+        error!("failed to migrate, {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn real_main(command: &str) -> MyResult<()> {
+    let config: MigrateConfig = envy::from_env::<MigrateConfig>()?;
+    let client = Migrator::new(&config)?;
+
+    match command {
+        "up" => {
+            let versions = client.migrate()?;
+            if versions.is_empty() {
+                info!("schema is up to date");
+            }
+            for v in versions {
+                info!("applied migration version:{}", v);
+            }
+            info!("current version:{}", client.schema_version()?);
+        }
+        "status" => {
+            info!("current version:{}", client.schema_version()?);
+            info!("latest version :{}", client.latest_version());
+        }
+        _ => {
+            return Err(format!("unknown command: {}", command).into());
+        }
+    }
+    Ok(())
+}
@@ -1,20 +1,31 @@
 use crate::env_logger::Builder;
+use chrono::NaiveDateTime;
 use trading_bot_rust::coincheck::model::Pair;
 use trading_bot_rust::config::Config;
 use trading_bot_rust::error::MyResult;
 use trading_bot_rust::simulator::base::Simulator;
+use trading_bot_rust::simulator::loader::MarketQuery;
 
 use env_logger;
 use log::{error, info};
 
 const MARKET_DATA_PATH: &str = "./market_data/markets__btc_updated_highest_price.csv";
+const PAIR: &str = "btc_jpy";
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
+// 使い方: simulator [市場データのパス(.csv/.csv.gz/.csv.zst/.parquet)] [取引ペア] [開始日時] [終了日時]
 #[tokio::main]
 async fn main() {
     let mut builder = Builder::from_default_env();
     builder.format_module_path(false).init();
 
-    match real_main("btc_jpy").await {
+    let args: Vec<String> = std::env::args().collect();
+    let path = args.get(1).map(|s| s.as_str()).unwrap_or(MARKET_DATA_PATH);
+    let pair = args.get(2).map(|s| s.as_str()).unwrap_or(PAIR);
+    let begin = args.get(3).map(|s| s.as_str());
+    let end = args.get(4).map(|s| s.as_str());
+
+    match real_main(path, pair, begin, end).await {
         Ok(_) => {
             info!("succeeded to simulation");
         }
@@ -25,18 +36,35 @@ async fn main() {
     info!("finished simulation");
 }
 
-async fn real_main(pair_str: &str) -> MyResult<()> {
+async fn real_main(
+    path: &str,
+    pair_str: &str,
+    begin: Option<&str>,
+    end: Option<&str>,
+) -> MyResult<()> {
     let config: Config = envy::from_env::<Config>()?;
 
     let simulator: Simulator = Simulator::new(&config)?;
     let pair = Pair::new(pair_str)?;
+    let mut query = MarketQuery::new(&pair);
+    if let Some(v) = begin {
+        query.begin = Some(NaiveDateTime::parse_from_str(v, DATETIME_FORMAT)?);
+    }
+    if let Some(v) = end {
+        query.end = Some(NaiveDateTime::parse_from_str(v, DATETIME_FORMAT)?);
+    }
 
     info!("===========================================");
     info!("start simulation");
-    info!("pair:{}", pair.to_string());
+    info!("data :{}", path);
+    info!("pair :{}", pair.to_string());
+    info!("begin:{:?}", query.begin);
+    info!("end  :{:?}", query.end);
     info!("===========================================");
 
-    simulator.run(MARKET_DATA_PATH, &pair).await?;
+    let result = simulator.run(path, &query).await?;
+    info!("total_jpy    :{}", result.total_jpy);
+    info!("total balance:{}", result.total_balance_jpy);
 
     Ok(())
 }
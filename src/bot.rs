@@ -1,3 +1,9 @@
 pub mod action;
 pub mod base;
+pub mod control;
 pub mod model;
+pub mod reconcile;
+pub mod report;
+pub mod saga;
+pub mod scheduler;
+pub mod shutdown;
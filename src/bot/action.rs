@@ -2,36 +2,52 @@ use crate::bot::model::ActionType;
 use crate::bot::model::AvgDownParam;
 use crate::bot::model::EntryParam;
 use crate::bot::model::LossCutParam;
+use crate::bot::model::RecoverParam;
 use crate::bot::model::SellParam;
 use crate::bot::model::SetProfitParam;
+use crate::bot::reconcile::{find_placed_order, is_ambiguous_error, OrderSnapshot};
+use crate::bot::saga::{
+    AvgDownState, LossCutState, SagaState, AVG_DOWN_SELL_COUNT, MAX_COMPENSATE_ATTEMPTS,
+    MAX_RESUME_ATTEMPTS,
+};
 use crate::coincheck::model::Balance;
+use crate::coincheck::model::Liquidity;
 use crate::coincheck::model::NewOrder;
+use crate::coincheck::model::Order;
+use crate::coincheck::model::OrderType;
 use crate::coincheck::model::Pair;
 use crate::config::Config;
+use crate::error::MyError::{InFlightActionRemains, UnresolvedOrder};
 use crate::error::MyResult;
-use crate::mysql::model::{Event, EventType};
-use crate::slack::client::TextMessage;
-use crate::{coincheck, mysql, slack};
+use crate::monitor::metrics;
+use crate::mysql::model::{BotStatus, Event, EventType};
+use crate::notifier::base::Notifier;
+use crate::notifier::model::{Event as NotifyEvent, Level, Notification};
+use crate::util;
+use crate::{coincheck, mysql};
 
 use colored::Colorize;
 use log::{debug, error, info, warn};
-use std::{thread, time};
+use std::time;
+
+// 注文結果が不明な場合に、注文が処理されたかを確認する回数
+const RECONCILE_CHECK_COUNT: u32 = 3;
 
 pub struct ActionBehavior<'a, T, U, V>
 where
-    T: slack::client::Client,
+    T: Notifier,
     U: mysql::client::Client,
     V: coincheck::client::Client,
 {
     pub config: &'a Config,
-    pub slack_client: &'a T,
+    pub notifier: &'a T,
     pub mysql_client: &'a U,
     pub coincheck_client: &'a V,
 }
 
 impl<T, U, V> ActionBehavior<'_, T, U, V>
 where
-    T: slack::client::Client,
+    T: Notifier,
     U: mysql::client::Client,
     V: coincheck::client::Client,
 {
@@ -39,92 +55,91 @@ where
         match t {
             ActionType::Entry(param) => match self.action_entry(&balance, &param).await {
                 Ok(_) => {
+                    metrics::shared().record_action(t.name(), true);
                     info!("{} entry ({:?})", "success".green(), param);
                 }
                 Err(err) => {
+                    metrics::shared().record_action(t.name(), false);
                     let message = format!("{} entry, {} ({:?})", "failure".red(), err, param);
                     error!("{}", message);
-                    if let Err(err) = self
-                        .slack_client
-                        .post_message(&TextMessage { text: message })
-                        .await
-                    {
-                        error!("{}", err);
-                    }
+                    self.notify_failure("entry", &*err, &format!("{:?}", param))
+                        .await;
                     error!("{} entry, {} ({:?})", "failure".red(), err, param);
                 }
             },
             ActionType::LossCut(param) => match self.action_loss_cut(&param).await {
                 Ok(_) => {
+                    metrics::shared().record_action(t.name(), true);
                     info!("{} loss cut ({:?})", "success".green(), param);
                 }
                 Err(err) => {
+                    metrics::shared().record_action(t.name(), false);
                     let message = format!("{} loss cut, {} ({:?})", "failure".red(), err, param);
                     error!("{}", message);
-                    if let Err(err) = self
-                        .slack_client
-                        .post_message(&TextMessage { text: message })
-                        .await
-                    {
-                        error!("{}", err);
-                    }
+                    self.notify_failure("loss cut", &*err, &format!("{:?}", param))
+                        .await;
                     error!("{} loss cut, {} ({:?})", "failure".red(), err, param);
                 }
             },
             ActionType::SetProfit(param) => match self.action_set_profit(&param).await {
                 Ok(_) => {
+                    metrics::shared().record_action(t.name(), true);
                     info!("{} set profit ({:?})", "success".green(), param);
                 }
                 Err(err) => {
+                    metrics::shared().record_action(t.name(), false);
                     let message = format!("{} set profit , {} ({:?})", "failure".red(), err, param);
                     error!("{}", message);
-                    if let Err(err) = self
-                        .slack_client
-                        .post_message(&TextMessage { text: message })
-                        .await
-                    {
-                        error!("{}", err);
-                    }
+                    self.notify_failure("set profit", &*err, &format!("{:?}", param))
+                        .await;
                     error!("{} set profit , {} ({:?})", "failure".red(), err, param);
                 }
             },
             ActionType::Sell(param) => match self.action_sell(&param).await {
                 Ok(_) => {
+                    metrics::shared().record_action(t.name(), true);
                     info!("{} sell ({:?})", "success".green(), param);
                 }
                 Err(err) => {
+                    metrics::shared().record_action(t.name(), false);
                     let message = format!("{} sell, {} ({:?})", "failure".red(), err, param);
                     error!("{}", message);
-                    if let Err(err) = self
-                        .slack_client
-                        .post_message(&TextMessage { text: message })
-                        .await
-                    {
-                        error!("{}", err);
-                    }
+                    self.notify_failure("sell", &*err, &format!("{:?}", param))
+                        .await;
                     error!("{} sell, {} ({:?})", "failure".red(), err, param);
                 }
             },
             ActionType::AvgDown(param) => match self.action_avg_down(&balance, &param).await {
                 Ok(_) => {
+                    metrics::shared().record_action(t.name(), true);
                     info!("{} avg down ({:?})", "success".green(), param);
                 }
                 Err(err) => {
+                    metrics::shared().record_action(t.name(), false);
                     let message = format!("{} avg down, {} ({:?})", "failure".red(), err, param);
                     error!("{}", message);
-                    if let Err(err) = self
-                        .slack_client
-                        .post_message(&TextMessage { text: message })
-                        .await
-                    {
-                        error!("{}", err);
-                    }
+                    self.notify_failure("avg down", &*err, &format!("{:?}", param))
+                        .await;
                     error!("{} avg down, {} ({:?})", "failure".red(), err, param);
                 }
             },
+            ActionType::Recover(param) => match self.action_recover(param).await {
+                Ok(_) => {
+                    metrics::shared().record_action(t.name(), true);
+                    info!("{} recover ({:?})", "success".green(), param);
+                }
+                Err(err) => {
+                    metrics::shared().record_action(t.name(), false);
+                    let message = format!("{} recover, {} ({:?})", "failure".red(), err, param);
+                    error!("{}", message);
+                    self.notify_failure("recover", &*err, &format!("{:?}", param))
+                        .await;
+                    error!("{} recover, {} ({:?})", "failure".red(), err, param);
+                }
+            },
             ActionType::Notify(param) => {
                 info!("{}", param.log_message);
-                if let Err(err) = self.slack_client.post_message(&param.slack_message).await {
+                if let Err(err) = self.notifier.notify(&param.notification).await {
                     error!("{}", err);
                 }
             }
@@ -137,14 +152,16 @@ where
             info!("{}", "skip entry as demo mode".green());
             return Ok(());
         }
-        // エントリーすると余裕なくなるならスキップする
+        let fee_rate = self.config.fee_rate(&param.pair.to_string());
+        // エントリーすると余裕なくなるならスキップする（成行買いの手数料を含める）
         let required = param.amount * self.config.keep_lot;
-        if balance_jpy.amount - param.amount < required {
+        let cost = param.amount * (1.0 + fee_rate.taker);
+        if balance_jpy.amount - cost < required {
             warn!(
                 "{}",
                 format!(
                     "skip entry, balance jpy is too little ({:.3} < {:.3})",
-                    balance_jpy.amount - param.amount,
+                    balance_jpy.amount - cost,
                     required
                 )
                 .yellow()
@@ -152,31 +169,57 @@ where
             return Ok(());
         }
 
+        // 売注文が最小注文数量を下回るなら、成行買いの前にスキップする（買った後に売れなくなるため）
+        let buy_rate = self
+            .coincheck_client
+            .get_exchange_orders_rate(OrderType::Buy, &param.pair.to_string(), 1.0)
+            .await?;
+        if let Err(err) = param.pair.validate_amount(param.amount / buy_rate) {
+            warn!("{}", format!("skip entry, {}", err).yellow());
+            return Ok(());
+        }
+
         // 成行買い注文
         let amount_coin = self.market_buy(&param.pair, param.amount).await?;
 
-        // 売り注文
+        // 売り注文（手数料を差し引いて目標利益になるレート）
         let used_jpy = param.amount;
-        let profit_jpy = used_jpy * param.profit_ratio;
-        let rate = (used_jpy + profit_jpy) / amount_coin * (1.0 + param.offset_sell_rate_ratio);
+        let rate = util::calc_sell_rate(
+            used_jpy,
+            amount_coin,
+            param.profit_ratio,
+            param.offset_sell_rate_ratio,
+            &fee_rate,
+        );
 
-        self.sell(&param.pair, rate, amount_coin).await?;
+        // 通知するレート（逆指値注文の場合は逆指値レート）
+        let order_rate = match self.config.stop_loss_rate_ratio {
+            // 利確の指値注文の代わりに逆指値注文を出す（Bot が停止しても取引所側で損切りされる）
+            Some(ratio) => {
+                let stop_loss_rate = util::calc_stop_loss_rate(used_jpy, amount_coin, ratio);
+                self.stop_loss(&param.pair, stop_loss_rate, amount_coin)
+                    .await?;
+                stop_loss_rate
+            }
+            None => {
+                self.sell(&param.pair, rate, amount_coin).await?;
+                rate
+            }
+        };
 
-        if let Err(err) = self
-            .slack_client
-            .post_message(&TextMessage {
-                text: format!(
-                    "entry completed!\npair:`{}`\nrate:`{:.3}`\namount:`{:.3}`\nparam:`{:?}`",
-                    self.config.target_pair, rate, amount_coin, param
+        self.notify(
+            Notification::new(NotifyEvent::Entry, "entry completed!", Level::Good)
+                .field("pair", &param.pair.to_string())
+                .field("rate", &format!("{:.3}", order_rate))
+                .field("amount", &format!("{:.8}", amount_coin))
+                .field("used jpy", &format!("{:.0}", param.amount))
+                .field(
+                    "stop loss",
+                    &self.config.stop_loss_rate_ratio.is_some().to_string(),
                 ),
-            })
-            .await
-        {
-            warn!(
-                "{}",
-                format!("failed to send message to slack, {}", err).yellow()
-            );
-        }
+            &param.pair,
+        )
+        .await;
 
         Ok(())
     }
@@ -187,27 +230,26 @@ where
             return Ok(());
         }
 
-        // 注文キャンセル
-        self.cancel(param.open_order_id).await?;
-
-        // 成行売り注文
-        self.market_sell(&param.pair, param.amount).await?;
+        let state = LossCutState {
+            pair: param.pair.to_string(),
+            open_order_id: param.open_order_id,
+            open_order_rate: param.open_order_rate,
+            amount: param.amount,
+            canceled: false,
+        };
+        self.start_saga(&SagaState::LossCut(state.clone()))?;
+        self.run_loss_cut(state, 0).await?;
+        self.finish_saga()?;
 
-        if let Err(err) = self
-            .slack_client
-            .post_message(&TextMessage {
-                text: format!(
-                    "losscut completed!\npair:`{}`\nparam:`{:?}`",
-                    self.config.target_pair, param
-                ),
-            })
-            .await
-        {
-            warn!(
-                "{}",
-                format!("failed to send message to slack, {}", err).yellow()
-            );
-        }
+        self.notify(
+            Notification::new(NotifyEvent::LossCut, "losscut completed!", Level::Danger)
+                .field("pair", &param.pair.to_string())
+                .field("order id", &param.open_order_id.to_string())
+                .field("order rate", &format!("{:.3}", param.open_order_rate))
+                .field("amount", &format!("{:.8}", param.amount)),
+            &param.pair,
+        )
+        .await;
 
         Ok(())
     }
@@ -223,24 +265,48 @@ where
             self.cancel(*id).await?;
         }
 
-        self.sell(&param.pair, param.rate, param.amount).await?;
+        if let Err(err) = self.sell(&param.pair, param.rate, param.amount).await {
+            // 売注文のないコインを残さないよう、キャンセルした売注文を元のレートで出し直す
+            if let Some(rate) = param.open_order_rate {
+                warn!(
+                    "{}",
+                    format!("failed to sell, re-sell at rate:{:.3}, {}", rate, err).yellow()
+                );
+                if let Err(err) = self.sell(&param.pair, rate, param.amount).await {
+                    self.notify_failure("re-sell", &*err, &format!("{:?}", param))
+                        .await;
+                }
+            }
+            return Err(err);
+        }
 
-        if let Err(err) = self
-            .slack_client
-            .post_message(&TextMessage {
-                text: format!(
-                    "sell completed!\npair:`{}`\nparam:`{:?}`",
-                    self.config.target_pair, param
-                ),
-            })
-            .await
-        {
-            warn!(
-                "{}",
-                format!("failed to send message to slack, {}", err).yellow()
-            );
+        // トレーリング中の最高値を記録する（次回以降、この値からの下げ幅で利確する）
+        if let Some(high) = param.trailing_high_rate {
+            let status = BotStatus {
+                bot_name: self.config.bot_name.to_owned(),
+                pair: param.pair.to_string(),
+                r#type: "trailing_high_rate".to_owned(),
+                value: high,
+                memo: "利確のトレーリング中の最高値".to_owned(),
+            };
+            if let Err(err) = self.mysql_client.upsert_bot_status(&status) {
+                warn!(
+                    "{}",
+                    format!("failed to upsert bot status, {} status = {:?}", err, status).yellow()
+                );
+            }
         }
 
+        let mut notification = Notification::new(NotifyEvent::Sell, "sell completed!", Level::Good)
+            .field("pair", &param.pair.to_string())
+            .field("rate", &format!("{:.3}", param.rate))
+            .field("amount", &format!("{:.8}", param.amount))
+            .field("canceled orders", &format!("{:?}", param.open_order_ids));
+        if let Some(high) = param.trailing_high_rate {
+            notification = notification.field("trailing high", &format!("{:.3}", high));
+        }
+        self.notify(notification, &param.pair).await;
+
         Ok(())
     }
 
@@ -249,14 +315,16 @@ where
             info!("{}", "skip avg down as demo mode".green());
             return Ok(());
         }
-        // ナンピンすると余裕なくなるならスキップする
+        let fee_rate = self.config.fee_rate(&param.pair.to_string());
+        // ナンピンすると余裕なくなるならスキップする（成行買いの手数料を含める）
         let required = param.buy_jpy_per_lot * self.config.keep_lot;
-        if balance_jpy.amount - param.market_buy_amount < required {
+        let cost = param.market_buy_amount * (1.0 + fee_rate.taker);
+        if balance_jpy.amount - cost < required {
             warn!(
                 "{}",
                 format!(
                     "skip avg down, balance jpy is too little ({:.3} < {:.3})",
-                    balance_jpy.amount - param.market_buy_amount,
+                    balance_jpy.amount - cost,
                     required
                 )
                 .yellow()
@@ -264,41 +332,51 @@ where
             return Ok(());
         }
 
-        // 成行買い注文
-        let amount_new_coin = self
-            .market_buy(&param.pair, param.market_buy_amount)
-            .await?;
-
-        self.cancel(param.open_order_id).await?;
-
-        // ナンピン後の注文は二分割する（ナンピンのための買注文の金額を肥大化させないため）
-        let amount_coin = (param.open_order_amount + amount_new_coin) / 2.0;
-        let rate = {
-            let ratio = 1.0 + param.offset_sell_rate_ratio;
-            let rate_without_offset = param.open_order_rate / ratio;
-            ((param.open_order_amount * rate_without_offset) + param.market_buy_amount)
-                / (amount_coin * 2.0)
-                * ratio
+        // 分割した売注文が最小注文数量を下回るなら、成行買いの前にスキップする（買った後に売れなくなるため）
+        // ナンピンは建値より安く買うため、建値で見積もった数量は実際の数量以下になる
+        let estimated_amount_coin = {
+            let rate_without_offset = param.open_order_rate / (1.0 + param.offset_sell_rate_ratio);
+            (param.open_order_amount + param.market_buy_amount / rate_without_offset) / 2.0
         };
-        self.sell(&param.pair, rate, amount_coin).await?;
-        self.sell(&param.pair, rate, amount_coin).await?;
-
-        if let Err(err) = self
-            .slack_client
-            .post_message(&TextMessage {
-                text: format!(
-                    "avg down completed!\npair:`{}`,rate:`{:.3}`\namount:`{:.3} * 2`\nparam:`{:?}`",
-                    self.config.target_pair, rate, amount_coin, param
-                ),
-            })
-            .await
-        {
-            warn!(
-                "{}",
-                format!("failed to send message to slack, {}", err).yellow()
-            );
+        if let Err(err) = param.pair.validate_amount(estimated_amount_coin) {
+            warn!("{}", format!("skip avg down, {}", err).yellow());
+            return Ok(());
         }
 
+        let state = AvgDownState {
+            pair: param.pair.to_string(),
+            open_order_id: param.open_order_id,
+            open_order_rate: param.open_order_rate,
+            open_order_amount: param.open_order_amount,
+            market_buy_amount: param.market_buy_amount,
+            offset_sell_rate_ratio: param.offset_sell_rate_ratio,
+            before_buy: None,
+            amount_new_coin: None,
+            canceled: false,
+            sold_count: 0,
+        };
+        self.start_saga(&SagaState::AvgDown(state.clone()))?;
+        let state = self.run_avg_down(state, 0).await?;
+        self.finish_saga()?;
+        let (rate, amount_coin) = state
+            .calc_sell_order(self.avg_down_fee_markup(&state.pair))
+            .unwrap_or_default();
+
+        self.notify(
+            Notification::new(NotifyEvent::AvgDown, "avg down completed!", Level::Warning)
+                .field("pair", &param.pair.to_string())
+                .field("rate", &format!("{:.3}", rate))
+                .field(
+                    "amount",
+                    &format!("{:.8} * {}", amount_coin, AVG_DOWN_SELL_COUNT),
+                )
+                .field("market buy jpy", &format!("{:.0}", param.market_buy_amount))
+                .field("order id", &param.open_order_id.to_string())
+                .field("order rate", &format!("{:.3}", param.open_order_rate)),
+            &param.pair,
+        )
+        .await;
+
         Ok(())
     }
 
@@ -314,25 +392,389 @@ where
         // 成行売り注文
         self.market_sell(&param.pair, param.amount).await?;
 
-        if let Err(err) = self
-            .slack_client
-            .post_message(&TextMessage {
-                text: format!(
-                    "set profit completed!\npair:`{}`\nparam:`{:?}`",
-                    self.config.target_pair, param
-                ),
-            })
-            .await
+        self.notify(
+            Notification::new(NotifyEvent::SetProfit, "set profit completed!", Level::Good)
+                .field("pair", &param.pair.to_string())
+                .field("order id", &param.open_order_id.to_string())
+                .field("amount", &format!("{:.8}", param.amount)),
+            &param.pair,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    // 売注文のないコインに、エントリー時と同じ方法で売注文を出す
+    async fn action_recover(&self, param: &RecoverParam) -> MyResult<()> {
+        if self.config.demo_mode {
+            info!("{}", "skip recover as demo mode".green());
+            return Ok(());
+        }
+        let fee_rate = self.config.fee_rate(&param.pair.to_string());
+        let used_jpy = param.cost_rate * param.amount;
+        let rate = util::calc_sell_rate(
+            used_jpy,
+            param.amount,
+            param.profit_ratio,
+            param.offset_sell_rate_ratio,
+            &fee_rate,
+        );
+
+        let order_rate = match self.config.stop_loss_rate_ratio {
+            Some(ratio) => {
+                let stop_loss_rate = util::calc_stop_loss_rate(used_jpy, param.amount, ratio);
+                self.stop_loss(&param.pair, stop_loss_rate, param.amount)
+                    .await?;
+                stop_loss_rate
+            }
+            None => {
+                self.sell(&param.pair, rate, param.amount).await?;
+                rate
+            }
+        };
+
+        self.notify(
+            Notification::new(
+                NotifyEvent::Recover,
+                "orphaned coin recovered!",
+                Level::Warning,
+            )
+            .field("pair", &param.pair.to_string())
+            .field("rate", &format!("{:.3}", order_rate))
+            .field("amount", &format!("{:.8}", param.amount))
+            .field("cost rate", &format!("{:.3}", param.cost_rate)),
+            &param.pair,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    // 通知に失敗しても、アクションは失敗させない
+    async fn notify(&self, notification: Notification, pair: &Pair) {
+        let notification = match &self.config.slack_link_url {
+            Some(url) => {
+                notification.link(&pair.to_string(), &url.replace("{pair}", &pair.to_string()))
+            }
+            None => notification,
+        };
+        if let Err(err) = self.notifier.notify(&notification).await {
+            warn!("{}", format!("failed to notify, {}", err).yellow());
+        }
+    }
+
+    async fn notify_failure(&self, action_name: &str, err: &dyn std::error::Error, param: &str) {
+        let notification = Notification::new(
+            NotifyEvent::Failure,
+            &format!("{} failed!", action_name),
+            Level::Danger,
+        )
+        .field("error", &err.to_string())
+        .field("param", param);
+        if let Err(err) = self.notifier.notify(&notification).await {
+            error!("{}", err);
+        }
+    }
+
+    // 前回途中で失敗したアクションがあれば、残りのステップを再開する
+    // 再開が MAX_RESUME_ATTEMPTS 回を超えて失敗した場合は、補償ステップで売注文を出し直して終了する
+    // 補償ステップも MAX_COMPENSATE_ATTEMPTS 回失敗した場合は、記録を残したまま諦めて取引を続ける
+    // （記録が残っている間は、ナンピン, 損切りを開始しない）
+    pub async fn resume_in_flight_action(&self) -> MyResult<()> {
+        let action = match self
+            .mysql_client
+            .select_in_flight_action(&self.config.bot_name)?
         {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        let state = SagaState::from_in_flight_action(&action)?;
+        let attempts = action.attempts + 1;
+        let pair = Pair::new(&action.pair)?;
+
+        if attempts > MAX_RESUME_ATTEMPTS + MAX_COMPENSATE_ATTEMPTS {
+            // 通知済みのため、ログのみ出す
+            warn!(
+                "{}",
+                format!("skip in flight action, waiting for operator ({:?})", state).yellow()
+            );
+            return Ok(());
+        }
+
+        let notification = if attempts > MAX_RESUME_ATTEMPTS {
             warn!(
                 "{}",
-                format!("failed to send message to slack, {}", err).yellow()
+                format!(
+                    "give up resuming in flight action, compensate ({:?})",
+                    state
+                )
+                .yellow()
             );
+            if let Err(err) = self.compensate(&state).await {
+                // 失敗した回数を記録し、次回の実行時に補償ステップを再試行する
+                self.save_saga(&state, attempts)?;
+                let title = if attempts == MAX_RESUME_ATTEMPTS + MAX_COMPENSATE_ATTEMPTS {
+                    "in flight action abandoned!"
+                } else {
+                    "in flight action compensation failed!"
+                };
+                let notification = Notification::new(NotifyEvent::InFlight, title, Level::Danger)
+                    .field("pair", &action.pair)
+                    .field("type", &action.action_type)
+                    .field("attempts", &attempts.to_string())
+                    .field("error", &err.to_string());
+                self.notify(notification, &pair).await;
+                return Ok(());
+            }
+            Notification::new(
+                NotifyEvent::InFlight,
+                "in flight action compensated!",
+                Level::Warning,
+            )
+        } else {
+            info!(
+                "resume in flight action, attempts:{} ({:?})",
+                attempts, state
+            );
+            self.save_saga(&state, attempts)?;
+            match state {
+                SagaState::AvgDown(s) => {
+                    self.run_avg_down(s, attempts).await?;
+                }
+                SagaState::LossCut(s) => {
+                    self.run_loss_cut(s, attempts).await?;
+                }
+            }
+            Notification::new(
+                NotifyEvent::InFlight,
+                "in flight action resumed!",
+                Level::Info,
+            )
+        };
+        self.finish_saga()?;
+
+        let notification = notification
+            .field("pair", &action.pair)
+            .field("type", &action.action_type)
+            .field("attempts", &attempts.to_string());
+        self.notify(notification, &pair).await;
+        Ok(())
+    }
+
+    // ナンピンの残りのステップを実行する（attempts: 再開した回数, 初回は0）
+    async fn run_avg_down(&self, mut s: AvgDownState, attempts: u32) -> MyResult<AvgDownState> {
+        let pair = Pair::new(&s.pair)?;
+        if s.amount_new_coin.is_none() {
+            if attempts > 0 {
+                // 成行買いの途中で停止した場合は、成行買い前の状態と照合して約定したかを確認する
+                match self.reconcile_avg_down_buy(&s).await? {
+                    Some(amount) => {
+                        s.amount_new_coin = Some(amount);
+                        self.save_saga_progress(&SagaState::AvgDown(s.clone()), attempts);
+                    }
+                    None => {
+                        // 約定していなければ、既存の売注文が残っている
+                        warn!(
+                            "{}",
+                            "skip resuming avg down, market buy is not placed".yellow()
+                        );
+                        return Ok(s);
+                    }
+                }
+            } else {
+                // 成行買い前の状態を記録する（記録できなければ買わない）
+                s.before_buy = Some(self.take_snapshot().await?);
+                self.save_saga(&SagaState::AvgDown(s.clone()), attempts)?;
+                // 成行買い注文
+                s.amount_new_coin = Some(self.market_buy(&pair, s.market_buy_amount).await?);
+                self.save_saga_progress(&SagaState::AvgDown(s.clone()), attempts);
+            }
+        }
+
+        if !s.canceled {
+            self.cancel_if_open(s.open_order_id, attempts).await?;
+            s.canceled = true;
+            self.save_saga_progress(&SagaState::AvgDown(s.clone()), attempts);
         }
 
+        let fee_markup = self.avg_down_fee_markup(&s.pair);
+        let (rate, amount_coin) = s.calc_sell_order(fee_markup).unwrap_or_default();
+        while s.sold_count < AVG_DOWN_SELL_COUNT {
+            let amount = if attempts > 0 {
+                // 再開時は残高を超えて売らない（停止中に約定, キャンセルされている場合があるため）
+                let remaining = (AVG_DOWN_SELL_COUNT - s.sold_count) as f64;
+                amount_coin.min(self.available_coin(&pair).await? / remaining)
+            } else {
+                amount_coin
+            };
+            self.sell(&pair, rate, amount).await?;
+            s.sold_count += 1;
+            self.save_saga_progress(&SagaState::AvgDown(s.clone()), attempts);
+        }
+        Ok(s)
+    }
+
+    // 損切りの残りのステップを実行する（attempts: 再開した回数, 初回は0）
+    async fn run_loss_cut(&self, mut s: LossCutState, attempts: u32) -> MyResult<LossCutState> {
+        let pair = Pair::new(&s.pair)?;
+        if !s.canceled {
+            // 注文キャンセル
+            self.cancel_if_open(s.open_order_id, attempts).await?;
+            s.canceled = true;
+            self.save_saga_progress(&SagaState::LossCut(s.clone()), attempts);
+        }
+
+        // 成行売り注文
+        let amount = if attempts > 0 {
+            s.amount.min(self.available_coin(&pair).await?)
+        } else {
+            s.amount
+        };
+        self.market_sell(&pair, amount).await?;
+        Ok(s)
+    }
+
+    // 成行買い前の状態と照合し、成行買いが約定していれば増えたコイン数を返す
+    // 成行買い前の状態が記録されていなければ約定したか分からないため、エラーにする（記録を残してオペレーターが対応する）
+    async fn reconcile_avg_down_buy(&self, s: &AvgDownState) -> MyResult<Option<f64>> {
+        let before = match &s.before_buy {
+            Some(v) => v,
+            None => {
+                return Err(Box::new(UnresolvedOrder(format!(
+                    "market buy of avg down is not recorded, pair:{}",
+                    s.pair
+                ))))
+            }
+        };
+        let req = NewOrder::new_market_buy_order(&Pair::new(&s.pair)?, s.market_buy_amount);
+        let balances = self.coincheck_client.get_accounts_balance().await?;
+        let open_orders = self.coincheck_client.get_exchange_orders_opens().await?;
+        let transactions = self
+            .coincheck_client
+            .get_exchange_orders_transactions()
+            .await?;
+        let order = find_placed_order(&req, before, &open_orders, &transactions, &balances)?;
+        Ok(order.and_then(|o| o.amount))
+    }
+
+    // 補償ステップ: 売注文のないコインを売注文で保護する
+    async fn compensate(&self, state: &SagaState) -> MyResult<()> {
+        match state {
+            SagaState::AvgDown(s) => {
+                let pair = Pair::new(&s.pair)?;
+                let fee_markup = self.avg_down_fee_markup(&s.pair);
+                let mut s = s.clone();
+                if s.amount_new_coin.is_none() {
+                    s.amount_new_coin = match self.reconcile_avg_down_buy(&s).await? {
+                        Some(v) => Some(v),
+                        // 成行買いが約定していなければ、既存の売注文が残っている
+                        None => return Ok(()),
+                    };
+                }
+                let rate = match s.calc_sell_order(fee_markup) {
+                    Some((rate, _)) => rate,
+                    None => return Ok(()),
+                };
+                let amount = s
+                    .unsold_amount(fee_markup)
+                    .min(self.available_coin(&pair).await?);
+                if amount > 0.0 {
+                    self.sell(&pair, rate, amount).await?;
+                }
+            }
+            SagaState::LossCut(s) => {
+                if !s.canceled {
+                    return Ok(());
+                }
+                // 損切りできないため、元の売注文を出し直す（次回以降の判定で改めて損切りする）
+                let pair = Pair::new(&s.pair)?;
+                let amount = s.amount.min(self.available_coin(&pair).await?);
+                if amount > 0.0 {
+                    self.sell(&pair, s.open_order_rate, amount).await?;
+                }
+            }
+        }
         Ok(())
     }
 
+    // 開始前に進捗を記録する（記録できなければ開始しない）
+    // 諦めたアクションの記録が残っていれば、オペレーターが対応するまで新しいアクションを開始しない（記録を上書きしないため）
+    fn start_saga(&self, state: &SagaState) -> MyResult<()> {
+        if let Some(action) = self
+            .mysql_client
+            .select_in_flight_action(&self.config.bot_name)?
+        {
+            return Err(Box::new(InFlightActionRemains {
+                action_type: action.action_type,
+            }));
+        }
+        self.save_saga(state, 0)
+    }
+
+    fn save_saga(&self, state: &SagaState, attempts: u32) -> MyResult<()> {
+        let action = state.to_in_flight_action(&self.config.bot_name, attempts)?;
+        self.mysql_client.upsert_in_flight_action(&action)
+    }
+
+    // 途中の進捗の記録に失敗しても、アクションは続ける（売注文を出さずに中断する方が危険なため）
+    fn save_saga_progress(&self, state: &SagaState, attempts: u32) {
+        if let Err(err) = self.save_saga(state, attempts) {
+            warn!(
+                "{}",
+                format!(
+                    "failed to save in flight action, {} state = {:?}",
+                    err, state
+                )
+                .yellow()
+            );
+        }
+    }
+
+    fn finish_saga(&self) -> MyResult<()> {
+        self.mysql_client
+            .delete_in_flight_action(&self.config.bot_name)
+    }
+
+    fn avg_down_fee_markup(&self, pair: &str) -> f64 {
+        util::calc_fee_markup(&self.config.fee_rate(pair), &Liquidity::Maker)
+    }
+
+    // 売注文に使えるコイン数（注文中を除く）
+    async fn available_coin(&self, pair: &Pair) -> MyResult<f64> {
+        let balances = self.coincheck_client.get_accounts_balance().await?;
+        Ok(balances.get(&pair.key).map(|b| b.amount).unwrap_or(0.0))
+    }
+
+    // 取引ペアの未約定の注文をすべてキャンセルする（キャンセルした注文のID）
+    pub async fn cancel_open_orders(&self, pair: &Pair) -> MyResult<Vec<u64>> {
+        if self.config.demo_mode {
+            info!("{}", "skip cancel open orders as demo mode".green());
+            return Ok(vec![]);
+        }
+        let open_orders = self.coincheck_client.get_exchange_orders_opens().await?;
+        let mut ids = vec![];
+        for open_order in open_orders.iter().filter(|o| o.pair == pair.to_string()) {
+            self.cancel(open_order.id).await?;
+            ids.push(open_order.id);
+        }
+        Ok(ids)
+    }
+
+    // 再開時は、既に約定, キャンセル済みの注文をキャンセルしない
+    async fn cancel_if_open(&self, open_order_id: u64, attempts: u32) -> MyResult<()> {
+        if attempts > 0 {
+            let open_orders = self.coincheck_client.get_exchange_orders_opens().await?;
+            if !open_orders.iter().any(|o| o.id == open_order_id) {
+                debug!(
+                    "{}",
+                    format!("skip cancel, order {} is not open", open_order_id).blue()
+                );
+                return Ok(());
+            }
+        }
+        self.cancel(open_order_id).await
+    }
+
     // 成行買い注文
     async fn market_buy(&self, pair: &Pair, amount_jpy: f64) -> MyResult<f64> {
         // 買い注文で増加したコイン数を算出するため最初の残高を保存しておく
@@ -345,7 +787,7 @@ where
         debug!("{}", "send market buy order".blue());
         let buy_order = {
             let req = NewOrder::new_market_buy_order(pair, amount_jpy);
-            self.coincheck_client.post_exchange_orders(&req).await?
+            self.post_order(&req).await?
         };
 
         // 約定待ち
@@ -363,9 +805,10 @@ where
                 break;
             }
             // 約定待ち
-            thread::sleep(time::Duration::from_secs(
+            tokio::time::sleep(time::Duration::from_secs(
                 self.config.external_service_wait_interval_sec,
-            ));
+            ))
+            .await;
         }
 
         let event = Event {
@@ -398,9 +841,10 @@ where
                 break amount;
             }
             // 残高反映待ち
-            thread::sleep(time::Duration::from_secs(
+            tokio::time::sleep(time::Duration::from_secs(
                 self.config.external_service_wait_interval_sec,
-            ));
+            ))
+            .await;
         };
 
         Ok(amount_coin)
@@ -410,10 +854,7 @@ where
     async fn market_sell(&self, pair: &Pair, amount_coin: f64) -> MyResult<()> {
         debug!("{}", "send market sell order".blue());
         let new_order = NewOrder::new_market_sell_order(pair, amount_coin);
-        let order = self
-            .coincheck_client
-            .post_exchange_orders(&new_order)
-            .await?;
+        let order = self.post_order(&new_order).await?;
 
         let event = Event {
             pair: order.pair,
@@ -441,7 +882,7 @@ where
     // 指値売り注文
     async fn sell(&self, pair: &Pair, rate: f64, amount_coin: f64) -> MyResult<()> {
         let req = NewOrder::new_sell_order(pair, rate, amount_coin);
-        let sell_order = self.coincheck_client.post_exchange_orders(&req).await?;
+        let sell_order = self.post_order(&req).await?;
         debug!(
             "{}",
             format!(
@@ -478,6 +919,119 @@ where
         Ok(())
     }
 
+    // 注文
+    // タイムアウトなど取引所で処理されたか不明なエラーの場合は、注文前後の未決済注文, 約定履歴, 残高を照合する
+    // 照合できなければ二重注文を避けるため再注文せず、エラーを返して次回の取引で照合する
+    // 照合用の状態の取得で注文ごとに GET を3回（照合時は最大 RECONCILE_CHECK_COUNT * 3 回）呼ぶため、レート制限の枠を消費する
+    async fn post_order(&self, req: &NewOrder) -> MyResult<Order> {
+        let snapshot = self.take_snapshot().await?;
+
+        let err = match self.coincheck_client.post_exchange_orders(req).await {
+            Ok(order) => return Ok(order),
+            Err(err) => err,
+        };
+        if !is_ambiguous_error(err.as_ref()) {
+            return Err(err);
+        }
+        warn!(
+            "{}",
+            format!(
+                "order result is unknown, reconcile order, err = {}, req = {:?}",
+                err, req
+            )
+            .yellow()
+        );
+
+        match self.reconcile_order(req, &snapshot).await? {
+            Some(order) => {
+                info!(
+                    "{}",
+                    format!("order has been placed, order = {:?}", order).green()
+                );
+                Ok(order)
+            }
+            None => Err(Box::new(UnresolvedOrder(format!(
+                "{}, req = {:?}",
+                err, req
+            )))),
+        }
+    }
+
+    // 注文前の状態（未決済注文, 約定履歴, 残高）
+    async fn take_snapshot(&self) -> MyResult<OrderSnapshot> {
+        let balances = self.coincheck_client.get_accounts_balance().await?;
+        let open_orders = self.coincheck_client.get_exchange_orders_opens().await?;
+        let transactions = self
+            .coincheck_client
+            .get_exchange_orders_transactions()
+            .await?;
+        Ok(OrderSnapshot::new(balances, &open_orders, &transactions))
+    }
+
+    // 注文前の状態と現在の未決済注文・約定履歴・残高を照合し、注文が処理されていればその注文を返す
+    async fn reconcile_order(
+        &self,
+        req: &NewOrder,
+        snapshot: &OrderSnapshot,
+    ) -> MyResult<Option<Order>> {
+        for _ in 0..RECONCILE_CHECK_COUNT {
+            // 取引所への反映待ち
+            tokio::time::sleep(time::Duration::from_secs(
+                self.config.external_service_wait_interval_sec,
+            ))
+            .await;
+
+            let balances = self.coincheck_client.get_accounts_balance().await?;
+            let open_orders = self.coincheck_client.get_exchange_orders_opens().await?;
+            let transactions = self
+                .coincheck_client
+                .get_exchange_orders_transactions()
+                .await?;
+            if let Some(order) =
+                find_placed_order(req, snapshot, &open_orders, &transactions, &balances)?
+            {
+                return Ok(Some(order));
+            }
+        }
+        Ok(None)
+    }
+
+    // 逆指値売り注文
+    // 指値レートは逆指値レートより下げ、逆指値に達した後に急落しても約定するようにする
+    async fn stop_loss(&self, pair: &Pair, stop_loss_rate: f64, amount_coin: f64) -> MyResult<()> {
+        let rate = stop_loss_rate * (1.0 - self.config.stop_loss_slippage_ratio);
+        let req = NewOrder::new_stop_loss_order(pair, stop_loss_rate, rate, amount_coin);
+        let order = self.post_order(&req).await?;
+        debug!(
+            "{}",
+            format!(
+                "send stop loss order (amount_coin:{:.3}, stop_loss_rate:{:.3})",
+                amount_coin, stop_loss_rate
+            )
+            .blue(),
+        );
+
+        let event = Event {
+            pair: order.pair,
+            event_type: EventType::Sell,
+            memo: format!(
+                "stop loss order completed! `{} stop_loss_rate:{:.3} amount:{:.3}`",
+                pair.to_string(),
+                stop_loss_rate,
+                amount_coin,
+            ),
+            recorded_at: order.created_at.naive_utc(),
+        };
+        if let Err(err) = self.mysql_client.insert_event(&event) {
+            warn!(
+                "{}",
+                format!("failed to insert event, {} event = {:.?}", err, event).yellow()
+            );
+        }
+
+        Ok(())
+    }
+
     // 注文キャンセル
     async fn cancel(&self, open_order_id: u64) -> MyResult<()> {
         debug!("{}", "cancel".blue());
@@ -497,9 +1051,10 @@ where
                 break;
             }
             // キャンセル待ち
-            thread::sleep(time::Duration::from_secs(
+            tokio::time::sleep(time::Duration::from_secs(
                 self.config.external_service_wait_interval_sec,
-            ));
+            ))
+            .await;
         }
 
         Ok(())
@@ -1,27 +1,30 @@
 use crate::bot::action::ActionBehavior;
-use crate::bot::model::{ActionType, LineMethod, TradeInfo, TradeInfoParam};
+use crate::bot::model::{ActionType, LineMethod, LossCutParam, TradeInfo, TradeInfoParam};
 use crate::coincheck::model::{Balance, OpenOrder, OrderType, Pair};
 use crate::config::Config;
+use crate::error::MyError::KeyNotFound;
 use crate::error::MyResult;
+use crate::monitor::metrics::{self, TradeStatus};
 use crate::mysql::model::{BotStatus, MarketsMethods};
+use crate::notifier::base::Notifier;
+use crate::notifier::model::{Event, Level, Notification};
 use crate::util::calc_slope;
-use crate::{coincheck, mysql, slack, strategy};
+use crate::{coincheck, mysql, strategy};
 
 use chrono::{DateTime, Duration, Utc};
 use colored::Colorize;
-use log::{debug, info};
+use log::{debug, info, warn};
 use std::collections::HashMap;
-use std::{thread, time};
 
 pub struct Bot<'a, T, U, V, W>
 where
-    T: slack::client::Client,
+    T: Notifier,
     U: mysql::client::Client,
     V: coincheck::client::Client,
     W: strategy::base::Strategy,
 {
     pub config: &'a Config,
-    pub slack_client: &'a T,
+    pub notifier: &'a T,
     pub mysql_client: &'a U,
     pub coincheck_client: &'a V,
     pub strategy: &'a W,
@@ -30,19 +33,86 @@ where
 
 impl<T, U, V, W> Bot<'_, T, U, V, W>
 where
-    T: slack::client::Client,
+    T: Notifier,
     U: mysql::client::Client,
     V: coincheck::client::Client + std::marker::Sync,
     W: strategy::base::Strategy,
 {
-    pub fn wait(&self) -> MyResult<()> {
-        let d = time::Duration::from_secs(self.config.interval_sec);
-        debug!("wait ... [{:?}]", d);
-        thread::sleep(d);
+    // 指定した売注文を損切りする（Slack のコマンドから要求された場合）
+    pub async fn loss_cut_order(&self, open_order_id: u64) -> MyResult<()> {
+        let pair = Pair::new(&self.config.target_pair)?;
+        let open_orders = self.coincheck_client.get_exchange_orders_opens().await?;
+        let open_order = open_orders
+            .iter()
+            .find(|o| {
+                o.id == open_order_id
+                    && o.pair == pair.to_string()
+                    && o.order_type == OrderType::Sell
+            })
+            .ok_or_else(|| KeyNotFound {
+                key: open_order_id.to_string(),
+                collection_name: "open sell orders".to_owned(),
+            })?;
+        let action = ActionType::LossCut(LossCutParam {
+            pair,
+            open_order_id,
+            open_order_rate: open_order.rate,
+            amount: open_order.pending_amount,
+        });
+        self.action(vec![action]).await
+    }
+
+    // 終了処理（実行中の取引が終わった後に呼ぶ）
+    // 設定に応じて未約定の注文をキャンセルし、最新の状態を記録してから終了を通知する
+    pub async fn shutdown(&self, now: &DateTime<Utc>) -> MyResult<()> {
+        info!("{}", "shutdown ...".yellow());
+        let pair = Pair::new(&self.config.target_pair)?;
+        let open_orders = if self.config.cancel_open_orders_on_shutdown {
+            let canceled = self.action_behavior.cancel_open_orders(&pair).await?;
+            format!("canceled {:?}", canceled)
+        } else {
+            let open_orders = self.coincheck_client.get_exchange_orders_opens().await?;
+            let ids: Vec<u64> = open_orders
+                .iter()
+                .filter(|o| o.pair == pair.to_string())
+                .map(|o| o.id)
+                .collect();
+            format!("kept {:?}", ids)
+        };
+
+        // 通知は送りたいため、記録に失敗しても終了処理は続ける
+        if let Err(err) = self.flush(now).await {
+            warn!(
+                "{}",
+                format!("failed to flush bot status, {}", err).yellow()
+            );
+        }
+
+        if let Err(err) = self
+            .notifier
+            .notify(
+                &Notification::new(Event::Shutdown, "bot shutdown!", Level::Info)
+                    .field("bot name", &self.config.bot_name)
+                    .field("pair", &self.config.target_pair)
+                    .field("open orders", &open_orders),
+            )
+            .await
+        {
+            warn!("{}", format!("failed to notify, {}", err).yellow());
+        }
+        info!("{}", "shutdown completed".yellow());
         Ok(())
     }
 
+    async fn flush(&self, now: &DateTime<Utc>) -> MyResult<()> {
+        let info = self.fetch(now).await?;
+        self.upsert(&info)
+    }
+
     pub async fn trade(&self, now: &DateTime<Utc>) -> MyResult<()> {
+        // 前回途中で失敗したアクションを先に片付ける
+        self.action_behavior.resume_in_flight_action().await?;
+
         let info = self.fetch(now).await?;
         info!(
             "{}",
@@ -120,6 +190,16 @@ where
             .mysql_client
             .select_market_summary(&self.config.target_pair, 1)?;
 
+        // 未設定, またはリセット済み（-1）ならトレーリングしていない
+        param.trailing_high_rate = match self.mysql_client.select_bot_status(
+            &self.config.bot_name,
+            &self.config.target_pair,
+            "trailing_high_rate",
+        ) {
+            Ok(v) if v.value > 0.0 => Some(v.value),
+            _ => None,
+        };
+
         param.support_line_period_long = self.config.support_line_period_long;
         param.support_line_period_short = self.config.support_line_period_short;
         param.support_line_offset = self.config.support_line_offset;
@@ -176,6 +256,17 @@ where
             memo: "約定待ちの売注文レート".to_owned(),
         })?;
 
+        // 売注文がなくなればトレーリングを終了する
+        if open_orders.is_empty() && info.trailing_high_rate.is_some() {
+            self.mysql_client.upsert_bot_status(&BotStatus {
+                bot_name: self.config.bot_name.to_owned(),
+                pair: info.pair.to_string(),
+                r#type: "trailing_high_rate".to_owned(),
+                value: -1.0,
+                memo: "利確のトレーリング中の最高値".to_owned(),
+            })?;
+        }
+
         self.mysql_client.upsert_bot_status(&BotStatus {
             bot_name: self.config.bot_name.to_owned(),
             pair: info.pair.to_string(),
@@ -224,14 +315,14 @@ where
             memo: "サポートライン（短期）の傾き".to_owned(),
         })?;
 
-        let long_trend = if info
+        let (long_trend, long_trend_name) = if info
             .is_up_trend(self.config.wma_period_short, self.config.wma_period_long)?
         {
-            1.0
+            (1.0, "up")
         } else if info.is_down_trend(self.config.wma_period_short, self.config.wma_period_long)? {
-            2.0
+            (2.0, "down")
         } else {
-            0.0
+            (0.0, "flat")
         };
         self.mysql_client.upsert_bot_status(&BotStatus {
             bot_name: self.config.bot_name.to_owned(),
@@ -241,7 +332,7 @@ where
             memo: "長期トレンド（1:上昇, 2:下降）".to_owned(),
         })?;
 
-        let total_balance_jpy = info.calc_total_balance_jpy();
+        let total_balance_jpy = info.calc_total_balance_jpy(&self.config.fee_rates);
         let total_jpy =
             match self
                 .mysql_client
@@ -261,6 +352,20 @@ where
             })?;
         }
 
+        metrics::shared().record_status(TradeStatus {
+            pair: info.pair.to_string(),
+            sell_rate: info.get_sell_rate()?,
+            buy_rate: info.buy_rate,
+            balances: info.balances.clone(),
+            open_orders: info.open_orders.clone(),
+            support_line_long: info.support_lines_long.get_latest(),
+            support_line_short: info.support_lines_short.get_latest(),
+            resistance_line: info.resistance_lines.get_latest(),
+            long_trend: long_trend_name.to_owned(),
+            total_balance_jpy,
+            updated_at: Utc::now(),
+        });
+
         Ok(())
     }
 
@@ -0,0 +1,75 @@
+use chrono::{DateTime, Utc};
+use std::sync::{Mutex, MutexGuard};
+
+// Slack のコマンドから変更するボットの実行状態
+// 変更は取引の合間に反映する（実行中の取引には影響しない）
+#[derive(Debug, Default)]
+pub struct BotControl {
+    state: Mutex<ControlState>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ControlState {
+    // 一時停止中か（停止中は取引しない）
+    pub paused: bool,
+    pub demo_mode: bool,
+    // 損切りを要求された注文のID
+    pub loss_cut_order_ids: Vec<u64>,
+    pub last_trade_at: Option<DateTime<Utc>>,
+    // 直近の取引のエラー（成功していれば None）
+    pub last_error: Option<String>,
+}
+
+impl BotControl {
+    pub fn new(demo_mode: bool) -> BotControl {
+        BotControl {
+            state: Mutex::new(ControlState {
+                demo_mode,
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn snapshot(&self) -> ControlState {
+        self.lock().clone()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.lock().paused
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.lock().paused = paused;
+    }
+
+    pub fn demo_mode(&self) -> bool {
+        self.lock().demo_mode
+    }
+
+    pub fn set_demo_mode(&self, demo_mode: bool) {
+        self.lock().demo_mode = demo_mode;
+    }
+
+    pub fn request_loss_cut(&self, open_order_id: u64) {
+        let mut state = self.lock();
+        if !state.loss_cut_order_ids.contains(&open_order_id) {
+            state.loss_cut_order_ids.push(open_order_id);
+        }
+    }
+
+    // 損切りを要求された注文のIDを取り出す
+    pub fn take_loss_cut_requests(&self) -> Vec<u64> {
+        std::mem::take(&mut self.lock().loss_cut_order_ids)
+    }
+
+    pub fn record_trade(&self, now: &DateTime<Utc>, error: Option<String>) {
+        let mut state = self.lock();
+        state.last_trade_at = Some(*now);
+        state.last_error = error;
+    }
+
+    // 状態の変更中にパニックしても、状態は壊れないため使い続ける
+    fn lock(&self) -> MutexGuard<'_, ControlState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -4,7 +4,7 @@ use crate::error::MyError::KeyNotFound;
 use crate::error::MyError::TooShort;
 use crate::error::MyResult;
 use crate::mysql::model::MarketSummary;
-use crate::slack::client::TextMessage;
+use crate::notifier::model::Notification;
 
 use std::collections::HashMap;
 
@@ -20,15 +20,20 @@ pub struct EntryParam {
 pub struct LossCutParam {
     pub pair: Pair,
     pub open_order_id: u64,
+    pub open_order_rate: f64,
     pub amount: f64,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct SellParam {
     pub open_order_ids: Vec<u64>,
+    // キャンセルする売注文のレート（新しい売注文に失敗した場合は、このレートで出し直す）
+    pub open_order_rate: Option<f64>,
     pub pair: Pair,
     pub rate: f64,
     pub amount: f64,
+    // 利確のトレーリング中の最高値（トレーリングでなければ None）
+    pub trailing_high_rate: Option<f64>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -50,10 +55,20 @@ pub struct SetProfitParam {
     pub amount: f64,
 }
 
+#[derive(Debug, PartialEq)]
+pub struct RecoverParam {
+    pub pair: Pair,
+    pub amount: f64,
+    // 直近の買いの約定から求めた取得レート
+    pub cost_rate: f64,
+    pub profit_ratio: f64,
+    pub offset_sell_rate_ratio: f64,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct NotifyParam {
     pub log_message: String,
-    pub slack_message: TextMessage,
+    pub notification: Notification,
 }
 
 #[derive(Debug, PartialEq)]
@@ -63,9 +78,25 @@ pub enum ActionType {
     Sell(SellParam),
     AvgDown(AvgDownParam),
     SetProfit(SetProfitParam),
+    Recover(RecoverParam),
     Notify(NotifyParam),
 }
 
+impl ActionType {
+    // メトリクスに記録する名前
+    pub fn name(&self) -> &str {
+        match self {
+            ActionType::Entry(_) => "entry",
+            ActionType::LossCut(_) => "loss_cut",
+            ActionType::Sell(_) => "sell",
+            ActionType::AvgDown(_) => "avg_down",
+            ActionType::SetProfit(_) => "set_profit",
+            ActionType::Recover(_) => "recover",
+            ActionType::Notify(_) => "notify",
+        }
+    }
+}
+
 pub trait LineMethod {
     fn get_latest(&self) -> Option<f64>;
     fn get_later(&self, size: usize) -> MyResult<Vec<f64>>;
@@ -114,6 +145,7 @@ pub struct TradeInfo {
     pub resistance_lines: StraightLine,
     pub order_books: OrderBooks,
     pub market_summary: MarketSummary,
+    pub trailing_high_rate: Option<f64>, // 利確のトレーリング中の最高値
 }
 
 #[derive(Debug, Default)]
@@ -128,6 +160,7 @@ pub struct TradeInfoParam {
     pub buy_volumes: Vec<f64>,
     pub order_books: OrderBooks,
     pub market_summary: MarketSummary,
+    pub trailing_high_rate: Option<f64>,
 
     pub support_line_period_long: usize,
     pub support_line_period_short: usize,
@@ -168,6 +201,7 @@ impl TradeInfoParam {
             resistance_lines: resistance_lines,
             order_books: self.order_books.clone(),
             market_summary: self.market_summary.clone(),
+            trailing_high_rate: self.trailing_high_rate,
         })
     }
 
@@ -325,7 +359,8 @@ impl TradeInfo {
         }
     }
 
-    pub fn calc_total_balance_jpy(&self) -> f64 {
+    // 残高の合計（コインは成行で売った場合の手数料を差し引いて評価する）
+    pub fn calc_total_balance_jpy(&self, fee_rates: &HashMap<String, FeeRate>) -> f64 {
         let mut total = 0.0;
         for (k, balance) in self.balances.iter() {
             if *k == self.pair.settlement {
@@ -333,7 +368,8 @@ impl TradeInfo {
             } else {
                 let pair = format!("{}_{}", k, self.pair.settlement);
                 let rate = self.sell_rates.get(&pair).unwrap();
-                total += balance.total() * rate;
+                let fee_rate = fee_rates.get(&pair).copied().unwrap_or_default();
+                total += balance.total() * rate * (1.0 - fee_rate.taker);
             }
         }
         total
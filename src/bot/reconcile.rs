@@ -0,0 +1,384 @@
+use crate::coincheck::model::{Balance, NewOrder, OpenOrder, Order, OrderType, Pair, Transaction};
+use crate::error::MyError;
+use crate::error::MyResult;
+use crate::util::to_request_string;
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use chrono::{FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+
+// 残高変化を注文によるものとみなす許容誤差（注文数量に対する割合）
+const BALANCE_DELTA_TOLERANCE_RATIO: f64 = 0.01;
+
+// 注文前の状態（注文結果が不明な場合の照合用）
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OrderSnapshot {
+    pub balances: HashMap<String, Balance>,
+    pub open_order_ids: Vec<u64>,
+    pub transaction_ids: Vec<u64>,
+}
+
+impl OrderSnapshot {
+    pub fn new(
+        balances: HashMap<String, Balance>,
+        open_orders: &[OpenOrder],
+        transactions: &[Transaction],
+    ) -> OrderSnapshot {
+        OrderSnapshot {
+            balances,
+            open_order_ids: open_orders.iter().map(|o| o.id).collect(),
+            transaction_ids: transactions.iter().map(|t| t.id).collect(),
+        }
+    }
+}
+
+// 注文が取引所で処理されたか不明なエラーか
+// 取引所がエラーを返した場合は注文されていないことが確実なので false
+pub fn is_ambiguous_error(err: &(dyn Error + 'static)) -> bool {
+    if let Some(e) = err.downcast_ref::<MyError>() {
+        return match e {
+            MyError::ResponseError { .. } => false,
+            // 送信前の検証エラー
+            MyError::InvalidOrder { .. } => false,
+            MyError::HttpStatusError { status, .. } => *status >= 500,
+            _ => true,
+        };
+    }
+    if let Some(e) = err.downcast_ref::<reqwest::Error>() {
+        return !e.is_connect() && !e.is_builder();
+    }
+    true
+}
+
+// 注文前後の未決済注文, 約定履歴, 残高から、注文が処理されたかを判定する
+// 処理されていれば注文情報を返す（残高から推定した成行注文は注文IDが分からないため 0 とする）
+pub fn find_placed_order(
+    req: &NewOrder,
+    before: &OrderSnapshot,
+    open_orders: &[OpenOrder],
+    transactions: &[Transaction],
+    balances: &HashMap<String, Balance>,
+) -> MyResult<Option<Order>> {
+    let pair = Pair::new(&req.pair)?;
+    let now = Utc::now().with_timezone(&FixedOffset::east_opt(0).unwrap());
+
+    if let OrderType::Buy | OrderType::Sell = req.order_type {
+        let rate = req.rate.map(to_request_string);
+        let amount = req.amount.unwrap_or(0.0);
+        let found = open_orders.iter().find(|o| {
+            !before.open_order_ids.contains(&o.id)
+                && o.pair == req.pair
+                && o.order_type == req.order_type
+                && Some(to_request_string(o.rate)) == rate
+                && o.pending_amount <= amount * (1.0 + BALANCE_DELTA_TOLERANCE_RATIO)
+        });
+        if let Some(o) = found {
+            return Ok(Some(Order {
+                id: o.id,
+                rate: Some(o.rate),
+                amount: Some(o.pending_amount),
+                order_type: o.order_type.clone(),
+                pair,
+                created_at: o.created_at,
+            }));
+        }
+    }
+
+    // 即時に約定した注文は未決済注文に現れないため、新しい約定から探す
+    if let Some(order) = find_order_in_transactions(req, &pair, before, transactions) {
+        return Ok(Some(order));
+    }
+
+    match req.order_type {
+        OrderType::Buy | OrderType::Sell => Ok(None),
+        OrderType::MarketBuy => {
+            let jpy = req.market_buy_amount.unwrap_or(0.0);
+            let spent = -balance_delta(&before.balances, balances, &pair.settlement);
+            let bought = balance_delta(&before.balances, balances, &pair.key);
+            if bought > 0.0 && is_near(spent, jpy) {
+                Ok(Some(Order {
+                    id: 0,
+                    rate: None,
+                    amount: Some(bought),
+                    order_type: OrderType::MarketBuy,
+                    pair,
+                    created_at: now,
+                }))
+            } else {
+                Ok(None)
+            }
+        }
+        OrderType::MarketSell => {
+            let amount = req.amount.unwrap_or(0.0);
+            let sold = -balance_delta(&before.balances, balances, &pair.key);
+            if is_near(sold, amount) {
+                Ok(Some(Order {
+                    id: 0,
+                    rate: None,
+                    amount: Some(sold),
+                    order_type: OrderType::MarketSell,
+                    pair,
+                    created_at: now,
+                }))
+            } else {
+                Ok(None)
+            }
+        }
+    }
+}
+
+// 注文前になかった約定のうち、注文と売買種別（と指値注文ならレート）が一致するものを探す
+fn find_order_in_transactions(
+    req: &NewOrder,
+    pair: &Pair,
+    before: &OrderSnapshot,
+    transactions: &[Transaction],
+) -> Option<Order> {
+    let side = match req.order_type {
+        OrderType::Buy | OrderType::MarketBuy => OrderType::Buy,
+        OrderType::Sell | OrderType::MarketSell => OrderType::Sell,
+    };
+    let rate = req.rate.map(to_request_string);
+    let matched: Vec<&Transaction> = transactions
+        .iter()
+        .filter(|t| {
+            !before.transaction_ids.contains(&t.id)
+                && !before.open_order_ids.contains(&t.order_id)
+                && t.pair == *pair
+                && t.side == side
+                && (rate.is_none() || Some(to_request_string(t.rate)) == rate)
+        })
+        .collect();
+    let first = matched.first()?;
+    let fills: Vec<&&Transaction> = matched
+        .iter()
+        .filter(|t| t.order_id == first.order_id)
+        .collect();
+    let created_at = fills.iter().map(|t| t.created_at).min()?;
+    Some(Order {
+        id: first.order_id,
+        rate: req.rate,
+        amount: Some(fills.iter().map(|t| t.amount()).sum()),
+        order_type: req.order_type.clone(),
+        pair: pair.clone(),
+        created_at,
+    })
+}
+
+// 通貨の合計残高（利用可能 + 注文中）の変化量
+fn balance_delta(
+    before: &HashMap<String, Balance>,
+    after: &HashMap<String, Balance>,
+    currency: &str,
+) -> f64 {
+    let total = |m: &HashMap<String, Balance>| m.get(currency).map(|b| b.total()).unwrap_or(0.0);
+    total(after) - total(before)
+}
+
+fn is_near(v: f64, want: f64) -> bool {
+    want > 0.0 && (v - want).abs() <= want * BALANCE_DELTA_TOLERANCE_RATIO
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::MyError::{HttpStatusError, ParseError, ResponseError};
+
+    use crate::coincheck::model::Liquidity;
+
+    use chrono::DateTime;
+
+    fn make_balances(jpy: f64, btc: f64, btc_reserved: f64) -> HashMap<String, Balance> {
+        let mut m = HashMap::new();
+        m.insert(
+            "jpy".to_owned(),
+            Balance {
+                amount: jpy,
+                reserved: 0.0,
+            },
+        );
+        m.insert(
+            "btc".to_owned(),
+            Balance {
+                amount: btc,
+                reserved: btc_reserved,
+            },
+        );
+        m
+    }
+
+    fn make_open_order(id: u64, rate: f64, amount: f64) -> OpenOrder {
+        OpenOrder {
+            id,
+            rate,
+            pending_amount: amount,
+            pending_market_buy_amount: None,
+            order_type: OrderType::Sell,
+            stop_loss_rate: None,
+            pair: "btc_jpy".to_owned(),
+            created_at: DateTime::parse_from_rfc3339("2021-05-16T12:00:00+00:00").unwrap(),
+        }
+    }
+
+    fn make_transaction(id: u64, order_id: u64, side: OrderType, amount: f64) -> Transaction {
+        let sign = if side == OrderType::Buy { 1.0 } else { -1.0 };
+        let mut funds = HashMap::new();
+        funds.insert("btc".to_owned(), sign * amount);
+        funds.insert("jpy".to_owned(), -sign * amount * 5000000.0);
+        Transaction {
+            id,
+            order_id,
+            pair: Pair::new("btc_jpy").unwrap(),
+            side,
+            rate: 5000000.0,
+            funds,
+            fee: 0.0,
+            fee_currency: None,
+            liquidity: Liquidity::Taker,
+            created_at: DateTime::parse_from_rfc3339("2021-05-16T12:00:00+00:00").unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_is_ambiguous_error() {
+        let err: Box<dyn Error> = Box::new(ResponseError {
+            message: "Amount 0.001 is less than minimum".to_owned(),
+            url: "".to_owned(),
+            request: "".to_owned(),
+        });
+        assert!(!is_ambiguous_error(err.as_ref()));
+
+        let err: Box<dyn Error> = Box::new(HttpStatusError {
+            status: 502,
+            url: "".to_owned(),
+            body: "".to_owned(),
+        });
+        assert!(is_ambiguous_error(err.as_ref()));
+
+        let err: Box<dyn Error> = Box::new(HttpStatusError {
+            status: 429,
+            url: "".to_owned(),
+            body: "".to_owned(),
+        });
+        assert!(!is_ambiguous_error(err.as_ref()));
+
+        let err: Box<dyn Error> = Box::new(ParseError("<html>".to_owned()));
+        assert!(is_ambiguous_error(err.as_ref()));
+    }
+
+    #[test]
+    fn test_find_placed_sell_order() {
+        let pair = Pair::new("btc_jpy").unwrap();
+        let req = NewOrder::new_sell_order(&pair, 5000000.0, 0.01);
+        let before = OrderSnapshot::new(
+            make_balances(0.0, 0.01, 0.0),
+            &[make_open_order(1, 5000000.0, 0.01)],
+            &[],
+        );
+
+        // 注文前からある注文は対象外
+        let open_orders = vec![make_open_order(1, 5000000.0, 0.01)];
+        let got = find_placed_order(
+            &req,
+            &before,
+            &open_orders,
+            &[],
+            &make_balances(0.0, 0.0, 0.01),
+        )
+        .unwrap();
+        assert!(got.is_none());
+
+        let open_orders = vec![
+            make_open_order(1, 5000000.0, 0.01),
+            make_open_order(2, 5000000.0, 0.01),
+        ];
+        let got = find_placed_order(
+            &req,
+            &before,
+            &open_orders,
+            &[],
+            &make_balances(0.0, 0.0, 0.01),
+        )
+        .unwrap();
+        assert_eq!(got.unwrap().id, 2);
+    }
+
+    #[test]
+    fn test_find_placed_market_buy_order() {
+        let pair = Pair::new("btc_jpy").unwrap();
+        let req = NewOrder::new_market_buy_order(&pair, 10000.0);
+        let before = OrderSnapshot::new(make_balances(100000.0, 0.0, 0.0), &[], &[]);
+
+        let got =
+            find_placed_order(&req, &before, &[], &[], &make_balances(100000.0, 0.0, 0.0)).unwrap();
+        assert!(got.is_none());
+
+        let got = find_placed_order(&req, &before, &[], &[], &make_balances(90000.0, 0.002, 0.0))
+            .unwrap();
+        let order = got.unwrap();
+        assert_eq!(order.order_type, OrderType::MarketBuy);
+        assert_eq!(order.amount, Some(0.002));
+    }
+
+    #[test]
+    fn test_find_placed_market_sell_order() {
+        let pair = Pair::new("btc_jpy").unwrap();
+        let req = NewOrder::new_market_sell_order(&pair, 0.01);
+        let before = OrderSnapshot::new(make_balances(0.0, 0.01, 0.02), &[], &[]);
+
+        // 指値売り注文の約定など、数量が異なる変化は対象外
+        let got = find_placed_order(&req, &before, &[], &[], &make_balances(100000.0, 0.01, 0.0))
+            .unwrap();
+        assert!(got.is_none());
+
+        let got =
+            find_placed_order(&req, &before, &[], &[], &make_balances(50000.0, 0.0, 0.02)).unwrap();
+        assert!(got.is_some());
+    }
+
+    #[test]
+    fn test_find_placed_order_in_transactions() {
+        let pair = Pair::new("btc_jpy").unwrap();
+        let req = NewOrder::new_market_buy_order(&pair, 10000.0);
+        let before = OrderSnapshot::new(
+            make_balances(100000.0, 0.0, 0.0),
+            &[],
+            &[make_transaction(1, 10, OrderType::Buy, 0.002)],
+        );
+
+        // 注文前からある約定と売買種別が異なる約定は対象外
+        let transactions = vec![
+            make_transaction(3, 12, OrderType::Sell, 0.001),
+            make_transaction(1, 10, OrderType::Buy, 0.002),
+        ];
+        let got = find_placed_order(
+            &req,
+            &before,
+            &[],
+            &transactions,
+            &make_balances(100000.0, 0.0, 0.0),
+        )
+        .unwrap();
+        assert!(got.is_none());
+
+        // 残高に反映される前でも約定から注文を特定できる
+        let transactions = vec![
+            make_transaction(5, 13, OrderType::Buy, 0.0012),
+            make_transaction(4, 13, OrderType::Buy, 0.0008),
+            make_transaction(1, 10, OrderType::Buy, 0.002),
+        ];
+        let got = find_placed_order(
+            &req,
+            &before,
+            &[],
+            &transactions,
+            &make_balances(100000.0, 0.0, 0.0),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(got.id, 13);
+        assert!((got.amount.unwrap() - 0.002).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,439 @@
+use crate::coincheck::model::{OrderType, Pagination, Pair, SortOrder, Transaction};
+use crate::config::Config;
+use crate::error::MyResult;
+use crate::mysql::model::Report;
+use crate::notifier::base::Notifier;
+use crate::notifier::model::{Event, Level, Notification};
+use crate::util::calc_cost_basis_rate;
+use crate::{coincheck, mysql};
+
+use chrono::{DateTime, Duration, Utc};
+use colored::Colorize;
+use log::{debug, info, warn};
+use std::collections::HashSet;
+
+// 約定履歴を取得する際の1ページの件数
+const TRANSACTIONS_PAGE_LIMIT: u32 = 100;
+// 約定履歴を取得する最大ページ数
+const MAX_TRANSACTION_PAGES: u32 = 20;
+// 期間前の買いの取得レートを求めるため、期間の開始より前の約定履歴も取得する（日）
+const COST_LOOKBACK_DAYS: i64 = 30;
+// 全体の集計の取引ペア名
+const ALL_PAIRS: &str = "all";
+
+// レポートの期間
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportPeriod {
+    Daily,
+    Weekly,
+}
+
+impl ReportPeriod {
+    pub fn name(&self) -> &str {
+        match self {
+            ReportPeriod::Daily => "daily",
+            ReportPeriod::Weekly => "weekly",
+        }
+    }
+
+    // スケジューラーに登録する処理名
+    pub fn job_name(&self) -> String {
+        format!("{}_report", self.name())
+    }
+
+    pub fn from_job_name(name: &str) -> Option<ReportPeriod> {
+        [ReportPeriod::Daily, ReportPeriod::Weekly]
+            .iter()
+            .find(|p| p.job_name() == name)
+            .copied()
+    }
+
+    pub fn duration(&self) -> Duration {
+        match self {
+            ReportPeriod::Daily => Duration::days(1),
+            ReportPeriod::Weekly => Duration::weeks(1),
+        }
+    }
+}
+
+// 期間内の約定の集計
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TradeSummary {
+    // 約定した注文の数
+    pub trade_count: u32,
+    // 実現損益（JPY, 手数料を含む）
+    pub realized_pnl: f64,
+    // 手数料（JPY）
+    pub fee: f64,
+    pub win_count: u32,
+    pub loss_count: u32,
+}
+
+// 取引ペアの約定履歴から、期間 [begin, end) の損益などを集計する
+// 実現損益は売りの約定金額と、それまでの買いの取得金額（移動平均）との差とする
+// 取得金額の分からない売り（期間前の履歴にない買い）は、売りのレートで取得したとみなす
+pub fn summarize(
+    transactions: &[Transaction],
+    pair: &Pair,
+    begin: &DateTime<Utc>,
+    end: &DateTime<Utc>,
+) -> TradeSummary {
+    let mut transactions: Vec<&Transaction> = transactions
+        .iter()
+        .filter(|t| t.pair == *pair && t.created_at < *end)
+        .collect();
+    transactions.sort_by_key(|t| (t.created_at, t.id));
+
+    let mut summary = TradeSummary::default();
+    let mut position = 0.0;
+    let mut cost = 0.0;
+    let mut order_ids = HashSet::new();
+    // 売注文毎の実現損益（約定順）
+    let mut sell_pnls: Vec<(u64, f64)> = vec![];
+    for t in transactions {
+        let amount = t.amount();
+        let jpy = t.funds.get(&pair.settlement).copied().unwrap_or(0.0);
+        let pnl = match t.side {
+            OrderType::Buy | OrderType::MarketBuy => {
+                position += amount;
+                cost -= jpy;
+                None
+            }
+            OrderType::Sell | OrderType::MarketSell => {
+                let covered = amount.min(position);
+                let avg_cost = if position > 0.0 { cost / position } else { 0.0 };
+                position -= covered;
+                cost -= avg_cost * covered;
+                Some(jpy - avg_cost * covered - t.rate * (amount - covered))
+            }
+        };
+        if t.created_at < *begin {
+            continue;
+        }
+
+        order_ids.insert(t.order_id);
+        summary.fee += match &t.fee_currency {
+            Some(c) if *c != pair.settlement => t.fee * t.rate,
+            _ => t.fee,
+        };
+        if let Some(pnl) = pnl {
+            summary.realized_pnl += pnl;
+            match sell_pnls.iter_mut().find(|(id, _)| *id == t.order_id) {
+                Some((_, v)) => *v += pnl,
+                None => sell_pnls.push((t.order_id, pnl)),
+            }
+        }
+    }
+    summary.trade_count = order_ids.len() as u32;
+    summary.win_count = sell_pnls.iter().filter(|(_, v)| *v > 0.0).count() as u32;
+    summary.loss_count = sell_pnls.iter().filter(|(_, v)| *v < 0.0).count() as u32;
+    summary
+}
+
+// 日次, 週次レポートを作成して保存し、通知する
+pub struct Reporter<'a, T, U, V>
+where
+    T: Notifier,
+    U: mysql::client::Client,
+    V: coincheck::client::Client,
+{
+    pub config: &'a Config,
+    pub notifier: &'a T,
+    pub mysql_client: &'a U,
+    pub coincheck_client: &'a V,
+}
+
+impl<T, U, V> Reporter<'_, T, U, V>
+where
+    T: Notifier,
+    U: mysql::client::Client,
+    V: coincheck::client::Client,
+{
+    // end: 期間の終了時刻（予定していた実行時刻）
+    pub async fn report(&self, period: ReportPeriod, end: &DateTime<Utc>) -> MyResult<Vec<Report>> {
+        let begin = *end - period.duration();
+        let transactions = self
+            .fetch_transactions(&(begin - Duration::days(COST_LOOKBACK_DAYS)))
+            .await?;
+        let balances = self.coincheck_client.get_accounts_balance().await?;
+
+        // 取引対象の取引ペアと、期間内に約定のあった取引ペア
+        let mut pairs = vec![Pair::new(&self.config.target_pair)?];
+        for t in transactions.iter() {
+            if t.created_at >= begin && t.created_at < *end && !pairs.contains(&t.pair) {
+                pairs.push(t.pair.clone());
+            }
+        }
+
+        let mut reports = vec![];
+        let mut total_jpy = balances
+            .get(&self.config.settlement_currency())
+            .map(|b| b.total())
+            .unwrap_or(0.0);
+        for pair in pairs.iter() {
+            let summary = summarize(&transactions, pair, &begin, end);
+            let open_amount = balances.get(&pair.key).map(|b| b.total()).unwrap_or(0.0);
+            let mut unrealized_pnl = 0.0;
+            if open_amount > 0.0 {
+                let rate = self
+                    .coincheck_client
+                    .get_exchange_orders_rate(OrderType::Sell, &pair.to_string(), 1.0)
+                    .await?;
+                total_jpy += open_amount * rate;
+                match calc_cost_basis_rate(&transactions, pair, open_amount) {
+                    Some(cost_rate) => unrealized_pnl = (rate - cost_rate) * open_amount,
+                    None => warn!(
+                        "{}",
+                        format!(
+                            "cost basis of {} is unknown, amount:{}",
+                            pair.to_string(),
+                            open_amount
+                        )
+                        .yellow()
+                    ),
+                }
+            }
+            reports.push(self.make_report(
+                period,
+                &pair.to_string(),
+                &begin,
+                end,
+                &summary,
+                open_amount,
+                unrealized_pnl,
+            ));
+        }
+
+        let mut all = reports.iter().fold(
+            self.make_report(
+                period,
+                ALL_PAIRS,
+                &begin,
+                end,
+                &TradeSummary::default(),
+                0.0,
+                0.0,
+            ),
+            |mut all, r| {
+                all.trade_count += r.trade_count;
+                all.realized_pnl += r.realized_pnl;
+                all.fee += r.fee;
+                all.win_count += r.win_count;
+                all.loss_count += r.loss_count;
+                all.unrealized_pnl += r.unrealized_pnl;
+                all
+            },
+        );
+        all.total_jpy = total_jpy;
+        all.total_jpy_change = match self.mysql_client.select_latest_report(
+            &self.config.bot_name,
+            period.name(),
+            ALL_PAIRS,
+            &end.naive_utc(),
+        )? {
+            Some(prev) => total_jpy - prev.total_jpy,
+            None => 0.0,
+        };
+        reports.push(all);
+
+        for r in reports.iter() {
+            debug!("{:?}", r);
+            self.mysql_client.upsert_report(r)?;
+        }
+
+        let notification = make_notification(period, &reports);
+        info!("{}", notification.to_plain_text());
+        if let Err(err) = self.notifier.notify(&notification).await {
+            warn!("failed to notify report, {}", err);
+        }
+        Ok(reports)
+    }
+
+    // 新しい順に since までの約定履歴を取得する
+    async fn fetch_transactions(&self, since: &DateTime<Utc>) -> MyResult<Vec<Transaction>> {
+        let mut transactions: Vec<Transaction> = vec![];
+        for _ in 0..MAX_TRANSACTION_PAGES {
+            let page = self
+                .coincheck_client
+                .get_exchange_orders_transactions_pagination(&Pagination {
+                    limit: Some(TRANSACTIONS_PAGE_LIMIT),
+                    order: Some(SortOrder::Desc),
+                    starting_after: transactions.last().map(|t| t.id),
+                    ending_before: None,
+                })
+                .await?;
+            let is_last = page.len() < TRANSACTIONS_PAGE_LIMIT as usize
+                || page.last().map(|t| t.created_at < *since).unwrap_or(true);
+            transactions.extend(page);
+            if is_last {
+                return Ok(transactions);
+            }
+        }
+        warn!(
+            "{}",
+            format!(
+                "transactions are too many to report, fetched:{}",
+                transactions.len()
+            )
+            .yellow()
+        );
+        Ok(transactions)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn make_report(
+        &self,
+        period: ReportPeriod,
+        pair: &str,
+        begin: &DateTime<Utc>,
+        end: &DateTime<Utc>,
+        summary: &TradeSummary,
+        open_amount: f64,
+        unrealized_pnl: f64,
+    ) -> Report {
+        Report {
+            bot_name: self.config.bot_name.clone(),
+            period: period.name().to_owned(),
+            pair: pair.to_owned(),
+            begin_at: begin.naive_utc(),
+            end_at: end.naive_utc(),
+            trade_count: summary.trade_count,
+            realized_pnl: summary.realized_pnl,
+            fee: summary.fee,
+            win_count: summary.win_count,
+            loss_count: summary.loss_count,
+            open_amount,
+            unrealized_pnl,
+            total_jpy: 0.0,
+            total_jpy_change: 0.0,
+        }
+    }
+}
+
+// 全体の集計の後に取引ペア毎の集計を並べる
+fn make_notification(period: ReportPeriod, reports: &[Report]) -> Notification {
+    let all = reports.iter().find(|r| r.pair == ALL_PAIRS);
+    let level = match all {
+        Some(r) if r.realized_pnl + r.unrealized_pnl < 0.0 => Level::Warning,
+        _ => Level::Info,
+    };
+    let mut notification =
+        Notification::new(Event::Report, &format!("{} report", period.name()), level);
+    if let Some(r) = all {
+        notification = notification
+            .field(
+                "period",
+                &format!(
+                    "{} - {}",
+                    r.begin_at.format("%Y-%m-%d %H:%M"),
+                    r.end_at.format("%Y-%m-%d %H:%M")
+                ),
+            )
+            .field(
+                "total jpy",
+                &format!("{:.0} ({:+.0})", r.total_jpy, r.total_jpy_change),
+            );
+    }
+    for r in reports.iter().filter(|r| r.pair != ALL_PAIRS) {
+        notification = notification.field(
+            &r.pair,
+            &format!(
+                "trades:{}, win/loss:{}/{}, realized:{:+.0}, fee:{:.0}, open:{:.8}, unrealized:{:+.0}",
+                r.trade_count,
+                r.win_count,
+                r.loss_count,
+                r.realized_pnl,
+                r.fee,
+                r.open_amount,
+                r.unrealized_pnl
+            ),
+        );
+    }
+    notification
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coincheck::model::Liquidity;
+    use chrono::{FixedOffset, TimeZone};
+    use std::collections::HashMap;
+
+    fn make_transaction(
+        id: u64,
+        order_id: u64,
+        side: OrderType,
+        rate: f64,
+        amount: f64,
+        fee: f64,
+        hour: u32,
+    ) -> Transaction {
+        let (key, settlement) = match side {
+            OrderType::Buy | OrderType::MarketBuy => (amount, -(rate * amount + fee)),
+            OrderType::Sell | OrderType::MarketSell => (-amount, rate * amount - fee),
+        };
+        let mut funds = HashMap::new();
+        funds.insert("btc".to_owned(), key);
+        funds.insert("jpy".to_owned(), settlement);
+        Transaction {
+            id,
+            order_id,
+            pair: Pair::new("btc_jpy").unwrap(),
+            side,
+            rate,
+            funds,
+            fee,
+            fee_currency: Some("jpy".to_owned()),
+            liquidity: Liquidity::Maker,
+            created_at: FixedOffset::east_opt(0)
+                .unwrap()
+                .with_ymd_and_hms(2021, 5, 16, hour, 0, 0)
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let pair = Pair::new("btc_jpy").unwrap();
+        let begin = Utc.with_ymd_and_hms(2021, 5, 16, 3, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2021, 5, 16, 12, 0, 0).unwrap();
+        // 新しい順に並んでいても、約定順に集計する
+        let transactions = vec![
+            // 期間外
+            make_transaction(8, 6, OrderType::Buy, 5000000.0, 0.01, 0.0, 12),
+            // 期間前の履歴にないコインの売り（損益なし）
+            make_transaction(7, 5, OrderType::MarketSell, 4000000.0, 0.01, 10.0, 10),
+            // 損失（2回に分けて約定）
+            make_transaction(6, 4, OrderType::Sell, 3900000.0, 0.005, 0.0, 9),
+            make_transaction(5, 4, OrderType::Sell, 3900000.0, 0.005, 0.0, 8),
+            // 利益（取得レートは 4000000.0 の移動平均）
+            make_transaction(4, 3, OrderType::Sell, 4200000.0, 0.01, 20.0, 6),
+            make_transaction(3, 2, OrderType::Buy, 3800000.0, 0.01, 0.0, 4),
+            make_transaction(2, 1, OrderType::Buy, 4200000.0, 0.01, 0.0, 2),
+            // 他の取引ペア
+            Transaction {
+                pair: Pair::new("mona_jpy").unwrap(),
+                ..make_transaction(1, 0, OrderType::Sell, 100.0, 1.0, 0.0, 5)
+            },
+        ];
+
+        let got = summarize(&transactions, &pair, &begin, &end);
+        assert_eq!(got.trade_count, 4);
+        assert_eq!(got.fee, 30.0);
+        assert_eq!(got.win_count, 1);
+        assert_eq!(got.loss_count, 2);
+        // 利益: 42000 - 20 - 40000, 損失: 39000 - 40000, 手数料: -10
+        assert!((got.realized_pnl - (1980.0 - 1000.0 - 10.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_report_period() {
+        assert_eq!(ReportPeriod::Daily.job_name(), "daily_report");
+        assert_eq!(
+            ReportPeriod::from_job_name("weekly_report"),
+            Some(ReportPeriod::Weekly)
+        );
+        assert_eq!(ReportPeriod::from_job_name("monthly_report"), None);
+        assert_eq!(ReportPeriod::Weekly.duration(), Duration::days(7));
+    }
+}
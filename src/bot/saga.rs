@@ -0,0 +1,176 @@
+use crate::bot::reconcile::OrderSnapshot;
+use crate::error::MyResult;
+use crate::mysql::model::InFlightAction;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+// 途中で失敗したアクションの再開を試みる最大回数
+// 超えた場合は補償ステップで売注文を出し直して終了する
+pub const MAX_RESUME_ATTEMPTS: u32 = 3;
+
+// 補償ステップを試みる最大回数
+// 超えた場合は記録を残したまま諦める（オペレーターが対応する）
+pub const MAX_COMPENSATE_ATTEMPTS: u32 = 3;
+
+// ナンピン後に分割して出す売注文の数
+pub const AVG_DOWN_SELL_COUNT: u32 = 2;
+
+// 複数ステップのアクションの進捗
+// ステップの完了ごとに in_flight_actions に記録し、途中で失敗しても次回の実行時（再起動後を含む）に再開する
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SagaState {
+    AvgDown(AvgDownState),
+    LossCut(LossCutState),
+}
+
+// ナンピン（成行買い → 既存の売注文のキャンセル → 売注文 * 2）
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AvgDownState {
+    pub pair: String,
+    pub open_order_id: u64,
+    pub open_order_rate: f64,
+    pub open_order_amount: f64,
+    pub market_buy_amount: f64,
+    pub offset_sell_rate_ratio: f64,
+    // 成行買い前の状態（再開時に成行買いが約定したかを照合する, 記録前の進捗では None）
+    #[serde(default)]
+    pub before_buy: Option<OrderSnapshot>,
+    // 成行買いで増えたコイン数（成行買いが完了するまでは None）
+    pub amount_new_coin: Option<f64>,
+    // 既存の売注文をキャンセル済みか
+    pub canceled: bool,
+    // 発注済みの売注文の数
+    pub sold_count: u32,
+}
+
+// 損切り（既存の売注文のキャンセル → 成行売り）
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LossCutState {
+    pub pair: String,
+    pub open_order_id: u64,
+    pub open_order_rate: f64,
+    pub amount: f64,
+    // 既存の売注文をキャンセル済みか
+    pub canceled: bool,
+}
+
+impl SagaState {
+    pub fn action_type(&self) -> &str {
+        match self {
+            SagaState::AvgDown(_) => "avg_down",
+            SagaState::LossCut(_) => "loss_cut",
+        }
+    }
+
+    pub fn pair(&self) -> &str {
+        match self {
+            SagaState::AvgDown(s) => &s.pair,
+            SagaState::LossCut(s) => &s.pair,
+        }
+    }
+
+    pub fn to_in_flight_action(&self, bot_name: &str, attempts: u32) -> MyResult<InFlightAction> {
+        Ok(InFlightAction {
+            bot_name: bot_name.to_owned(),
+            pair: self.pair().to_owned(),
+            action_type: self.action_type().to_owned(),
+            state: serde_json::to_string(self)?,
+            attempts,
+            updated_at: Utc::now().naive_utc(),
+        })
+    }
+
+    pub fn from_in_flight_action(action: &InFlightAction) -> MyResult<SagaState> {
+        Ok(serde_json::from_str(&action.state)?)
+    }
+}
+
+impl AvgDownState {
+    // ナンピン後の売注文（レート, 1注文あたりの数量）
+    // 成行買いが完了していなければ None
+    // fee_markup: 追加で買った分の手数料を差し引いて建値を回収するための倍率
+    pub fn calc_sell_order(&self, fee_markup: f64) -> Option<(f64, f64)> {
+        let amount_new_coin = self.amount_new_coin?;
+        // ナンピン後の注文は二分割する（ナンピンのための買注文の金額を肥大化させないため）
+        let amount_coin = (self.open_order_amount + amount_new_coin) / AVG_DOWN_SELL_COUNT as f64;
+        let ratio = 1.0 + self.offset_sell_rate_ratio;
+        let rate_without_offset = self.open_order_rate / ratio;
+        let market_buy_amount = self.market_buy_amount * fee_markup;
+        let rate = ((self.open_order_amount * rate_without_offset) + market_buy_amount)
+            / (amount_coin * AVG_DOWN_SELL_COUNT as f64)
+            * ratio;
+        Some((rate, amount_coin))
+    }
+
+    // まだ売注文を出していないコイン数
+    pub fn unsold_amount(&self, fee_markup: f64) -> f64 {
+        match self.calc_sell_order(fee_markup) {
+            Some((_, amount_coin)) => {
+                if self.canceled {
+                    amount_coin * (AVG_DOWN_SELL_COUNT - self.sold_count) as f64
+                } else {
+                    // 既存の売注文が残っていれば、追加で買った分だけ
+                    self.amount_new_coin.unwrap_or(0.0)
+                }
+            }
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_avg_down_state() -> AvgDownState {
+        AvgDownState {
+            pair: "btc_jpy".to_owned(),
+            open_order_id: 100,
+            open_order_rate: 101.0,
+            open_order_amount: 1.0,
+            market_buy_amount: 90.0,
+            offset_sell_rate_ratio: 0.01,
+            before_buy: None,
+            amount_new_coin: None,
+            canceled: false,
+            sold_count: 0,
+        }
+    }
+
+    #[test]
+    fn test_saga_state_round_trip() {
+        let state = SagaState::AvgDown(AvgDownState {
+            amount_new_coin: Some(1.0),
+            ..make_avg_down_state()
+        });
+        let action = state.to_in_flight_action("bot", 2).unwrap();
+        assert_eq!(action.action_type, "avg_down");
+        assert_eq!(action.pair, "btc_jpy");
+        assert_eq!(action.attempts, 2);
+        assert!(action.state.contains("\"type\":\"avg_down\""));
+        assert_eq!(SagaState::from_in_flight_action(&action).unwrap(), state);
+    }
+
+    #[test]
+    fn test_avg_down_calc_sell_order() {
+        let mut state = make_avg_down_state();
+        assert_eq!(state.calc_sell_order(1.0), None);
+        assert_eq!(state.unsold_amount(1.0), 0.0);
+
+        // 建値 100.0 で 1.0, 90.0 で 1.0 買った場合の平均は 95.0
+        state.amount_new_coin = Some(1.0);
+        let (rate, amount_coin) = state.calc_sell_order(1.0).unwrap();
+        assert!((rate - 95.0 * 1.01).abs() < 1e-9);
+        assert_eq!(amount_coin, 1.0);
+
+        // キャンセル前は追加で買った分, キャンセル後は売注文を出していない分
+        assert_eq!(state.unsold_amount(1.0), 1.0);
+        state.canceled = true;
+        state.sold_count = 1;
+        assert_eq!(state.unsold_amount(1.0), 1.0);
+        state.sold_count = 0;
+        assert_eq!(state.unsold_amount(1.0), 2.0);
+    }
+}
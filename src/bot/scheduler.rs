@@ -0,0 +1,475 @@
+use crate::bot::shutdown::Shutdown;
+use crate::config::OverrunPolicy;
+use crate::error::MyError::ParseError;
+use crate::error::MyResult;
+
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc,
+};
+use log::{debug, warn};
+
+// 遅れを取り戻す場合に、続けて実行する最大回数（超えた分は飛ばす）
+const MAX_CATCH_UP: i64 = 3;
+
+// 次の時刻を探す範囲（条件に合う日が存在しない cron 式で無限に探さないため）
+const SEARCH_LIMIT_DAYS: i64 = 366 * 5;
+
+// 実行するタスク
+#[derive(Debug, Clone, PartialEq)]
+pub enum Task {
+    // 取引（予定時刻）
+    Trade(DateTime<Utc>),
+    // 定期実行の処理（名前, 予定時刻）
+    Job(String, DateTime<Utc>),
+}
+
+// 取引を一定間隔で, 定期実行の処理を cron 式の時刻に実行する
+// 取引の時刻は間隔の倍数（UNIX時間）に揃え、取引にかかった時間の分だけずれていかないようにする
+#[derive(Debug)]
+pub struct Scheduler {
+    interval: Duration,
+    overrun_policy: OverrunPolicy,
+    offset: FixedOffset,
+    next_trade_at: DateTime<Utc>,
+    jobs: Vec<Job>,
+}
+
+#[derive(Debug)]
+struct Job {
+    name: String,
+    schedule: CronSchedule,
+    next_at: Option<DateTime<Utc>>,
+}
+
+impl Scheduler {
+    // offset: cron 式の時刻のタイムゾーン
+    pub fn new(
+        interval_sec: u64,
+        overrun_policy: OverrunPolicy,
+        offset: FixedOffset,
+        now: &DateTime<Utc>,
+    ) -> Scheduler {
+        let interval = Duration::seconds(interval_sec.max(1) as i64);
+        Scheduler {
+            interval,
+            overrun_policy,
+            offset,
+            // 起動直後に1回取引する
+            next_trade_at: *now,
+            jobs: vec![],
+        }
+    }
+
+    pub fn add_job(&mut self, name: &str, schedule: CronSchedule, now: &DateTime<Utc>) {
+        let next_at = schedule.next_after(now, &self.offset);
+        debug!("add job {}, next:{:?}", name, next_at);
+        self.jobs.push(Job {
+            name: name.to_owned(),
+            schedule,
+            next_at,
+        });
+    }
+
+    // 次にタスクを実行する時刻
+    pub fn next_at(&self) -> DateTime<Utc> {
+        self.jobs
+            .iter()
+            .filter_map(|j| j.next_at)
+            .fold(self.next_trade_at, |m, v| m.min(v))
+    }
+
+    // now の時点で実行するタスク（なければ None）
+    // 定期実行の処理は、複数回分の時刻を過ぎていても1回だけ実行する
+    pub fn poll(&mut self, now: &DateTime<Utc>) -> Option<Task> {
+        let offset = self.offset;
+        if let Some(job) = self
+            .jobs
+            .iter_mut()
+            .filter(|j| j.next_at.is_some_and(|t| t <= *now))
+            .min_by_key(|j| j.next_at)
+        {
+            let scheduled_at = job.next_at?;
+            job.next_at = job.schedule.next_after(now, &offset);
+            return Some(Task::Job(job.name.clone(), scheduled_at));
+        }
+
+        if self.next_trade_at > *now {
+            return None;
+        }
+        let scheduled_at = self.next_trade_at;
+        let aligned = self.align_after(now);
+        self.next_trade_at = match self.overrun_policy {
+            OverrunPolicy::Skip => aligned,
+            OverrunPolicy::CatchUp => {
+                let next = self.align_after(&scheduled_at);
+                if aligned - next > self.interval * (MAX_CATCH_UP as i32) {
+                    warn!(
+                        "too many overruns, skip to {} (scheduled at {})",
+                        aligned, scheduled_at
+                    );
+                    aligned
+                } else {
+                    next
+                }
+            }
+        };
+        if self.next_trade_at != aligned {
+            debug!("catch up, next trade at {}", self.next_trade_at);
+        }
+        Some(Task::Trade(scheduled_at))
+    }
+
+    // 次のタスクの時刻まで待つ（終了が要求された場合は None）
+    // 待機はタイマーで行い、ランタイムをブロックしない
+    pub async fn next(&mut self, shutdown: &mut Shutdown) -> Option<Task> {
+        loop {
+            if shutdown.is_requested() {
+                return None;
+            }
+            let now = Utc::now();
+            if let Some(task) = self.poll(&now) {
+                return Some(task);
+            }
+            let d = (self.next_at() - now)
+                .to_std()
+                .unwrap_or(std::time::Duration::ZERO);
+            debug!("wait ... [{:?}]", d);
+            shutdown.sleep(d).await;
+        }
+    }
+
+    // t より後の、間隔の倍数の時刻
+    fn align_after(&self, t: &DateTime<Utc>) -> DateTime<Utc> {
+        let interval_ms = self.interval.num_milliseconds();
+        let ms = t.timestamp_millis();
+        let aligned = (ms.div_euclid(interval_ms) + 1) * interval_ms;
+        Utc.timestamp_millis_opt(aligned).unwrap()
+    }
+}
+
+// cron 式（分 時 日 月 曜日）
+// 各項目は * , 数値, 範囲（1-5）, 列挙（1,3,5）, 間隔（*/15, 0-30/10）に対応する
+// 曜日は 0〜7（0 と 7 は日曜日）
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // 日, 曜日が * か（両方指定された場合は、どちらかに一致すれば実行する）
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expr: &str) -> MyResult<CronSchedule> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(Box::new(ParseError(format!(
+                "cron `{}`, 5 fields are required",
+                expr
+            ))));
+        }
+        let parse = |field: &str, min: u32, max: u32| {
+            parse_field(field, min, max).ok_or_else(|| {
+                Box::new(ParseError(format!(
+                    "cron `{}`, invalid field `{}`",
+                    expr, field
+                )))
+            })
+        };
+        let mut weekdays = parse(fields[4], 0, 7)?;
+        // 7 は日曜日として扱う
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(CronSchedule {
+            minutes: parse(fields[0], 0, 59)?,
+            hours: parse(fields[1], 0, 23)?,
+            days: parse(fields[2], 1, 31)?,
+            months: parse(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+
+    // t より後で、条件に合う最初の時刻（offset のタイムゾーンで判定する）
+    pub fn next_after(&self, t: &DateTime<Utc>, offset: &FixedOffset) -> Option<DateTime<Utc>> {
+        let local = t.with_timezone(offset).naive_local();
+        let mut cur = local.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = cur + Duration::days(SEARCH_LIMIT_DAYS);
+        while cur < limit {
+            if !has(self.months, cur.month()) {
+                cur = first_of_next_month(&cur)?;
+                continue;
+            }
+            if !self.matches_day(&cur) {
+                cur = cur.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !has(self.hours, cur.hour()) {
+                cur = cur.with_minute(0)? + Duration::hours(1);
+                continue;
+            }
+            if !has(self.minutes, cur.minute()) {
+                cur += Duration::minutes(1);
+                continue;
+            }
+            return offset
+                .from_local_datetime(&cur)
+                .single()
+                .map(|v| v.with_timezone(&Utc));
+        }
+        None
+    }
+
+    fn matches_day(&self, t: &NaiveDateTime) -> bool {
+        let day = has(self.days, t.day());
+        let weekday = has(self.weekdays, t.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (false, true) => day,
+            (true, false) => weekday,
+            (false, false) => day || weekday,
+        }
+    }
+}
+
+fn has(bits: u64, v: u32) -> bool {
+    bits & (1 << v) != 0
+}
+
+fn first_of_next_month(t: &NaiveDateTime) -> Option<NaiveDateTime> {
+    let (year, month) = if t.month() == 12 {
+        (t.year() + 1, 1)
+    } else {
+        (t.year(), t.month() + 1)
+    };
+    NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)
+}
+
+// 項目を、該当する値のビットを立てた値に変換する
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|v| *v > 0)?),
+            None => (part, 1),
+        };
+        let (begin, end) = if range == "*" {
+            (min, max)
+        } else if let Some((begin, end)) = range.split_once('-') {
+            (begin.parse().ok()?, end.parse().ok()?)
+        } else {
+            let v = range.parse().ok()?;
+            // 5/10 のような指定は 5 から最大値まで
+            if part.contains('/') {
+                (v, max)
+            } else {
+                (v, v)
+            }
+        };
+        if begin < min || end > max || begin > end {
+            return None;
+        }
+        for v in (begin..=end).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Some(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_time(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn utc() -> FixedOffset {
+        FixedOffset::east_opt(0).unwrap()
+    }
+
+    #[test]
+    fn test_cron_next_after() {
+        let jst = FixedOffset::east_opt(9 * 3600).unwrap();
+        let params = vec![
+            (
+                "every 15 minutes",
+                "*/15 * * * *",
+                utc(),
+                "2021-05-16T12:03:10Z",
+                Some("2021-05-16T12:15:00Z"),
+            ),
+            (
+                "exactly on time is excluded",
+                "0 * * * *",
+                utc(),
+                "2021-05-16T12:00:00Z",
+                Some("2021-05-16T13:00:00Z"),
+            ),
+            (
+                "daily at 9:00 JST",
+                "0 9 * * *",
+                jst,
+                "2021-05-16T01:00:00Z",
+                Some("2021-05-17T00:00:00Z"),
+            ),
+            (
+                "weekly on monday",
+                "30 8 * * 1",
+                utc(),
+                "2021-05-16T12:00:00Z",
+                Some("2021-05-17T08:30:00Z"),
+            ),
+            (
+                "sunday as 7",
+                "0 0 * * 7",
+                utc(),
+                "2021-05-10T00:00:00Z",
+                Some("2021-05-16T00:00:00Z"),
+            ),
+            (
+                "first day of month",
+                "0 0 1 * *",
+                utc(),
+                "2021-12-15T00:00:00Z",
+                Some("2022-01-01T00:00:00Z"),
+            ),
+            (
+                "day or weekday",
+                "0 0 20 * 1",
+                utc(),
+                "2021-05-16T00:00:00Z",
+                Some("2021-05-17T00:00:00Z"),
+            ),
+            ("never", "0 0 31 2 *", utc(), "2021-05-16T00:00:00Z", None),
+        ];
+        for (name, expr, offset, now, want) in params {
+            let schedule = CronSchedule::parse(expr).unwrap();
+            let got = schedule.next_after(&parse_time(now), &offset);
+            assert_eq!(got, want.map(parse_time), "{}, failure", name);
+        }
+    }
+
+    #[test]
+    fn test_cron_parse_error() {
+        for expr in [
+            "* * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(CronSchedule::parse(expr).is_err(), "{}, failure", expr);
+        }
+    }
+
+    #[test]
+    fn test_poll_skip() {
+        let now = parse_time("2021-05-16T12:03:10Z");
+        let mut scheduler = Scheduler::new(30, OverrunPolicy::Skip, utc(), &now);
+
+        // 起動直後に1回, 以降は間隔の倍数の時刻
+        assert_eq!(scheduler.poll(&now), Some(Task::Trade(now)));
+        assert_eq!(scheduler.poll(&now), None);
+        assert_eq!(scheduler.next_at(), parse_time("2021-05-16T12:03:30Z"));
+
+        // 取引が長引いて時刻を過ぎた分は飛ばす
+        let now = parse_time("2021-05-16T12:04:45Z");
+        assert_eq!(
+            scheduler.poll(&now),
+            Some(Task::Trade(parse_time("2021-05-16T12:03:30Z")))
+        );
+        assert_eq!(scheduler.poll(&now), None);
+        assert_eq!(scheduler.next_at(), parse_time("2021-05-16T12:05:00Z"));
+    }
+
+    #[test]
+    fn test_poll_catch_up() {
+        let now = parse_time("2021-05-16T12:03:00Z");
+        let mut scheduler = Scheduler::new(30, OverrunPolicy::CatchUp, utc(), &now);
+        assert_eq!(scheduler.poll(&now), Some(Task::Trade(now)));
+
+        // 過ぎた時刻の分を続けて実行する
+        let now = parse_time("2021-05-16T12:04:10Z");
+        let mut got = vec![];
+        while let Some(task) = scheduler.poll(&now) {
+            got.push(task);
+        }
+        assert_eq!(
+            got,
+            vec![
+                Task::Trade(parse_time("2021-05-16T12:03:30Z")),
+                Task::Trade(parse_time("2021-05-16T12:04:00Z")),
+            ]
+        );
+
+        // 遅れが大きすぎる場合は飛ばす
+        let now = parse_time("2021-05-16T13:00:10Z");
+        assert_eq!(
+            scheduler.poll(&now),
+            Some(Task::Trade(parse_time("2021-05-16T12:04:30Z")))
+        );
+        assert_eq!(scheduler.poll(&now), None);
+        assert_eq!(scheduler.next_at(), parse_time("2021-05-16T13:00:30Z"));
+    }
+
+    #[test]
+    fn test_poll_job() {
+        let now = parse_time("2021-05-16T12:03:00Z");
+        let mut scheduler = Scheduler::new(60, OverrunPolicy::Skip, utc(), &now);
+        scheduler.add_job("report", CronSchedule::parse("*/5 * * * *").unwrap(), &now);
+        assert_eq!(scheduler.poll(&now), Some(Task::Trade(now)));
+        assert_eq!(scheduler.next_at(), parse_time("2021-05-16T12:04:00Z"));
+
+        // 定期実行の処理を取引より先に実行する
+        let now = parse_time("2021-05-16T12:05:00Z");
+        assert_eq!(
+            scheduler.poll(&now),
+            Some(Task::Job(
+                "report".to_owned(),
+                parse_time("2021-05-16T12:05:00Z")
+            ))
+        );
+        assert_eq!(
+            scheduler.poll(&now),
+            Some(Task::Trade(parse_time("2021-05-16T12:04:00Z")))
+        );
+        assert_eq!(scheduler.poll(&now), None);
+
+        // 複数回分の時刻を過ぎていても1回だけ実行する
+        let now = parse_time("2021-05-16T12:21:00Z");
+        assert_eq!(
+            scheduler.poll(&now),
+            Some(Task::Job(
+                "report".to_owned(),
+                parse_time("2021-05-16T12:10:00Z")
+            ))
+        );
+        assert_eq!(
+            scheduler.poll(&now),
+            Some(Task::Trade(parse_time("2021-05-16T12:06:00Z")))
+        );
+        assert_eq!(scheduler.poll(&now), None);
+        assert_eq!(scheduler.next_at(), parse_time("2021-05-16T12:22:00Z"));
+    }
+
+    #[tokio::test]
+    async fn test_next_returns_none_on_shutdown() {
+        let now = Utc::now();
+        let mut scheduler = Scheduler::new(3600, OverrunPolicy::Skip, utc(), &now);
+        let (trigger, mut shutdown) = Shutdown::channel();
+        assert!(matches!(
+            scheduler.next(&mut shutdown).await,
+            Some(Task::Trade(_))
+        ));
+
+        trigger.trigger();
+        assert_eq!(scheduler.next(&mut shutdown).await, None);
+    }
+}
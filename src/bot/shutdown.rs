@@ -0,0 +1,103 @@
+use crate::error::MyResult;
+
+use log::info;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+// 終了の要求（SIGINT, SIGTERM）
+// 実行中の取引は中断せず、取引の合間に終了を確認する
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    rx: watch::Receiver<bool>,
+}
+
+// 終了を要求する側（シグナル以外から終了させる場合に使う）
+#[derive(Debug)]
+pub struct ShutdownTrigger {
+    tx: watch::Sender<bool>,
+}
+
+impl Shutdown {
+    pub fn channel() -> (ShutdownTrigger, Shutdown) {
+        let (tx, rx) = watch::channel(false);
+        (ShutdownTrigger { tx }, Shutdown { rx })
+    }
+
+    // SIGINT, SIGTERM を受信したら終了を要求する
+    pub fn listen() -> MyResult<Shutdown> {
+        let (trigger, shutdown) = Shutdown::channel();
+        let mut sigterm = signal(SignalKind::terminate())?;
+        let mut sigint = signal(SignalKind::interrupt())?;
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = sigterm.recv() => info!("received SIGTERM"),
+                _ = sigint.recv() => info!("received SIGINT"),
+            }
+            trigger.trigger();
+        });
+        Ok(shutdown)
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    // 終了が要求されるまで待つ
+    pub async fn requested(&mut self) {
+        while !self.is_requested() {
+            // 要求する側が破棄された場合は、終了が要求されることはない
+            if self.rx.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    // 指定時間待つ（待機中に終了が要求されたらすぐに戻る）
+    pub async fn sleep(&mut self, d: Duration) {
+        tokio::select! {
+            _ = tokio::time::sleep(d) => {}
+            _ = self.requested() => {}
+        }
+    }
+}
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        // 受信側が破棄されていれば通知先はないため、結果は無視する
+        let _ = self.tx.send(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn test_sleep_interrupted() {
+        let (trigger, mut shutdown) = Shutdown::channel();
+        assert!(!shutdown.is_requested());
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            trigger.trigger();
+        });
+        let begin = Instant::now();
+        shutdown.sleep(Duration::from_secs(10)).await;
+        assert!(begin.elapsed() < Duration::from_secs(5));
+        assert!(shutdown.is_requested());
+    }
+
+    #[tokio::test]
+    async fn test_sleep_without_trigger() {
+        let (trigger, mut shutdown) = Shutdown::channel();
+        drop(trigger);
+
+        let begin = Instant::now();
+        shutdown.sleep(Duration::from_millis(50)).await;
+        assert!(begin.elapsed() >= Duration::from_millis(50));
+        assert!(!shutdown.is_requested());
+    }
+}
@@ -1,5 +1,7 @@
 pub mod client;
 pub mod mock;
 pub mod model;
+pub mod nonce;
 pub mod request;
 pub mod response;
+pub mod retry;
@@ -1,14 +1,25 @@
-use crate::coincheck::model::{Balance, NewOrder, OpenOrder, Order, OrderBooks, OrderType};
+use crate::coincheck::model::{
+    Balance, NewOrder, OpenOrder, Order, OrderBooks, OrderType, Pagination, Ticker, Trade,
+    Transaction,
+};
+use crate::coincheck::nonce;
+use crate::coincheck::nonce::NonceGenerator;
 use crate::coincheck::request::OrdersPostRequest;
 use crate::coincheck::response::OrdersCancelStatusGetResponse;
 use crate::coincheck::response::OrdersDeleteResponse;
 use crate::coincheck::response::*;
-use crate::error::MyError::{ParseError, ResponseError};
+use crate::coincheck::retry;
+use crate::coincheck::retry::{RateLimiter, RetryPolicy};
+use crate::config::Config;
+use crate::error::MyError::{HttpStatusError, ParseError, ResponseError};
 use crate::error::MyResult;
+use crate::monitor::metrics;
 use std::time::Duration;
 
 use std::collections::HashMap;
-use std::time::SystemTime;
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
 
 use async_trait::async_trait;
 use log::warn;
@@ -17,12 +28,14 @@ use mockall::*;
 use openssl::hash::MessageDigest;
 use openssl::pkey::PKey;
 use openssl::sign::Signer;
+use reqwest::{Method, StatusCode};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
 const BASE_URL: &str = "https://coincheck.com";
-const MAX_RETRY_COUNT: i32 = 5;
-const RETRY_INTERVAL_MS: u64 = 10;
+// 取引所の制限を超えないよう、クライアント側でリクエスト数を抑える
+const RATE_LIMIT_BURST: u32 = 10;
+const RATE_LIMIT_PER_SEC: f64 = 5.0;
 
 #[async_trait]
 #[automock]
@@ -45,27 +58,81 @@ pub trait Client {
     async fn get_exchange_orders_cancel_status(&self, id: u64) -> MyResult<bool>;
 
     async fn get_accounts_balance(&self) -> MyResult<HashMap<String, Balance>>;
+
+    async fn get_exchange_orders_transactions(&self) -> MyResult<Vec<Transaction>>;
+
+    async fn get_exchange_orders_transactions_pagination(
+        &self,
+        pagination: &Pagination,
+    ) -> MyResult<Vec<Transaction>>;
+
+    async fn get_trades(&self, pair: &str, pagination: &Pagination) -> MyResult<Vec<Trade>>;
+
+    async fn get_ticker(&self, pair: &str) -> MyResult<Ticker>;
+}
+
+// HTTPクライアントの設定
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientOptions {
+    pub base_url: String,
+    pub timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    pub proxy: Option<String>,
+    pub user_agent: Option<String>,
+    pub retry_policy: RetryPolicy,
+    // 連続で送信できる最大数と1秒あたりのリクエスト数（None なら制限しない）
+    pub rate_limit: Option<(u32, f64)>,
+    // nonce の上限値を保存するファイル（None なら保存しない）
+    pub nonce_path: Option<String>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            base_url: BASE_URL.to_owned(),
+            timeout: None,
+            connect_timeout: None,
+            proxy: None,
+            user_agent: None,
+            retry_policy: RetryPolicy::default(),
+            rate_limit: Some((RATE_LIMIT_BURST, RATE_LIMIT_PER_SEC)),
+            nonce_path: None,
+        }
+    }
+}
+
+impl ClientOptions {
+    pub fn from_config(config: &Config) -> ClientOptions {
+        ClientOptions {
+            base_url: config.exchange_base_url.to_owned(),
+            timeout: config.exchange_timeout_sec.map(Duration::from_secs),
+            connect_timeout: config.exchange_connect_timeout_sec.map(Duration::from_secs),
+            proxy: config.exchange_proxy.clone(),
+            user_agent: config.exchange_user_agent.clone(),
+            nonce_path: config.exchange_nonce_path.clone(),
+            ..Default::default()
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct DefaultClient {
     client: reqwest::Client,
+    base_url: String,
     access_key: String,
     secret_key: String,
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<RateLimiter>,
+    nonce: Arc<NonceGenerator>,
 }
 
 #[async_trait]
 impl Client for DefaultClient {
     async fn get_order_books(&self, pair: &str) -> MyResult<OrderBooks> {
-        let url = format!("{}{}", BASE_URL, "/api/order_books");
-        let params = [("pair", pair)];
+        let url = format!("{}{}", self.base_url, "/api/order_books");
+        let url = reqwest::Url::parse_with_params(&url, &[("pair", pair)])?;
         let body = self
-            .client
-            .get(&url)
-            .query(&params)
-            .send()
-            .await?
-            .json::<OrdersBooksGetResponse>()
+            .get_request::<OrdersBooksGetResponse>(url.as_str())
             .await?;
         body.to_model()
     }
@@ -76,7 +143,7 @@ impl Client for DefaultClient {
         pair: &str,
         amount: f64,
     ) -> MyResult<f64> {
-        let url = format!("{}{}", BASE_URL, "/api/exchange/orders/rate");
+        let url = format!("{}{}", self.base_url, "/api/exchange/orders/rate");
         let amount_str = format!("{:.3}", amount);
         let params = [
             (
@@ -91,20 +158,16 @@ impl Client for DefaultClient {
             ("pair", pair),
             ("amount", &amount_str),
         ];
+        let url = reqwest::Url::parse_with_params(&url, &params)?;
         let body = self
-            .client
-            .get(&url)
-            .query(&params)
-            .send()
-            .await?
-            .json::<OrdersRateGetResponse>()
+            .get_request::<OrdersRateGetResponse>(url.as_str())
             .await?;
         let rate = body.rate.parse::<f64>()?;
         Ok(rate)
     }
 
     async fn post_exchange_orders(&self, req: &NewOrder) -> MyResult<Order> {
-        let url = format!("{}{}", BASE_URL, "/api/exchange/orders");
+        let url = format!("{}{}", self.base_url, "/api/exchange/orders");
         let req_body = OrdersPostRequest::new(req)?;
 
         let res = self
@@ -122,7 +185,7 @@ impl Client for DefaultClient {
     }
 
     async fn get_exchange_orders_opens(&self) -> MyResult<Vec<OpenOrder>> {
-        let url = format!("{}{}", BASE_URL, "/api/exchange/orders/opens");
+        let url = format!("{}{}", self.base_url, "/api/exchange/orders/opens");
         let body = self
             .get_request_with_auth::<OrdersOpensGetResponse>(&url)
             .await?;
@@ -135,7 +198,7 @@ impl Client for DefaultClient {
     }
 
     async fn delete_exchange_orders(&self, id: u64) -> MyResult<u64> {
-        let url = format!("{}{}{}", BASE_URL, "/api/exchange/orders/", id);
+        let url = format!("{}{}{}", self.base_url, "/api/exchange/orders/", id);
         let body = self
             .delete_request_with_auth::<OrdersDeleteResponse>(&url)
             .await?;
@@ -145,7 +208,7 @@ impl Client for DefaultClient {
     async fn get_exchange_orders_cancel_status(&self, id: u64) -> MyResult<bool> {
         let url: String = format!(
             "{}{}{}",
-            BASE_URL, "/api/exchange/orders/cancel_status?id=", id
+            self.base_url, "/api/exchange/orders/cancel_status?id=", id
         );
         let body = self
             .get_request_with_auth::<OrdersCancelStatusGetResponse>(&url)
@@ -154,67 +217,126 @@ impl Client for DefaultClient {
     }
 
     async fn get_accounts_balance(&self) -> MyResult<HashMap<String, Balance>> {
-        let url: String = format!("{}{}", BASE_URL, "/api/accounts/balance");
+        let url: String = format!("{}{}", self.base_url, "/api/accounts/balance");
         let body = self
             .get_request_with_auth::<BalanceGetResponse>(&url)
             .await?;
         Ok(body.to_map()?)
     }
+
+    async fn get_exchange_orders_transactions(&self) -> MyResult<Vec<Transaction>> {
+        let url = format!("{}{}", self.base_url, "/api/exchange/orders/transactions");
+        let body = self
+            .get_request_with_auth::<TransactionsGetResponse>(&url)
+            .await?;
+        let mut res: Vec<Transaction> = Vec::new();
+        for t in body.transactions {
+            res.push(t.to_model()?);
+        }
+        Ok(res)
+    }
+
+    async fn get_exchange_orders_transactions_pagination(
+        &self,
+        pagination: &Pagination,
+    ) -> MyResult<Vec<Transaction>> {
+        let url = format!(
+            "{}{}",
+            self.base_url, "/api/exchange/orders/transactions_pagination"
+        );
+        let url = reqwest::Url::parse_with_params(&url, &pagination.to_params())?;
+        let body = self
+            .get_request_with_auth::<TransactionsPaginationGetResponse>(url.as_str())
+            .await?;
+        let mut res: Vec<Transaction> = Vec::new();
+        for t in body.data {
+            res.push(t.to_model()?);
+        }
+        Ok(res)
+    }
+
+    async fn get_trades(&self, pair: &str, pagination: &Pagination) -> MyResult<Vec<Trade>> {
+        let url = format!("{}{}", self.base_url, "/api/trades");
+        let mut params = vec![("pair", pair.to_owned())];
+        params.extend(pagination.to_params());
+        let url = reqwest::Url::parse_with_params(&url, &params)?;
+        let body = self.get_request::<TradesGetResponse>(url.as_str()).await?;
+        let mut res: Vec<Trade> = Vec::new();
+        for t in body.data {
+            res.push(t.to_model()?);
+        }
+        Ok(res)
+    }
+
+    async fn get_ticker(&self, pair: &str) -> MyResult<Ticker> {
+        let url = format!("{}{}", self.base_url, "/api/ticker");
+        let url = reqwest::Url::parse_with_params(&url, &[("pair", pair)])?;
+        let body = self.get_request::<TickerGetResponse>(url.as_str()).await?;
+        body.to_model()
+    }
 }
 
 impl DefaultClient {
     pub fn new(access_key: &str, secret_key: &str) -> MyResult<DefaultClient> {
-        let client = reqwest::Client::builder().build()?;
+        DefaultClient::with_options(access_key, secret_key, &ClientOptions::default())
+    }
+
+    // 接続先を変更する（テスト用のスタブサーバーなど）
+    pub fn with_base_url(
+        access_key: &str,
+        secret_key: &str,
+        base_url: &str,
+    ) -> MyResult<DefaultClient> {
+        let options = ClientOptions {
+            base_url: base_url.to_owned(),
+            ..Default::default()
+        };
+        DefaultClient::with_options(access_key, secret_key, &options)
+    }
+
+    pub fn with_options(
+        access_key: &str,
+        secret_key: &str,
+        options: &ClientOptions,
+    ) -> MyResult<DefaultClient> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(v) = options.timeout {
+            builder = builder.timeout(v);
+        }
+        if let Some(v) = options.connect_timeout {
+            builder = builder.connect_timeout(v);
+        }
+        if let Some(v) = &options.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(v)?);
+        }
+        if let Some(v) = &options.user_agent {
+            builder = builder.user_agent(v);
+        }
+        let client = builder.build()?;
+        // 複数のクライアントから並行してリクエストしても nonce が重複しないよう共有する
+        let nonce = match &options.nonce_path {
+            Some(path) => nonce::init_shared(Path::new(path))?,
+            None => nonce::shared(),
+        };
         Ok(DefaultClient {
             client: client,
+            base_url: options.base_url.trim_end_matches('/').to_string(),
+            retry_policy: options.retry_policy.clone(),
+            rate_limiter: options
+                .rate_limit
+                .map(|(burst, per_sec)| RateLimiter::new(burst, per_sec)),
             access_key: access_key.to_string(),
             secret_key: secret_key.to_string(),
+            nonce,
         })
     }
 
+    async fn get_request<T: DeserializeOwned>(&self, url: &str) -> MyResult<T> {
+        self.request(Method::GET, url, None, false).await
+    }
+
     async fn get_request_with_auth<T: DeserializeOwned>(&self, url: &str) -> MyResult<T> {
-        let mut retry_count: i32 = 0;
-        loop {
-            let nonce = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)?
-                .as_millis();
-            let signature = make_signature(nonce, &url, "", &self.secret_key);
-
-            let res_text = self
-                .client
-                .get(url)
-                .header("ACCESS-KEY", &self.access_key)
-                .header("ACCESS-NONCE", format!("{}", nonce))
-                .header("ACCESS-SIGNATURE", signature)
-                .send()
-                .await?
-                .text()
-                .await?;
-
-            if let Ok(res) = serde_json::from_str::<T>(&res_text) {
-                return Ok(res);
-            }
-            if let Ok(res) = serde_json::from_str::<ErrorResponse>(&res_text) {
-                if DefaultClient::should_retry(&res) {
-                    retry_count += 1;
-                    if retry_count <= MAX_RETRY_COUNT {
-                        warn!(
-                            "response is error, retry request retry_count:{} <= max:{}, error:{}",
-                            retry_count, MAX_RETRY_COUNT, res.error,
-                        );
-                        let d = Duration::from_millis(RETRY_INTERVAL_MS);
-                        std::thread::sleep(d);
-                        continue;
-                    }
-                }
-                return Err(Box::new(ResponseError {
-                    message: res.error,
-                    url: url.to_owned(),
-                    request: "".to_owned(),
-                }));
-            }
-            return Err(Box::new(ParseError(res_text)));
-        }
+        self.request(Method::GET, url, None, true).await
     }
 
     async fn post_request_with_auth<T, U>(&self, url: &str, body: T) -> MyResult<U>
@@ -222,104 +344,157 @@ impl DefaultClient {
         T: Serialize,
         U: DeserializeOwned,
     {
-        let mut retry_count: i32 = 0;
+        let json = serde_json::to_string(&body)?;
+        self.request(Method::POST, url, Some(json), true).await
+    }
+
+    async fn delete_request_with_auth<T: DeserializeOwned>(&self, url: &str) -> MyResult<T> {
+        self.request(Method::DELETE, url, None, true).await
+    }
+
+    // リクエストを送信し、一時的なエラーなら待ってから再試行する
+    // 注文やキャンセルは二重に処理されないよう、未処理であることが確実な場合のみ再試行する
+    async fn request<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<String>,
+        with_auth: bool,
+    ) -> MyResult<T> {
+        let idempotent = method == Method::GET;
+        let mut attempt: u32 = 0;
         loop {
-            let nonce = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)?
-                .as_millis();
-            let json = serde_json::to_string(&body)?;
-            let signature = make_signature(nonce, &url, &json, &self.secret_key);
-
-            let res_text = self
-                .client
-                .post(url)
-                .header("Content-Type", "application/json")
-                .header("ACCESS-KEY", &self.access_key)
-                .header("ACCESS-NONCE", format!("{}", nonce))
-                .header("ACCESS-SIGNATURE", signature)
-                .body(json.clone())
-                .send()
-                .await?
-                .text()
-                .await?;
-
-            if let Ok(res) = serde_json::from_str::<U>(&res_text) {
-                return Ok(res);
+            if let Some(limiter) = &self.rate_limiter {
+                limiter.acquire().await;
             }
-            if let Ok(res) = serde_json::from_str::<ErrorResponse>(&res_text) {
-                if DefaultClient::should_retry(&res) {
-                    retry_count += 1;
-                    if retry_count <= MAX_RETRY_COUNT {
-                        warn!(
-                            "response is error, retry request retry_count:{} <= max:{}, error:{}",
-                            retry_count, MAX_RETRY_COUNT, res.error,
-                        );
-                        let d = Duration::from_millis(RETRY_INTERVAL_MS);
-                        std::thread::sleep(d);
-                        continue;
+
+            // 再試行時も nonce は毎回更新する
+            let nonce = if with_auth {
+                Some(self.nonce.next()?)
+            } else {
+                None
+            };
+            let (err, retryable): (Box<dyn Error + Send + Sync>, bool) =
+                match self.send(&method, url, &body, nonce).await {
+                    Ok((status, res_text)) => {
+                        match parse_response::<T>(status, res_text, url, &body, idempotent) {
+                            Ok(res) => return Ok(res),
+                            Err(v) => v,
+                        }
                     }
-                }
-                return Err(Box::new(ResponseError {
-                    message: res.error,
-                    url: url.to_owned(),
-                    request: json,
-                }));
+                    Err(err) => {
+                        let retryable = retry::is_retryable_error(&err, idempotent);
+                        (Box::new(err), retryable)
+                    }
+                };
+
+            // 再試行したリクエストのエラーも数える
+            metrics::shared().record_api_error(
+                &metrics::endpoint(&self.base_url, url),
+                metrics::error_kind(&*err),
+            );
+            if !retryable {
+                return Err(err as Box<dyn Error>);
+            }
+            attempt += 1;
+            if attempt > self.retry_policy.max_retries {
+                return Err(err as Box<dyn Error>);
             }
-            return Err(Box::new(ParseError(res_text)));
+            let d = self.retry_policy.backoff_with_random_jitter(attempt);
+            warn!(
+                "request is failed, retry request after {:?}, retry_count:{} <= max:{}, error:{}",
+                d, attempt, self.retry_policy.max_retries, err,
+            );
+            tokio::time::sleep(d).await;
         }
     }
 
-    async fn delete_request_with_auth<T: DeserializeOwned>(&self, url: &str) -> MyResult<T> {
-        let mut retry_count: i32 = 0;
-        loop {
-            let nonce = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)?
-                .as_millis();
-            let signature = make_signature(nonce, &url, "", &self.secret_key);
-
-            let res_text = self
-                .client
-                .delete(url)
+    async fn send(
+        &self,
+        method: &Method,
+        url: &str,
+        body: &Option<String>,
+        nonce: Option<u64>,
+    ) -> Result<(StatusCode, String), reqwest::Error> {
+        let mut builder = self.client.request(method.clone(), url);
+        if let Some(nonce) = nonce {
+            let signature =
+                make_signature(nonce, url, body.as_deref().unwrap_or(""), &self.secret_key);
+            builder = builder
                 .header("ACCESS-KEY", &self.access_key)
                 .header("ACCESS-NONCE", format!("{}", nonce))
-                .header("ACCESS-SIGNATURE", signature)
-                .send()
-                .await?
-                .text()
-                .await?;
-
-            if let Ok(res) = serde_json::from_str::<T>(&res_text) {
-                return Ok(res);
-            }
-            if let Ok(res) = serde_json::from_str::<ErrorResponse>(&res_text) {
-                if DefaultClient::should_retry(&res) {
-                    retry_count += 1;
-                    if retry_count <= MAX_RETRY_COUNT {
-                        warn!(
-                            "response is error, retry request retry_count:{} <= max:{}, error:{}",
-                            retry_count, MAX_RETRY_COUNT, res.error,
-                        );
-                        let d = Duration::from_millis(RETRY_INTERVAL_MS);
-                        std::thread::sleep(d);
-                        continue;
-                    }
-                }
-                return Err(Box::new(ResponseError {
-                    message: res.error,
-                    url: url.to_owned(),
-                    request: "".to_owned(),
-                }));
-            }
-            return Err(Box::new(ParseError(res_text)));
+                .header("ACCESS-SIGNATURE", signature);
+        }
+        if let Some(json) = body {
+            builder = builder
+                .header("Content-Type", "application/json")
+                .body(json.clone());
         }
+        let res = builder.send().await?;
+        let status = res.status();
+        let text = res.text().await?;
+        Ok((status, text))
     }
+}
 
-    fn should_retry(res: &ErrorResponse) -> bool {
-        res.error == "Nonce must be incremented"
+// レスポンスを解析する（エラーの場合は再試行すべきかどうかも返す）
+fn parse_response<T: DeserializeOwned>(
+    status: StatusCode,
+    res_text: String,
+    url: &str,
+    body: &Option<String>,
+    idempotent: bool,
+) -> Result<T, (Box<dyn Error + Send + Sync>, bool)> {
+    // 5xx は本文に関わらず処理されたか不明なエラーとする
+    if status.is_server_error() {
+        return Err((
+            Box::new(HttpStatusError {
+                status: status.as_u16(),
+                url: url.to_owned(),
+                body: res_text,
+            }),
+            is_retryable_status(status, idempotent),
+        ));
+    }
+    // 一部のレスポンスは全項目が省略可能なため、エラーレスポンスかを先に判定する
+    if let Some(res) = serde_json::from_str::<ErrorResponse>(&res_text)
+        .ok()
+        .filter(|res| !res.success)
+    {
+        let retryable = retry::is_transient_error_message(&res.error)
+            || is_retryable_status(status, idempotent);
+        return Err((
+            Box::new(ResponseError {
+                message: res.error,
+                url: url.to_owned(),
+                request: body.clone().unwrap_or_default(),
+            }),
+            retryable,
+        ));
+    }
+    if retry::is_retryable_status(status) {
+        let retryable = is_retryable_status(status, idempotent);
+        return Err((
+            Box::new(HttpStatusError {
+                status: status.as_u16(),
+                url: url.to_owned(),
+                body: res_text,
+            }),
+            retryable,
+        ));
+    }
+    match serde_json::from_str::<T>(&res_text) {
+        Ok(res) => Ok(res),
+        Err(_) => Err((Box::new(ParseError(res_text)), false)),
     }
 }
 
-fn make_signature(nonce: u128, url: &str, body: &str, secret_key: &str) -> String {
+// 5xx は処理されたか不明なため、idempotent なリクエストのみ再試行する
+fn is_retryable_status(status: StatusCode, idempotent: bool) -> bool {
+    retry::is_retryable_status(status) && (idempotent || status == StatusCode::TOO_MANY_REQUESTS)
+}
+
+fn make_signature(nonce: u64, url: &str, body: &str, secret_key: &str) -> String {
     let key = PKey::hmac(secret_key.as_bytes()).unwrap();
     let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
     let v = format!("{}{}{}", nonce, url, body);
@@ -333,6 +508,26 @@ fn make_signature(nonce: u128, url: &str, body: &str, secret_key: &str) -> Strin
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_with_options() {
+        let options = ClientOptions {
+            base_url: "http://localhost:8080/".to_owned(),
+            timeout: Some(Duration::from_secs(10)),
+            connect_timeout: Some(Duration::from_secs(3)),
+            proxy: Some("http://localhost:3128".to_owned()),
+            user_agent: Some("trading-bot".to_owned()),
+            ..Default::default()
+        };
+        let client = DefaultClient::with_options("key", "secret", &options).unwrap();
+        assert_eq!(client.base_url, "http://localhost:8080");
+
+        let options = ClientOptions {
+            proxy: Some("not a url".to_owned()),
+            ..Default::default()
+        };
+        assert!(DefaultClient::with_options("key", "secret", &options).is_err());
+    }
+
     #[test]
     fn test_make_signature() {
         assert_eq!(
@@ -2,52 +2,304 @@ use crate::bot::model::TradeInfo;
 use crate::bot::model::TradeInfoParam;
 use crate::coincheck::client::Client;
 use crate::coincheck::model::Pair;
-use crate::coincheck::model::{Balance, NewOrder, OpenOrder, Order, OrderBooks, OrderType};
+use crate::coincheck::model::{
+    Balance, FeeRate, Liquidity, NewOrder, OpenOrder, Order, OrderBooks, OrderType, Pagination,
+    Ticker, Trade, Transaction,
+};
 use crate::config::Config;
-use crate::error::MyError::{EmptyCollection, KeyNotFound};
+use crate::error::MyError::{EmptyCollection, InvalidOrder, KeyNotFound};
 use crate::error::MyResult;
 use crate::mysql::model::Market;
 use async_trait::async_trait;
 use chrono::FixedOffset;
 use chrono::TimeZone;
+use chrono::Utc;
 use std::collections::HashMap;
+use std::sync::Mutex;
 
 #[derive(Debug)]
 pub struct SimulationClient {
-    markets: HashMap<String, Vec<Market>>,
+    // 取引ペア => 最新の市場データ
+    markets: Mutex<HashMap<String, Market>>,
+    // 取引ペア => 手数料率
+    fee_rates: HashMap<String, FeeRate>,
+    // 約定（新しいものが後ろ）
+    transactions: Mutex<Vec<Transaction>>,
+    // 通貨 => 残高（約定に応じて増減する, 指値注文の分は注文中に移す）
+    balances: Mutex<HashMap<String, Balance>>,
+    // 未約定の指値注文, 逆指値注文（市場データを追加したときに約定を判定する）
+    open_orders: Mutex<Vec<OpenOrder>>,
+    // 最後に採番した注文ID
+    last_order_id: Mutex<u64>,
 }
 
 impl SimulationClient {
     pub fn new() -> MyResult<SimulationClient> {
         Ok(SimulationClient {
-            markets: HashMap::new(),
+            markets: Mutex::new(HashMap::new()),
+            fee_rates: HashMap::new(),
+            transactions: Mutex::new(vec![]),
+            balances: Mutex::new(HashMap::new()),
+            open_orders: Mutex::new(vec![]),
+            last_order_id: Mutex::new(0),
         })
     }
 
-    pub fn add_market(&mut self, market: &Market) -> MyResult<()> {
-        let pair = market.pair.clone();
-        if !self.markets.contains_key(&pair) {
-            self.markets.insert(pair.to_string(), vec![]);
+    pub fn set_balance(&self, currency: &str, amount: f64) {
+        self.balances.lock().unwrap().insert(
+            currency.to_owned(),
+            Balance {
+                amount,
+                reserved: 0.0,
+            },
+        );
+    }
+
+    // 残高の合計（コインは直近の売りレートで評価する, レートの分からないコインは含めない）
+    pub fn total_balance_jpy(&self, settlement: &str) -> f64 {
+        let markets = self.markets.lock().unwrap();
+        let balances = self.balances.lock().unwrap();
+        balances
+            .iter()
+            .map(|(currency, balance)| {
+                if currency == settlement {
+                    return balance.total();
+                }
+                let pair = format!("{}_{}", currency, settlement);
+                match markets.get(&pair) {
+                    Some(market) => balance.total() * market.ex_rate_sell,
+                    None => 0.0,
+                }
+            })
+            .sum()
+    }
+
+    pub fn set_fee_rates(&mut self, fee_rates: &HashMap<String, FeeRate>) {
+        self.fee_rates = fee_rates.clone();
+    }
+
+    // 支払った手数料の合計（通貨 => 手数料）
+    pub fn total_fees(&self) -> HashMap<String, f64> {
+        let mut fees = HashMap::new();
+        for t in self.transactions.lock().unwrap().iter() {
+            if let Some(currency) = &t.fee_currency {
+                *fees.entry(currency.to_owned()).or_insert(0.0) += t.fee;
+            }
+        }
+        fees
+    }
+
+    fn next_order_id(&self) -> u64 {
+        let mut id = self.last_order_id.lock().unwrap();
+        *id += 1;
+        *id
+    }
+
+    // 注文分の残高を注文中に移す（残高が足りなければエラー）
+    fn reserve(&self, pair: &Pair, currency: &str, v: f64) -> MyResult<()> {
+        let mut balances = self.balances.lock().unwrap();
+        let balance = balances.entry(currency.to_owned()).or_insert(Balance {
+            amount: 0.0,
+            reserved: 0.0,
+        });
+        if balance.amount < v {
+            return Err(Box::new(InvalidOrder {
+                pair: pair.to_string(),
+                message: format!(
+                    "balance {} is not enough, {:.8} < {:.8}",
+                    currency, balance.amount, v
+                ),
+            }));
+        }
+        balance.amount -= v;
+        balance.reserved += v;
+        Ok(())
+    }
+
+    // 注文中の残高を戻す
+    fn release(&self, currency: &str, v: f64) {
+        let mut balances = self.balances.lock().unwrap();
+        if let Some(balance) = balances.get_mut(currency) {
+            balance.amount += v;
+            balance.reserved -= v;
+        }
+    }
+
+    // 指値注文で注文中にする通貨と数量
+    fn reserved_of(order: &OpenOrder, pair: &Pair) -> (String, f64) {
+        if order.order_type == OrderType::Buy {
+            (
+                pair.settlement.to_owned(),
+                order.rate * order.pending_amount,
+            )
+        } else {
+            (pair.key.to_owned(), order.pending_amount)
         }
+    }
+
+    // 約定を市場データの時刻で記録し、手数料を差し引いて残高に反映する
+    fn settle(
+        &self,
+        order_id: u64,
+        side: OrderType,
+        rate: f64,
+        amount_coin: f64,
+        liquidity: Liquidity,
+        market: &Market,
+    ) -> MyResult<()> {
+        let pair = Pair::new(&market.pair)?;
+        let tz = FixedOffset::east_opt(9 * 60 * 60).unwrap();
+        let fee_rate = self
+            .fee_rates
+            .get(&pair.to_string())
+            .copied()
+            .unwrap_or_default();
+        let funds_jpy = rate * amount_coin;
+        let fee = fee_rate.calc_fee(&liquidity, funds_jpy);
+        let mut funds = HashMap::new();
+        if side == OrderType::Buy {
+            funds.insert(pair.key.to_owned(), amount_coin);
+            funds.insert(pair.settlement.to_owned(), -(funds_jpy + fee));
+        } else {
+            funds.insert(pair.key.to_owned(), -amount_coin);
+            funds.insert(pair.settlement.to_owned(), funds_jpy - fee);
+        }
+
+        {
+            let mut balances = self.balances.lock().unwrap();
+            for (currency, v) in funds.iter() {
+                balances
+                    .entry(currency.to_owned())
+                    .or_insert(Balance {
+                        amount: 0.0,
+                        reserved: 0.0,
+                    })
+                    .amount += v;
+            }
+        }
+
+        let mut transactions = self.transactions.lock().unwrap();
+        let id = transactions.len() as u64 + 1;
+        transactions.push(Transaction {
+            id,
+            order_id,
+            pair: pair.clone(),
+            side,
+            rate,
+            funds,
+            fee,
+            fee_currency: Some(pair.settlement.to_owned()),
+            liquidity,
+            created_at: tz.from_utc_datetime(&market.recorded_at),
+        });
+        Ok(())
+    }
+
+    // 成行注文を現在のレートでテイカーとして約定させる（残高が足りなければエラー）
+    fn fill_market_order(&self, order: &Order, req: &NewOrder, market: &Market) -> MyResult<()> {
+        let (side, rate, amount_coin, currency, required) = match req.order_type {
+            OrderType::MarketBuy => {
+                let rate = market.ex_rate_buy;
+                let amount_jpy = req.market_buy_amount.unwrap_or(0.0);
+                (
+                    OrderType::Buy,
+                    rate,
+                    amount_jpy / rate,
+                    &order.pair.settlement,
+                    amount_jpy,
+                )
+            }
+            OrderType::MarketSell => {
+                let amount_coin = req.amount.unwrap_or(0.0);
+                (
+                    OrderType::Sell,
+                    market.ex_rate_sell,
+                    amount_coin,
+                    &order.pair.key,
+                    amount_coin,
+                )
+            }
+            _ => return Ok(()),
+        };
+        // 残高の確認のみ（すぐに約定するため注文中には残さない）
+        self.reserve(&order.pair, currency, required)?;
+        self.release(currency, required);
+        self.settle(order.id, side, rate, amount_coin, Liquidity::Taker, market)
+    }
+
+    // 指値注文, 逆指値注文を未約定の注文として登録する
+    fn place_limit_order(&self, order: &Order, req: &NewOrder) -> MyResult<()> {
+        let open_order = OpenOrder {
+            id: order.id,
+            rate: req.rate.unwrap_or(0.0),
+            pending_amount: req.amount.unwrap_or(0.0),
+            pending_market_buy_amount: None,
+            order_type: req.order_type.clone(),
+            stop_loss_rate: req.stop_loss_rate,
+            pair: req.pair.to_owned(),
+            created_at: order.created_at,
+        };
+        let (currency, v) = SimulationClient::reserved_of(&open_order, &order.pair);
+        self.reserve(&order.pair, &currency, v)?;
+        self.open_orders.lock().unwrap().push(open_order);
+        Ok(())
+    }
+
+    // 市場データのレートに達した注文を、注文のレートで約定させる
+    // 逆指値注文はレートが逆指値以下になったら指値注文に切り替える（すぐに約定すればテイカー）
+    fn fill_open_orders(&self, market: &Market) -> MyResult<()> {
+        let pair = Pair::new(&market.pair)?;
+        let mut filled = vec![];
+        {
+            let mut open_orders = self.open_orders.lock().unwrap();
+            for o in open_orders.iter_mut().filter(|o| o.pair == market.pair) {
+                let mut liquidity = Liquidity::Maker;
+                if let Some(stop_loss_rate) = o.stop_loss_rate {
+                    if market.ex_rate_sell > stop_loss_rate {
+                        continue;
+                    }
+                    o.stop_loss_rate = None;
+                    liquidity = Liquidity::Taker;
+                }
+                let reached = match o.order_type {
+                    OrderType::Buy => market.ex_rate_buy <= o.rate,
+                    _ => market.ex_rate_sell >= o.rate,
+                };
+                if reached {
+                    filled.push((o.clone(), liquidity));
+                }
+            }
+            open_orders.retain(|o| !filled.iter().any(|(f, _)| f.id == o.id));
+        }
+
+        for (o, liquidity) in filled {
+            let (currency, v) = SimulationClient::reserved_of(&o, &pair);
+            self.release(&currency, v);
+            let side = if o.order_type == OrderType::Buy {
+                OrderType::Buy
+            } else {
+                OrderType::Sell
+            };
+            self.settle(o.id, side, o.rate, o.pending_amount, liquidity, market)?;
+        }
+        Ok(())
+    }
+
+    pub fn add_market(&self, market: &Market) -> MyResult<()> {
         self.markets
-            .get_mut(&pair.to_string())
+            .lock()
             .unwrap()
-            .push(market.clone());
-        Ok(())
+            .insert(market.pair.to_owned(), market.clone());
+        self.fill_open_orders(market)
     }
 
     pub fn get_market(&self, pair: &str) -> MyResult<Option<Market>> {
-        if !self.markets.contains_key(pair) {
-            return Err(Box::new(KeyNotFound {
+        match self.markets.lock().unwrap().get(pair) {
+            Some(market) => Ok(Some(market.clone())),
+            None => Err(Box::new(KeyNotFound {
                 key: pair.to_owned(),
                 collection_name: "markets".to_owned(),
-            }));
-        }
-
-        if let Some(market) = self.markets.get(pair).unwrap().iter().last() {
-            Ok(Some(market.clone()))
-        } else {
-            Ok(None)
+            })),
         }
     }
 
@@ -101,30 +353,48 @@ impl Client for SimulationClient {
     }
 
     async fn post_exchange_orders(&self, req: &NewOrder) -> MyResult<Order> {
-        let tz = FixedOffset::east(9 * 60 * 60);
+        let tz = FixedOffset::east_opt(9 * 60 * 60).unwrap();
         if let Some(market) = self.get_market(&req.pair)? {
-            // TODO 実装
-            Ok(Order {
-                id: 0,
-                rate: None,
-                amount: None,
+            let order = Order {
+                id: self.next_order_id(),
+                rate: req.rate,
+                amount: req.amount,
                 order_type: req.order_type.clone(),
                 pair: Pair::new(&req.pair)?,
                 created_at: tz.from_utc_datetime(&market.recorded_at),
-            })
+            };
+            match req.order_type {
+                OrderType::MarketBuy | OrderType::MarketSell => {
+                    self.fill_market_order(&order, req, &market)?
+                }
+                OrderType::Buy | OrderType::Sell => self.place_limit_order(&order, req)?,
+            }
+            Ok(order)
         } else {
             Err(Box::new(EmptyCollection("markets".to_string())))
         }
     }
 
     async fn get_exchange_orders_opens(&self) -> MyResult<Vec<OpenOrder>> {
-        // TODO 実装
-        Ok(vec![])
+        Ok(self.open_orders.lock().unwrap().clone())
     }
 
-    async fn delete_exchange_orders(&self, _id: u64) -> MyResult<u64> {
-        // TODO 実装
-        Ok(0)
+    async fn delete_exchange_orders(&self, id: u64) -> MyResult<u64> {
+        let open_order = {
+            let mut open_orders = self.open_orders.lock().unwrap();
+            let i = open_orders
+                .iter()
+                .position(|o| o.id == id)
+                .ok_or_else(|| KeyNotFound {
+                    key: id.to_string(),
+                    collection_name: "open orders".to_owned(),
+                })?;
+            open_orders.remove(i)
+        };
+        let (currency, v) =
+            SimulationClient::reserved_of(&open_order, &Pair::new(&open_order.pair)?);
+        self.release(&currency, v);
+        Ok(id)
     }
 
     async fn get_exchange_orders_cancel_status(&self, _id: u64) -> MyResult<bool> {
@@ -132,7 +402,85 @@ impl Client for SimulationClient {
     }
 
     async fn get_accounts_balance(&self) -> MyResult<HashMap<String, Balance>> {
+        Ok(self.balances.lock().unwrap().clone())
+    }
+
+    async fn get_exchange_orders_transactions(&self) -> MyResult<Vec<Transaction>> {
+        // 新しい順
+        let transactions = self.transactions.lock().unwrap();
+        Ok(transactions.iter().rev().cloned().collect())
+    }
+
+    async fn get_exchange_orders_transactions_pagination(
+        &self,
+        _pagination: &Pagination,
+    ) -> MyResult<Vec<Transaction>> {
         // TODO 実装
-        Ok(HashMap::new())
+        Ok(vec![])
+    }
+
+    async fn get_trades(&self, _pair: &str, _pagination: &Pagination) -> MyResult<Vec<Trade>> {
+        Ok(vec![])
+    }
+
+    async fn get_ticker(&self, pair: &str) -> MyResult<Ticker> {
+        if let Some(market) = self.get_market(pair)? {
+            Ok(Ticker {
+                last: market.ex_rate_sell,
+                bid: market.ex_rate_sell,
+                ask: market.ex_rate_buy,
+                high: market.ex_rate_buy,
+                low: market.ex_rate_sell,
+                volume: market.ex_volume_sell + market.ex_volume_buy,
+                timestamp: Utc.from_utc_datetime(&market.recorded_at),
+            })
+        } else {
+            Err(Box::new(EmptyCollection("markets".to_string())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    #[tokio::test]
+    async fn test_balances() {
+        let client = SimulationClient::new().unwrap();
+        client.set_balance("jpy", 100000.0);
+        client
+            .add_market(&Market {
+                pair: "btc_jpy".to_owned(),
+                store_rate_avg: 0.0,
+                ex_rate_sell: 4000000.0,
+                ex_rate_buy: 5000000.0,
+                ex_volume_sell: 0.0,
+                ex_volume_buy: 0.0,
+                recorded_at: NaiveDateTime::parse_from_str(
+                    "2021-05-16 12:00:00",
+                    "%Y-%m-%d %H:%M:%S",
+                )
+                .unwrap(),
+            })
+            .unwrap();
+
+        client
+            .post_exchange_orders(&NewOrder {
+                pair: "btc_jpy".to_owned(),
+                order_type: OrderType::MarketBuy,
+                rate: None,
+                amount: None,
+                market_buy_amount: Some(10000.0),
+                stop_loss_rate: None,
+            })
+            .await
+            .unwrap();
+
+        let balances = client.get_accounts_balance().await.unwrap();
+        assert_eq!(balances["jpy"].amount, 90000.0);
+        assert_eq!(balances["btc"].amount, 0.002);
+        // コインは売りレートで評価する
+        assert_eq!(client.total_balance_jpy("jpy"), 98000.0);
     }
 }
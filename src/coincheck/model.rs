@@ -1,11 +1,13 @@
-use crate::error::MyError::ParseError;
+use crate::error::MyError::{InvalidOrder, ParseError};
 use crate::error::MyResult;
 
+use std::collections::HashMap;
 use std::fmt;
 
 use chrono::DateTime;
 use chrono::FixedOffset;
-use serde::Deserialize;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct Pair {
@@ -26,6 +28,92 @@ impl Pair {
     pub fn to_string(&self) -> String {
         format!("{}_{}", self.key, self.settlement)
     }
+
+    // 取引所の注文ルール（未登録の取引ペアは None）
+    pub fn metadata(&self) -> Option<PairMetadata> {
+        PAIR_METADATA
+            .iter()
+            .find(|(p, _)| *p == self.to_string())
+            .map(|(_, m)| *m)
+    }
+
+    // 注文数量が最小注文数量以上か
+    pub fn validate_amount(&self, amount: f64) -> MyResult<()> {
+        if let Some(m) = self.metadata() {
+            if amount < m.min_amount {
+                return Err(Box::new(InvalidOrder {
+                    pair: self.to_string(),
+                    message: format!("amount {} is less than minimum {}", amount, m.min_amount),
+                }));
+            }
+        }
+        Ok(())
+    }
+}
+
+// 取引ペア毎の注文ルール
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PairMetadata {
+    // 最小注文数量
+    pub min_amount: f64,
+    // 注文レートの刻み
+    pub rate_tick: f64,
+    // 注文数量の小数点以下の桁数
+    pub amount_precision: u32,
+}
+
+// 取引所の注文ルール（取引ペアを追加する場合は取引所の注文ルールを確認して追記する）
+const PAIR_METADATA: [(&str, PairMetadata); 1] = [(
+    "btc_jpy",
+    PairMetadata {
+        min_amount: 0.005,
+        rate_tick: 1.0,
+        amount_precision: 8,
+    },
+)];
+
+impl PairMetadata {
+    // 刻みの倍数に切り下げる（買いは目標より高く買わないように）
+    pub fn round_rate_down(&self, rate: f64) -> f64 {
+        self.snap_to_tick(rate, f64::floor)
+    }
+
+    // 刻みの倍数に切り上げる（売りは目標より安く売らないように）
+    pub fn round_rate_up(&self, rate: f64) -> f64 {
+        self.snap_to_tick(rate, f64::ceil)
+    }
+
+    // 保有数より多く売らないように切り捨てる
+    pub fn round_amount(&self, amount: f64) -> f64 {
+        let scale = 10f64.powi(self.amount_precision as i32);
+        // 浮動小数点の誤差で切り捨てすぎないようにする
+        (amount * scale + 1e-6).floor() / scale
+    }
+
+    pub fn format_rate(&self, rate: f64) -> String {
+        let decimals = (-self.rate_tick.log10()).ceil().max(0.0) as usize;
+        format!("{:.*}", decimals, rate)
+    }
+
+    pub fn format_amount(&self, amount: f64) -> String {
+        let s = format!("{:.*}", self.amount_precision as usize, amount);
+        if s.contains('.') {
+            s.trim_end_matches('0').trim_end_matches('.').to_owned()
+        } else {
+            s
+        }
+    }
+
+    fn snap_to_tick(&self, rate: f64, round: fn(f64) -> f64) -> f64 {
+        // 浮動小数点の誤差で刻みがずれないようにする
+        let ticks = rate / self.rate_tick;
+        let nearest = ticks.round();
+        if (ticks - nearest).abs() < 1e-9 {
+            nearest * self.rate_tick
+        } else {
+            round(ticks) * self.rate_tick
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, PartialEq, Clone)]
@@ -79,29 +167,50 @@ pub struct NewOrder {
     pub stop_loss_rate: Option<f64>,
 }
 
+// 注文のレート, 数量は取引ペアの注文ルールに合わせて丸める
 impl NewOrder {
     pub fn new_buy_order(pair: &Pair, rate: f64, amount: f64) -> NewOrder {
+        let m = pair.metadata();
         NewOrder {
             pair: pair.to_string(),
             order_type: OrderType::Buy,
-            rate: Some(rate),
-            amount: Some(amount),
+            rate: Some(m.map_or(rate, |m| m.round_rate_down(rate))),
+            amount: Some(m.map_or(amount, |m| m.round_amount(amount))),
             market_buy_amount: None,
             stop_loss_rate: None,
         }
     }
 
     pub fn new_sell_order(pair: &Pair, rate: f64, amount: f64) -> NewOrder {
+        let m = pair.metadata();
         NewOrder {
             pair: pair.to_string(),
             order_type: OrderType::Sell,
-            rate: Some(rate),
-            amount: Some(amount),
+            rate: Some(m.map_or(rate, |m| m.round_rate_up(rate))),
+            amount: Some(m.map_or(amount, |m| m.round_amount(amount))),
             market_buy_amount: None,
             stop_loss_rate: None,
         }
     }
 
+    // 逆指値の売り注文（レートが stop_loss_rate 以下になったら rate の指値で売る）
+    pub fn new_stop_loss_order(
+        pair: &Pair,
+        stop_loss_rate: f64,
+        rate: f64,
+        amount: f64,
+    ) -> NewOrder {
+        let m = pair.metadata();
+        NewOrder {
+            pair: pair.to_string(),
+            order_type: OrderType::Sell,
+            rate: Some(m.map_or(rate, |m| m.round_rate_down(rate))),
+            amount: Some(m.map_or(amount, |m| m.round_amount(amount))),
+            market_buy_amount: None,
+            stop_loss_rate: Some(m.map_or(stop_loss_rate, |m| m.round_rate_up(stop_loss_rate))),
+        }
+    }
+
     pub fn new_market_buy_order(pair: &Pair, market_buy_amount: f64) -> NewOrder {
         NewOrder {
             pair: pair.to_string(),
@@ -114,15 +223,49 @@ impl NewOrder {
     }
 
     pub fn new_market_sell_order(pair: &Pair, amount: f64) -> NewOrder {
+        let m = pair.metadata();
         NewOrder {
             pair: pair.to_string(),
             order_type: OrderType::MarketSell,
             rate: None,
-            amount: Some(amount),
+            amount: Some(m.map_or(amount, |m| m.round_amount(amount))),
             market_buy_amount: None,
             stop_loss_rate: None,
         }
     }
+
+    // 取引所に送る前に注文ルールを満たすか確認する
+    pub fn validate(&self) -> MyResult<()> {
+        let pair = Pair::new(&self.pair)?;
+        let invalid = |message: String| -> MyResult<()> {
+            Err(Box::new(InvalidOrder {
+                pair: self.pair.to_owned(),
+                message,
+            }))
+        };
+        for rate in [self.rate, self.stop_loss_rate].iter().flatten() {
+            if *rate <= 0.0 {
+                return invalid(format!("rate {} must be positive", rate));
+            }
+            if let Some(m) = pair.metadata() {
+                if m.round_rate_down(*rate) != *rate {
+                    return invalid(format!(
+                        "rate {} is not a multiple of tick {}",
+                        rate, m.rate_tick
+                    ));
+                }
+            }
+        }
+        if let Some(amount) = self.amount {
+            pair.validate_amount(amount)?;
+        }
+        if let Some(amount) = self.market_buy_amount {
+            if amount <= 0.0 {
+                return invalid(format!("market buy amount {} must be positive", amount));
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -142,11 +285,13 @@ pub struct OpenOrder {
     pub pending_amount: f64,
     pub pending_market_buy_amount: Option<f64>,
     pub order_type: OrderType,
+    // 逆指値注文なら逆指値レート
+    pub stop_loss_rate: Option<f64>,
     pub pair: String,
     pub created_at: DateTime<FixedOffset>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Balance {
     pub amount: f64,
     pub reserved: f64,
@@ -167,3 +312,144 @@ impl fmt::Display for Balance {
         )
     }
 }
+
+// 約定の種別（M: メイカー, T: テイカー）
+#[derive(Debug, Clone, PartialEq)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+impl Liquidity {
+    pub fn parse(t: &str) -> MyResult<Liquidity> {
+        match t {
+            "M" => Ok(Liquidity::Maker),
+            "T" => Ok(Liquidity::Taker),
+            _ => Err(Box::new(ParseError(t.to_owned()))),
+        }
+    }
+
+    pub fn to_str(&self) -> &str {
+        match self {
+            Liquidity::Maker => "M",
+            Liquidity::Taker => "T",
+        }
+    }
+}
+
+// 取引手数料率（約定金額に対する割合, マイナスはリベート）
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FeeRate {
+    pub maker: f64,
+    pub taker: f64,
+}
+
+impl FeeRate {
+    pub fn ratio(&self, liquidity: &Liquidity) -> f64 {
+        match liquidity {
+            Liquidity::Maker => self.maker,
+            Liquidity::Taker => self.taker,
+        }
+    }
+
+    // 約定金額に対する手数料
+    pub fn calc_fee(&self, liquidity: &Liquidity, funds: f64) -> f64 {
+        funds * self.ratio(liquidity)
+    }
+}
+
+// 自分の注文の約定
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    pub id: u64,
+    pub order_id: u64,
+    pub pair: Pair,
+    // Buy or Sell（成行注文も同様）
+    pub side: OrderType,
+    pub rate: f64,
+    // 通貨 => 増減量（手数料を含む）
+    pub funds: HashMap<String, f64>,
+    pub fee: f64,
+    pub fee_currency: Option<String>,
+    pub liquidity: Liquidity,
+    pub created_at: DateTime<FixedOffset>,
+}
+
+impl Transaction {
+    // 約定したコインの数量
+    pub fn amount(&self) -> f64 {
+        self.funds
+            .get(&self.pair.key)
+            .map(|v| v.abs())
+            .unwrap_or(0.0)
+    }
+}
+
+// 取引所全体の取引履歴
+#[derive(Debug, Clone)]
+pub struct Trade {
+    pub id: u64,
+    pub pair: Pair,
+    pub order_type: OrderType,
+    pub rate: f64,
+    pub amount: f64,
+    pub created_at: DateTime<FixedOffset>,
+}
+
+// ティッカー
+#[derive(Debug, Clone)]
+pub struct Ticker {
+    pub last: f64,
+    pub bid: f64,
+    pub ask: f64,
+    pub high: f64,
+    pub low: f64,
+    // 24時間の取引量
+    pub volume: f64,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    pub fn to_str(&self) -> &str {
+        match self {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        }
+    }
+}
+
+// ページネーション（未指定の項目は取引所の既定値）
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Pagination {
+    pub limit: Option<u32>,
+    pub order: Option<SortOrder>,
+    // 指定したIDより後（古い側）を取得する
+    pub starting_after: Option<u64>,
+    // 指定したIDより前（新しい側）を取得する
+    pub ending_before: Option<u64>,
+}
+
+impl Pagination {
+    pub fn to_params(&self) -> Vec<(&str, String)> {
+        let mut params = Vec::new();
+        if let Some(v) = self.limit {
+            params.push(("limit", v.to_string()));
+        }
+        if let Some(v) = &self.order {
+            params.push(("order", v.to_str().to_owned()));
+        }
+        if let Some(v) = self.starting_after {
+            params.push(("starting_after", v.to_string()));
+        }
+        if let Some(v) = self.ending_before {
+            params.push(("ending_before", v.to_string()));
+        }
+        params
+    }
+}
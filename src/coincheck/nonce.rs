@@ -0,0 +1,175 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use log::warn;
+
+// 永続化する上限値を、使用済みの nonce からどれだけ先まで確保するか
+// （毎リクエストでファイルに書き込まないよう、まとめて確保する）
+const RESERVE_SIZE: u64 = 10_000;
+
+static SHARED: OnceLock<Arc<NonceGenerator>> = OnceLock::new();
+
+// 単調増加する nonce の生成
+// 同一ミリ秒内のリクエストでも重複しないよう、前回値より必ず大きい値を返す
+#[derive(Debug)]
+pub struct NonceGenerator {
+    last: AtomicU64,
+    // 再起動後も前回より大きい値から始めるため、確保済みの上限値を保存する
+    store: Option<Mutex<Store>>,
+}
+
+#[derive(Debug)]
+struct Store {
+    path: PathBuf,
+    reserved: u64,
+}
+
+impl NonceGenerator {
+    pub fn new() -> NonceGenerator {
+        NonceGenerator {
+            last: AtomicU64::new(0),
+            store: None,
+        }
+    }
+
+    // path に保存された上限値より大きい値から生成する
+    pub fn with_path(path: &Path) -> io::Result<NonceGenerator> {
+        let reserved = match fs::read_to_string(path) {
+            Ok(s) => s
+                .trim()
+                .parse::<u64>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        Ok(NonceGenerator {
+            last: AtomicU64::new(reserved),
+            store: Some(Mutex::new(Store {
+                path: path.to_path_buf(),
+                reserved,
+            })),
+        })
+    }
+
+    pub fn next(&self) -> io::Result<u64> {
+        let now = now_millis();
+        let prev = self
+            .last
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(now.max(last + 1))
+            })
+            .unwrap_or_else(|v| v);
+        let nonce = now.max(prev + 1);
+
+        if let Some(store) = &self.store {
+            let mut store = store.lock().unwrap_or_else(|e| e.into_inner());
+            if nonce > store.reserved {
+                let reserved = nonce + RESERVE_SIZE;
+                write_atomically(&store.path, &reserved.to_string())?;
+                store.reserved = reserved;
+            }
+        }
+        Ok(nonce)
+    }
+}
+
+impl Default for NonceGenerator {
+    fn default() -> Self {
+        NonceGenerator::new()
+    }
+}
+
+// プロセス全体で共有する nonce の生成
+pub fn shared() -> Arc<NonceGenerator> {
+    SHARED
+        .get_or_init(|| Arc::new(NonceGenerator::new()))
+        .clone()
+}
+
+// 上限値を path に保存する nonce の生成をプロセス全体で共有する
+// 既に初期化済みの場合はそれを返す
+pub fn init_shared(path: &Path) -> io::Result<Arc<NonceGenerator>> {
+    if let Some(generator) = SHARED.get() {
+        warn!(
+            "nonce generator is already initialized, ignore path = {}",
+            path.display()
+        );
+        return Ok(generator.clone());
+    }
+    let generator = Arc::new(NonceGenerator::with_path(path)?);
+    Ok(SHARED.get_or_init(|| generator).clone())
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// 書き込み途中で終了しても壊れないよう、一時ファイルに書いてから置き換える
+fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+    use std::thread;
+
+    #[test]
+    fn test_next_is_monotonic() {
+        let generator = Arc::new(NonceGenerator::new());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let generator = generator.clone();
+                thread::spawn(move || {
+                    (0..1000)
+                        .map(|_| generator.next().unwrap())
+                        .collect::<Vec<u64>>()
+                })
+            })
+            .collect();
+
+        let mut all = HashSet::new();
+        for h in handles {
+            let nonces = h.join().unwrap();
+            assert!(nonces.windows(2).all(|w| w[0] < w[1]));
+            all.extend(nonces);
+        }
+        assert_eq!(all.len(), 4000);
+    }
+
+    #[test]
+    fn test_with_path() {
+        let path = std::env::temp_dir().join(format!("nonce_test_{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let last = {
+            let generator = NonceGenerator::with_path(&path).unwrap();
+            let first = generator.next().unwrap();
+            let saved: u64 = fs::read_to_string(&path).unwrap().parse().unwrap();
+            assert_eq!(saved, first + RESERVE_SIZE);
+            generator.next().unwrap()
+        };
+
+        // 再起動後は保存された上限値より大きい値から始まる
+        let saved: u64 = fs::read_to_string(&path).unwrap().parse().unwrap();
+        fs::write(&path, (saved + 1_000_000).to_string()).unwrap();
+        let generator = NonceGenerator::with_path(&path).unwrap();
+        let nonce = generator.next().unwrap();
+        assert!(nonce > last);
+        assert!(nonce > saved + 1_000_000);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -25,21 +25,26 @@ pub struct OrdersPostRequest {
 
 impl OrdersPostRequest {
     pub fn new(order: &model::NewOrder) -> MyResult<OrdersPostRequest> {
-        let rate = if let Some(v) = order.rate {
-            Some(to_request_string(v))
-        } else {
-            None
+        order.validate()?;
+
+        // 注文ルールが登録されている取引ペアは、その精度で送る
+        let metadata = model::Pair::new(&order.pair)?.metadata();
+        let format_rate = |v: f64| match metadata {
+            Some(m) => m.format_rate(v),
+            None => to_request_string(v),
         };
-        let amount = if let Some(v) = order.amount {
-            Some(to_request_string(v))
-        } else {
-            None
+        let format_amount = |v: f64| match metadata {
+            Some(m) => m.format_amount(v),
+            None => to_request_string(v),
         };
+        let rate = order.rate.map(format_rate);
+        let amount = order.amount.map(format_amount);
         let market_buy_amount = if let Some(v) = order.market_buy_amount {
             Some(to_request_string(v))
         } else {
             None
         };
+        let stop_loss_rate = order.stop_loss_rate.map(format_rate);
 
         Ok(OrdersPostRequest {
             pair: order.pair.to_string(),
@@ -47,7 +52,57 @@ impl OrdersPostRequest {
             rate: rate,
             amount: amount,
             market_buy_amount: market_buy_amount,
-            stop_loss_rate: None,
+            stop_loss_rate,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::coincheck::model::{NewOrder, Pair};
+    use crate::coincheck::request::OrdersPostRequest;
+
+    #[test]
+    fn test_new_orders_post_request_with_stop_loss_rate() {
+        let pair = Pair::new("btc_jpy").unwrap();
+        let order = NewOrder::new_stop_loss_order(&pair, 3800000.0, 3762000.0, 0.01);
+        let req = OrdersPostRequest::new(&order).unwrap();
+        assert_eq!(
+            serde_json::to_string(&req).unwrap(),
+            "{\"pair\":\"btc_jpy\",\"order_type\":\"sell\",\"rate\":\"3762000\",\"amount\":\"0.01\",\"stop_loss_rate\":\"3800000\"}"
+        );
+
+        let order = NewOrder::new_sell_order(&pair, 4000000.0, 0.01);
+        let req = OrdersPostRequest::new(&order).unwrap();
+        assert!(!serde_json::to_string(&req)
+            .unwrap()
+            .contains("stop_loss_rate"));
+    }
+
+    #[test]
+    fn test_new_orders_post_request_with_pair_metadata() {
+        let pair = Pair::new("btc_jpy").unwrap();
+        // 売りのレートは切り上げ, 数量は切り捨て
+        let order = NewOrder::new_sell_order(&pair, 4000000.4, 0.012345678912);
+        let req = OrdersPostRequest::new(&order).unwrap();
+        assert_eq!(req.rate, Some("4000001".to_owned()));
+        assert_eq!(req.amount, Some("0.01234567".to_owned()));
+
+        // 買いのレートは切り下げ
+        let order = NewOrder::new_buy_order(&pair, 4000000.9, 0.01);
+        let req = OrdersPostRequest::new(&order).unwrap();
+        assert_eq!(req.rate, Some("4000000".to_owned()));
+
+        // 最小注文数量未満
+        let order = NewOrder::new_sell_order(&pair, 4000000.0, 0.001);
+        let err = OrdersPostRequest::new(&order).unwrap_err();
+        assert!(err.to_string().contains("less than minimum"));
+
+        // 注文ルールが未登録の取引ペアはそのまま送る
+        let pair = Pair::new("mona_jpy").unwrap();
+        let order = NewOrder::new_sell_order(&pair, 123.456789, 0.001);
+        let req = OrdersPostRequest::new(&order).unwrap();
+        assert_eq!(req.rate, Some("123.45".to_owned()));
+        assert_eq!(req.amount, Some("0.0010".to_owned()));
+    }
+}
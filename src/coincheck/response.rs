@@ -4,7 +4,7 @@ use crate::error::MyResult;
 
 use std::collections::HashMap;
 
-use chrono::DateTime;
+use chrono::{DateTime, TimeZone, Utc};
 use serde::Deserialize;
 
 #[derive(Deserialize, Debug)]
@@ -149,6 +149,11 @@ impl OpenOrder {
                 None
             },
             order_type: model::OrderType::parse(&self.order_type)?,
+            stop_loss_rate: if let Some(rate) = &self.stop_loss_rate {
+                Some(rate.parse()?)
+            } else {
+                None
+            },
             pair: self.pair.to_owned(),
             created_at: DateTime::parse_from_rfc3339(&self.created_at)?,
         })
@@ -251,9 +256,128 @@ impl BalanceGetResponse {
     }
 }
 
+// 約定履歴
+// GET /api/exchange/orders/transactions
+#[derive(Deserialize, Debug)]
+pub struct TransactionsGetResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    pub transactions: Vec<Transaction>,
+}
+
+// 約定履歴（ページネーション）
+// GET /api/exchange/orders/transactions_pagination
+#[derive(Deserialize, Debug)]
+pub struct TransactionsPaginationGetResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    pub data: Vec<Transaction>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Transaction {
+    pub id: u64,
+    pub order_id: u64,
+    pub created_at: String,
+    pub funds: HashMap<String, String>,
+    pub pair: String,
+    pub rate: String,
+    pub fee_currency: Option<String>,
+    pub fee: String,
+    pub liquidity: String,
+    pub side: String,
+}
+
+impl Transaction {
+    pub fn to_model(&self) -> MyResult<model::Transaction> {
+        let mut funds = HashMap::new();
+        for (currency, amount) in self.funds.iter() {
+            funds.insert(currency.to_owned(), amount.parse()?);
+        }
+        Ok(model::Transaction {
+            id: self.id,
+            order_id: self.order_id,
+            pair: model::Pair::new(&self.pair)?,
+            side: model::OrderType::parse(&self.side)?,
+            rate: self.rate.parse()?,
+            funds,
+            fee: self.fee.parse()?,
+            fee_currency: self.fee_currency.clone(),
+            liquidity: model::Liquidity::parse(&self.liquidity)?,
+            created_at: DateTime::parse_from_rfc3339(&self.created_at)?,
+        })
+    }
+}
+
+// 取引履歴
+// GET /api/trades
+#[derive(Deserialize, Debug)]
+pub struct TradesGetResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    pub data: Vec<Trade>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Trade {
+    pub id: u64,
+    pub amount: String,
+    pub rate: String,
+    pub pair: String,
+    pub order_type: String,
+    pub created_at: String,
+}
+
+impl Trade {
+    pub fn to_model(&self) -> MyResult<model::Trade> {
+        Ok(model::Trade {
+            id: self.id,
+            pair: model::Pair::new(&self.pair)?,
+            order_type: model::OrderType::parse(&self.order_type)?,
+            rate: self.rate.parse()?,
+            amount: self.amount.parse()?,
+            created_at: DateTime::parse_from_rfc3339(&self.created_at)?,
+        })
+    }
+}
+
+// ティッカー
+// GET /api/ticker
+#[derive(Deserialize, Debug)]
+pub struct TickerGetResponse {
+    pub last: f64,
+    pub bid: f64,
+    pub ask: f64,
+    pub high: f64,
+    pub low: f64,
+    pub volume: String,
+    pub timestamp: i64,
+}
+
+impl TickerGetResponse {
+    pub fn to_model(&self) -> MyResult<model::Ticker> {
+        let timestamp = Utc
+            .timestamp_opt(self.timestamp, 0)
+            .single()
+            .ok_or_else(|| format!("timestamp is invalid, {}", self.timestamp))?;
+        Ok(model::Ticker {
+            last: self.last,
+            bid: self.bid,
+            ask: self.ask,
+            high: self.high,
+            low: self.low,
+            volume: self.volume.parse()?,
+            timestamp,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::coincheck::response::OrdersPostResponse;
+    use crate::coincheck::model::{Liquidity, OrderType};
+    use crate::coincheck::response::{
+        OrdersPostResponse, TickerGetResponse, TradesGetResponse, TransactionsGetResponse,
+    };
 
     #[test]
     fn test_deserialize_orders_post_response_1() {
@@ -292,4 +416,41 @@ mod tests {
         };
         assert_eq!(get, want);
     }
+
+    #[test]
+    fn test_deserialize_transactions_get_response() {
+        let body = "{\"success\":true,\"transactions\":[{\"id\":38,\"order_id\":49,\"created_at\":\"2015-11-18T07:02:21.000Z\",\"funds\":{\"btc\":\"0.1\",\"jpy\":\"-4096.135\"},\"pair\":\"btc_jpy\",\"rate\":\"40900.0\",\"fee_currency\":\"JPY\",\"fee\":\"6.135\",\"liquidity\":\"T\",\"side\":\"buy\"}]}";
+        let res = serde_json::from_str::<TransactionsGetResponse>(body).unwrap();
+        let got = res.transactions[0].to_model().unwrap();
+        assert_eq!(got.id, 38);
+        assert_eq!(got.order_id, 49);
+        assert_eq!(got.side, OrderType::Buy);
+        assert_eq!(got.rate, 40900.0);
+        assert_eq!(got.amount(), 0.1);
+        assert_eq!(got.funds.get("jpy"), Some(&-4096.135));
+        assert_eq!(got.fee, 6.135);
+        assert_eq!(got.liquidity, Liquidity::Taker);
+    }
+
+    #[test]
+    fn test_deserialize_trades_get_response() {
+        let body = "{\"success\":true,\"pagination\":{\"limit\":1,\"order\":\"desc\",\"starting_after\":null,\"ending_before\":null},\"data\":[{\"id\":82,\"amount\":\"0.28391\",\"rate\":\"35400.0\",\"pair\":\"btc_jpy\",\"order_type\":\"sell\",\"created_at\":\"2015-01-10T05:55:38.000Z\"}]}";
+        let res = serde_json::from_str::<TradesGetResponse>(body).unwrap();
+        let got = res.data[0].to_model().unwrap();
+        assert_eq!(got.id, 82);
+        assert_eq!(got.order_type, OrderType::Sell);
+        assert_eq!(got.rate, 35400.0);
+        assert_eq!(got.amount, 0.28391);
+    }
+
+    #[test]
+    fn test_deserialize_ticker_get_response() {
+        let body = "{\"last\":27390,\"bid\":26900,\"ask\":27390,\"high\":27659,\"low\":26400,\"volume\":\"50.29627103\",\"timestamp\":1423377841}";
+        let res = serde_json::from_str::<TickerGetResponse>(body).unwrap();
+        let got = res.to_model().unwrap();
+        assert_eq!(got.last, 27390.0);
+        assert_eq!(got.bid, 26900.0);
+        assert_eq!(got.volume, 50.29627103);
+        assert_eq!(got.timestamp.timestamp(), 1423377841);
+    }
 }
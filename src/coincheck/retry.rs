@@ -0,0 +1,220 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use tokio::time::Instant;
+
+// 一時的なエラーとして再試行する取引所のエラーメッセージ
+const TRANSIENT_ERROR_MESSAGES: [&str; 3] = [
+    "Nonce must be incremented",
+    "too many requests",
+    "temporarily unavailable",
+];
+
+// 再試行の方針（ジッター付き指数バックオフ）
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    // 最大再試行回数（初回リクエストは含まない）
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // attempt 回目（1始まり）の再試行までの待ち時間
+    // 上限 base_delay * 2^(attempt-1) の半分を固定で待ち、残り半分をジッターとする
+    // jitter には 0.0〜1.0 の乱数を渡す
+    pub fn backoff(&self, attempt: u32, jitter: f64) -> Duration {
+        let exp = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let cap = self.base_delay.saturating_mul(exp).min(self.max_delay);
+        let half = cap / 2;
+        half + half.mul_f64(jitter.clamp(0.0, 1.0))
+    }
+
+    pub fn backoff_with_random_jitter(&self, attempt: u32) -> Duration {
+        self.backoff(attempt, rand::random::<f64>())
+    }
+}
+
+// 再試行すべきHTTPステータスか（429, 5xx）
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+// 再試行すべき通信エラーか
+// idempotent でないリクエスト（注文, キャンセル）は、送信されていないことが確実な接続エラーのみ再試行する
+pub fn is_retryable_error(err: &reqwest::Error, idempotent: bool) -> bool {
+    if err.is_connect() {
+        return true;
+    }
+    idempotent && (err.is_timeout() || err.is_request())
+}
+
+// 再試行すべき取引所のエラーメッセージか
+pub fn is_transient_error_message(message: &str) -> bool {
+    let message = message.to_lowercase();
+    TRANSIENT_ERROR_MESSAGES
+        .iter()
+        .any(|m| message.contains(&m.to_lowercase()))
+}
+
+// トークンバケットによるリクエスト数の制限
+#[derive(Debug)]
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    // capacity: 連続で送信できる最大数, refill_per_sec: 1秒あたりに回復する数
+    pub fn new(capacity: u32, refill_per_sec: f64) -> RateLimiter {
+        RateLimiter {
+            capacity: capacity as f64,
+            refill_per_sec,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity as f64,
+                updated_at: Instant::now(),
+            }),
+        }
+    }
+
+    // トークンを1つ取得する（なければ回復するまで待つ）
+    pub async fn acquire(&self) {
+        loop {
+            match self.try_acquire(Instant::now()) {
+                Ok(_) => return,
+                Err(wait) => tokio::time::sleep(wait).await,
+            }
+        }
+    }
+
+    // トークンを取得できなければ、回復までの待ち時間を返す
+    pub fn try_acquire(&self, now: Instant) -> Result<(), Duration> {
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        bucket.tokens =
+            (bucket.tokens + elapsed.as_secs_f64() * self.refill_per_sec).min(self.capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / self.refill_per_sec;
+            Err(Duration::from_secs_f64(wait))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+        };
+        struct Param {
+            attempt: u32,
+            jitter: f64,
+            want: u64,
+        }
+        let params = [
+            Param {
+                attempt: 1,
+                jitter: 0.0,
+                want: 50,
+            },
+            Param {
+                attempt: 1,
+                jitter: 1.0,
+                want: 100,
+            },
+            Param {
+                attempt: 2,
+                jitter: 0.5,
+                want: 150,
+            },
+            Param {
+                attempt: 3,
+                jitter: 0.0,
+                want: 200,
+            },
+            Param {
+                attempt: 5,
+                jitter: 1.0,
+                want: 1000,
+            },
+            Param {
+                attempt: 40,
+                jitter: 0.0,
+                want: 500,
+            },
+        ];
+        for p in params.iter() {
+            assert_eq!(
+                policy.backoff(p.attempt, p.jitter),
+                Duration::from_millis(p.want),
+                "attempt:{}, jitter:{}, failure",
+                p.attempt,
+                p.jitter
+            );
+        }
+    }
+
+    #[test]
+    fn test_is_retryable_status() {
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable_status(StatusCode::BAD_GATEWAY));
+        assert!(!is_retryable_status(StatusCode::OK));
+        assert!(!is_retryable_status(StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn test_is_transient_error_message() {
+        assert!(is_transient_error_message("Nonce must be incremented"));
+        assert!(is_transient_error_message("Too Many Requests"));
+        assert!(!is_transient_error_message(
+            "Amount 0.001 is less than minimum"
+        ));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2, 4.0);
+        let now = Instant::now();
+        assert!(limiter.try_acquire(now).is_ok());
+        assert!(limiter.try_acquire(now).is_ok());
+        assert_eq!(limiter.try_acquire(now), Err(Duration::from_millis(250)));
+
+        let later = now + Duration::from_millis(250);
+        assert!(limiter.try_acquire(later).is_ok());
+        assert!(limiter.try_acquire(later).is_err());
+
+        // 回復は最大数まで
+        let much_later = later + Duration::from_secs(10);
+        assert!(limiter.try_acquire(much_later).is_ok());
+        assert!(limiter.try_acquire(much_later).is_ok());
+        assert!(limiter.try_acquire(much_later).is_err());
+    }
+}
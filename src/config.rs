@@ -1,6 +1,10 @@
-use serde::Deserialize;
+use crate::coincheck::model::FeeRate;
+use crate::notifier::model::{Channel, Event};
 
-#[derive(Deserialize, Debug, PartialEq)]
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct Config {
     // ボット名
     pub bot_name: String,
@@ -8,12 +12,21 @@ pub struct Config {
     pub target_pair: String,
     // 定期実行間隔（秒）
     pub interval_sec: u64,
+    // 取引が長引いて次の実行時刻を過ぎた場合の扱い（skip: 過ぎた分は飛ばす, catch_up: 続けて実行する）
+    #[serde(default)]
+    pub overrun_policy: OverrunPolicy,
+    // 定期実行の処理（cron 式）の時刻のタイムゾーン（UTCからの時差, 時間）
+    #[serde(default)]
+    pub schedule_utc_offset_hours: i32,
     // レート取得期間
     pub rate_period_minutes: i64,
     // 外部サービスの処理待ち間隔（秒）
     pub external_service_wait_interval_sec: u64,
     // デモモード（有効にすると注文を出さない）
     pub demo_mode: bool,
+    // 終了時に未約定の注文をキャンセルする（無効なら注文を残したまま終了する）
+    #[serde(default)]
+    pub cancel_open_orders_on_shutdown: bool,
 
     // 加重移動平均の期間（短期）
     pub wma_period_short: usize,
@@ -55,6 +68,11 @@ pub struct Config {
     // 利益最大化のため指値売注文は目標より高いレート
     // （例）本設定値を0.1にしたら目標レートの1.01倍で指値売注文
     pub offset_sell_rate_ratio: f64,
+    // 取引所の手数料率（取引ペア毎にメイカー, テイカーの順で約定金額に対する割合を指定）
+    // 目標利益率は手数料を差し引いた利益に対して判定する（未指定の取引ペアは手数料なし）
+    // 例: FEE_RATES=btc_jpy:0.0:0.0,etc_jpy:0.0005:0.001
+    #[serde(default, deserialize_with = "deserialize_fee_rates")]
+    pub fee_rates: HashMap<String, FeeRate>,
 
     // ポジション保有期間の最大時間（分）
     pub hold_limit_minutes: i64,
@@ -65,6 +83,22 @@ pub struct Config {
 
     // 損切り基準レート（約定待ちレートに対する割合を指定）
     pub loss_cut_rate_ratio: f64,
+    // 取引所側の逆指値注文で損切りする場合の逆指値レート（建値に対する割合を指定）
+    // 指定するとエントリー時に利確の指値注文の代わりに逆指値注文を出し、利確は成行で行う
+    #[serde(default)]
+    pub stop_loss_rate_ratio: Option<f64>,
+    // 逆指値注文の指値レートを逆指値レートより下げる幅（逆指値レートに対する割合を指定）
+    // 急落時に逆指値に達した後も約定するようにする
+    #[serde(default = "default_stop_loss_slippage_ratio")]
+    pub stop_loss_slippage_ratio: f64,
+    // 利確をトレーリングする場合の、最高値から売るまでの下げ幅（取引ペア毎に最高値に対する割合を指定）
+    // 例: TRAILING_TAKE_PROFIT_RATIOS=btc_jpy:0.005,mona_jpy:0.01
+    #[serde(default, deserialize_with = "deserialize_pair_ratios")]
+    pub trailing_take_profit_ratios: HashMap<String, f64>,
+    // トレーリングで売注文を引き上げる最小幅（現在の売注文のレートに対する割合を指定）
+    // 小刻みな上昇のたびに注文を出し直さないようにする
+    #[serde(default = "default_trailing_step_ratio")]
+    pub trailing_step_ratio: f64,
     // スキップ基準レート（約定待ちレートに対する割合を指定）
     pub entry_skip_rate_ratio: f64,
     // 売られすぎと判断する売り出来高しきい値（昨日の合計出来高に対する割合を指定）
@@ -74,20 +108,113 @@ pub struct Config {
 
     // 最低限残すロット数
     pub keep_lot: f64,
+    // 売注文のないコイン（成行買いの後に停止した場合など）に、取得レートから求めた売注文を出す
+    #[serde(default)]
+    pub recover_orphaned_coin: bool,
 
     // 取引所関連
     pub exchange_access_key: String,
     pub exchange_secret_key: String,
+    // 接続先（スタブサーバーや記録用プロキシに向ける場合に変更）
+    #[serde(default = "default_exchange_base_url")]
+    pub exchange_base_url: String,
+    // リクエストのタイムアウト（秒, 未指定なら無制限）
+    #[serde(default)]
+    pub exchange_timeout_sec: Option<u64>,
+    // 接続のタイムアウト（秒, 未指定なら無制限）
+    #[serde(default)]
+    pub exchange_connect_timeout_sec: Option<u64>,
+    // プロキシのURL
+    #[serde(default)]
+    pub exchange_proxy: Option<String>,
+    // User-Agent ヘッダー
+    #[serde(default)]
+    pub exchange_user_agent: Option<String>,
+    // nonce の上限値を保存するファイル（再起動後も前回より大きい nonce を使うため）
+    #[serde(default)]
+    pub exchange_nonce_path: Option<String>,
 
     // DB関連
+    // 使用するDB（mysql or sqlite）
+    #[serde(default)]
+    pub db_type: DbType,
+    // SQLiteのDBファイルパス（db_type=sqlite の場合のみ使用）
+    #[serde(default = "default_db_path")]
+    pub db_path: String,
+    // MySQLの接続情報（db_type=mysql の場合のみ使用）
+    #[serde(default)]
     pub db_host: String,
+    #[serde(default)]
     pub db_port: u16,
+    #[serde(default)]
     pub db_name: String,
+    #[serde(default)]
     pub db_user_name: String,
+    #[serde(default)]
     pub db_password: String,
 
     // Slack関連
     pub slack_url: String,
+    // 通知に付けるリンク（{pair} は取引ペアに置き換える, 例: チャートのURL）
+    #[serde(default)]
+    pub slack_link_url: Option<String>,
+    // スラッシュコマンドを受け付けるアドレス（例: 0.0.0.0:3000, 未指定なら受け付けない）
+    #[serde(default)]
+    pub slack_command_addr: Option<String>,
+    // スラッシュコマンドのリクエストの署名の検証に使う Signing Secret
+    #[serde(default)]
+    pub slack_signing_secret: Option<String>,
+
+    // 監視関連
+    // /health, /status, /metrics を受け付けるアドレス（例: 0.0.0.0:9100, 未指定なら受け付けない）
+    #[serde(default)]
+    pub monitor_addr: Option<String>,
+
+    // 通知関連
+    // Discord の Webhook URL（未指定なら通知しない）
+    #[serde(default)]
+    pub discord_webhook_url: Option<String>,
+    // LINE Notify のアクセストークン（未指定なら通知しない）
+    #[serde(default)]
+    pub line_notify_token: Option<String>,
+    // メール送信に使う SMTP サーバー（未指定なら通知しない）
+    #[serde(default)]
+    pub smtp_host: Option<String>,
+    #[serde(default = "default_smtp_port")]
+    pub smtp_port: u16,
+    // 接続の暗号化（none, starttls, tls）
+    #[serde(default)]
+    pub smtp_security: SmtpSecurity,
+    #[serde(default)]
+    pub smtp_username: Option<String>,
+    #[serde(default)]
+    pub smtp_password: Option<String>,
+    #[serde(default)]
+    pub smtp_from: String,
+    // 宛先（カンマ区切り）
+    #[serde(default)]
+    pub smtp_to: Vec<String>,
+    // 通知の種類毎の送り先（指定しない種類は設定済みの全ての送り先に送る）
+    // 例: NOTIFY_ROUTES=loss_cut:slack|discord|line|email,entry:slack
+    #[serde(default, deserialize_with = "deserialize_notify_routes")]
+    pub notify_routes: HashMap<Event, Vec<Channel>>,
+    // 同じ内容の通知を送らない期間（秒, 0なら重複しても送る）
+    #[serde(default = "default_notify_dedup_window_sec")]
+    pub notify_dedup_window_sec: u64,
+    // まとめて送る通知の種類（カンマ区切り, 例: unused_coin,in_flight）
+    #[serde(default, deserialize_with = "deserialize_events")]
+    pub notify_digest_events: Vec<Event>,
+    // まとめた通知を送る間隔（秒）
+    #[serde(default = "default_notify_digest_interval_sec")]
+    pub notify_digest_interval_sec: u64,
+
+    // レポート関連
+    // 日次レポートを送る時刻（cron 式, 未指定なら送らない, 例: 0 0 * * *）
+    #[serde(default)]
+    pub report_daily_cron: Option<String>,
+    // 週次レポートを送る時刻（cron 式, 未指定なら送らない, 例: 0 0 * * 1）
+    #[serde(default)]
+    pub report_weekly_cron: Option<String>,
 }
 
 impl Config {
@@ -99,4 +226,234 @@ impl Config {
         let splited: Vec<&str> = self.target_pair.split('_').collect();
         splited[1].to_string()
     }
+    // 取引ペアの手数料率
+    pub fn fee_rate(&self, pair: &str) -> FeeRate {
+        self.fee_rates.get(pair).copied().unwrap_or_default()
+    }
+    // トレーリングの下げ幅（未指定の取引ペアはトレーリングしない）
+    pub fn trailing_take_profit_ratio(&self, pair: &str) -> Option<f64> {
+        self.trailing_take_profit_ratios.get(pair).copied()
+    }
+}
+
+// "pair:ratio,pair:ratio" 形式の設定を読み込む
+fn deserialize_pair_ratios<'de, D>(deserializer: D) -> Result<HashMap<String, f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    parse_pair_ratios(&s).map_err(serde::de::Error::custom)
+}
+
+fn parse_pair_ratios(s: &str) -> Result<HashMap<String, f64>, String> {
+    let mut map = HashMap::new();
+    for item in s.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+        let (pair, ratio) = item
+            .split_once(':')
+            .ok_or_else(|| format!("invalid format, want pair:ratio, got {}", item))?;
+        let ratio: f64 = ratio
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid ratio, {}, {}", item, e))?;
+        if ratio <= 0.0 || ratio >= 1.0 {
+            return Err(format!("ratio must be between 0 and 1, {}", item));
+        }
+        map.insert(pair.trim().to_owned(), ratio);
+    }
+    Ok(map)
+}
+
+// "pair:maker:taker,pair:maker:taker" 形式の設定を読み込む
+fn deserialize_fee_rates<'de, D>(deserializer: D) -> Result<HashMap<String, FeeRate>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    parse_fee_rates(&s).map_err(serde::de::Error::custom)
+}
+
+fn parse_fee_rates(s: &str) -> Result<HashMap<String, FeeRate>, String> {
+    let mut map = HashMap::new();
+    for item in s.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+        let values: Vec<&str> = item.split(':').map(|v| v.trim()).collect();
+        if values.len() != 3 {
+            return Err(format!(
+                "invalid format, want pair:maker:taker, got {}",
+                item
+            ));
+        }
+        let parse = |v: &str| -> Result<f64, String> {
+            let ratio: f64 = v
+                .parse()
+                .map_err(|e| format!("invalid fee rate, {}, {}", item, e))?;
+            // リベート（マイナス）は許容する
+            if ratio.abs() >= 1.0 {
+                return Err(format!("fee rate must be between -1 and 1, {}", item));
+            }
+            Ok(ratio)
+        };
+        map.insert(
+            values[0].to_owned(),
+            FeeRate {
+                maker: parse(values[1])?,
+                taker: parse(values[2])?,
+            },
+        );
+    }
+    Ok(map)
+}
+
+// "event:channel|channel,event:channel" 形式の設定を読み込む
+fn deserialize_notify_routes<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<Event, Vec<Channel>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    parse_notify_routes(&s).map_err(serde::de::Error::custom)
+}
+
+fn parse_notify_routes(s: &str) -> Result<HashMap<Event, Vec<Channel>>, String> {
+    let mut map = HashMap::new();
+    for item in s.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+        let (event, channels) = item
+            .split_once(':')
+            .ok_or_else(|| format!("invalid format, want event:channel|channel, got {}", item))?;
+        let channels = channels
+            .split('|')
+            .filter(|v| !v.trim().is_empty())
+            .map(Channel::parse)
+            .collect::<Result<Vec<Channel>, String>>()?;
+        map.insert(Event::parse(event)?, channels);
+    }
+    Ok(map)
+}
+
+// "event,event" 形式の設定を読み込む
+fn deserialize_events<'de, D>(deserializer: D) -> Result<Vec<Event>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    s.split(',')
+        .filter(|v| !v.trim().is_empty())
+        .map(Event::parse)
+        .collect::<Result<Vec<Event>, String>>()
+        .map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pair_ratios() {
+        let got = parse_pair_ratios("btc_jpy:0.005, mona_jpy:0.01").unwrap();
+        assert_eq!(got.len(), 2);
+        assert_eq!(got.get("btc_jpy"), Some(&0.005));
+        assert_eq!(got.get("mona_jpy"), Some(&0.01));
+
+        assert!(parse_pair_ratios("").unwrap().is_empty());
+        assert!(parse_pair_ratios("btc_jpy").is_err());
+        assert!(parse_pair_ratios("btc_jpy:abc").is_err());
+        assert!(parse_pair_ratios("btc_jpy:1.5").is_err());
+    }
+
+    #[test]
+    fn test_parse_fee_rates() {
+        let got = parse_fee_rates("btc_jpy:0.0:0.0, etc_jpy:-0.0005:0.001").unwrap();
+        assert_eq!(got.len(), 2);
+        assert_eq!(
+            got.get("etc_jpy"),
+            Some(&FeeRate {
+                maker: -0.0005,
+                taker: 0.001,
+            })
+        );
+
+        assert!(parse_fee_rates("").unwrap().is_empty());
+        assert!(parse_fee_rates("btc_jpy:0.0").is_err());
+        assert!(parse_fee_rates("btc_jpy:0.0:abc").is_err());
+        assert!(parse_fee_rates("btc_jpy:0.0:1.0").is_err());
+    }
+
+    #[test]
+    fn test_parse_notify_routes() {
+        let got = parse_notify_routes("loss_cut:slack|discord|line|email, entry:slack").unwrap();
+        assert_eq!(got.len(), 2);
+        assert_eq!(
+            got.get(&Event::LossCut),
+            Some(&vec![
+                Channel::Slack,
+                Channel::Discord,
+                Channel::Line,
+                Channel::Email
+            ])
+        );
+        assert_eq!(got.get(&Event::Entry), Some(&vec![Channel::Slack]));
+
+        // 送り先なし（通知しない）
+        assert_eq!(
+            parse_notify_routes("sell:").unwrap().get(&Event::Sell),
+            Some(&vec![])
+        );
+        assert!(parse_notify_routes("").unwrap().is_empty());
+        assert!(parse_notify_routes("entry").is_err());
+        assert!(parse_notify_routes("entry:teams").is_err());
+        assert!(parse_notify_routes("unknown:slack").is_err());
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DbType {
+    #[default]
+    Mysql,
+    Sqlite,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OverrunPolicy {
+    #[default]
+    Skip,
+    CatchUp,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    None,
+    #[default]
+    Starttls,
+    Tls,
+}
+
+fn default_stop_loss_slippage_ratio() -> f64 {
+    0.01
+}
+
+fn default_trailing_step_ratio() -> f64 {
+    0.001
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_notify_dedup_window_sec() -> u64 {
+    60 * 60
+}
+
+fn default_notify_digest_interval_sec() -> u64 {
+    60 * 60
+}
+
+fn default_exchange_base_url() -> String {
+    "https://coincheck.com".to_owned()
+}
+
+fn default_db_path() -> String {
+    "trading_bot.sqlite3".to_owned()
 }
@@ -22,6 +22,13 @@ pub enum MyError {
         request: String,
     },
 
+    #[error("http status is error, status:{}, url:{}, body:{}", status, url, body)]
+    HttpStatusError {
+        status: u16,
+        url: String,
+        body: String,
+    },
+
     #[error("{} not found in {}", key, collection_name)]
     KeyNotFound {
         key: String,
@@ -30,6 +37,18 @@ pub enum MyError {
 
     #[error("{0} is empty")]
     EmptyCollection(String),
+
+    #[error("invalid order for {}, {}", pair, message)]
+    InvalidOrder { pair: String, message: String },
+
+    #[error("failed to get lock {0}")]
+    LockFailed(String),
+
+    #[error("order result is unresolved, {0}")]
+    UnresolvedOrder(String),
+
+    #[error("in flight action remains, {}", action_type)]
+    InFlightActionRemains { action_type: String },
 }
 
 pub type MyResult<T> = Result<T, Box<dyn Error>>;
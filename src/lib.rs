@@ -2,8 +2,11 @@ pub mod bot;
 pub mod coincheck;
 pub mod config;
 pub mod error;
+pub mod monitor;
 pub mod mysql;
+pub mod notifier;
 pub mod simulator;
 pub mod slack;
+pub mod sqlite;
 pub mod strategy;
 pub mod util;
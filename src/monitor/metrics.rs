@@ -0,0 +1,430 @@
+use crate::coincheck::model::{Balance, OpenOrder};
+use crate::error::MyError;
+
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+static SHARED: OnceLock<Arc<Metrics>> = OnceLock::new();
+
+// 取引の処理時間のヒストグラムの区切り（秒）
+const CYCLE_DURATION_BUCKETS: [f64; 8] = [0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+// 直近の取引で取得した状態（/status, 残高のメトリクスに使う）
+#[derive(Debug, Clone)]
+pub struct TradeStatus {
+    pub pair: String,
+    pub sell_rate: f64,
+    pub buy_rate: f64,
+    // (k,v)=(coin,balance)
+    pub balances: HashMap<String, Balance>,
+    pub open_orders: Vec<OpenOrder>,
+    pub support_line_long: Option<f64>,
+    pub support_line_short: Option<f64>,
+    pub resistance_line: Option<f64>,
+    // up, down, flat
+    pub long_trend: String,
+    pub total_balance_jpy: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+// 稼働状況の計測値
+// 取引所のクライアントなどからも記録するため、プロセス全体で共有する（shared）
+#[derive(Debug)]
+pub struct Metrics {
+    started_at: DateTime<Utc>,
+    state: Mutex<MetricsState>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct MetricsState {
+    // CYCLE_DURATION_BUCKETS 毎の件数（区切り以下の処理時間の件数）
+    pub cycle_buckets: Vec<u64>,
+    pub cycle_duration_sum: f64,
+    pub cycle_success_count: u64,
+    pub cycle_failure_count: u64,
+    pub last_cycle_at: Option<DateTime<Utc>>,
+    pub last_cycle_duration_sec: Option<f64>,
+    // (エンドポイント, エラーの種類) => 件数
+    pub api_errors: BTreeMap<(String, String), u64>,
+    // (アクションの種類, 結果) => 件数
+    pub actions: BTreeMap<(String, String), u64>,
+    pub status: Option<TradeStatus>,
+}
+
+impl Metrics {
+    pub fn new(now: &DateTime<Utc>) -> Metrics {
+        Metrics {
+            started_at: *now,
+            state: Mutex::new(MetricsState {
+                cycle_buckets: vec![0; CYCLE_DURATION_BUCKETS.len()],
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn started_at(&self) -> DateTime<Utc> {
+        self.started_at
+    }
+
+    pub fn snapshot(&self) -> MetricsState {
+        self.lock().clone()
+    }
+
+    pub fn record_cycle(&self, now: &DateTime<Utc>, duration_sec: f64, success: bool) {
+        let mut state = self.lock();
+        for (i, le) in CYCLE_DURATION_BUCKETS.iter().enumerate() {
+            if duration_sec <= *le {
+                state.cycle_buckets[i] += 1;
+            }
+        }
+        state.cycle_duration_sum += duration_sec;
+        if success {
+            state.cycle_success_count += 1;
+        } else {
+            state.cycle_failure_count += 1;
+        }
+        state.last_cycle_at = Some(*now);
+        state.last_cycle_duration_sec = Some(duration_sec);
+    }
+
+    pub fn record_api_error(&self, endpoint: &str, kind: &str) {
+        *self
+            .lock()
+            .api_errors
+            .entry((endpoint.to_owned(), kind.to_owned()))
+            .or_default() += 1;
+    }
+
+    pub fn record_action(&self, action: &str, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        *self
+            .lock()
+            .actions
+            .entry((action.to_owned(), result.to_owned()))
+            .or_default() += 1;
+    }
+
+    pub fn record_status(&self, status: TradeStatus) {
+        self.lock().status = Some(status);
+    }
+
+    // Prometheus のテキスト形式で出力する
+    // 複数のボットを区別できるよう、全ての値に bot ラベルを付ける
+    pub fn render(&self, bot_name: &str, paused: bool, demo_mode: bool) -> String {
+        let state = self.snapshot();
+        let bot = format!("bot=\"{}\"", escape_label(bot_name));
+        let mut out = String::new();
+
+        write_header(
+            &mut out,
+            "trading_bot_up_since_seconds",
+            "gauge",
+            "start time of the bot",
+        );
+        write_value(
+            &mut out,
+            "trading_bot_up_since_seconds",
+            &bot,
+            self.started_at.timestamp() as f64,
+        );
+        write_header(&mut out, "trading_bot_paused", "gauge", "1 if paused");
+        write_value(&mut out, "trading_bot_paused", &bot, bool_value(paused));
+        write_header(&mut out, "trading_bot_demo_mode", "gauge", "1 if demo mode");
+        write_value(
+            &mut out,
+            "trading_bot_demo_mode",
+            &bot,
+            bool_value(demo_mode),
+        );
+
+        write_header(
+            &mut out,
+            "trading_bot_cycle_duration_seconds",
+            "histogram",
+            "duration of trade cycles",
+        );
+        for (le, count) in CYCLE_DURATION_BUCKETS
+            .iter()
+            .zip(state.cycle_buckets.iter())
+        {
+            write_value(
+                &mut out,
+                "trading_bot_cycle_duration_seconds_bucket",
+                &format!("{},le=\"{}\"", bot, le),
+                *count as f64,
+            );
+        }
+        let cycle_count = state.cycle_success_count + state.cycle_failure_count;
+        write_value(
+            &mut out,
+            "trading_bot_cycle_duration_seconds_bucket",
+            &format!("{},le=\"+Inf\"", bot),
+            cycle_count as f64,
+        );
+        write_value(
+            &mut out,
+            "trading_bot_cycle_duration_seconds_sum",
+            &bot,
+            state.cycle_duration_sum,
+        );
+        write_value(
+            &mut out,
+            "trading_bot_cycle_duration_seconds_count",
+            &bot,
+            cycle_count as f64,
+        );
+
+        write_header(
+            &mut out,
+            "trading_bot_cycles_total",
+            "counter",
+            "trade cycles by result",
+        );
+        for (result, count) in [
+            ("success", state.cycle_success_count),
+            ("failure", state.cycle_failure_count),
+        ] {
+            write_value(
+                &mut out,
+                "trading_bot_cycles_total",
+                &format!("{},result=\"{}\"", bot, result),
+                count as f64,
+            );
+        }
+        if let Some(t) = state.last_cycle_at {
+            write_header(
+                &mut out,
+                "trading_bot_last_cycle_timestamp_seconds",
+                "gauge",
+                "time of the last trade cycle",
+            );
+            write_value(
+                &mut out,
+                "trading_bot_last_cycle_timestamp_seconds",
+                &bot,
+                t.timestamp() as f64,
+            );
+        }
+
+        write_header(
+            &mut out,
+            "trading_bot_api_errors_total",
+            "counter",
+            "exchange api errors by endpoint and kind",
+        );
+        for ((endpoint, kind), count) in state.api_errors.iter() {
+            write_value(
+                &mut out,
+                "trading_bot_api_errors_total",
+                &format!(
+                    "{},endpoint=\"{}\",kind=\"{}\"",
+                    bot,
+                    escape_label(endpoint),
+                    kind
+                ),
+                *count as f64,
+            );
+        }
+
+        write_header(
+            &mut out,
+            "trading_bot_actions_total",
+            "counter",
+            "actions by type and result",
+        );
+        for ((action, result), count) in state.actions.iter() {
+            write_value(
+                &mut out,
+                "trading_bot_actions_total",
+                &format!("{},action=\"{}\",result=\"{}\"", bot, action, result),
+                *count as f64,
+            );
+        }
+
+        if let Some(s) = &state.status {
+            let pair = format!("{},pair=\"{}\"", bot, escape_label(&s.pair));
+            write_header(
+                &mut out,
+                "trading_bot_balance",
+                "gauge",
+                "balance by currency",
+            );
+            let balances: BTreeMap<&String, &Balance> = s.balances.iter().collect();
+            for (currency, balance) in balances {
+                for (kind, v) in [("amount", balance.amount), ("reserved", balance.reserved)] {
+                    write_value(
+                        &mut out,
+                        "trading_bot_balance",
+                        &format!(
+                            "{},currency=\"{}\",kind=\"{}\"",
+                            bot,
+                            escape_label(currency),
+                            kind
+                        ),
+                        v,
+                    );
+                }
+            }
+            write_header(
+                &mut out,
+                "trading_bot_total_balance_jpy",
+                "gauge",
+                "total balance in jpy",
+            );
+            write_value(
+                &mut out,
+                "trading_bot_total_balance_jpy",
+                &bot,
+                s.total_balance_jpy,
+            );
+            write_header(&mut out, "trading_bot_rate", "gauge", "sell and buy rate");
+            write_value(
+                &mut out,
+                "trading_bot_rate",
+                &format!("{},side=\"sell\"", pair),
+                s.sell_rate,
+            );
+            write_value(
+                &mut out,
+                "trading_bot_rate",
+                &format!("{},side=\"buy\"", pair),
+                s.buy_rate,
+            );
+            write_header(
+                &mut out,
+                "trading_bot_open_orders",
+                "gauge",
+                "number of open orders",
+            );
+            write_value(
+                &mut out,
+                "trading_bot_open_orders",
+                &pair,
+                s.open_orders.len() as f64,
+            );
+        }
+        out
+    }
+
+    // 記録中にパニックしても、計測値は壊れないため使い続ける
+    fn lock(&self) -> MutexGuard<'_, MetricsState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// プロセス全体で共有する計測値
+pub fn shared() -> Arc<Metrics> {
+    SHARED
+        .get_or_init(|| Arc::new(Metrics::new(&Utc::now())))
+        .clone()
+}
+
+// 取引所の API のエラーの種類
+pub fn error_kind(err: &(dyn Error + 'static)) -> &'static str {
+    if let Some(e) = err.downcast_ref::<MyError>() {
+        return match e {
+            MyError::HttpStatusError { .. } => "http_status",
+            MyError::ResponseError { .. } => "response",
+            MyError::ParseError(_) => "parse",
+            _ => "other",
+        };
+    }
+    if let Some(e) = err.downcast_ref::<reqwest::Error>() {
+        return if e.is_timeout() { "timeout" } else { "network" };
+    }
+    "other"
+}
+
+// URL から接続先, クエリを除き、ID（数字のみの部分）を :id に置き換える（値の種類を増やさないため）
+pub fn endpoint(base_url: &str, url: &str) -> String {
+    let path = url.strip_prefix(base_url).unwrap_or(url);
+    let path = path.split('?').next().unwrap_or_default();
+    path.split('/')
+        .map(|v| {
+            if !v.is_empty() && v.chars().all(|c| c.is_ascii_digit()) {
+                ":id"
+            } else {
+                v
+            }
+        })
+        .collect::<Vec<&str>>()
+        .join("/")
+}
+
+fn write_header(out: &mut String, name: &str, t: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, t);
+}
+
+fn write_value(out: &mut String, name: &str, labels: &str, value: f64) {
+    let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+}
+
+fn escape_label(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn bool_value(v: bool) -> f64 {
+    if v {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint() {
+        let base = "https://coincheck.com";
+        assert_eq!(
+            endpoint(base, "https://coincheck.com/api/exchange/orders/123"),
+            "/api/exchange/orders/:id"
+        );
+        assert_eq!(
+            endpoint(
+                base,
+                "https://coincheck.com/api/exchange/orders/cancel_status?id=123"
+            ),
+            "/api/exchange/orders/cancel_status"
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let now = DateTime::parse_from_rfc3339("2021-05-16T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let metrics = Metrics::new(&now);
+        metrics.record_cycle(&now, 0.8, true);
+        metrics.record_cycle(&now, 3.0, false);
+        metrics.record_api_error("/api/exchange/orders", "http_status");
+        metrics.record_api_error("/api/exchange/orders", "http_status");
+        metrics.record_action("loss_cut", true);
+
+        let got = metrics.render("bot\"1", true, false);
+        let lines: Vec<&str> = got.lines().collect();
+        for want in [
+            "trading_bot_paused{bot=\"bot\\\"1\"} 1",
+            "trading_bot_cycle_duration_seconds_bucket{bot=\"bot\\\"1\",le=\"0.5\"} 0",
+            "trading_bot_cycle_duration_seconds_bucket{bot=\"bot\\\"1\",le=\"1\"} 1",
+            "trading_bot_cycle_duration_seconds_bucket{bot=\"bot\\\"1\",le=\"5\"} 2",
+            "trading_bot_cycle_duration_seconds_bucket{bot=\"bot\\\"1\",le=\"+Inf\"} 2",
+            "trading_bot_cycle_duration_seconds_sum{bot=\"bot\\\"1\"} 3.8",
+            "trading_bot_cycles_total{bot=\"bot\\\"1\",result=\"failure\"} 1",
+            "trading_bot_api_errors_total{bot=\"bot\\\"1\",endpoint=\"/api/exchange/orders\",kind=\"http_status\"} 2",
+            "trading_bot_actions_total{bot=\"bot\\\"1\",action=\"loss_cut\",result=\"success\"} 1",
+        ] {
+            assert!(lines.contains(&want), "{} not found in\n{}", want, got);
+        }
+        // 取引の前は残高を出力しない
+        assert!(!got.contains("trading_bot_balance"));
+    }
+}
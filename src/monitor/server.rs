@@ -0,0 +1,192 @@
+use crate::bot::control::BotControl;
+use crate::bot::shutdown::Shutdown;
+use crate::config::Config;
+use crate::error::MyResult;
+use crate::monitor::metrics::{Metrics, TradeStatus};
+
+use chrono::{DateTime, Duration, Utc};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::info;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+pub const HEALTH_PATH: &str = "/health";
+pub const STATUS_PATH: &str = "/status";
+pub const METRICS_PATH: &str = "/metrics";
+
+// 取引が止まったとみなすまでの、実行間隔に対する倍数と最小の時間（秒）
+const STALE_INTERVAL_MULTIPLIER: i64 = 3;
+const MIN_STALE_SEC: i64 = 60;
+
+// 死活監視, 状態の確認, メトリクスの収集を受け付けるサーバー
+pub struct MonitorServer {
+    context: Arc<Context>,
+}
+
+struct Context {
+    config: Config,
+    control: Arc<BotControl>,
+    metrics: Arc<Metrics>,
+}
+
+impl MonitorServer {
+    pub fn new(config: &Config, control: Arc<BotControl>, metrics: Arc<Metrics>) -> MonitorServer {
+        MonitorServer {
+            context: Arc::new(Context {
+                config: config.clone(),
+                control,
+                metrics,
+            }),
+        }
+    }
+
+    // 終了が要求されるまでリクエストを受け付ける
+    pub async fn serve(&self, addr: &SocketAddr, mut shutdown: Shutdown) -> MyResult<()> {
+        let context = self.context.clone();
+        let make_svc = make_service_fn(move |_| {
+            let context = context.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let context = context.clone();
+                    async move { Ok::<_, Infallible>(handle(&context, req, &Utc::now())) }
+                }))
+            }
+        });
+        let server = Server::try_bind(addr)?.serve(make_svc);
+        info!("listen monitor on http://{}", server.local_addr());
+        server
+            .with_graceful_shutdown(async move { shutdown.requested().await })
+            .await?;
+        Ok(())
+    }
+
+    pub fn handle(&self, req: Request<Body>, now: &DateTime<Utc>) -> Response<Body> {
+        handle(&self.context, req, now)
+    }
+}
+
+fn handle(context: &Context, req: Request<Body>, now: &DateTime<Utc>) -> Response<Body> {
+    if req.method() != Method::GET {
+        return text_response(StatusCode::NOT_FOUND, "not found");
+    }
+    match req.uri().path() {
+        HEALTH_PATH => health(context, now),
+        STATUS_PATH => json_response(StatusCode::OK, &status(context)),
+        METRICS_PATH => {
+            let control = context.control.snapshot();
+            Response::builder()
+                .header("Content-Type", "text/plain; version=0.0.4")
+                .body(Body::from(context.metrics.render(
+                    &context.config.bot_name,
+                    control.paused,
+                    control.demo_mode,
+                )))
+                .unwrap()
+        }
+        _ => text_response(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+// 一定時間取引していなければ 503（一時停止中は取引しないため正常とする）
+fn health(context: &Context, now: &DateTime<Utc>) -> Response<Body> {
+    let control = context.control.snapshot();
+    let last_cycle_at = context.metrics.snapshot().last_cycle_at;
+    let since = last_cycle_at.unwrap_or_else(|| context.metrics.started_at());
+    let stale_after = Duration::seconds(
+        (context.config.interval_sec as i64 * STALE_INTERVAL_MULTIPLIER).max(MIN_STALE_SEC),
+    );
+    let (status, text) = if control.paused {
+        (StatusCode::OK, "paused")
+    } else if *now - since > stale_after {
+        (StatusCode::SERVICE_UNAVAILABLE, "stale")
+    } else {
+        (StatusCode::OK, "ok")
+    };
+    json_response(
+        status,
+        &json!({
+            "status": text,
+            "last_cycle_at": last_cycle_at.map(|t| t.to_rfc3339()),
+        }),
+    )
+}
+
+fn status(context: &Context) -> Value {
+    let config = &context.config;
+    let control = context.control.snapshot();
+    let metrics = context.metrics.snapshot();
+    json!({
+        "config": {
+            "bot_name": config.bot_name,
+            "pair": config.target_pair,
+            "interval_sec": config.interval_sec,
+            "demo_mode": control.demo_mode,
+            "exchange": config.exchange_base_url,
+            "db_type": format!("{:?}", config.db_type),
+        },
+        "paused": control.paused,
+        "started_at": context.metrics.started_at().to_rfc3339(),
+        "last_cycle": {
+            "at": metrics.last_cycle_at.map(|t| t.to_rfc3339()),
+            "duration_sec": metrics.last_cycle_duration_sec,
+            "error": control.last_error,
+        },
+        "loss_cut_requests": control.loss_cut_order_ids,
+        "trade": metrics.status.as_ref().map(trade_status),
+    })
+}
+
+fn trade_status(s: &TradeStatus) -> Value {
+    let balances: BTreeMap<&String, Value> = s
+        .balances
+        .iter()
+        .map(|(k, b)| (k, json!({ "amount": b.amount, "reserved": b.reserved })))
+        .collect();
+    let open_orders: Vec<Value> = s
+        .open_orders
+        .iter()
+        .map(|o| {
+            json!({
+                "id": o.id,
+                "pair": o.pair,
+                "order_type": o.order_type.to_str(),
+                "rate": o.rate,
+                "pending_amount": o.pending_amount,
+                "stop_loss_rate": o.stop_loss_rate,
+                "created_at": o.created_at.to_rfc3339(),
+            })
+        })
+        .collect();
+    json!({
+        "pair": s.pair,
+        "sell_rate": s.sell_rate,
+        "buy_rate": s.buy_rate,
+        "balances": balances,
+        "total_balance_jpy": s.total_balance_jpy,
+        "open_orders": open_orders,
+        "support_line_long": s.support_line_long,
+        "support_line_short": s.support_line_short,
+        "resistance_line": s.resistance_line,
+        "long_trend": s.long_trend,
+        "updated_at": s.updated_at.to_rfc3339(),
+    })
+}
+
+fn json_response(status: StatusCode, body: &Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn text_response(status: StatusCode, text: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(text.to_owned()))
+        .unwrap()
+}
@@ -1,2 +1,4 @@
 pub mod client;
+pub mod memory;
+pub mod migration;
 pub mod model;
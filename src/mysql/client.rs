@@ -1,16 +1,154 @@
 use crate::error::MyError::RecordNotFound;
 use crate::error::MyResult;
+use crate::mysql::migration;
+use crate::mysql::migration::MIGRATIONS;
 use crate::mysql::model::MarketSummary;
-use crate::mysql::model::{BotStatus, Event, EventType, Market, Markets};
+use crate::mysql::model::{BotStatus, Event, InFlightAction, Markets, Report};
 
 use chrono::DateTime;
+use chrono::NaiveDateTime;
 use chrono::Utc;
 use indoc::indoc;
+use mysql::params;
 use mysql::prelude::Queryable;
 use mysql::OptsBuilder;
 use mysql::Pool;
 use mysql::PooledConn;
 
+const SELECT_MARKETS_SQL: &str = indoc!(
+    "
+    SELECT
+        pair, store_rate_avg, ex_rate_sell, ex_rate_buy, ex_volume_sell, ex_volume_buy, recorded_at
+    FROM
+        markets
+    WHERE
+        pair = :pair
+        AND recorded_at > :begin
+    ORDER BY
+        recorded_at
+    "
+);
+
+const UPSERT_BOT_STATUS_SQL: &str = indoc!(
+    "
+    INSERT INTO bot_statuses (bot_name, pair, type, value, memo)
+    VALUES (:bot_name, :pair, :type, :value, :memo)
+    ON DUPLICATE KEY UPDATE value = :value
+    "
+);
+
+const SELECT_BOT_STATUS_SQL: &str = indoc!(
+    "
+    SELECT
+        bot_name, pair, type, value, memo
+    FROM
+        bot_statuses
+    WHERE
+        bot_name = :bot_name
+        AND pair = :pair
+        AND type = :type
+    "
+);
+
+const INSERT_EVENT_SQL: &str = indoc!(
+    "
+    INSERT INTO events (pair, event_type, memo, recorded_at)
+    VALUES (:pair, :event_type, :memo, :recorded_at)
+    "
+);
+
+const UPSERT_IN_FLIGHT_ACTION_SQL: &str = indoc!(
+    "
+    INSERT INTO in_flight_actions (bot_name, pair, action_type, state, attempts, updated_at)
+    VALUES (:bot_name, :pair, :action_type, :state, :attempts, :updated_at)
+    ON DUPLICATE KEY UPDATE
+        pair = :pair, action_type = :action_type, state = :state, attempts = :attempts, updated_at = :updated_at
+    "
+);
+
+const SELECT_IN_FLIGHT_ACTION_SQL: &str = indoc!(
+    "
+    SELECT
+        bot_name, pair, action_type, state, attempts, updated_at
+    FROM
+        in_flight_actions
+    WHERE
+        bot_name = :bot_name
+    "
+);
+
+const DELETE_IN_FLIGHT_ACTION_SQL: &str =
+    "DELETE FROM in_flight_actions WHERE bot_name = :bot_name";
+
+const UPSERT_REPORT_SQL: &str = indoc!(
+    "
+    INSERT INTO reports (
+        bot_name, period, pair, begin_at, end_at, trade_count, realized_pnl, fee,
+        win_count, loss_count, open_amount, unrealized_pnl, total_jpy, total_jpy_change
+    )
+    VALUES (
+        :bot_name, :period, :pair, :begin_at, :end_at, :trade_count, :realized_pnl, :fee,
+        :win_count, :loss_count, :open_amount, :unrealized_pnl, :total_jpy, :total_jpy_change
+    )
+    ON DUPLICATE KEY UPDATE
+        begin_at = :begin_at, trade_count = :trade_count, realized_pnl = :realized_pnl, fee = :fee,
+        win_count = :win_count, loss_count = :loss_count, open_amount = :open_amount,
+        unrealized_pnl = :unrealized_pnl, total_jpy = :total_jpy, total_jpy_change = :total_jpy_change
+    "
+);
+
+const SELECT_LATEST_REPORT_SQL: &str = indoc!(
+    "
+    SELECT
+        bot_name, period, pair, begin_at, end_at, trade_count, realized_pnl, fee,
+        win_count, loss_count, open_amount, unrealized_pnl, total_jpy, total_jpy_change
+    FROM
+        reports
+    WHERE
+        bot_name = :bot_name
+        AND period = :period
+        AND pair = :pair
+        AND end_at < :before
+    ORDER BY
+        end_at DESC
+    LIMIT 1
+    "
+);
+
+// 集計対象が0件の場合は行を返さない（HAVING）
+const SELECT_MARKET_SUMMARY_SQL: &str = indoc!(
+    "
+    SELECT
+        COUNT(1) count,
+        MIN(m.recorded_at) recorded_at_begin,
+        MAX(m.recorded_at) recorded_at_end,
+        MAX(m.ex_rate_sell) ex_rate_sell_max,
+        MIN(m.ex_rate_sell) ex_rate_sell_min,
+        MAX(m.ex_rate_buy) ex_rate_buy_max,
+        MIN(m.ex_rate_buy) ex_rate_buy_min,
+        SUM(m.ex_volume_sell) ex_volume_sell_total,
+        SUM(m.ex_volume_buy) ex_volume_buy_total,
+        SUM(m.trade_count) / COUNT(1) trade_frequency_ratio
+    FROM (
+        SELECT
+            recorded_at,
+            ex_rate_sell,
+            ex_rate_buy,
+            ex_volume_sell,
+            ex_volume_buy,
+            CASE WHEN ex_volume_sell + ex_volume_buy = 0 THEN 0 ELSE 1 END trade_count
+        FROM
+            markets
+        WHERE
+            pair = :pair
+            AND recorded_at >= :begin
+            AND recorded_at <= :end
+    ) m
+    HAVING
+        COUNT(1) > 0
+    "
+);
+
 pub trait Client {
     fn select_markets(&self, pair: &str, begin: DateTime<Utc>) -> MyResult<Markets>;
 
@@ -21,6 +159,23 @@ pub trait Client {
     fn insert_event(&self, event: &Event) -> MyResult<()>;
 
     fn select_market_summary(&self, pair: &str, offset_hour: u64) -> MyResult<MarketSummary>;
+
+    fn upsert_in_flight_action(&self, action: &InFlightAction) -> MyResult<()>;
+
+    fn select_in_flight_action(&self, bot_name: &str) -> MyResult<Option<InFlightAction>>;
+
+    fn delete_in_flight_action(&self, bot_name: &str) -> MyResult<()>;
+
+    fn upsert_report(&self, report: &Report) -> MyResult<()>;
+
+    // before より前に終わった期間のうち、最新のレポート
+    fn select_latest_report(
+        &self,
+        bot_name: &str,
+        period: &str,
+        pair: &str,
+        before: &NaiveDateTime,
+    ) -> MyResult<Option<Report>>;
 }
 
 #[derive(Debug)]
@@ -48,6 +203,17 @@ impl DefaultClient {
         })
     }
 
+    // 未適用のマイグレーションを適用する
+    pub fn migrate(&self) -> MyResult<Vec<u32>> {
+        let mut conn = self.get_conn()?;
+        migration::migrate(&mut conn, &MIGRATIONS)
+    }
+
+    pub fn schema_version(&self) -> MyResult<u32> {
+        let mut conn = self.get_conn()?;
+        migration::current_version(&mut conn)
+    }
+
     fn get_conn(&self) -> MyResult<PooledConn> {
         match self.pool.get_conn() {
             Ok(v) => Ok(v),
@@ -59,32 +225,11 @@ impl DefaultClient {
 impl Client for DefaultClient {
     fn select_markets(&self, pair: &str, begin: DateTime<Utc>) -> MyResult<Markets> {
         let mut conn = self.get_conn()?;
-
-        let sql = format!(
-            "SELECT pair, store_rate_avg, ex_rate_sell, ex_rate_buy, ex_volume_sell, ex_volume_buy, recorded_at FROM markets WHERE pair = '{}' AND recorded_at > '{}' ORDER BY recorded_at",
-            pair,
-            begin.format("%Y-%m-%d %H:%M:%S"),
-        );
-        let markets = conn.query_map(
-            sql,
-            |(
-                pair,
-                store_rate_avg,
-                ex_rate_sell,
-                ex_rate_buy,
-                ex_volume_sell,
-                ex_volume_buy,
-                recorded_at,
-            )| {
-                Market {
-                    pair: pair,
-                    store_rate_avg: store_rate_avg,
-                    ex_rate_sell: ex_rate_sell,
-                    ex_rate_buy: ex_rate_buy,
-                    ex_volume_sell: ex_volume_sell,
-                    ex_volume_buy: ex_volume_buy,
-                    recorded_at: recorded_at,
-                }
+        let markets = conn.exec(
+            SELECT_MARKETS_SQL,
+            params! {
+                "pair" => pair,
+                "begin" => begin.naive_utc(),
             },
         )?;
         Ok(markets)
@@ -92,119 +237,230 @@ impl Client for DefaultClient {
 
     fn upsert_bot_status(&self, s: &BotStatus) -> MyResult<()> {
         let mut conn = self.get_conn()?;
-        let sql = format!(
-                "INSERT INTO bot_statuses (bot_name, pair, type, value, memo) VALUES ('{}', '{}', '{}', {}, '{}') ON DUPLICATE KEY UPDATE value = {};",
-                s.bot_name, s.pair, s.r#type, s.value, s.memo, s.value
-        );
-
-        conn.query_drop(sql)?;
+        conn.exec_drop(
+            UPSERT_BOT_STATUS_SQL,
+            params! {
+                "bot_name" => &s.bot_name,
+                "pair" => &s.pair,
+                "type" => &s.r#type,
+                "value" => s.value,
+                "memo" => &s.memo,
+            },
+        )?;
         Ok(())
     }
 
     fn select_bot_status(&self, bot_name: &str, pair: &str, r#type: &str) -> MyResult<BotStatus> {
         let mut conn = self.get_conn()?;
-
-        let sql = format!(
-                "SELECT bot_name, pair, type, value, memo FROM bot_statuses WHERE bot_name = '{}' AND pair = '{}' AND type = '{}'",
-                bot_name, pair, r#type,
-            );
-        if let Some((bot_name, pair, r#type, value, memo)) = conn.query_first(sql)? {
-            Ok(BotStatus {
-                bot_name: bot_name,
-                pair: pair,
-                r#type: r#type,
-                value: value,
-                memo: memo,
-            })
-        } else {
-            Err(Box::new(RecordNotFound {
+        let status: Option<BotStatus> = conn.exec_first(
+            SELECT_BOT_STATUS_SQL,
+            params! {
+                "bot_name" => bot_name,
+                "pair" => pair,
+                "type" => r#type,
+            },
+        )?;
+        status.ok_or_else(|| {
+            Box::new(RecordNotFound {
                 table: "bot_statuses".to_owned(),
                 param: format!("bot_name:{}, type:{}", bot_name, r#type),
-            }))
-        }
+            })
+            .into()
+        })
     }
 
     fn insert_event(&self, event: &Event) -> MyResult<()> {
         let mut conn = self.get_conn()?;
-        let event_type = match event.event_type {
-            EventType::Buy => 0,
-            EventType::Sell => 1,
-        };
-        let sql = format!(
-            "INSERT INTO events (pair, event_type, memo, recorded_at) VALUES ('{}', {}, '{}', '{}');",
-            event.pair.to_string(), event_type, event.memo, event.recorded_at.format("%Y-%m-%d %H:%M:%S"),
-        );
-        conn.query_drop(sql)?;
+        conn.exec_drop(
+            INSERT_EVENT_SQL,
+            params! {
+                "pair" => event.pair.to_string(),
+                "event_type" => event.event_type.to_value(),
+                "memo" => &event.memo,
+                "recorded_at" => event.recorded_at,
+            },
+        )?;
         Ok(())
     }
 
     fn select_market_summary(&self, pair: &str, offset_hour: u64) -> MyResult<MarketSummary> {
         let mut conn = self.get_conn()?;
+        let (begin, end) = MarketSummary::period(Utc::now(), offset_hour);
+        let summary: Option<MarketSummary> = conn.exec_first(
+            SELECT_MARKET_SUMMARY_SQL,
+            params! {
+                "pair" => pair,
+                "begin" => begin,
+                "end" => end,
+            },
+        )?;
+        summary.ok_or_else(|| {
+            Box::new(RecordNotFound {
+                table: "markets".to_owned(),
+                param: format!("pair:{}, offset_hour:{}", pair, offset_hour),
+            })
+            .into()
+        })
+    }
 
-        let sql = format!(
-            indoc!(
-                "
-                SELECT
-                    COUNT(1) count,
-                    MIN(m.recorded_at) recorded_at_begin,
-                    MAX(m.recorded_at) recorded_at_end,
-                    MAX(m.ex_rate_sell) ex_rate_sell_max,
-                    MIN(m.ex_rate_sell) ex_rate_sell_min,
-                    MAX(m.ex_rate_buy) ex_rate_buy_max,
-                    MIN(m.ex_rate_buy) ex_rate_buy_min,
-                    SUM(m.ex_volume_sell) ex_volume_sell_total,
-                    SUM(m.ex_volume_buy) ex_volume_buy_total,
-                    SUM(m.trade_count) / COUNT(1) trade_frequency_ratio
-                FROM (
-                    SELECT
-                        recorded_at,
-                        ex_rate_sell,
-                        ex_rate_buy,
-                        ex_volume_sell,
-                        ex_volume_buy,
-                        CASE WHEN ex_volume_sell + ex_volume_buy = 0 THEN 0 ELSE 1 END trade_count
-                    FROM
-                        markets
-                    WHERE
-                        pair = '{}'
-                        AND recorded_at <= DATE_SUB(NOW(), INTERVAL {} HOUR)
-                        AND recorded_at >= DATE_SUB(NOW(), INTERVAL 24 + {} HOUR)
-                ) m
-            "
-            ),
-            pair, offset_hour, offset_hour
+    fn upsert_in_flight_action(&self, action: &InFlightAction) -> MyResult<()> {
+        let mut conn = self.get_conn()?;
+        conn.exec_drop(
+            UPSERT_IN_FLIGHT_ACTION_SQL,
+            params! {
+                "bot_name" => &action.bot_name,
+                "pair" => &action.pair,
+                "action_type" => &action.action_type,
+                "state" => &action.state,
+                "attempts" => action.attempts,
+                "updated_at" => action.updated_at,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn select_in_flight_action(&self, bot_name: &str) -> MyResult<Option<InFlightAction>> {
+        let mut conn = self.get_conn()?;
+        let action = conn.exec_first(
+            SELECT_IN_FLIGHT_ACTION_SQL,
+            params! {
+                "bot_name" => bot_name,
+            },
+        )?;
+        Ok(action)
+    }
+
+    fn delete_in_flight_action(&self, bot_name: &str) -> MyResult<()> {
+        let mut conn = self.get_conn()?;
+        conn.exec_drop(
+            DELETE_IN_FLIGHT_ACTION_SQL,
+            params! {
+                "bot_name" => bot_name,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn upsert_report(&self, report: &Report) -> MyResult<()> {
+        let mut conn = self.get_conn()?;
+        conn.exec_drop(
+            UPSERT_REPORT_SQL,
+            params! {
+                "bot_name" => &report.bot_name,
+                "period" => &report.period,
+                "pair" => &report.pair,
+                "begin_at" => report.begin_at,
+                "end_at" => report.end_at,
+                "trade_count" => report.trade_count,
+                "realized_pnl" => report.realized_pnl,
+                "fee" => report.fee,
+                "win_count" => report.win_count,
+                "loss_count" => report.loss_count,
+                "open_amount" => report.open_amount,
+                "unrealized_pnl" => report.unrealized_pnl,
+                "total_jpy" => report.total_jpy,
+                "total_jpy_change" => report.total_jpy_change,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn select_latest_report(
+        &self,
+        bot_name: &str,
+        period: &str,
+        pair: &str,
+        before: &NaiveDateTime,
+    ) -> MyResult<Option<Report>> {
+        let mut conn = self.get_conn()?;
+        let report = conn.exec_first(
+            SELECT_LATEST_REPORT_SQL,
+            params! {
+                "bot_name" => bot_name,
+                "period" => period,
+                "pair" => pair,
+                "before" => before,
+            },
+        )?;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coincheck::model::Pair;
+    use crate::mysql::model::EventType;
+
+    // ローカルのMySQL（configs/db.env と同じ環境変数）に接続する
+    fn make_client() -> DefaultClient {
+        let env = |key: &str| std::env::var(key).unwrap();
+        let client = DefaultClient::new(
+            &env("DB_USER_NAME"),
+            &env("DB_PASSWORD"),
+            &env("DB_HOST"),
+            env("DB_PORT").parse().unwrap(),
+            &env("DB_NAME"),
+        )
+        .unwrap();
+        client.migrate().unwrap();
+        client
+    }
+
+    #[test]
+    #[ignore = "requires local MySQL"]
+    fn test_migrate_twice() {
+        let client = make_client();
+        assert_eq!(client.migrate().unwrap(), Vec::<u32>::new());
+        assert_eq!(
+            client.schema_version().unwrap(),
+            MIGRATIONS.last().unwrap().version
         );
-        if let Some((
-            count,
-            recorded_at_begin,
-            recorded_at_end,
-            ex_rate_sell_max,
-            ex_rate_sell_min,
-            ex_rate_buy_max,
-            ex_rate_buy_min,
-            ex_volume_sell_total,
-            ex_volume_buy_total,
-            trade_frequency_ratio,
-        )) = conn.query_first(sql)?
-        {
-            if count > 0 {
-                return Ok(MarketSummary {
-                    count: count,
-                    recorded_at_begin: recorded_at_begin,
-                    recorded_at_end: recorded_at_end,
-                    ex_rate_sell_max: ex_rate_sell_max,
-                    ex_rate_sell_min: ex_rate_sell_min,
-                    ex_rate_buy_max: ex_rate_buy_max,
-                    ex_rate_buy_min: ex_rate_buy_min,
-                    ex_volume_sell_total: ex_volume_sell_total,
-                    ex_volume_buy_total: ex_volume_buy_total,
-                    trade_frequency_ratio: trade_frequency_ratio,
-                });
-            }
-        }
-        Err(Box::new(RecordNotFound {
-            table: "markets".to_owned(),
-            param: format!("pair:{}, offset_hour:{}", pair, offset_hour),
-        }))
+    }
+
+    #[test]
+    #[ignore = "requires local MySQL"]
+    fn test_bot_status_with_quoted_memo() {
+        let client = make_client();
+        let status = BotStatus {
+            bot_name: "test_bot'; --".to_owned(),
+            pair: "btc_jpy".to_owned(),
+            r#type: "test_type".to_owned(),
+            value: 123.0,
+            memo: "it's \"quoted\"".to_owned(),
+        };
+        client.upsert_bot_status(&status).unwrap();
+
+        let got = client
+            .select_bot_status(&status.bot_name, &status.pair, &status.r#type)
+            .unwrap();
+        assert_eq!(got.value, status.value);
+        assert_eq!(got.memo, status.memo);
+    }
+
+    #[test]
+    #[ignore = "requires local MySQL"]
+    fn test_insert_event_with_quoted_memo() {
+        let client = make_client();
+        let event = Event {
+            pair: Pair::new("btc_jpy").unwrap(),
+            event_type: EventType::Buy,
+            memo: "market buy completed! `it's 'quoted'`".to_owned(),
+            recorded_at: Utc::now().naive_utc(),
+        };
+        client.insert_event(&event).unwrap();
+    }
+
+    #[test]
+    #[ignore = "requires local MySQL"]
+    fn test_select_markets_with_quoted_pair() {
+        let client = make_client();
+        let got = client
+            .select_markets(
+                "btc_jpy' OR '1' = '1",
+                Utc::now() - chrono::Duration::days(1),
+            )
+            .unwrap();
+        assert!(got.is_empty());
     }
 }
@@ -0,0 +1,431 @@
+use crate::error::MyError::RecordNotFound;
+use crate::error::MyResult;
+use crate::mysql::client::Client;
+use crate::mysql::model::{
+    BotStatus, Event, InFlightAction, Market, MarketSummary, Markets, Report,
+};
+
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+// メモリ上にデータを保持するクライアント（テスト, シミュレーション用）
+// 複数スレッドから参照されても良いように内部のデータは Mutex で保護する
+#[derive(Debug, Default)]
+pub struct MemoryClient {
+    store: Mutex<Store>,
+}
+
+#[derive(Debug, Default)]
+struct Store {
+    markets: Markets,
+    // (bot_name, pair, type) => BotStatus
+    bot_statuses: BTreeMap<(String, String, String), BotStatus>,
+    events: Vec<Event>,
+    // bot_name => InFlightAction
+    in_flight_actions: BTreeMap<String, InFlightAction>,
+    // (bot_name, period, pair, end_at) => Report
+    reports: BTreeMap<(String, String, String, NaiveDateTime), Report>,
+    // 集計の基準時刻（未設定なら現在時刻）
+    now: Option<DateTime<Utc>>,
+}
+
+impl MemoryClient {
+    pub fn new() -> MemoryClient {
+        MemoryClient::default()
+    }
+
+    pub fn add_market(&self, market: &Market) -> MyResult<()> {
+        let mut store = self.lock()?;
+        store.markets.push(market.clone());
+        Ok(())
+    }
+
+    // 指定時刻より前の市場データを削除する（シミュレーションで保持する期間を限る）
+    pub fn remove_markets_before(&self, begin: NaiveDateTime) -> MyResult<()> {
+        let mut store = self.lock()?;
+        store.markets.retain(|m| m.recorded_at >= begin);
+        Ok(())
+    }
+
+    // select_market_summary の基準時刻を固定する（シミュレーションでは市場データの時刻を使う）
+    pub fn set_now(&self, now: DateTime<Utc>) -> MyResult<()> {
+        let mut store = self.lock()?;
+        store.now = Some(now);
+        Ok(())
+    }
+
+    // 登録済みのイベント（登録順）
+    pub fn events(&self) -> MyResult<Vec<Event>> {
+        let store = self.lock()?;
+        Ok(store.events.clone())
+    }
+
+    // 登録済みのレポート（bot_name, period, pair, end_at 順）
+    pub fn reports(&self) -> MyResult<Vec<Report>> {
+        let store = self.lock()?;
+        Ok(store.reports.values().cloned().collect())
+    }
+
+    // 登録済みのボット状態（bot_name, pair, type 順）
+    pub fn bot_statuses(&self) -> MyResult<Vec<BotStatus>> {
+        let store = self.lock()?;
+        Ok(store.bot_statuses.values().cloned().collect())
+    }
+
+    // ボット状態を1行ずつ文字列化したもの（ログ出力用）
+    pub fn dump_bot_statuses(&self) -> MyResult<Vec<String>> {
+        let statuses = self.bot_statuses()?;
+        Ok(statuses
+            .iter()
+            .map(|s| {
+                format!(
+                    "{} {} {} = {} ({})",
+                    s.bot_name, s.pair, s.r#type, s.value, s.memo
+                )
+            })
+            .collect())
+    }
+
+    fn lock(&self) -> MyResult<MutexGuard<'_, Store>> {
+        match self.store.lock() {
+            Ok(v) => Ok(v),
+            Err(e) => Err(e.to_string().into()),
+        }
+    }
+}
+
+impl Client for MemoryClient {
+    fn select_markets(&self, pair: &str, begin: DateTime<Utc>) -> MyResult<Markets> {
+        let store = self.lock()?;
+        let begin = begin.naive_utc();
+        let mut markets: Markets = store
+            .markets
+            .iter()
+            .filter(|m| m.pair == pair && m.recorded_at > begin)
+            .cloned()
+            .collect();
+        markets.sort_by_key(|m| m.recorded_at);
+        Ok(markets)
+    }
+
+    // MySQL版と同じく、既存レコードの更新時は value のみ更新する
+    fn upsert_bot_status(&self, s: &BotStatus) -> MyResult<()> {
+        let mut store = self.lock()?;
+        let key = (s.bot_name.clone(), s.pair.clone(), s.r#type.clone());
+        match store.bot_statuses.get_mut(&key) {
+            Some(v) => v.value = s.value,
+            None => {
+                store.bot_statuses.insert(key, s.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn select_bot_status(&self, bot_name: &str, pair: &str, r#type: &str) -> MyResult<BotStatus> {
+        let store = self.lock()?;
+        let key = (bot_name.to_owned(), pair.to_owned(), r#type.to_owned());
+        store.bot_statuses.get(&key).cloned().ok_or_else(|| {
+            Box::new(RecordNotFound {
+                table: "bot_statuses".to_owned(),
+                param: format!("bot_name:{}, type:{}", bot_name, r#type),
+            })
+            .into()
+        })
+    }
+
+    fn insert_event(&self, event: &Event) -> MyResult<()> {
+        let mut store = self.lock()?;
+        store.events.push(event.clone());
+        Ok(())
+    }
+
+    fn select_market_summary(&self, pair: &str, offset_hour: u64) -> MyResult<MarketSummary> {
+        let store = self.lock()?;
+        let now = store.now.unwrap_or_else(Utc::now);
+        let (begin, end) = MarketSummary::period(now, offset_hour);
+        let markets: Vec<&Market> = store
+            .markets
+            .iter()
+            .filter(|m| m.pair == pair && begin <= m.recorded_at && m.recorded_at <= end)
+            .collect();
+        if markets.is_empty() {
+            return Err(Box::new(RecordNotFound {
+                table: "markets".to_owned(),
+                param: format!("pair:{}, offset_hour:{}", pair, offset_hour),
+            }));
+        }
+
+        let count = markets.len();
+        let trade_count = markets
+            .iter()
+            .filter(|m| m.ex_volume_sell + m.ex_volume_buy != 0.0)
+            .count();
+        Ok(MarketSummary {
+            count: count as u64,
+            recorded_at_begin: markets.iter().map(|m| m.recorded_at).min().unwrap(),
+            recorded_at_end: markets.iter().map(|m| m.recorded_at).max().unwrap(),
+            ex_rate_sell_max: markets
+                .iter()
+                .map(|m| m.ex_rate_sell)
+                .fold(f64::MIN, f64::max),
+            ex_rate_sell_min: markets
+                .iter()
+                .map(|m| m.ex_rate_sell)
+                .fold(f64::MAX, f64::min),
+            ex_rate_buy_max: markets
+                .iter()
+                .map(|m| m.ex_rate_buy)
+                .fold(f64::MIN, f64::max),
+            ex_rate_buy_min: markets
+                .iter()
+                .map(|m| m.ex_rate_buy)
+                .fold(f64::MAX, f64::min),
+            ex_volume_sell_total: markets.iter().map(|m| m.ex_volume_sell).sum(),
+            ex_volume_buy_total: markets.iter().map(|m| m.ex_volume_buy).sum(),
+            trade_frequency_ratio: trade_count as f64 / count as f64,
+        })
+    }
+
+    fn upsert_in_flight_action(&self, action: &InFlightAction) -> MyResult<()> {
+        let mut store = self.lock()?;
+        store
+            .in_flight_actions
+            .insert(action.bot_name.clone(), action.clone());
+        Ok(())
+    }
+
+    fn select_in_flight_action(&self, bot_name: &str) -> MyResult<Option<InFlightAction>> {
+        let store = self.lock()?;
+        Ok(store.in_flight_actions.get(bot_name).cloned())
+    }
+
+    fn delete_in_flight_action(&self, bot_name: &str) -> MyResult<()> {
+        let mut store = self.lock()?;
+        store.in_flight_actions.remove(bot_name);
+        Ok(())
+    }
+
+    fn upsert_report(&self, report: &Report) -> MyResult<()> {
+        let mut store = self.lock()?;
+        let key = (
+            report.bot_name.clone(),
+            report.period.clone(),
+            report.pair.clone(),
+            report.end_at,
+        );
+        store.reports.insert(key, report.clone());
+        Ok(())
+    }
+
+    fn select_latest_report(
+        &self,
+        bot_name: &str,
+        period: &str,
+        pair: &str,
+        before: &NaiveDateTime,
+    ) -> MyResult<Option<Report>> {
+        let store = self.lock()?;
+        let report = store
+            .reports
+            .values()
+            .filter(|r| {
+                r.bot_name == bot_name && r.period == period && r.pair == pair && r.end_at < *before
+            })
+            .max_by_key(|r| r.end_at)
+            .cloned();
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coincheck::model::Pair;
+    use crate::mysql::model::EventType;
+
+    use std::sync::Arc;
+    use std::thread;
+
+    use chrono::Duration;
+
+    fn make_market(pair: &str, rate: f64, volume: f64, recorded_at: DateTime<Utc>) -> Market {
+        Market {
+            pair: pair.to_owned(),
+            store_rate_avg: rate,
+            ex_rate_sell: rate,
+            ex_rate_buy: rate + 1.0,
+            ex_volume_sell: volume,
+            ex_volume_buy: volume,
+            recorded_at: recorded_at.naive_utc(),
+        }
+    }
+
+    fn make_status(r#type: &str, value: f64) -> BotStatus {
+        BotStatus {
+            bot_name: "bot".to_owned(),
+            pair: "all".to_owned(),
+            r#type: r#type.to_owned(),
+            value,
+            memo: format!("memo of {}", r#type),
+        }
+    }
+
+    #[test]
+    fn test_select_markets() {
+        let client = MemoryClient::new();
+        let now = Utc::now();
+        client
+            .add_market(&make_market(
+                "btc_jpy",
+                110.0,
+                1.0,
+                now - Duration::minutes(1),
+            ))
+            .unwrap();
+        client
+            .add_market(&make_market(
+                "btc_jpy",
+                100.0,
+                1.0,
+                now - Duration::minutes(3),
+            ))
+            .unwrap();
+        client
+            .add_market(&make_market(
+                "btc_jpy",
+                105.0,
+                1.0,
+                now - Duration::minutes(2),
+            ))
+            .unwrap();
+        client
+            .add_market(&make_market(
+                "mona_jpy",
+                200.0,
+                1.0,
+                now - Duration::minutes(1),
+            ))
+            .unwrap();
+
+        let got = client
+            .select_markets("btc_jpy", now - Duration::minutes(3))
+            .unwrap();
+        let rates: Vec<f64> = got.iter().map(|m| m.ex_rate_sell).collect();
+        assert_eq!(rates, vec![105.0, 110.0]);
+
+        client
+            .remove_markets_before((now - Duration::minutes(2)).naive_utc())
+            .unwrap();
+        let got = client
+            .select_markets("btc_jpy", now - Duration::minutes(10))
+            .unwrap();
+        let rates: Vec<f64> = got.iter().map(|m| m.ex_rate_sell).collect();
+        assert_eq!(rates, vec![105.0, 110.0]);
+    }
+
+    #[test]
+    fn test_bot_status() {
+        let client = MemoryClient::new();
+        assert!(client.select_bot_status("bot", "all", "total_jpy").is_err());
+
+        client
+            .upsert_bot_status(&make_status("total_jpy", 1000.0))
+            .unwrap();
+        client
+            .upsert_bot_status(&make_status("long_trend", 1.0))
+            .unwrap();
+        let mut status = make_status("total_jpy", 2000.0);
+        status.memo = "changed".to_owned();
+        client.upsert_bot_status(&status).unwrap();
+
+        let got = client.select_bot_status("bot", "all", "total_jpy").unwrap();
+        assert_eq!(got.value, 2000.0);
+        assert_eq!(got.memo, "memo of total_jpy");
+
+        assert_eq!(
+            client.dump_bot_statuses().unwrap(),
+            vec![
+                "bot all long_trend = 1 (memo of long_trend)".to_owned(),
+                "bot all total_jpy = 2000 (memo of total_jpy)".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_insert_event_from_threads() {
+        let client = Arc::new(MemoryClient::new());
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let client = Arc::clone(&client);
+                thread::spawn(move || {
+                    client
+                        .insert_event(&Event {
+                            pair: Pair::new("btc_jpy").unwrap(),
+                            event_type: EventType::Buy,
+                            memo: format!("event {}", i),
+                            recorded_at: Utc::now().naive_utc(),
+                        })
+                        .unwrap();
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        let events = client.events().unwrap();
+        assert_eq!(events.len(), 4);
+        assert!(events.iter().all(|e| e.event_type == EventType::Buy));
+    }
+
+    #[test]
+    fn test_select_market_summary() {
+        let client = MemoryClient::new();
+        let now = DateTime::parse_from_rfc3339("2021-05-16T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        client.set_now(now).unwrap();
+        assert!(client.select_market_summary("btc_jpy", 1).is_err());
+
+        // 集計期間外（直近1時間以内）
+        client
+            .add_market(&make_market(
+                "btc_jpy",
+                500.0,
+                1.0,
+                now - Duration::minutes(30),
+            ))
+            .unwrap();
+        client
+            .add_market(&make_market(
+                "btc_jpy",
+                100.0,
+                0.0,
+                now - Duration::hours(3),
+            ))
+            .unwrap();
+        client
+            .add_market(&make_market(
+                "btc_jpy",
+                120.0,
+                2.0,
+                now - Duration::hours(2),
+            ))
+            .unwrap();
+
+        let got = client.select_market_summary("btc_jpy", 1).unwrap();
+        assert_eq!(got.count, 2);
+        assert_eq!(
+            got.recorded_at_begin,
+            (now - Duration::hours(3)).naive_utc()
+        );
+        assert_eq!(got.recorded_at_end, (now - Duration::hours(2)).naive_utc());
+        assert_eq!(got.ex_rate_sell_max, 120.0);
+        assert_eq!(got.ex_rate_sell_min, 100.0);
+        assert_eq!(got.ex_rate_buy_max, 121.0);
+        assert_eq!(got.ex_volume_sell_total, 2.0);
+        assert_eq!(got.trade_frequency_ratio, 0.5);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::error::MyError::LockFailed;
+use crate::error::MyResult;
+
+use indoc::indoc;
+use mysql::prelude::Queryable;
+use mysql::{params, PooledConn};
+
+// スキーマのマイグレーション
+// バージョン順に適用し、適用済みのバージョンは schema_migrations に記録する
+#[derive(Debug, PartialEq)]
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+pub const MIGRATIONS: [Migration; 5] = [
+    Migration {
+        version: 1,
+        name: "create_markets",
+        sql: include_str!("../../migrations/mysql/V001__create_markets.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "create_bot_statuses",
+        sql: include_str!("../../migrations/mysql/V002__create_bot_statuses.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "create_events",
+        sql: include_str!("../../migrations/mysql/V003__create_events.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "create_in_flight_actions",
+        sql: include_str!("../../migrations/mysql/V004__create_in_flight_actions.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "create_reports",
+        sql: include_str!("../../migrations/mysql/V005__create_reports.sql"),
+    },
+];
+
+const CREATE_SCHEMA_MIGRATIONS_SQL: &str = indoc!(
+    "
+    CREATE TABLE IF NOT EXISTS schema_migrations (
+        version INT UNSIGNED NOT NULL,
+        name VARCHAR(255) NOT NULL,
+        applied_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        PRIMARY KEY (version)
+    ) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4
+    "
+);
+
+const SELECT_APPLIED_VERSIONS_SQL: &str = "SELECT version FROM schema_migrations ORDER BY version";
+
+const INSERT_APPLIED_VERSION_SQL: &str =
+    "INSERT INTO schema_migrations (version, name) VALUES (:version, :name)";
+
+// 複数のbotが同時に起動してもマイグレーションを1つずつ適用するためのロック
+const GET_LOCK_SQL: &str = "SELECT GET_LOCK('schema_migrations', :timeout)";
+const RELEASE_LOCK_SQL: &str = "SELECT RELEASE_LOCK('schema_migrations')";
+const LOCK_TIMEOUT_SECONDS: u32 = 60;
+
+// 未適用のマイグレーションを適用し、適用したバージョンを返す
+// MySQLのDDLは暗黙コミットされるため、失敗したマイグレーションは途中まで反映されうる
+// （各SQLは IF NOT EXISTS などで再実行できるように書くこと）
+pub fn migrate(conn: &mut PooledConn, migrations: &[Migration]) -> MyResult<Vec<u32>> {
+    let locked: Option<Option<u32>> = conn.exec_first(
+        GET_LOCK_SQL,
+        params! {
+            "timeout" => LOCK_TIMEOUT_SECONDS,
+        },
+    )?;
+    if locked.flatten() != Some(1) {
+        return Err(Box::new(LockFailed("schema_migrations".to_owned())));
+    }
+    let result = migrate_locked(conn, migrations);
+    conn.query_drop(RELEASE_LOCK_SQL)?;
+    result
+}
+
+fn migrate_locked(conn: &mut PooledConn, migrations: &[Migration]) -> MyResult<Vec<u32>> {
+    conn.query_drop(CREATE_SCHEMA_MIGRATIONS_SQL)?;
+    let applied: Vec<u32> = conn.query(SELECT_APPLIED_VERSIONS_SQL)?;
+
+    let mut versions = vec![];
+    for m in pending(migrations, &applied) {
+        for statement in split_statements(m.sql) {
+            conn.query_drop(statement)?;
+        }
+        conn.exec_drop(
+            INSERT_APPLIED_VERSION_SQL,
+            params! {
+                "version" => m.version,
+                "name" => m.name,
+            },
+        )?;
+        versions.push(m.version);
+    }
+    Ok(versions)
+}
+
+// 適用済みの最新バージョン（未適用なら0）
+pub fn current_version(conn: &mut PooledConn) -> MyResult<u32> {
+    conn.query_drop(CREATE_SCHEMA_MIGRATIONS_SQL)?;
+    let applied: Vec<u32> = conn.query(SELECT_APPLIED_VERSIONS_SQL)?;
+    Ok(applied.into_iter().max().unwrap_or(0))
+}
+
+pub fn pending<'a>(migrations: &'a [Migration], applied: &[u32]) -> Vec<&'a Migration> {
+    let mut pending: Vec<&Migration> = migrations
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .collect();
+    pending.sort_by_key(|m| m.version);
+    pending
+}
+
+// SQLファイルを文単位に分割する（コメントと空文は除く）
+pub fn split_statements(sql: &str) -> Vec<String> {
+    let mut statements = vec![];
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) => {
+                current.push(c);
+                if c == q {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '\'' | '"' | '`' => {
+                    current.push(c);
+                    quote = Some(c);
+                }
+                '-' if chars.peek() == Some(&'-') => {
+                    // 行末までコメント
+                    for c in chars.by_ref() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                    current.push('\n');
+                }
+                ';' => {
+                    push_statement(&mut statements, &current);
+                    current.clear();
+                }
+                _ => current.push(c),
+            },
+        }
+    }
+    push_statement(&mut statements, &current);
+    statements
+}
+
+fn push_statement(statements: &mut Vec<String>, s: &str) {
+    let s = s.trim();
+    if !s.is_empty() {
+        statements.push(s.to_owned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        for (i, m) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(m.version, i as u32 + 1, "{}, failure", m.name);
+            assert!(!split_statements(m.sql).is_empty(), "{}, failure", m.name);
+        }
+    }
+
+    #[test]
+    fn test_pending() {
+        let got: Vec<u32> = pending(&MIGRATIONS, &[1, 3])
+            .iter()
+            .map(|m| m.version)
+            .collect();
+        assert_eq!(got, vec![2, 4, 5]);
+
+        let got: Vec<u32> = pending(&MIGRATIONS, &[])
+            .iter()
+            .map(|m| m.version)
+            .collect();
+        assert_eq!(got, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_split_statements() {
+        let sql = indoc!(
+            "
+            -- comment; with semicolon
+            CREATE TABLE a (memo VARCHAR(8) NOT NULL DEFAULT ';');
+            INSERT INTO a (memo) VALUES ('it''s');
+
+            ;
+            "
+        );
+        let got = split_statements(sql);
+        assert_eq!(
+            got,
+            vec![
+                "CREATE TABLE a (memo VARCHAR(8) NOT NULL DEFAULT ';')".to_owned(),
+                "INSERT INTO a (memo) VALUES ('it''s')".to_owned(),
+            ]
+        );
+    }
+}
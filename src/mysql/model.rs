@@ -1,5 +1,7 @@
 use crate::coincheck::model::Pair;
-use chrono::Utc;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use mysql::prelude::{FromRow, FromValue};
+use mysql::{from_row_opt, FromRowError, Row};
 
 #[derive(Debug, Clone)]
 pub struct Market {
@@ -12,6 +14,29 @@ pub struct Market {
     pub recorded_at: chrono::NaiveDateTime,
 }
 
+impl FromRow for Market {
+    fn from_row_opt(row: Row) -> Result<Self, FromRowError> {
+        let (
+            pair,
+            store_rate_avg,
+            ex_rate_sell,
+            ex_rate_buy,
+            ex_volume_sell,
+            ex_volume_buy,
+            recorded_at,
+        ) = from_row_opt(row)?;
+        Ok(Market {
+            pair,
+            store_rate_avg,
+            ex_rate_sell,
+            ex_rate_buy,
+            ex_volume_sell,
+            ex_volume_buy,
+            recorded_at,
+        })
+    }
+}
+
 pub type Markets = Vec<Market>;
 
 pub trait MarketsMethods {
@@ -48,6 +73,44 @@ pub struct MarketSummary {
     pub trade_frequency_ratio: f64,
 }
 
+impl MarketSummary {
+    // 集計期間（offset_hour 時間前までの24時間, UTC）
+    pub fn period(now: DateTime<Utc>, offset_hour: u64) -> (NaiveDateTime, NaiveDateTime) {
+        let end = now - Duration::hours(offset_hour as i64);
+        let begin = end - Duration::hours(24);
+        (begin.naive_utc(), end.naive_utc())
+    }
+}
+
+impl FromRow for MarketSummary {
+    fn from_row_opt(row: Row) -> Result<Self, FromRowError> {
+        let (
+            count,
+            recorded_at_begin,
+            recorded_at_end,
+            ex_rate_sell_max,
+            ex_rate_sell_min,
+            ex_rate_buy_max,
+            ex_rate_buy_min,
+            ex_volume_sell_total,
+            ex_volume_buy_total,
+            trade_frequency_ratio,
+        ) = from_row_opt(row)?;
+        Ok(MarketSummary {
+            count,
+            recorded_at_begin,
+            recorded_at_end,
+            ex_rate_sell_max,
+            ex_rate_sell_min,
+            ex_rate_buy_max,
+            ex_rate_buy_min,
+            ex_volume_sell_total,
+            ex_volume_buy_total,
+            trade_frequency_ratio,
+        })
+    }
+}
+
 impl Default for MarketSummary {
     fn default() -> Self {
         let now = Utc::now();
@@ -66,7 +129,7 @@ impl Default for MarketSummary {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BotStatus {
     pub bot_name: String,
     pub pair: String,
@@ -75,16 +138,259 @@ pub struct BotStatus {
     pub memo: String,
 }
 
-#[derive(Debug)]
+impl FromRow for BotStatus {
+    fn from_row_opt(row: Row) -> Result<Self, FromRowError> {
+        let (bot_name, pair, r#type, value, memo) = from_row_opt(row)?;
+        Ok(BotStatus {
+            bot_name,
+            pair,
+            r#type,
+            value,
+            memo,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum EventType {
     Sell,
     Buy,
 }
 
-#[derive(Debug)]
+impl EventType {
+    // DB上の値（events.event_type）
+    pub fn to_value(&self) -> u8 {
+        match self {
+            EventType::Buy => 0,
+            EventType::Sell => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Event {
     pub pair: Pair,
     pub event_type: EventType,
     pub memo: String,
     pub recorded_at: chrono::NaiveDateTime,
 }
+
+// 実行中のアクション（複数ステップのアクションの進捗, ボット毎に1件）
+#[derive(Debug, Clone, PartialEq)]
+pub struct InFlightAction {
+    pub bot_name: String,
+    pub pair: String,
+    pub action_type: String,
+    // 進捗（JSON）
+    pub state: String,
+    // 再開を試みた回数
+    pub attempts: u32,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl FromRow for InFlightAction {
+    fn from_row_opt(row: Row) -> Result<Self, FromRowError> {
+        let (bot_name, pair, action_type, state, attempts, updated_at) = from_row_opt(row)?;
+        Ok(InFlightAction {
+            bot_name,
+            pair,
+            action_type,
+            state,
+            attempts,
+            updated_at,
+        })
+    }
+}
+
+// 定期レポート（期間毎, 取引ペア毎と全体 pair="all" の集計）
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub bot_name: String,
+    // daily or weekly
+    pub period: String,
+    pub pair: String,
+    pub begin_at: chrono::NaiveDateTime,
+    pub end_at: chrono::NaiveDateTime,
+    // 約定した注文の数
+    pub trade_count: u32,
+    // 実現損益（JPY, 手数料を含む）
+    pub realized_pnl: f64,
+    // 手数料（JPY）
+    pub fee: f64,
+    // 利益, 損失になった売注文の数
+    pub win_count: u32,
+    pub loss_count: u32,
+    // 保有しているコインの数量と含み損益（JPY）
+    pub open_amount: f64,
+    pub unrealized_pnl: f64,
+    // 残高（JPY）と前回のレポートからの増減（全体のみ, 取引ペア毎は0）
+    pub total_jpy: f64,
+    pub total_jpy_change: f64,
+}
+
+impl FromRow for Report {
+    // 列が多く組（タプル）で受け取れないため、列毎に変換する
+    fn from_row_opt(row: Row) -> Result<Self, FromRowError> {
+        fn column<T: FromValue>(row: &Row, index: usize) -> Option<T> {
+            row.get_opt(index)?.ok()
+        }
+        let report = (|| {
+            Some(Report {
+                bot_name: column(&row, 0)?,
+                period: column(&row, 1)?,
+                pair: column(&row, 2)?,
+                begin_at: column(&row, 3)?,
+                end_at: column(&row, 4)?,
+                trade_count: column(&row, 5)?,
+                realized_pnl: column(&row, 6)?,
+                fee: column(&row, 7)?,
+                win_count: column(&row, 8)?,
+                loss_count: column(&row, 9)?,
+                open_amount: column(&row, 10)?,
+                unrealized_pnl: column(&row, 11)?,
+                total_jpy: column(&row, 12)?,
+                total_jpy_change: column(&row, 13)?,
+            })
+        })();
+        report.ok_or(FromRowError(row))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use mysql::consts::ColumnType;
+    use mysql::{Column, Value};
+    use mysql_common::packets::column_from_payload;
+    use mysql_common::row::new_row;
+
+    fn make_column() -> Column {
+        // catalog("def"), schema, table, org_table, name, org_name, 固定長フィールド
+        let mut payload = vec![3, b'd', b'e', b'f', 0, 0, 0, 0, 0, 0x0c];
+        payload.extend_from_slice(&[0x21, 0x00]);
+        payload.extend_from_slice(&[0, 0, 0, 0]);
+        payload.push(ColumnType::MYSQL_TYPE_VAR_STRING as u8);
+        payload.extend_from_slice(&[0, 0, 0, 0, 0]);
+        column_from_payload(payload).unwrap()
+    }
+
+    fn make_row(values: Vec<Value>) -> Row {
+        let columns: Vec<Column> = values.iter().map(|_| make_column()).collect();
+        new_row(values, Arc::from(columns))
+    }
+
+    fn bytes(s: &str) -> Value {
+        Value::Bytes(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_market_from_row() {
+        let row = make_row(vec![
+            bytes("btc_jpy"),
+            Value::Double(100.0),
+            bytes("101.5"),
+            Value::Double(102.0),
+            Value::Double(1.0),
+            Value::Double(2.0),
+            Value::Date(2021, 5, 16, 12, 38, 7, 0),
+        ]);
+        let got = Market::from_row_opt(row).unwrap();
+        assert_eq!(got.pair, "btc_jpy");
+        assert_eq!(got.store_rate_avg, 100.0);
+        assert_eq!(got.ex_rate_sell, 101.5);
+        assert_eq!(got.ex_volume_buy, 2.0);
+        assert_eq!(
+            got.recorded_at,
+            NaiveDateTime::parse_from_str("2021-05-16 12:38:07", "%Y-%m-%d %H:%M:%S").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_bot_status_from_row() {
+        let memo = "it's \"quoted\"; DROP TABLE bot_statuses; --";
+        let row = make_row(vec![
+            bytes("bot"),
+            bytes("all"),
+            bytes("total_jpy"),
+            Value::Double(1000.0),
+            bytes(memo),
+        ]);
+        let got = BotStatus::from_row_opt(row).unwrap();
+        assert_eq!(got.r#type, "total_jpy");
+        assert_eq!(got.value, 1000.0);
+        assert_eq!(got.memo, memo);
+    }
+
+    #[test]
+    fn test_market_summary_from_row() {
+        let row = make_row(vec![
+            Value::Int(2),
+            Value::Date(2021, 5, 16, 12, 0, 0, 0),
+            Value::Date(2021, 5, 16, 12, 1, 0, 0),
+            Value::Double(110.0),
+            Value::Double(100.0),
+            Value::Double(111.0),
+            Value::Double(101.0),
+            Value::Double(3.0),
+            Value::Double(4.0),
+            // SUM() / COUNT() は DECIMAL で返る
+            bytes("0.5000"),
+        ]);
+        let got = MarketSummary::from_row_opt(row).unwrap();
+        assert_eq!(got.count, 2);
+        assert_eq!(got.ex_rate_sell_max, 110.0);
+        assert_eq!(got.ex_volume_buy_total, 4.0);
+        assert_eq!(got.trade_frequency_ratio, 0.5);
+    }
+
+    #[test]
+    fn test_market_summary_period() {
+        let now = DateTime::parse_from_rfc3339("2021-05-16T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let (begin, end) = MarketSummary::period(now, 1);
+        assert_eq!(begin.to_string(), "2021-05-15 11:00:00");
+        assert_eq!(end.to_string(), "2021-05-16 11:00:00");
+    }
+
+    #[test]
+    fn test_market_summary_from_row_with_null() {
+        let mut values = vec![Value::Int(0)];
+        values.extend((0..9).map(|_| Value::NULL));
+        let got = MarketSummary::from_row_opt(make_row(values));
+        assert!(got.is_err());
+    }
+
+    #[test]
+    fn test_report_from_row() {
+        let mut values = vec![
+            bytes("bot"),
+            bytes("daily"),
+            bytes("all"),
+            Value::Date(2021, 5, 15, 12, 0, 0, 0),
+            Value::Date(2021, 5, 16, 12, 0, 0, 0),
+            Value::Int(3),
+            Value::Double(120.5),
+            Value::Double(1.5),
+            Value::Int(2),
+            Value::Int(1),
+            Value::Double(0.01),
+            Value::Double(-30.0),
+            Value::Double(10120.5),
+            Value::Double(120.5),
+        ];
+        let got = Report::from_row_opt(make_row(values.clone())).unwrap();
+        assert_eq!(got.period, "daily");
+        assert_eq!(got.end_at.to_string(), "2021-05-16 12:00:00");
+        assert_eq!(got.trade_count, 3);
+        assert_eq!(got.loss_count, 1);
+        assert_eq!(got.unrealized_pnl, -30.0);
+        assert_eq!(got.total_jpy_change, 120.5);
+
+        values[6] = Value::NULL;
+        assert!(Report::from_row_opt(make_row(values)).is_err());
+    }
+}
@@ -0,0 +1,7 @@
+pub mod base;
+pub mod discord;
+pub mod email;
+pub mod line;
+pub mod model;
+pub mod router;
+pub mod throttle;
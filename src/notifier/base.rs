@@ -0,0 +1,17 @@
+use crate::error::MyResult;
+use crate::notifier::model::Notification;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+// 通知の送り先（Slack, Discord, LINE, メールなど）
+#[async_trait]
+pub trait Notifier {
+    async fn notify(&self, notification: &Notification) -> MyResult<()>;
+
+    // 送信を保留している通知（まとめた通知, 再送待ちの通知）があれば送る
+    // force: 送信予定の時刻になっていなくても送る（終了時など）
+    async fn flush(&self, _now: &DateTime<Utc>, _force: bool) -> MyResult<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use crate::error::MyError::HttpStatusError;
+use crate::error::MyResult;
+use crate::notifier::base::Notifier;
+use crate::notifier::model::Notification;
+
+use async_trait::async_trait;
+use log::debug;
+use serde_json::{json, Value};
+
+// 1つの Embed に表示できる項目の最大数（Discord の制限）
+const MAX_FIELDS_PER_EMBED: usize = 25;
+// 項目の値の最大文字数（Discord の制限）
+const MAX_FIELD_VALUE_LEN: usize = 1024;
+
+// Discord の Webhook に通知する
+#[derive(Debug)]
+pub struct DiscordNotifier {
+    client: reqwest::Client,
+    webhook_url: String,
+}
+
+impl DiscordNotifier {
+    pub fn new(webhook_url: &str) -> MyResult<DiscordNotifier> {
+        let client = reqwest::Client::builder().build()?;
+        Ok(DiscordNotifier {
+            client,
+            webhook_url: webhook_url.to_owned(),
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    async fn notify(&self, notification: &Notification) -> MyResult<()> {
+        let res = self
+            .client
+            .post(&self.webhook_url)
+            .json(&to_payload(notification))
+            .send()
+            .await?;
+        let status = res.status();
+        let body = res.text().await?;
+        debug!("post discord webhook response ... {} {}", status, body);
+        if !status.is_success() {
+            // URL にはトークンが含まれるため、エラーには残さない
+            return Err(Box::new(HttpStatusError {
+                status: status.as_u16(),
+                url: "discord webhook".to_owned(),
+                body,
+            }));
+        }
+        Ok(())
+    }
+}
+
+// Embed のメッセージにする（色は10進数の整数で指定する）
+fn to_payload(notification: &Notification) -> Value {
+    let color = u32::from_str_radix(notification.level.color().trim_start_matches('#'), 16)
+        .unwrap_or_default();
+    let fields: Vec<Value> = notification
+        .fields
+        .iter()
+        .take(MAX_FIELDS_PER_EMBED)
+        .map(|f| {
+            json!({
+                "name": f.title,
+                "value": truncate(&f.value, MAX_FIELD_VALUE_LEN),
+                "inline": true,
+            })
+        })
+        .collect();
+    let mut embed = json!({
+        "title": notification.title,
+        "color": color,
+        "fields": fields,
+    });
+    if let Some(link) = &notification.link {
+        embed["url"] = json!(link.url);
+        embed["footer"] = json!({ "text": link.text });
+    }
+    json!({ "embeds": [embed] })
+}
+
+fn truncate(s: &str, max_len: usize) -> String {
+    if s.chars().count() <= max_len {
+        return s.to_owned();
+    }
+    let mut v: String = s.chars().take(max_len - 1).collect();
+    v.push('…');
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::model::{Event, Level};
+
+    #[test]
+    fn test_to_payload() {
+        let notification = Notification::new(Event::LossCut, "losscut completed!", Level::Danger)
+            .field("pair", "btc_jpy")
+            .field("error", &"x".repeat(2000))
+            .link("chart", "https://example.com/btc_jpy");
+        let got = to_payload(&notification);
+        assert_eq!(got["embeds"][0]["title"], "losscut completed!");
+        assert_eq!(got["embeds"][0]["color"], 0xa30200);
+        assert_eq!(got["embeds"][0]["url"], "https://example.com/btc_jpy");
+        assert_eq!(got["embeds"][0]["footer"]["text"], "chart");
+        assert_eq!(
+            got["embeds"][0]["fields"][0],
+            json!({ "name": "pair", "value": "btc_jpy", "inline": true })
+        );
+        let value = got["embeds"][0]["fields"][1]["value"].as_str().unwrap();
+        assert_eq!(value.chars().count(), MAX_FIELD_VALUE_LEN);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::config::{Config, SmtpSecurity};
+use crate::error::MyError::ResponseError;
+use crate::error::MyResult;
+use crate::notifier::base::Notifier;
+use crate::notifier::model::Notification;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use log::debug;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_native_tls::native_tls;
+
+// 送信全体のタイムアウト（応答しないサーバーで取引を止めないため）
+const SEND_TIMEOUT_SEC: u64 = 30;
+// 本文（base64）の1行の文字数
+const BODY_LINE_LEN: usize = 76;
+
+// SMTP の接続情報
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmtpSetting {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+impl SmtpSetting {
+    // SMTP サーバーが未指定なら None
+    pub fn from_config(config: &Config) -> Option<SmtpSetting> {
+        let host = config.smtp_host.clone()?;
+        Some(SmtpSetting {
+            host,
+            port: config.smtp_port,
+            security: config.smtp_security,
+            username: config.smtp_username.clone(),
+            password: config.smtp_password.clone(),
+            from: config.smtp_from.clone(),
+            to: config.smtp_to.clone(),
+        })
+    }
+}
+
+// SMTP でメールを送って通知する
+#[derive(Debug)]
+pub struct EmailNotifier {
+    setting: SmtpSetting,
+}
+
+impl EmailNotifier {
+    pub fn new(setting: SmtpSetting) -> EmailNotifier {
+        EmailNotifier { setting }
+    }
+
+    async fn send(&self, notification: &Notification) -> MyResult<()> {
+        let s = &self.setting;
+        let stream = TcpStream::connect((s.host.as_str(), s.port)).await?;
+        let mut session = match s.security {
+            SmtpSecurity::Tls => Session::new(self.url(), tls_connect(&s.host, stream).await?),
+            _ => Session::new(self.url(), Box::new(stream)),
+        };
+        session.reply(&[220]).await?;
+        session.command("EHLO localhost", &[250]).await?;
+        if s.security == SmtpSecurity::Starttls {
+            session.command("STARTTLS", &[220]).await?;
+            session = session.upgrade(&s.host).await?;
+            session.command("EHLO localhost", &[250]).await?;
+        }
+        if let (Some(username), Some(password)) = (&s.username, &s.password) {
+            let credential = base64::encode(format!("\0{}\0{}", username, password));
+            session
+                .command_masked(
+                    &format!("AUTH PLAIN {}", credential),
+                    "AUTH PLAIN ***",
+                    &[235],
+                )
+                .await?;
+        }
+        session
+            .command(&format!("MAIL FROM:<{}>", s.from), &[250])
+            .await?;
+        for to in s.to.iter() {
+            session
+                .command(&format!("RCPT TO:<{}>", to), &[250, 251])
+                .await?;
+        }
+        session.command("DATA", &[354]).await?;
+        session
+            .command(&format!("{}\r\n.", make_message(s, notification)), &[250])
+            .await?;
+        session.command("QUIT", &[221]).await?;
+        Ok(())
+    }
+
+    fn url(&self) -> String {
+        format!("smtp://{}:{}", self.setting.host, self.setting.port)
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    async fn notify(&self, notification: &Notification) -> MyResult<()> {
+        tokio::time::timeout(
+            Duration::from_secs(SEND_TIMEOUT_SEC),
+            self.send(notification),
+        )
+        .await?
+    }
+}
+
+trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
+
+async fn tls_connect<T>(host: &str, stream: T) -> MyResult<Box<dyn AsyncStream>>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let connector = tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+    Ok(Box::new(connector.connect(host, stream).await?))
+}
+
+// SMTP のコマンドを送って応答を確認する
+struct Session {
+    url: String,
+    stream: BufReader<Box<dyn AsyncStream>>,
+}
+
+impl Session {
+    fn new(url: String, stream: Box<dyn AsyncStream>) -> Session {
+        Session {
+            url,
+            stream: BufReader::new(stream),
+        }
+    }
+
+    // STARTTLS の後に TLS の接続に切り替える
+    async fn upgrade(self, host: &str) -> MyResult<Session> {
+        let stream = tls_connect(host, self.stream.into_inner()).await?;
+        Ok(Session::new(self.url, stream))
+    }
+
+    async fn command(&mut self, line: &str, want: &[u16]) -> MyResult<String> {
+        self.command_masked(line, line, want).await
+    }
+
+    // masked: ログ, エラーに残すコマンド（認証情報を隠すため）
+    async fn command_masked(&mut self, line: &str, masked: &str, want: &[u16]) -> MyResult<String> {
+        debug!(
+            "smtp command ... {}",
+            masked.lines().next().unwrap_or_default()
+        );
+        let stream = self.stream.get_mut();
+        stream.write_all(format!("{}\r\n", line).as_bytes()).await?;
+        stream.flush().await?;
+        self.reply(want).await.map_err(|err| {
+            let err: Box<dyn std::error::Error> = Box::new(ResponseError {
+                message: err.to_string(),
+                url: self.url.clone(),
+                request: masked.lines().next().unwrap_or_default().to_owned(),
+            });
+            err
+        })
+    }
+
+    // 複数行の応答（"250-..." が続き "250 ..." で終わる）を読む
+    async fn reply(&mut self, want: &[u16]) -> MyResult<String> {
+        let mut lines = vec![];
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await? == 0 {
+                return Err(Box::new(ResponseError {
+                    message: "connection closed".to_owned(),
+                    url: self.url.clone(),
+                    request: "".to_owned(),
+                }));
+            }
+            let line = line.trim_end().to_owned();
+            let is_last = line.as_bytes().get(3) != Some(&b'-');
+            lines.push(line);
+            if is_last {
+                break;
+            }
+        }
+        let reply = lines.join("\n");
+        debug!("smtp reply ... {}", reply);
+        let code: u16 = reply
+            .get(..3)
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default();
+        if !want.contains(&code) {
+            return Err(Box::new(ResponseError {
+                message: format!("unexpected reply, {}", reply),
+                url: self.url.clone(),
+                request: "".to_owned(),
+            }));
+        }
+        Ok(reply)
+    }
+}
+
+// 件名, 本文は日本語を含められるよう base64 で符号化する
+fn make_message(setting: &SmtpSetting, notification: &Notification) -> String {
+    let body = base64::encode(notification.to_plain_text().replace('\n', "\r\n"));
+    let body: Vec<&str> = body
+        .as_bytes()
+        .chunks(BODY_LINE_LEN)
+        .map(|v| std::str::from_utf8(v).unwrap_or_default())
+        .collect();
+    [
+        format!("From: {}", setting.from),
+        format!("To: {}", setting.to.join(", ")),
+        format!(
+            "Subject: =?UTF-8?B?{}?=",
+            base64::encode(&notification.title)
+        ),
+        format!("Date: {}", Utc::now().to_rfc2822()),
+        "MIME-Version: 1.0".to_owned(),
+        "Content-Type: text/plain; charset=UTF-8".to_owned(),
+        "Content-Transfer-Encoding: base64".to_owned(),
+        "".to_owned(),
+        body.join("\r\n"),
+    ]
+    .join("\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::model::{Event, Level};
+    use tokio::net::TcpListener;
+
+    // 受け取ったコマンドを記録する SMTP サーバー
+    async fn serve_once(listener: TcpListener) -> Vec<String> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        let mut received = vec![];
+        stream.get_mut().write_all(b"220 ready\r\n").await.unwrap();
+        loop {
+            let mut line = String::new();
+            if stream.read_line(&mut line).await.unwrap() == 0 {
+                break;
+            }
+            let line = line.trim_end().to_owned();
+            let reply: &[u8] = if line.starts_with("EHLO") {
+                b"250-localhost\r\n250 AUTH PLAIN\r\n"
+            } else if line.starts_with("AUTH") {
+                b"235 ok\r\n"
+            } else if line == "DATA" {
+                b"354 go ahead\r\n"
+            } else if line == "." {
+                b"250 queued\r\n"
+            } else if line == "QUIT" {
+                b"221 bye\r\n"
+            } else if line.starts_with("MAIL") || line.starts_with("RCPT") {
+                b"250 ok\r\n"
+            } else {
+                // メールの本文
+                b""
+            };
+            received.push(line.clone());
+            stream.get_mut().write_all(reply).await.unwrap();
+            if line == "QUIT" {
+                break;
+            }
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn test_notify() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(serve_once(listener));
+
+        let notifier = EmailNotifier::new(SmtpSetting {
+            host: "127.0.0.1".to_owned(),
+            port,
+            security: SmtpSecurity::None,
+            username: Some("user".to_owned()),
+            password: Some("pass".to_owned()),
+            from: "bot@example.com".to_owned(),
+            to: vec!["a@example.com".to_owned(), "b@example.com".to_owned()],
+        });
+        let notification = Notification::new(Event::LossCut, "losscut completed!", Level::Danger)
+            .field("pair", "btc_jpy");
+        notifier.notify(&notification).await.unwrap();
+
+        let received = server.await.unwrap();
+        assert_eq!(
+            &received[..5],
+            &[
+                "EHLO localhost".to_owned(),
+                format!("AUTH PLAIN {}", base64::encode("\0user\0pass")),
+                "MAIL FROM:<bot@example.com>".to_owned(),
+                "RCPT TO:<a@example.com>".to_owned(),
+                "RCPT TO:<b@example.com>".to_owned(),
+            ]
+        );
+        assert!(received.contains(&"To: a@example.com, b@example.com".to_owned()));
+        assert!(received.contains(&format!(
+            "Subject: =?UTF-8?B?{}?=",
+            base64::encode("losscut completed!")
+        )));
+        assert!(received.contains(&base64::encode("losscut completed!\r\npair: btc_jpy")));
+        assert_eq!(
+            &received[received.len() - 2..],
+            &[".".to_owned(), "QUIT".to_owned()]
+        );
+    }
+}
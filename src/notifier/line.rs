@@ -0,0 +1,83 @@
+use crate::error::MyError::HttpStatusError;
+use crate::error::MyResult;
+use crate::notifier::base::Notifier;
+use crate::notifier::model::Notification;
+
+use async_trait::async_trait;
+use log::debug;
+
+const NOTIFY_URL: &str = "https://notify-api.line.me/api/notify";
+// メッセージの最大文字数（LINE Notify の制限）
+const MAX_MESSAGE_LEN: usize = 1000;
+
+// LINE Notify で通知する
+#[derive(Debug)]
+pub struct LineNotifier {
+    client: reqwest::Client,
+    token: String,
+}
+
+impl LineNotifier {
+    pub fn new(token: &str) -> MyResult<LineNotifier> {
+        let client = reqwest::Client::builder().build()?;
+        Ok(LineNotifier {
+            client,
+            token: token.to_owned(),
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for LineNotifier {
+    async fn notify(&self, notification: &Notification) -> MyResult<()> {
+        let res = self
+            .client
+            .post(NOTIFY_URL)
+            .bearer_auth(&self.token)
+            .form(&[("message", to_message(notification))])
+            .send()
+            .await?;
+        let status = res.status();
+        let body = res.text().await?;
+        debug!("post line notify response ... {} {}", status, body);
+        if !status.is_success() {
+            return Err(Box::new(HttpStatusError {
+                status: status.as_u16(),
+                url: NOTIFY_URL.to_owned(),
+                body,
+            }));
+        }
+        Ok(())
+    }
+}
+
+// 先頭にトークン名が付くため、改行してから本文を続ける
+fn to_message(notification: &Notification) -> String {
+    let text = format!("\n{}", notification.to_plain_text());
+    if text.chars().count() <= MAX_MESSAGE_LEN {
+        return text;
+    }
+    let mut v: String = text.chars().take(MAX_MESSAGE_LEN - 1).collect();
+    v.push('…');
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::model::{Event, Level};
+
+    #[test]
+    fn test_to_message() {
+        let notification = Notification::new(Event::Entry, "entry completed!", Level::Good)
+            .field("pair", "btc_jpy");
+        assert_eq!(
+            to_message(&notification),
+            "\nentry completed!\npair: btc_jpy"
+        );
+
+        let notification = Notification::new(Event::Failure, "entry failed!", Level::Danger)
+            .field("error", &"x".repeat(2000));
+        assert_eq!(to_message(&notification).chars().count(), MAX_MESSAGE_LEN);
+    }
+}
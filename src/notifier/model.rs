@@ -0,0 +1,160 @@
+use serde::de::IntoDeserializer;
+use serde::Deserialize;
+
+// 通知の種類（送り先の振り分けに使う）
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    Entry,
+    LossCut,
+    Sell,
+    SetProfit,
+    AvgDown,
+    Recover,
+    // 途中で失敗したアクションの再開, 補償
+    InFlight,
+    // アクションの失敗
+    Failure,
+    UnusedCoin,
+    Shutdown,
+    // 日次, 週次レポート
+    Report,
+    // 優先度の低い通知をまとめたもの
+    Digest,
+}
+
+// 通知の送り先
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Slack,
+    Discord,
+    Line,
+    Email,
+}
+
+impl Event {
+    pub fn parse(s: &str) -> Result<Event, String> {
+        Event::deserialize(s.trim().into_deserializer())
+            .map_err(|e: serde::de::value::Error| format!("invalid event, {}", e))
+    }
+}
+
+impl Channel {
+    pub fn parse(s: &str) -> Result<Channel, String> {
+        Channel::deserialize(s.trim().into_deserializer())
+            .map_err(|e: serde::de::value::Error| format!("invalid channel, {}", e))
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Channel::Slack => "slack",
+            Channel::Discord => "discord",
+            Channel::Line => "line",
+            Channel::Email => "email",
+        }
+    }
+}
+
+// 通知の重要度（色の表示に使う, 後のものほど重要）
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    // 利確, エントリーなど
+    Good,
+    Info,
+    // ナンピン, 回収など注意が必要なもの
+    Warning,
+    // 損切り, 失敗
+    Danger,
+}
+
+impl Level {
+    pub fn color(&self) -> &str {
+        match self {
+            Level::Good => "#2eb886",
+            Level::Info => "#439fe0",
+            Level::Warning => "#daa038",
+            Level::Danger => "#a30200",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub title: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub text: String,
+    pub url: String,
+}
+
+// 構造化した通知（タイトル, 項目, リンク）
+// 表示方法は送り先毎に変える（Slack は Block Kit, Discord は Embed, LINE, メールは文字列）
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub event: Event,
+    pub title: String,
+    pub level: Level,
+    pub fields: Vec<Field>,
+    pub link: Option<Link>,
+}
+
+impl Notification {
+    pub fn new(event: Event, title: &str, level: Level) -> Notification {
+        Notification {
+            event,
+            title: title.to_owned(),
+            level,
+            fields: vec![],
+            link: None,
+        }
+    }
+
+    pub fn field(mut self, title: &str, value: &str) -> Notification {
+        self.fields.push(Field {
+            title: title.to_owned(),
+            value: value.to_owned(),
+        });
+        self
+    }
+
+    pub fn link(mut self, text: &str, url: &str) -> Notification {
+        self.link = Some(Link {
+            text: text.to_owned(),
+            url: url.to_owned(),
+        });
+        self
+    }
+
+    // 装飾なしの文字列（通知を表示できない環境, ログ用）
+    pub fn to_plain_text(&self) -> String {
+        let mut lines = vec![self.title.clone()];
+        lines.extend(
+            self.fields
+                .iter()
+                .map(|f| format!("{}: {}", f.title, f.value)),
+        );
+        if let Some(link) = &self.link {
+            lines.push(format!("{}: {}", link.text, link.url));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Event::parse("loss_cut"), Ok(Event::LossCut));
+        assert_eq!(Event::parse(" in_flight "), Ok(Event::InFlight));
+        assert!(Event::parse("losscut").is_err());
+
+        assert_eq!(Channel::parse("line"), Ok(Channel::Line));
+        assert!(Channel::parse("teams").is_err());
+    }
+}
@@ -0,0 +1,345 @@
+use crate::config::Config;
+use crate::error::MyError::ResponseError;
+use crate::error::MyResult;
+use crate::notifier::base::Notifier;
+use crate::notifier::discord::DiscordNotifier;
+use crate::notifier::email::{EmailNotifier, SmtpSetting};
+use crate::notifier::line::LineNotifier;
+use crate::notifier::model::{Channel, Event, Notification};
+use crate::slack;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use colored::Colorize;
+use log::{error, info, warn};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+
+// 再送の間隔（秒, 失敗する毎に倍にする）
+const RETRY_INTERVAL_SEC: i64 = 30;
+const MAX_RETRY_INTERVAL_SEC: i64 = 60 * 30;
+// 再送待ちにする通知の最大数（超えた場合は古いものから捨てる）
+const MAX_PENDING: usize = 100;
+
+// 通知の種類に応じて、複数の送り先に通知する
+// 送り先を指定していない種類は、全ての送り先に通知する
+// 送信に失敗した通知は送り先毎に再送待ちにし、flush で成功するまで送り直す
+// （再送待ちはメモリ上にのみ持つため、再起動すると失われる）
+pub struct Router {
+    notifiers: Vec<(Channel, Box<dyn Notifier + Send + Sync>)>,
+    routes: HashMap<Event, Vec<Channel>>,
+    pending: Mutex<VecDeque<Pending>>,
+}
+
+// 再送待ちの通知
+#[derive(Debug, Clone)]
+struct Pending {
+    channel: Channel,
+    notification: Notification,
+    attempts: u32,
+    next_at: DateTime<Utc>,
+}
+
+impl Pending {
+    fn new(channel: Channel, notification: &Notification, now: &DateTime<Utc>) -> Pending {
+        Pending {
+            channel,
+            notification: notification.clone(),
+            attempts: 1,
+            next_at: *now + Duration::seconds(RETRY_INTERVAL_SEC),
+        }
+    }
+
+    fn retry_later(mut self, now: &DateTime<Utc>) -> Pending {
+        let interval = RETRY_INTERVAL_SEC
+            .saturating_mul(1 << self.attempts.min(16))
+            .min(MAX_RETRY_INTERVAL_SEC);
+        self.attempts += 1;
+        self.next_at = *now + Duration::seconds(interval);
+        self
+    }
+}
+
+impl Router {
+    pub fn new(routes: HashMap<Event, Vec<Channel>>) -> Router {
+        Router {
+            notifiers: vec![],
+            routes,
+            pending: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn add(mut self, channel: Channel, notifier: Box<dyn Notifier + Send + Sync>) -> Router {
+        self.notifiers.push((channel, notifier));
+        self
+    }
+
+    // 設定済みの送り先を全て追加する
+    pub fn from_config(config: &Config) -> MyResult<Router> {
+        let mut router = Router::new(config.notify_routes.clone());
+        if !config.slack_url.is_empty() {
+            router = router.add(
+                Channel::Slack,
+                Box::new(slack::client::DefaultClient::new(&config.slack_url)?),
+            );
+        }
+        if let Some(url) = &config.discord_webhook_url {
+            router = router.add(Channel::Discord, Box::new(DiscordNotifier::new(url)?));
+        }
+        if let Some(token) = &config.line_notify_token {
+            router = router.add(Channel::Line, Box::new(LineNotifier::new(token)?));
+        }
+        if let Some(setting) = SmtpSetting::from_config(config) {
+            router = router.add(Channel::Email, Box::new(EmailNotifier::new(setting)));
+        }
+
+        for channel in router.routes.values().flatten() {
+            if !router.channels().contains(channel) {
+                warn!(
+                    "{}",
+                    format!("notify route to {} is not configured", channel.name()).yellow()
+                );
+            }
+        }
+        Ok(router)
+    }
+
+    // 追加済みの送り先
+    pub fn channels(&self) -> Vec<Channel> {
+        self.notifiers.iter().map(|(c, _)| *c).collect()
+    }
+
+    // 再送待ちの通知の数
+    pub fn pending_count(&self) -> usize {
+        self.lock_pending().len()
+    }
+
+    fn is_routed(&self, event: &Event, channel: &Channel) -> bool {
+        match self.routes.get(event) {
+            Some(channels) => channels.contains(channel),
+            None => true,
+        }
+    }
+
+    fn notifier(&self, channel: &Channel) -> Option<&(dyn Notifier + Send + Sync)> {
+        self.notifiers
+            .iter()
+            .find(|(c, _)| c == channel)
+            .map(|(_, n)| n.as_ref())
+    }
+
+    fn push_pending(&self, pending: Pending) {
+        let mut queue = self.lock_pending();
+        if queue.len() >= MAX_PENDING {
+            if let Some(dropped) = queue.pop_front() {
+                error!(
+                    "drop pending notification to {}, {}",
+                    dropped.channel.name(),
+                    dropped.notification.title
+                );
+            }
+        }
+        queue.push_back(pending);
+    }
+
+    // 通知中にパニックしても、再送待ちは壊れないため使い続ける
+    fn lock_pending(&self) -> MutexGuard<'_, VecDeque<Pending>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl Notifier for Router {
+    // 一部の送り先に失敗しても、残りの送り先には通知する
+    async fn notify(&self, notification: &Notification) -> MyResult<()> {
+        let now = Utc::now();
+        let mut failures = vec![];
+        for (channel, notifier) in self.notifiers.iter() {
+            if !self.is_routed(&notification.event, channel) {
+                continue;
+            }
+            if let Err(err) = notifier.notify(notification).await {
+                failures.push(format!("{}: {}", channel.name(), err));
+                self.push_pending(Pending::new(*channel, notification, &now));
+            }
+        }
+        if !failures.is_empty() {
+            return Err(Box::new(ResponseError {
+                message: format!("{} (queued for retry)", failures.join(", ")),
+                url: "notify".to_owned(),
+                request: notification.title.clone(),
+            }));
+        }
+        Ok(())
+    }
+
+    // 再送の時刻になった通知を送り直す（失敗したものは間隔を空けて再送待ちに戻す）
+    async fn flush(&self, now: &DateTime<Utc>, force: bool) -> MyResult<()> {
+        let targets: Vec<Pending> = {
+            let mut queue = self.lock_pending();
+            let (targets, rest): (Vec<Pending>, Vec<Pending>) =
+                queue.drain(..).partition(|p| force || p.next_at <= *now);
+            *queue = rest.into();
+            targets
+        };
+        let mut failures = vec![];
+        for pending in targets {
+            let notifier = match self.notifier(&pending.channel) {
+                Some(v) => v,
+                None => continue,
+            };
+            match notifier.notify(&pending.notification).await {
+                Ok(_) => info!(
+                    "retry notification to {} succeeded, attempts:{}, {}",
+                    pending.channel.name(),
+                    pending.attempts + 1,
+                    pending.notification.title
+                ),
+                Err(err) => {
+                    failures.push(format!("{}: {}", pending.channel.name(), err));
+                    self.push_pending(pending.retry_later(now));
+                }
+            }
+        }
+        if !failures.is_empty() {
+            return Err(Box::new(ResponseError {
+                message: format!("{} (queued for retry)", failures.join(", ")),
+                url: "notify".to_owned(),
+                request: "retry".to_owned(),
+            }));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::model::Level;
+    use std::sync::{Arc, Mutex};
+
+    struct RecordingNotifier {
+        channel: Channel,
+        // 失敗させる回数
+        failures: Mutex<u32>,
+        received: Arc<Mutex<Vec<(Channel, String)>>>,
+    }
+
+    impl RecordingNotifier {
+        fn new(
+            channel: Channel,
+            failures: u32,
+            received: &Arc<Mutex<Vec<(Channel, String)>>>,
+        ) -> RecordingNotifier {
+            RecordingNotifier {
+                channel,
+                failures: Mutex::new(failures),
+                received: received.clone(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Notifier for RecordingNotifier {
+        async fn notify(&self, notification: &Notification) -> MyResult<()> {
+            self.received
+                .lock()
+                .unwrap()
+                .push((self.channel, notification.title.clone()));
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err("unavailable".into());
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_notify() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let mut routes = HashMap::new();
+        routes.insert(Event::Entry, vec![Channel::Slack]);
+        routes.insert(Event::Sell, vec![]);
+        let router = [Channel::Slack, Channel::Discord, Channel::Line]
+            .iter()
+            .fold(Router::new(routes), |router, channel| {
+                let failures = if *channel == Channel::Discord { 1 } else { 0 };
+                router.add(
+                    *channel,
+                    Box::new(RecordingNotifier::new(*channel, failures, &received)),
+                )
+            });
+
+        // 指定した送り先のみ
+        let got = router
+            .notify(&Notification::new(Event::Entry, "entry", Level::Good))
+            .await;
+        assert!(got.is_ok());
+        // 送り先なし
+        let got = router
+            .notify(&Notification::new(Event::Sell, "sell", Level::Good))
+            .await;
+        assert!(got.is_ok());
+        // 指定していない種類は全ての送り先, 失敗した送り先があってもエラーになる
+        let got = router
+            .notify(&Notification::new(Event::LossCut, "losscut", Level::Danger))
+            .await;
+        assert!(got
+            .unwrap_err()
+            .to_string()
+            .contains("discord: unavailable"));
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                (Channel::Slack, "entry".to_owned()),
+                (Channel::Slack, "losscut".to_owned()),
+                (Channel::Discord, "losscut".to_owned()),
+                (Channel::Line, "losscut".to_owned()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flush() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let router = Router::new(HashMap::new())
+            .add(
+                Channel::Slack,
+                Box::new(RecordingNotifier::new(Channel::Slack, 2, &received)),
+            )
+            .add(
+                Channel::Discord,
+                Box::new(RecordingNotifier::new(Channel::Discord, 0, &received)),
+            );
+        let now = Utc::now();
+
+        let notification = Notification::new(Event::LossCut, "losscut", Level::Danger);
+        assert!(router.notify(&notification).await.is_err());
+        assert_eq!(router.pending_count(), 1);
+
+        // 再送の時刻の前は送らない
+        assert!(router.flush(&now, false).await.is_ok());
+        // 再送に失敗したら再送待ちに戻す
+        let now = now + Duration::seconds(RETRY_INTERVAL_SEC + 1);
+        assert!(router.flush(&now, false).await.is_err());
+        assert_eq!(router.pending_count(), 1);
+        assert!(router.flush(&now, false).await.is_ok());
+        assert_eq!(router.pending_count(), 1);
+        // 終了時などは時刻に関わらず送る
+        assert!(router.flush(&now, true).await.is_ok());
+        assert_eq!(router.pending_count(), 0);
+
+        // 成功した送り先には送り直さない
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                (Channel::Slack, "losscut".to_owned()),
+                (Channel::Discord, "losscut".to_owned()),
+                (Channel::Slack, "losscut".to_owned()),
+                (Channel::Slack, "losscut".to_owned()),
+            ]
+        );
+    }
+}
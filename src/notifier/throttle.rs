@@ -0,0 +1,290 @@
+use crate::config::Config;
+use crate::error::MyResult;
+use crate::notifier::base::Notifier;
+use crate::notifier::model::{Event, Level, Notification};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use log::debug;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+// まとめた通知に載せる通知の最大数（超えた分は件数のみ）
+const MAX_DIGEST_ITEMS: usize = 20;
+
+// 通知の頻度を抑える
+// 同じ内容の通知は一定時間送らず、優先度の低い種類の通知は一定時間毎にまとめて送る
+pub struct Throttle<T>
+where
+    T: Notifier,
+{
+    inner: T,
+    dedup_window: Duration,
+    digest_interval: Duration,
+    digest_events: Vec<Event>,
+    state: Mutex<ThrottleState>,
+}
+
+#[derive(Debug, Default)]
+struct ThrottleState {
+    // 通知の内容毎の最後に送った時刻
+    sent_at: HashMap<String, DateTime<Utc>>,
+    // まとめて送るまで保留している通知
+    digest: Vec<Notification>,
+    // 保留を始めた時刻
+    digest_since: Option<DateTime<Utc>>,
+}
+
+impl<T> Throttle<T>
+where
+    T: Notifier + std::marker::Sync,
+{
+    pub fn new(
+        inner: T,
+        dedup_window_sec: u64,
+        digest_interval_sec: u64,
+        digest_events: Vec<Event>,
+    ) -> Throttle<T> {
+        Throttle {
+            inner,
+            dedup_window: Duration::seconds(dedup_window_sec as i64),
+            digest_interval: Duration::seconds(digest_interval_sec as i64),
+            digest_events,
+            state: Mutex::new(ThrottleState::default()),
+        }
+    }
+
+    pub fn from_config(inner: T, config: &Config) -> Throttle<T> {
+        Throttle::new(
+            inner,
+            config.notify_dedup_window_sec,
+            config.notify_digest_interval_sec,
+            config.notify_digest_events.clone(),
+        )
+    }
+
+    async fn notify_at(&self, notification: &Notification, now: &DateTime<Utc>) -> MyResult<()> {
+        if !self.accept(notification, now) {
+            return Ok(());
+        }
+        self.inner.notify(notification).await
+    }
+
+    // すぐに送る通知なら true（重複していれば捨て, まとめる種類なら保留する）
+    fn accept(&self, notification: &Notification, now: &DateTime<Utc>) -> bool {
+        let mut state = self.lock();
+        let window = self.dedup_window;
+        state.sent_at.retain(|_, t| *now - *t < window);
+
+        let key = format!("{:?}\n{}", notification.event, notification.to_plain_text());
+        if state.sent_at.contains_key(&key) {
+            debug!("skip duplicated notification, {}", notification.title);
+            return false;
+        }
+        state.sent_at.insert(key, *now);
+
+        if self.digest_events.contains(&notification.event) {
+            debug!("hold notification for digest, {}", notification.title);
+            state.digest_since.get_or_insert(*now);
+            state.digest.push(notification.clone());
+            return false;
+        }
+        true
+    }
+
+    // まとめて送る時刻になっていれば、保留している通知を取り出す
+    fn take_digest(&self, now: &DateTime<Utc>, force: bool) -> Option<Notification> {
+        let mut state = self.lock();
+        let since = state.digest_since?;
+        if !force && *now < since + self.digest_interval {
+            return None;
+        }
+        state.digest_since = None;
+        let items = std::mem::take(&mut state.digest);
+        Some(make_digest(&items))
+    }
+
+    // 通知中にパニックしても、状態は壊れないため使い続ける
+    fn lock(&self) -> MutexGuard<'_, ThrottleState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl<T> Notifier for Throttle<T>
+where
+    T: Notifier + std::marker::Send + std::marker::Sync,
+{
+    async fn notify(&self, notification: &Notification) -> MyResult<()> {
+        self.notify_at(notification, &Utc::now()).await
+    }
+
+    async fn flush(&self, now: &DateTime<Utc>, force: bool) -> MyResult<()> {
+        let digest_error = match self.take_digest(now, force) {
+            Some(digest) => self
+                .inner
+                .notify(&digest)
+                .await
+                .err()
+                .map(|e| e.to_string()),
+            None => None,
+        };
+        // まとめた通知に失敗しても、再送は行う
+        let flush_result = self.inner.flush(now, force).await;
+        match digest_error {
+            Some(err) => Err(err.into()),
+            None => flush_result,
+        }
+    }
+}
+
+// 重要度は最も重要な通知に合わせる
+fn make_digest(items: &[Notification]) -> Notification {
+    let level = items
+        .iter()
+        .map(|n| n.level)
+        .fold(Level::Good, |a, b| if b > a { b } else { a });
+    let title = format!("digest ({} notifications)", items.len());
+    let mut digest = items.iter().take(MAX_DIGEST_ITEMS).fold(
+        Notification::new(Event::Digest, &title, level),
+        |d, n| {
+            let value = n
+                .fields
+                .iter()
+                .map(|f| format!("{}: {}", f.title, f.value))
+                .collect::<Vec<String>>()
+                .join(", ");
+            d.field(&n.title, if value.is_empty() { "-" } else { &value })
+        },
+    );
+    if items.len() > MAX_DIGEST_ITEMS {
+        digest = digest.field(
+            "more",
+            &format!("{} notifications", items.len() - MAX_DIGEST_ITEMS),
+        );
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    struct RecordingNotifier {
+        received: Arc<Mutex<Vec<Notification>>>,
+    }
+
+    #[async_trait]
+    impl Notifier for RecordingNotifier {
+        async fn notify(&self, notification: &Notification) -> MyResult<()> {
+            self.received.lock().unwrap().push(notification.clone());
+            Ok(())
+        }
+    }
+
+    fn make_throttle() -> (Throttle<RecordingNotifier>, Arc<Mutex<Vec<Notification>>>) {
+        let received = Arc::new(Mutex::new(vec![]));
+        let inner = RecordingNotifier {
+            received: received.clone(),
+        };
+        (
+            Throttle::new(inner, 600, 3600, vec![Event::UnusedCoin]),
+            received,
+        )
+    }
+
+    fn titles(received: &Arc<Mutex<Vec<Notification>>>) -> Vec<String> {
+        received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|n| n.title.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_dedup() {
+        let (throttle, received) = make_throttle();
+        let now = DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let failure = |error: &str| {
+            Notification::new(Event::Failure, "entry failed!", Level::Danger).field("error", error)
+        };
+
+        throttle.notify_at(&failure("a"), &now).await.unwrap();
+        // 同じ内容は送らない
+        let t = now + Duration::seconds(599);
+        throttle.notify_at(&failure("a"), &t).await.unwrap();
+        // 内容が違えば送る
+        throttle.notify_at(&failure("b"), &t).await.unwrap();
+        // 一定時間経てば同じ内容でも送る
+        let t = now + Duration::seconds(600);
+        throttle.notify_at(&failure("a"), &t).await.unwrap();
+
+        let got: Vec<String> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|n| n.fields[0].value.clone())
+            .collect();
+        assert_eq!(got, vec!["a", "b", "a"]);
+    }
+
+    #[tokio::test]
+    async fn test_digest() {
+        let (throttle, received) = make_throttle();
+        let now = DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let unused_coin = |amount: &str| {
+            Notification::new(Event::UnusedCoin, "unused coin exist", Level::Warning)
+                .field("amount", amount)
+        };
+
+        throttle.notify_at(&unused_coin("1.0"), &now).await.unwrap();
+        let t = now + Duration::seconds(60);
+        throttle.notify_at(&unused_coin("2.0"), &t).await.unwrap();
+        throttle
+            .notify_at(
+                &Notification::new(Event::Entry, "entry completed!", Level::Good),
+                &t,
+            )
+            .await
+            .unwrap();
+        assert_eq!(titles(&received), vec!["entry completed!"]);
+
+        // 保留を始めてから一定時間経つまでは送らない
+        let t = now + Duration::seconds(3599);
+        throttle.flush(&t, false).await.unwrap();
+        assert_eq!(received.lock().unwrap().len(), 1);
+
+        let t = now + Duration::seconds(3600);
+        throttle.flush(&t, false).await.unwrap();
+        throttle.flush(&t, true).await.unwrap();
+        assert_eq!(
+            titles(&received),
+            vec!["entry completed!", "digest (2 notifications)"]
+        );
+        let digest = received.lock().unwrap()[1].clone();
+        assert_eq!(digest.event, Event::Digest);
+        assert_eq!(digest.level, Level::Warning);
+        assert_eq!(digest.fields[0].title, "unused coin exist");
+        assert_eq!(digest.fields[0].value, "amount: 1.0");
+        assert_eq!(digest.fields[1].value, "amount: 2.0");
+    }
+
+    #[test]
+    fn test_make_digest() {
+        let items: Vec<Notification> = (0..25)
+            .map(|i| Notification::new(Event::Recover, &format!("n{}", i), Level::Info))
+            .collect();
+        let digest = make_digest(&items);
+        assert_eq!(digest.title, "digest (25 notifications)");
+        assert_eq!(digest.level, Level::Info);
+        assert_eq!(digest.fields.len(), MAX_DIGEST_ITEMS + 1);
+        assert_eq!(digest.fields[0].value, "-");
+        assert_eq!(digest.fields[MAX_DIGEST_ITEMS].value, "5 notifications");
+    }
+}
@@ -1,15 +1,24 @@
+use crate::bot::action::ActionBehavior;
+use crate::bot::base::Bot;
 use crate::coincheck::mock::SimulationClient;
-use crate::coincheck::model::Pair;
 use crate::config::Config;
 use crate::error::MyResult;
-use crate::mysql::model::Market;
-use crate::simulator::model::CSVRecord;
+use crate::mysql::client::Client;
+use crate::mysql::memory::MemoryClient;
+use crate::mysql::model::{BotStatus, Market};
+use crate::notifier::base::Notifier;
+use crate::notifier::model::Notification;
+use crate::simulator::loader::{load_markets, MarketQuery};
 use crate::strategy::base::Strategy;
 use crate::strategy::scalping::ScalpingStrategy;
+use async_trait::async_trait;
 use chrono::DateTime;
+use chrono::Duration;
 use chrono::Utc;
-use std::fs::File;
-use std::io::BufReader;
+use log::{debug, info};
+
+// 保持する市場データの期間（select_market_summary は1時間前までの24時間分を使う）
+const MARKET_RETENTION_HOURS: i64 = 25;
 
 #[derive(Debug, PartialEq)]
 pub struct Simulator<'a> {
@@ -17,63 +26,121 @@ pub struct Simulator<'a> {
 }
 
 #[derive(Debug, PartialEq)]
-pub struct SimulationResult {}
+pub struct SimulationResult {
+    // 終了時にボットが記録している残高（total_jpy）
+    pub total_jpy: f64,
+    // 終了時の残高の合計（コインは直近の売りレートで評価する）
+    pub total_balance_jpy: f64,
+    // 支払った手数料の合計（通貨 => 手数料）
+    pub total_fees: Vec<(String, f64)>,
+}
+
+// シミュレーションでは通知せず、ログのみ出す
+struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn notify(&self, notification: &Notification) -> MyResult<()> {
+        debug!("notify: {:?}", notification);
+        Ok(())
+    }
+}
 
 impl Simulator<'_> {
-    pub fn new(config: &Config) -> MyResult<Simulator> {
+    pub fn new(config: &Config) -> MyResult<Simulator<'_>> {
         Ok(Simulator { config: config })
     }
 
-    pub async fn run(&self, market_data_path: &str, pair: &Pair) -> MyResult<SimulationResult> {
+    pub async fn run(
+        &self,
+        market_data_path: &str,
+        query: &MarketQuery,
+    ) -> MyResult<SimulationResult> {
+        self.run_markets(load_markets(market_data_path, query)?)
+            .await
+    }
+
+    // 市場データを1件ずつ追加しながら、本番と同じ Bot::trade で取引する
+    pub async fn run_markets(&self, markets: Vec<Market>) -> MyResult<SimulationResult> {
         let mut client: SimulationClient = SimulationClient::new()?;
+        client.set_fee_rates(&self.config.fee_rates);
+        let storage = MemoryClient::new();
+        let notifier = LogNotifier;
         let strategy = ScalpingStrategy {
             config: self.config,
         };
+        let action_behavior = ActionBehavior {
+            config: self.config,
+            notifier: &notifier,
+            mysql_client: &storage,
+            coincheck_client: &client,
+        };
+        let bot = Bot {
+            config: self.config,
+            coincheck_client: &client,
+            mysql_client: &storage,
+            notifier: &notifier,
+            strategy: &strategy,
+            action_behavior: &action_behavior,
+        };
 
+        let settlement = self.config.settlement_currency();
         let balance_jpy = 100000.0;
-        let buy_jpy_per_lot = balance_jpy * self.config.funds_ratio_per_order;
+        client.set_balance(&settlement, balance_jpy);
+        client.set_balance(&self.config.key_currency(), 0.0);
+        storage.upsert_bot_status(&BotStatus {
+            bot_name: self.config.bot_name.to_owned(),
+            pair: "all".to_owned(),
+            r#type: "total_jpy".to_owned(),
+            value: balance_jpy,
+            memo: "残高（JPY）".to_owned(),
+        })?;
 
-        let buf = BufReader::new(File::open(market_data_path)?);
-        let mut csv_reader = csv::ReaderBuilder::new().has_headers(true).from_reader(buf);
-        for r in csv_reader.deserialize() {
-            let record: CSVRecord = r?;
-            if record.pair != pair.to_string() {
-                continue;
+        for market in markets.iter() {
+            if let Err(err) = self.run_one_step(&client, &storage, &bot, market).await {
+                debug!("failed to trade, {}", err);
             }
-            let market = record.to_model()?;
+        }
 
-            match self
-                .run_one_step(buy_jpy_per_lot, &mut client, &strategy, &market)
-                .await
-            {
-                Ok(_) => {}
-                Err(_err) => {}
-            };
+        for line in storage.dump_bot_statuses()? {
+            info!("{}", line);
+        }
+        let mut total_fees: Vec<(String, f64)> = client.total_fees().into_iter().collect();
+        total_fees.sort_by(|a, b| a.0.cmp(&b.0));
+        for (currency, fee) in total_fees.iter() {
+            info!("total fee: {} {}", fee, currency);
         }
 
-        Ok(SimulationResult {})
+        let total_jpy = storage
+            .select_bot_status(&self.config.bot_name, "all", "total_jpy")?
+            .value;
+        Ok(SimulationResult {
+            total_jpy,
+            total_balance_jpy: client.total_balance_jpy(&settlement),
+            total_fees,
+        })
     }
 
-    async fn run_one_step<T>(
+    async fn run_one_step<T, W>(
         &self,
-        buy_jpy_per_lot: f64,
-        client: &mut SimulationClient,
-        strategy: &T,
+        client: &SimulationClient,
+        storage: &MemoryClient,
+        bot: &Bot<'_, T, MemoryClient, SimulationClient, W>,
         market: &Market,
     ) -> MyResult<()>
     where
-        T: Strategy,
+        T: Notifier,
+        W: Strategy,
     {
+        // 市場データのレートに達した注文は、取引の前に約定させる
         client.add_market(market)?;
 
-        let info = client.make_info(&market.pair, self.config)?;
-        let now = DateTime::<Utc>::from_utc(market.recorded_at, Utc);
-
-        match strategy.judge(&now, &info, buy_jpy_per_lot, client).await {
-            Ok(_actions) => {}
-            Err(_err) => {}
-        };
+        let now = DateTime::<Utc>::from_naive_utc_and_offset(market.recorded_at, Utc);
+        storage.add_market(market)?;
+        storage
+            .remove_markets_before((now - Duration::hours(MARKET_RETENTION_HOURS)).naive_utc())?;
+        storage.set_now(now)?;
 
-        Ok(())
+        bot.trade(&now).await
     }
 }
@@ -0,0 +1,511 @@
+use crate::coincheck::model::Pair;
+use crate::error::MyError::{KeyNotFound, ParseError};
+use crate::error::MyResult;
+use crate::mysql::model::{Market, Markets};
+use crate::simulator::model::CSVRecord;
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufReader, Read};
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Float64Type, TimestampSecondType};
+use arrow_array::{Array, ArrayRef, BooleanArray, RecordBatch};
+use arrow_cast::cast;
+use arrow_schema::{ArrowError, DataType, Schema, TimeUnit};
+use chrono::NaiveDateTime;
+use flate2::read::GzDecoder;
+use parquet::arrow::arrow_reader::{ArrowPredicateFn, ParquetRecordBatchReaderBuilder, RowFilter};
+use parquet::arrow::ProjectionMask;
+use parquet::basic::Type as PhysicalType;
+use parquet::file::metadata::RowGroupMetaData;
+
+const RECORDED_AT_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+const COLUMN_PAIR: &str = "pair";
+const COLUMN_RECORDED_AT: &str = "recorded_at";
+const COLUMNS_RATE: [&str; 5] = [
+    "store_rate_avg",
+    "ex_rate_sell",
+    "ex_rate_buy",
+    "ex_volume_sell",
+    "ex_volume_buy",
+];
+
+// 市場データの読み込み条件
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarketQuery {
+    pub pair: String,
+    // 指定した日時以降（この日時を含む）のデータのみ読み込む
+    pub begin: Option<NaiveDateTime>,
+    // 指定した日時以前（この日時を含む）のデータのみ読み込む
+    pub end: Option<NaiveDateTime>,
+}
+
+impl MarketQuery {
+    pub fn new(pair: &Pair) -> MarketQuery {
+        MarketQuery {
+            pair: pair.to_string(),
+            begin: None,
+            end: None,
+        }
+    }
+
+    fn begin_timestamp(&self) -> Option<i64> {
+        self.begin.map(|v| v.and_utc().timestamp())
+    }
+
+    fn end_timestamp(&self) -> Option<i64> {
+        self.end.map(|v| v.and_utc().timestamp())
+    }
+}
+
+// 市場データのファイル形式（拡張子で判別）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MarketDataFormat {
+    Csv,
+    CsvGzip,
+    CsvZstd,
+    Parquet,
+}
+
+impl MarketDataFormat {
+    pub fn detect(path: &str) -> MyResult<MarketDataFormat> {
+        let lower = path.to_lowercase();
+        if lower.ends_with(".csv") {
+            Ok(MarketDataFormat::Csv)
+        } else if lower.ends_with(".csv.gz") || lower.ends_with(".csv.gzip") {
+            Ok(MarketDataFormat::CsvGzip)
+        } else if lower.ends_with(".csv.zst") || lower.ends_with(".csv.zstd") {
+            Ok(MarketDataFormat::CsvZstd)
+        } else if lower.ends_with(".parquet") {
+            Ok(MarketDataFormat::Parquet)
+        } else {
+            Err(Box::new(ParseError(format!(
+                "market data format of {}",
+                path
+            ))))
+        }
+    }
+}
+
+// 市場データを読み込む（取引ペアと期間で絞り込む）
+pub fn load_markets(path: &str, query: &MarketQuery) -> MyResult<Markets> {
+    match MarketDataFormat::detect(path)? {
+        MarketDataFormat::Csv => load_csv(File::open(path)?, query),
+        MarketDataFormat::CsvGzip => load_csv(GzDecoder::new(File::open(path)?), query),
+        MarketDataFormat::CsvZstd => load_csv(zstd::Decoder::new(File::open(path)?)?, query),
+        MarketDataFormat::Parquet => load_parquet(File::open(path)?, query),
+    }
+}
+
+fn load_csv<R: Read>(reader: R, query: &MarketQuery) -> MyResult<Markets> {
+    let buf = BufReader::new(reader);
+    let mut csv_reader = csv::ReaderBuilder::new().has_headers(true).from_reader(buf);
+    let headers = csv_reader.byte_headers()?.clone();
+    let pair_idx = find_csv_column(&headers, COLUMN_PAIR)?;
+    let recorded_at_idx = find_csv_column(&headers, COLUMN_RECORDED_AT)?;
+
+    // 日時は固定書式なので文字列のまま大小比較できる
+    let begin = query
+        .begin
+        .map(|v| v.format(RECORDED_AT_FORMAT).to_string());
+    let end = query.end.map(|v| v.format(RECORDED_AT_FORMAT).to_string());
+
+    let mut markets = Markets::new();
+    let mut record = csv::ByteRecord::new();
+    while csv_reader.read_byte_record(&mut record)? {
+        // 対象外の行は全体をデシリアライズする前に読み飛ばす
+        if record.get(pair_idx) != Some(query.pair.as_bytes()) {
+            continue;
+        }
+        let recorded_at = record.get(recorded_at_idx).unwrap_or_default();
+        if let Some(begin) = &begin {
+            if recorded_at < begin.as_bytes() {
+                continue;
+            }
+        }
+        if let Some(end) = &end {
+            if recorded_at > end.as_bytes() {
+                continue;
+            }
+        }
+
+        let r: CSVRecord = record.deserialize(Some(&headers))?;
+        markets.push(r.to_model()?);
+    }
+    Ok(markets)
+}
+
+fn find_csv_column(headers: &csv::ByteRecord, name: &str) -> MyResult<usize> {
+    headers
+        .iter()
+        .position(|h| h == name.as_bytes())
+        .ok_or_else(|| {
+            Box::new(KeyNotFound {
+                key: name.to_owned(),
+                collection_name: "csv header".to_owned(),
+            })
+            .into()
+        })
+}
+
+fn load_parquet(file: File, query: &MarketQuery) -> MyResult<Markets> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(file)?;
+    let schema = builder.schema().clone();
+
+    let pair_idx = find_parquet_column(&schema, COLUMN_PAIR)?;
+    let recorded_at_idx = find_parquet_column(&schema, COLUMN_RECORDED_AT)?;
+    let mut indices = vec![pair_idx, recorded_at_idx];
+    for name in COLUMNS_RATE.iter() {
+        indices.push(find_parquet_column(&schema, name)?);
+    }
+    let projection = ProjectionMask::roots(builder.parquet_schema(), indices);
+    let filter_projection =
+        ProjectionMask::roots(builder.parquet_schema(), vec![pair_idx, recorded_at_idx]);
+
+    // 統計情報から対象外と分かる行グループは読み込まない
+    let recorded_at_unit = match schema.field(recorded_at_idx).data_type() {
+        DataType::Timestamp(unit, _) => Some(*unit),
+        _ => None,
+    };
+    let row_groups: Vec<usize> = builder
+        .metadata()
+        .row_groups()
+        .iter()
+        .enumerate()
+        .filter(|(_, rg)| {
+            may_contain_pair(rg, pair_idx, &query.pair)
+                && may_contain_range(rg, recorded_at_idx, recorded_at_unit, query)
+        })
+        .map(|(i, _)| i)
+        .collect();
+
+    // 行単位の絞り込みは取引ペアと日時の列だけデコードして判定する
+    let predicate = {
+        let pair = query.pair.clone();
+        let begin = query.begin_timestamp();
+        let end = query.end_timestamp();
+        ArrowPredicateFn::new(filter_projection, move |batch: RecordBatch| {
+            let pairs = string_column(&batch, COLUMN_PAIR)?;
+            let recorded_ats = timestamp_column(&batch, COLUMN_RECORDED_AT)?;
+            let pairs = pairs.as_string::<i32>();
+            let recorded_ats = recorded_ats.as_primitive::<TimestampSecondType>();
+            Ok((0..batch.num_rows())
+                .map(|i| {
+                    if pairs.is_null(i) || recorded_ats.is_null(i) {
+                        return Some(false);
+                    }
+                    let t = recorded_ats.value(i);
+                    Some(
+                        pairs.value(i) == pair
+                            && begin.is_none_or(|b| t >= b)
+                            && end.is_none_or(|e| t <= e),
+                    )
+                })
+                .collect::<BooleanArray>())
+        })
+    };
+
+    let reader = builder
+        .with_row_groups(row_groups)
+        .with_projection(projection)
+        .with_row_filter(RowFilter::new(vec![Box::new(predicate)]))
+        .build()?;
+
+    let mut markets = Markets::new();
+    for batch in reader {
+        let batch = batch?;
+        let pairs = string_column(&batch, COLUMN_PAIR)?;
+        let pairs = pairs.as_string::<i32>();
+        let recorded_ats = timestamp_column(&batch, COLUMN_RECORDED_AT)?;
+        let recorded_ats = recorded_ats.as_primitive::<TimestampSecondType>();
+        let mut rates = vec![];
+        for name in COLUMNS_RATE.iter() {
+            rates.push(float_column(&batch, name)?);
+        }
+        let rates: Vec<&arrow_array::Float64Array> = rates
+            .iter()
+            .map(|a| a.as_primitive::<Float64Type>())
+            .collect();
+
+        for i in 0..batch.num_rows() {
+            let recorded_at = chrono::DateTime::from_timestamp(recorded_ats.value(i), 0)
+                .ok_or_else(|| ParseError(format!("recorded_at {}", recorded_ats.value(i))))?
+                .naive_utc();
+            // 欠損した値を 0 として扱うと不正なレートになるため、読み込みを中止する
+            if let Some(j) = (0..rates.len()).find(|j| rates[*j].is_null(i)) {
+                return Err(Box::new(ParseError(format!(
+                    "{} is null, recorded_at {}",
+                    COLUMNS_RATE[j], recorded_at
+                ))));
+            }
+            markets.push(Market {
+                pair: pairs.value(i).to_owned(),
+                store_rate_avg: rates[0].value(i),
+                ex_rate_sell: rates[1].value(i),
+                ex_rate_buy: rates[2].value(i),
+                ex_volume_sell: rates[3].value(i),
+                ex_volume_buy: rates[4].value(i),
+                recorded_at,
+            });
+        }
+    }
+    Ok(markets)
+}
+
+fn find_parquet_column(schema: &Schema, name: &str) -> MyResult<usize> {
+    schema.index_of(name).map_err(|_| {
+        Box::new(KeyNotFound {
+            key: name.to_owned(),
+            collection_name: "parquet schema".to_owned(),
+        })
+        .into()
+    })
+}
+
+fn column(batch: &RecordBatch, name: &str) -> Result<ArrayRef, ArrowError> {
+    batch
+        .column_by_name(name)
+        .cloned()
+        .ok_or_else(|| ArrowError::SchemaError(format!("column {} not found", name)))
+}
+
+fn string_column(batch: &RecordBatch, name: &str) -> Result<ArrayRef, ArrowError> {
+    cast(&column(batch, name)?, &DataType::Utf8)
+}
+
+fn timestamp_column(batch: &RecordBatch, name: &str) -> Result<ArrayRef, ArrowError> {
+    cast(
+        &column(batch, name)?,
+        &DataType::Timestamp(TimeUnit::Second, None),
+    )
+}
+
+fn float_column(batch: &RecordBatch, name: &str) -> Result<ArrayRef, ArrowError> {
+    cast(&column(batch, name)?, &DataType::Float64)
+}
+
+fn may_contain_pair(rg: &RowGroupMetaData, idx: usize, pair: &str) -> bool {
+    let stats = match rg.column(idx).statistics() {
+        Some(v) => v,
+        None => return true,
+    };
+    match (stats.min_bytes_opt(), stats.max_bytes_opt()) {
+        (Some(min), Some(max)) => min <= pair.as_bytes() && pair.as_bytes() <= max,
+        _ => true,
+    }
+}
+
+fn may_contain_range(
+    rg: &RowGroupMetaData,
+    idx: usize,
+    unit: Option<TimeUnit>,
+    query: &MarketQuery,
+) -> bool {
+    let column = rg.column(idx);
+    let unit = match unit {
+        Some(v) if column.column_type() == PhysicalType::INT64 => v,
+        _ => return true,
+    };
+    let stats = match column.statistics() {
+        Some(v) => v,
+        None => return true,
+    };
+    let (min, max) = match (stats.min_bytes_opt(), stats.max_bytes_opt()) {
+        (Some(min), Some(max)) if min.len() == 8 && max.len() == 8 => (
+            to_seconds(i64::from_le_bytes(min.try_into().unwrap()), unit),
+            to_seconds(i64::from_le_bytes(max.try_into().unwrap()), unit),
+        ),
+        _ => return true,
+    };
+    if let Some(begin) = query.begin_timestamp() {
+        if max < begin {
+            return false;
+        }
+    }
+    if let Some(end) = query.end_timestamp() {
+        if min > end {
+            return false;
+        }
+    }
+    true
+}
+
+fn to_seconds(v: i64, unit: TimeUnit) -> i64 {
+    match unit {
+        TimeUnit::Second => v,
+        TimeUnit::Millisecond => v.div_euclid(1_000),
+        TimeUnit::Microsecond => v.div_euclid(1_000_000),
+        TimeUnit::Nanosecond => v.div_euclid(1_000_000_000),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use arrow_array::{Float64Array, StringArray, TimestampMillisecondArray};
+    use arrow_schema::Field;
+    use flate2::write::GzEncoder;
+    use parquet::arrow::ArrowWriter;
+    use parquet::file::properties::WriterProperties;
+
+    const CSV: &str = "id,pair,store_rate_avg,ex_rate_sell,ex_rate_buy,ex_volume_sell,ex_volume_buy,recorded_at,created_at,updated_at
+1,btc_jpy,100.0,101.0,102.0,1.0,2.0,2021-05-16 12:00:00,2021-05-16 12:00:00,2021-05-16 12:00:00
+2,mona_jpy,200.0,201.0,202.0,3.0,4.0,2021-05-16 12:00:00,2021-05-16 12:00:00,2021-05-16 12:00:00
+3,btc_jpy,110.0,111.0,112.0,5.0,6.0,2021-05-16 12:01:00,2021-05-16 12:01:00,2021-05-16 12:01:00
+4,btc_jpy,120.0,121.0,122.0,7.0,8.0,2021-05-16 12:02:00,2021-05-16 12:02:00,2021-05-16 12:02:00
+";
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "trading_bot_rust_loader_{}_{}",
+            std::process::id(),
+            name
+        ))
+    }
+
+    fn parse(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, RECORDED_AT_FORMAT).unwrap()
+    }
+
+    fn make_query() -> MarketQuery {
+        MarketQuery {
+            pair: "btc_jpy".to_owned(),
+            begin: Some(parse("2021-05-16 12:01:00")),
+            end: None,
+        }
+    }
+
+    #[test]
+    fn test_detect_format() {
+        let params = vec![
+            ("a.csv", Some(MarketDataFormat::Csv)),
+            ("a.CSV.GZ", Some(MarketDataFormat::CsvGzip)),
+            ("a.csv.zst", Some(MarketDataFormat::CsvZstd)),
+            ("a.parquet", Some(MarketDataFormat::Parquet)),
+            ("a.json", None),
+        ];
+        for (path, want) in params {
+            let got = MarketDataFormat::detect(path).ok();
+            assert_eq!(got, want, "{}, failure", path);
+        }
+    }
+
+    #[test]
+    fn test_load_markets_csv() {
+        let plain = temp_path("markets.csv");
+        std::fs::write(&plain, CSV).unwrap();
+
+        let gzip = temp_path("markets.csv.gz");
+        let mut encoder = GzEncoder::new(File::create(&gzip).unwrap(), Default::default());
+        encoder.write_all(CSV.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        let zstd = temp_path("markets.csv.zst");
+        std::fs::write(&zstd, zstd::encode_all(CSV.as_bytes(), 0).unwrap()).unwrap();
+
+        for path in [plain, gzip, zstd].iter() {
+            let got = load_markets(path.to_str().unwrap(), &make_query());
+            std::fs::remove_file(path).unwrap();
+
+            let got = got.unwrap();
+            let rates: Vec<f64> = got.iter().map(|m| m.ex_rate_sell).collect();
+            assert_eq!(rates, vec![111.0, 121.0], "{:?}, failure", path);
+            assert_eq!(got[1].recorded_at, parse("2021-05-16 12:02:00"));
+        }
+    }
+
+    // 取引ペア毎に1行ずつ、全てのレート, 出来高の列に rates の値を入れる
+    fn write_parquet(name: &str, rates: Vec<Option<f64>>) -> PathBuf {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("pair", DataType::Utf8, false),
+            Field::new("store_rate_avg", DataType::Float64, true),
+            Field::new("ex_rate_sell", DataType::Float64, true),
+            Field::new("ex_rate_buy", DataType::Float64, true),
+            Field::new("ex_volume_sell", DataType::Float64, true),
+            Field::new("ex_volume_buy", DataType::Float64, true),
+            Field::new(
+                "recorded_at",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+        ]));
+        let pairs = vec!["btc_jpy", "mona_jpy", "btc_jpy", "btc_jpy"];
+        let recorded_ats: Vec<i64> = [
+            "2021-05-16 12:00:00",
+            "2021-05-16 12:00:00",
+            "2021-05-16 12:01:00",
+            "2021-05-16 12:02:00",
+        ]
+        .iter()
+        .map(|s| parse(s).and_utc().timestamp_millis())
+        .collect();
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(pairs)),
+                Arc::new(Float64Array::from(rates.clone())),
+                Arc::new(Float64Array::from(rates.clone())),
+                Arc::new(Float64Array::from(rates.clone())),
+                Arc::new(Float64Array::from(rates.clone())),
+                Arc::new(Float64Array::from(rates)),
+                Arc::new(TimestampMillisecondArray::from(recorded_ats)),
+            ],
+        )
+        .unwrap();
+
+        let path = temp_path(name);
+        // 行グループの読み飛ばしも通るように小さく分割する
+        let props = WriterProperties::builder()
+            .set_max_row_group_size(2)
+            .build();
+        let mut writer =
+            ArrowWriter::try_new(File::create(&path).unwrap(), schema, Some(props)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_load_markets_parquet() {
+        let path = write_parquet(
+            "markets.parquet",
+            vec![Some(101.0), Some(201.0), Some(111.0), Some(121.0)],
+        );
+        let got = load_markets(path.to_str().unwrap(), &make_query());
+        std::fs::remove_file(&path).unwrap();
+
+        let got = got.unwrap();
+        let rates: Vec<f64> = got.iter().map(|m| m.ex_rate_sell).collect();
+        assert_eq!(rates, vec![111.0, 121.0]);
+        assert_eq!(got[0].pair, "btc_jpy");
+        assert_eq!(got[1].recorded_at, parse("2021-05-16 12:02:00"));
+    }
+
+    #[test]
+    fn test_load_markets_parquet_null() {
+        // 対象外の取引ペアの欠損は無視する
+        let path = write_parquet(
+            "markets_null_other.parquet",
+            vec![Some(101.0), None, Some(111.0), Some(121.0)],
+        );
+        let got = load_markets(path.to_str().unwrap(), &make_query());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(got.unwrap().len(), 2);
+
+        let path = write_parquet(
+            "markets_null.parquet",
+            vec![Some(101.0), Some(201.0), Some(111.0), None],
+        );
+        let got = load_markets(path.to_str().unwrap(), &make_query());
+        std::fs::remove_file(&path).unwrap();
+        let err = got.unwrap_err().to_string();
+        assert!(err.contains("store_rate_avg is null"), "{}", err);
+    }
+}
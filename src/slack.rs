@@ -1 +1,4 @@
 pub mod client;
+pub mod command;
+pub mod notification;
+pub mod server;
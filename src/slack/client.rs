@@ -18,6 +18,17 @@ pub struct DefaultClient {
 #[derive(Serialize, Debug, PartialEq)]
 pub struct TextMessage {
     pub text: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+}
+
+// 色付きの添付（中身は Block Kit のブロック）
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Attachment {
+    pub color: String,
+    // 通知など、ブロックを表示できない場合の文字列
+    pub fallback: String,
+    pub blocks: Vec<serde_json::Value>,
 }
 
 impl DefaultClient {
@@ -0,0 +1,233 @@
+use crate::bot::control::BotControl;
+use crate::config::Config;
+use crate::error::MyError::ParseError;
+use crate::error::MyResult;
+
+use chrono::{DateTime, Utc};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde::Deserialize;
+
+// リクエストの時刻の許容範囲（秒, リプレイ攻撃を防ぐため）
+const MAX_TIMESTAMP_DIFF_SEC: i64 = 60 * 5;
+
+// Slack から受け付けるコマンド（/bot <command>）
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Status,
+    Pause,
+    Resume,
+    LossCut(u64),
+    SetDemoMode(bool),
+    Help,
+}
+
+// スラッシュコマンドのリクエスト（application/x-www-form-urlencoded）
+// インタラクティブなメッセージの操作は payload に JSON で送られる
+#[derive(Deserialize, Debug, Default)]
+struct CommandForm {
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    user_name: String,
+    payload: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct InteractivePayload {
+    user: Option<InteractiveUser>,
+    #[serde(default)]
+    actions: Vec<InteractiveAction>,
+}
+
+#[derive(Deserialize, Debug)]
+struct InteractiveUser {
+    #[serde(default)]
+    username: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct InteractiveAction {
+    value: Option<String>,
+}
+
+impl Command {
+    pub fn parse(text: &str) -> MyResult<Command> {
+        let args: Vec<&str> = text.split_whitespace().collect();
+        let command = match args.as_slice() {
+            [] | ["help"] => Command::Help,
+            ["status"] => Command::Status,
+            ["pause"] => Command::Pause,
+            ["resume"] => Command::Resume,
+            ["losscut", id] => Command::LossCut(
+                id.parse()
+                    .map_err(|_| ParseError(format!("order id `{}`", id)))?,
+            ),
+            ["set", "demo_mode", v] => Command::SetDemoMode(match *v {
+                "on" | "true" => true,
+                "off" | "false" => false,
+                _ => return Err(Box::new(ParseError(format!("demo_mode `{}`", v)))),
+            }),
+            _ => return Err(Box::new(ParseError(format!("command `{}`", text)))),
+        };
+        Ok(command)
+    }
+
+    // リクエストの本文からコマンドと実行したユーザー名を取り出す
+    pub fn from_request_body(body: &str) -> MyResult<(Command, String)> {
+        let form: CommandForm = serde_urlencoded::from_str(body)?;
+        match form.payload {
+            Some(payload) => {
+                let payload: InteractivePayload = serde_json::from_str(&payload)?;
+                let text = payload
+                    .actions
+                    .first()
+                    .and_then(|a| a.value.clone())
+                    .unwrap_or_default();
+                let user_name = payload.user.map(|u| u.username).unwrap_or_default();
+                Ok((Command::parse(&text)?, user_name))
+            }
+            None => Ok((Command::parse(&form.text)?, form.user_name)),
+        }
+    }
+
+    // コマンドを実行して、返信するメッセージを返す
+    // 損切りは要求を受け付けるだけで、次の取引の前に実行する
+    pub fn execute(&self, control: &BotControl, config: &Config) -> String {
+        match self {
+            Command::Status => {
+                let state = control.snapshot();
+                format!(
+                    "bot_name:`{}`\npair:`{}`\npaused:`{}`\ndemo_mode:`{}`\nlast trade:`{}`\nlast error:`{}`\nloss cut requests:`{:?}`",
+                    config.bot_name,
+                    config.target_pair,
+                    state.paused,
+                    state.demo_mode,
+                    state
+                        .last_trade_at
+                        .map(|t| t.to_rfc3339())
+                        .unwrap_or_else(|| "-".to_owned()),
+                    state.last_error.unwrap_or_else(|| "-".to_owned()),
+                    state.loss_cut_order_ids,
+                )
+            }
+            Command::Pause => {
+                control.set_paused(true);
+                "paused, trade will be skipped until resume".to_owned()
+            }
+            Command::Resume => {
+                control.set_paused(false);
+                "resumed".to_owned()
+            }
+            Command::LossCut(id) => {
+                control.request_loss_cut(*id);
+                format!("loss cut of order {} is requested", id)
+            }
+            Command::SetDemoMode(v) => {
+                control.set_demo_mode(*v);
+                format!("demo_mode is set to {}", v)
+            }
+            Command::Help => {
+                "usage: /bot status | pause | resume | losscut <order_id> | set demo_mode on|off"
+                    .to_owned()
+            }
+        }
+    }
+}
+
+// リクエストの署名を作る（v0=HMAC-SHA256("v0:<timestamp>:<body>") の16進数）
+pub fn make_signature(signing_secret: &str, timestamp: &str, body: &str) -> MyResult<String> {
+    let key = PKey::hmac(signing_secret.as_bytes())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(format!("v0:{}:{}", timestamp, body).as_bytes())?;
+    let signature = signer
+        .sign_to_vec()?
+        .iter()
+        .fold("v0=".to_owned(), |s, b| format!("{}{:02x}", s, b));
+    Ok(signature)
+}
+
+// リクエストが Slack から送られたものか検証する
+pub fn verify_signature(
+    signing_secret: &str,
+    timestamp: &str,
+    body: &str,
+    signature: &str,
+    now: &DateTime<Utc>,
+) -> MyResult<()> {
+    let t: i64 = timestamp
+        .parse()
+        .map_err(|_| ParseError(format!("timestamp `{}`", timestamp)))?;
+    if (now.timestamp() - t).abs() > MAX_TIMESTAMP_DIFF_SEC {
+        return Err(Box::new(ParseError(format!(
+            "timestamp `{}` is too old",
+            timestamp
+        ))));
+    }
+    let want = make_signature(signing_secret, timestamp, body)?;
+    // 一致するまでの時間から署名を推測されないよう、固定時間で比較する
+    if want.len() != signature.len() || !openssl::memcmp::eq(want.as_bytes(), signature.as_bytes())
+    {
+        return Err(Box::new(ParseError("signature is invalid".to_owned())));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let params = vec![
+            ("", Some(Command::Help)),
+            ("status", Some(Command::Status)),
+            (" pause ", Some(Command::Pause)),
+            ("resume", Some(Command::Resume)),
+            ("losscut 12345", Some(Command::LossCut(12345))),
+            ("set demo_mode on", Some(Command::SetDemoMode(true))),
+            ("set demo_mode off", Some(Command::SetDemoMode(false))),
+            ("losscut abc", None),
+            ("set demo_mode yes", None),
+            ("unknown", None),
+        ];
+        for (text, want) in params {
+            let got = Command::parse(text).ok();
+            assert_eq!(got, want, "`{}`, failure", text);
+        }
+    }
+
+    #[test]
+    fn test_from_request_body() {
+        let body = "command=%2Fbot&text=losscut+100&user_name=alice";
+        let (command, user_name) = Command::from_request_body(body).unwrap();
+        assert_eq!(command, Command::LossCut(100));
+        assert_eq!(user_name, "alice");
+
+        let payload = r#"{"type":"block_actions","user":{"username":"bob"},"actions":[{"action_id":"pause","value":"pause"}]}"#;
+        let body = serde_urlencoded::to_string([("payload", payload)]).unwrap();
+        let (command, user_name) = Command::from_request_body(&body).unwrap();
+        assert_eq!(command, Command::Pause);
+        assert_eq!(user_name, "bob");
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        // Slack のドキュメントの例
+        let secret = "8f742231b10e8888abcd99yyyzzz85a5";
+        let timestamp = "1531420618";
+        let body = "token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J&team_domain=testteamnow&channel_id=G8PSS9T3V&channel_name=foobar&user_id=U2CERLKJA&user_name=roadrunner&command=%2Fwebhook-collect&text=&response_url=https%3A%2F%2Fhooks.slack.com%2Fcommands%2FT1DC2JH3J%2F397700885554%2F96rGlfmibIGlgcZRskXaIFfN&trigger_id=398738663015.47445629121.803a0bc887a14d10d2c447fce8b6703c";
+        let signature = "v0=a2114d57b48eac39b9ad189dd8316235a7b4a8d21a10bd27519666489c69b503";
+        assert_eq!(make_signature(secret, timestamp, body).unwrap(), signature);
+
+        let now = DateTime::from_timestamp(1531420618 + 10, 0).unwrap();
+        assert!(verify_signature(secret, timestamp, body, signature, &now).is_ok());
+        assert!(verify_signature("other", timestamp, body, signature, &now).is_err());
+        assert!(verify_signature(secret, timestamp, "tampered", signature, &now).is_err());
+        assert!(verify_signature(secret, timestamp, body, "v0=00", &now).is_err());
+
+        let now = DateTime::from_timestamp(1531420618 + 60 * 10, 0).unwrap();
+        assert!(verify_signature(secret, timestamp, body, signature, &now).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use crate::error::MyResult;
+use crate::notifier::base::Notifier;
+use crate::notifier::model::Notification;
+use crate::slack::client::{Attachment, Client, TextMessage};
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+
+// 1つのセクションに表示できる項目の最大数（Block Kit の制限）
+const MAX_FIELDS_PER_SECTION: usize = 10;
+
+// Block Kit の添付にする
+pub fn to_message(notification: &Notification) -> TextMessage {
+    let mut blocks = vec![json!({
+        "type": "section",
+        "text": { "type": "mrkdwn", "text": format!("*{}*", escape(&notification.title)) },
+    })];
+    for fields in notification.fields.chunks(MAX_FIELDS_PER_SECTION) {
+        let fields: Vec<Value> = fields
+            .iter()
+            .map(|f| {
+                json!({
+                    "type": "mrkdwn",
+                    "text": format!("*{}*\n`{}`", escape(&f.title), escape(&f.value)),
+                })
+            })
+            .collect();
+        blocks.push(json!({ "type": "section", "fields": fields }));
+    }
+    if let Some(link) = &notification.link {
+        blocks.push(json!({
+            "type": "context",
+            "elements": [{
+                "type": "mrkdwn",
+                "text": format!("<{}|{}>", link.url, escape(&link.text)),
+            }],
+        }));
+    }
+    TextMessage {
+        text: notification.title.clone(),
+        attachments: vec![Attachment {
+            color: notification.level.color().to_owned(),
+            fallback: notification.to_plain_text(),
+            blocks,
+        }],
+    }
+}
+
+// Slack のクライアントはそのまま通知の送り先として使う
+#[async_trait]
+impl<T> Notifier for T
+where
+    T: Client + std::marker::Sync,
+{
+    async fn notify(&self, notification: &Notification) -> MyResult<()> {
+        self.post_message(&to_message(notification)).await
+    }
+}
+
+// mrkdwn で特別な意味を持つ文字をエスケープする
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifier::model::{Event, Level};
+
+    #[test]
+    fn test_to_message() {
+        let notification = Notification::new(Event::Entry, "entry completed!", Level::Good)
+            .field("pair", "btc_jpy")
+            .field("rate", "4000000.000")
+            .link("chart", "https://example.com/btc_jpy");
+        let message = to_message(&notification);
+        assert_eq!(message.text, "entry completed!");
+        assert_eq!(
+            message.attachments[0].fallback,
+            "entry completed!\npair: btc_jpy\nrate: 4000000.000\nchart: https://example.com/btc_jpy"
+        );
+
+        let got = serde_json::to_value(&message).unwrap();
+        let want = json!({
+            "text": "entry completed!",
+            "attachments": [{
+                "color": "#2eb886",
+                "fallback": "entry completed!\npair: btc_jpy\nrate: 4000000.000\nchart: https://example.com/btc_jpy",
+                "blocks": [
+                    {
+                        "type": "section",
+                        "text": { "type": "mrkdwn", "text": "*entry completed!*" },
+                    },
+                    {
+                        "type": "section",
+                        "fields": [
+                            { "type": "mrkdwn", "text": "*pair*\n`btc_jpy`" },
+                            { "type": "mrkdwn", "text": "*rate*\n`4000000.000`" },
+                        ],
+                    },
+                    {
+                        "type": "context",
+                        "elements": [
+                            { "type": "mrkdwn", "text": "<https://example.com/btc_jpy|chart>" },
+                        ],
+                    },
+                ],
+            }],
+        });
+        assert_eq!(got, want);
+    }
+
+    #[test]
+    fn test_to_message_splits_fields() {
+        let notification = (0..12).fold(
+            Notification::new(Event::Failure, "t", Level::Danger),
+            |n, i| n.field(&format!("f{}", i), "<v>"),
+        );
+        let message = to_message(&notification);
+        let blocks = &message.attachments[0].blocks;
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[1]["fields"].as_array().unwrap().len(), 10);
+        assert_eq!(blocks[2]["fields"].as_array().unwrap().len(), 2);
+        assert_eq!(blocks[2]["fields"][0]["text"], "*f10*\n`&lt;v&gt;`");
+        assert_eq!(message.attachments[0].color, "#a30200");
+    }
+}
@@ -0,0 +1,118 @@
+use crate::bot::control::BotControl;
+use crate::bot::shutdown::Shutdown;
+use crate::config::Config;
+use crate::error::MyResult;
+use crate::slack::command::{verify_signature, Command};
+
+use chrono::Utc;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{info, warn};
+use serde_json::json;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+pub const COMMAND_PATH: &str = "/slack/commands";
+
+// スラッシュコマンド, インタラクティブなメッセージの操作を受け付けるサーバー
+pub struct CommandServer {
+    context: Arc<Context>,
+}
+
+struct Context {
+    config: Config,
+    signing_secret: String,
+    control: Arc<BotControl>,
+}
+
+impl CommandServer {
+    pub fn new(config: &Config, signing_secret: &str, control: Arc<BotControl>) -> CommandServer {
+        CommandServer {
+            context: Arc::new(Context {
+                config: config.clone(),
+                signing_secret: signing_secret.to_owned(),
+                control,
+            }),
+        }
+    }
+
+    // 終了が要求されるまでリクエストを受け付ける
+    pub async fn serve(&self, addr: &SocketAddr, mut shutdown: Shutdown) -> MyResult<()> {
+        let context = self.context.clone();
+        let make_svc = make_service_fn(move |_| {
+            let context = context.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let context = context.clone();
+                    async move { Ok::<_, Infallible>(handle(&context, req).await) }
+                }))
+            }
+        });
+        let server = Server::try_bind(addr)?.serve(make_svc);
+        info!("listen slack commands on http://{}", server.local_addr());
+        server
+            .with_graceful_shutdown(async move { shutdown.requested().await })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        handle(&self.context, req).await
+    }
+}
+
+async fn handle(context: &Context, req: Request<Body>) -> Response<Body> {
+    if req.method() != Method::POST || req.uri().path() != COMMAND_PATH {
+        return text_response(StatusCode::NOT_FOUND, "not found");
+    }
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_owned()
+    };
+    let timestamp = header("x-slack-request-timestamp");
+    let signature = header("x-slack-signature");
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(v) => String::from_utf8_lossy(&v).to_string(),
+        Err(err) => return text_response(StatusCode::BAD_REQUEST, &err.to_string()),
+    };
+
+    if let Err(err) = verify_signature(
+        &context.signing_secret,
+        &timestamp,
+        &body,
+        &signature,
+        &Utc::now(),
+    ) {
+        warn!("reject slack command, {}", err);
+        return text_response(StatusCode::UNAUTHORIZED, "unauthorized");
+    }
+
+    // 不明なコマンドは使い方を返信する（Slack にはエラーのステータスを返さない）
+    let text = match Command::from_request_body(&body) {
+        Ok((command, user_name)) => {
+            info!("slack command {:?} by {}", command, user_name);
+            command.execute(&context.control, &context.config)
+        }
+        Err(err) => format!(
+            "{}\n{}",
+            err,
+            Command::Help.execute(&context.control, &context.config)
+        ),
+    };
+    let body = json!({ "response_type": "ephemeral", "text": text }).to_string();
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn text_response(status: StatusCode, text: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(text.to_owned()))
+        .unwrap()
+}
@@ -0,0 +1,706 @@
+use crate::error::MyError::RecordNotFound;
+use crate::error::MyResult;
+use crate::mysql::client::Client;
+use crate::mysql::model::{
+    BotStatus, Event, InFlightAction, Market, MarketSummary, Markets, Report,
+};
+use crate::sqlite::migration;
+use crate::sqlite::migration::MIGRATIONS;
+
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::DateTime;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use indoc::indoc;
+use rusqlite::{named_params, Connection, OptionalExtension, Row};
+
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+const SELECT_MARKETS_SQL: &str = indoc!(
+    "
+    SELECT
+        pair, store_rate_avg, ex_rate_sell, ex_rate_buy, ex_volume_sell, ex_volume_buy, recorded_at
+    FROM
+        markets
+    WHERE
+        pair = :pair
+        AND recorded_at > :begin
+    ORDER BY
+        recorded_at
+    "
+);
+
+const INSERT_MARKET_SQL: &str = indoc!(
+    "
+    INSERT INTO markets (pair, store_rate_avg, ex_rate_sell, ex_rate_buy, ex_volume_sell, ex_volume_buy, recorded_at)
+    VALUES (:pair, :store_rate_avg, :ex_rate_sell, :ex_rate_buy, :ex_volume_sell, :ex_volume_buy, :recorded_at)
+    "
+);
+
+const UPSERT_BOT_STATUS_SQL: &str = indoc!(
+    "
+    INSERT INTO bot_statuses (bot_name, pair, type, value, memo)
+    VALUES (:bot_name, :pair, :type, :value, :memo)
+    ON CONFLICT (bot_name, pair, type) DO UPDATE SET value = excluded.value, updated_at = CURRENT_TIMESTAMP
+    "
+);
+
+const SELECT_BOT_STATUS_SQL: &str = indoc!(
+    "
+    SELECT
+        bot_name, pair, type, value, memo
+    FROM
+        bot_statuses
+    WHERE
+        bot_name = :bot_name
+        AND pair = :pair
+        AND type = :type
+    "
+);
+
+const INSERT_EVENT_SQL: &str = indoc!(
+    "
+    INSERT INTO events (pair, event_type, memo, recorded_at)
+    VALUES (:pair, :event_type, :memo, :recorded_at)
+    "
+);
+
+const UPSERT_IN_FLIGHT_ACTION_SQL: &str = indoc!(
+    "
+    INSERT INTO in_flight_actions (bot_name, pair, action_type, state, attempts, updated_at)
+    VALUES (:bot_name, :pair, :action_type, :state, :attempts, :updated_at)
+    ON CONFLICT (bot_name) DO UPDATE SET
+        pair = excluded.pair,
+        action_type = excluded.action_type,
+        state = excluded.state,
+        attempts = excluded.attempts,
+        updated_at = excluded.updated_at
+    "
+);
+
+const SELECT_IN_FLIGHT_ACTION_SQL: &str = indoc!(
+    "
+    SELECT
+        bot_name, pair, action_type, state, attempts, updated_at
+    FROM
+        in_flight_actions
+    WHERE
+        bot_name = :bot_name
+    "
+);
+
+const DELETE_IN_FLIGHT_ACTION_SQL: &str =
+    "DELETE FROM in_flight_actions WHERE bot_name = :bot_name";
+
+const UPSERT_REPORT_SQL: &str = indoc!(
+    "
+    INSERT INTO reports (
+        bot_name, period, pair, begin_at, end_at, trade_count, realized_pnl, fee,
+        win_count, loss_count, open_amount, unrealized_pnl, total_jpy, total_jpy_change
+    )
+    VALUES (
+        :bot_name, :period, :pair, :begin_at, :end_at, :trade_count, :realized_pnl, :fee,
+        :win_count, :loss_count, :open_amount, :unrealized_pnl, :total_jpy, :total_jpy_change
+    )
+    ON CONFLICT (bot_name, period, pair, end_at) DO UPDATE SET
+        begin_at = excluded.begin_at,
+        trade_count = excluded.trade_count,
+        realized_pnl = excluded.realized_pnl,
+        fee = excluded.fee,
+        win_count = excluded.win_count,
+        loss_count = excluded.loss_count,
+        open_amount = excluded.open_amount,
+        unrealized_pnl = excluded.unrealized_pnl,
+        total_jpy = excluded.total_jpy,
+        total_jpy_change = excluded.total_jpy_change,
+        updated_at = CURRENT_TIMESTAMP
+    "
+);
+
+const SELECT_LATEST_REPORT_SQL: &str = indoc!(
+    "
+    SELECT
+        bot_name, period, pair, begin_at, end_at, trade_count, realized_pnl, fee,
+        win_count, loss_count, open_amount, unrealized_pnl, total_jpy, total_jpy_change
+    FROM
+        reports
+    WHERE
+        bot_name = :bot_name
+        AND period = :period
+        AND pair = :pair
+        AND end_at < :before
+    ORDER BY
+        end_at DESC
+    LIMIT 1
+    "
+);
+
+// SQLiteの整数同士の除算は切り捨てになるため REAL に変換してから割る
+const SELECT_MARKET_SUMMARY_SQL: &str = indoc!(
+    "
+    SELECT
+        COUNT(1) count,
+        MIN(m.recorded_at) recorded_at_begin,
+        MAX(m.recorded_at) recorded_at_end,
+        MAX(m.ex_rate_sell) ex_rate_sell_max,
+        MIN(m.ex_rate_sell) ex_rate_sell_min,
+        MAX(m.ex_rate_buy) ex_rate_buy_max,
+        MIN(m.ex_rate_buy) ex_rate_buy_min,
+        SUM(m.ex_volume_sell) ex_volume_sell_total,
+        SUM(m.ex_volume_buy) ex_volume_buy_total,
+        CAST(SUM(m.trade_count) AS REAL) / COUNT(1) trade_frequency_ratio
+    FROM (
+        SELECT
+            recorded_at,
+            ex_rate_sell,
+            ex_rate_buy,
+            ex_volume_sell,
+            ex_volume_buy,
+            CASE WHEN ex_volume_sell + ex_volume_buy = 0 THEN 0 ELSE 1 END trade_count
+        FROM
+            markets
+        WHERE
+            pair = :pair
+            AND recorded_at >= :begin
+            AND recorded_at <= :end
+    ) m
+    HAVING
+        COUNT(1) > 0
+    "
+);
+
+// SQLiteをストレージに使うクライアント（ローカル開発, CI, 小規模運用向け）
+#[derive(Debug)]
+pub struct DefaultClient {
+    conn: Mutex<Connection>,
+}
+
+impl DefaultClient {
+    pub fn new(path: &str) -> MyResult<DefaultClient> {
+        let conn = Connection::open(path)?;
+        Ok(DefaultClient {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn new_in_memory() -> MyResult<DefaultClient> {
+        let conn = Connection::open_in_memory()?;
+        Ok(DefaultClient {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    // 未適用のマイグレーションを適用する
+    pub fn migrate(&self) -> MyResult<Vec<u32>> {
+        let mut conn = self.get_conn()?;
+        migration::migrate(&mut conn, &MIGRATIONS)
+    }
+
+    pub fn schema_version(&self) -> MyResult<u32> {
+        let conn = self.get_conn()?;
+        migration::current_version(&conn)
+    }
+
+    // 市場データの登録（markets は本来ボット外で記録されるため Client には含めない）
+    pub fn insert_market(&self, m: &Market) -> MyResult<()> {
+        let conn = self.get_conn()?;
+        conn.execute(
+            INSERT_MARKET_SQL,
+            named_params! {
+                ":pair": m.pair,
+                ":store_rate_avg": m.store_rate_avg,
+                ":ex_rate_sell": m.ex_rate_sell,
+                ":ex_rate_buy": m.ex_rate_buy,
+                ":ex_volume_sell": m.ex_volume_sell,
+                ":ex_volume_buy": m.ex_volume_buy,
+                ":recorded_at": to_text(&m.recorded_at),
+            },
+        )?;
+        Ok(())
+    }
+
+    fn get_conn(&self) -> MyResult<MutexGuard<'_, Connection>> {
+        match self.conn.lock() {
+            Ok(v) => Ok(v),
+            Err(e) => Err(e.to_string().into()),
+        }
+    }
+}
+
+impl Client for DefaultClient {
+    fn select_markets(&self, pair: &str, begin: DateTime<Utc>) -> MyResult<Markets> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(SELECT_MARKETS_SQL)?;
+        let rows = stmt.query_map(
+            named_params! {
+                ":pair": pair,
+                ":begin": to_text(&begin.naive_utc()),
+            },
+            to_market,
+        )?;
+        let mut markets = Markets::new();
+        for m in rows {
+            markets.push(m?);
+        }
+        Ok(markets)
+    }
+
+    fn upsert_bot_status(&self, s: &BotStatus) -> MyResult<()> {
+        let conn = self.get_conn()?;
+        conn.execute(
+            UPSERT_BOT_STATUS_SQL,
+            named_params! {
+                ":bot_name": s.bot_name,
+                ":pair": s.pair,
+                ":type": s.r#type,
+                ":value": s.value,
+                ":memo": s.memo,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn select_bot_status(&self, bot_name: &str, pair: &str, r#type: &str) -> MyResult<BotStatus> {
+        let conn = self.get_conn()?;
+        let status = conn
+            .query_row(
+                SELECT_BOT_STATUS_SQL,
+                named_params! {
+                    ":bot_name": bot_name,
+                    ":pair": pair,
+                    ":type": r#type,
+                },
+                |row| {
+                    Ok(BotStatus {
+                        bot_name: row.get(0)?,
+                        pair: row.get(1)?,
+                        r#type: row.get(2)?,
+                        value: row.get(3)?,
+                        memo: row.get(4)?,
+                    })
+                },
+            )
+            .optional()?;
+        status.ok_or_else(|| {
+            Box::new(RecordNotFound {
+                table: "bot_statuses".to_owned(),
+                param: format!("bot_name:{}, type:{}", bot_name, r#type),
+            })
+            .into()
+        })
+    }
+
+    fn insert_event(&self, event: &Event) -> MyResult<()> {
+        let conn = self.get_conn()?;
+        conn.execute(
+            INSERT_EVENT_SQL,
+            named_params! {
+                ":pair": event.pair.to_string(),
+                ":event_type": event.event_type.to_value(),
+                ":memo": event.memo,
+                ":recorded_at": to_text(&event.recorded_at),
+            },
+        )?;
+        Ok(())
+    }
+
+    fn select_market_summary(&self, pair: &str, offset_hour: u64) -> MyResult<MarketSummary> {
+        let conn = self.get_conn()?;
+        let (begin, end) = MarketSummary::period(Utc::now(), offset_hour);
+        let summary = conn
+            .query_row(
+                SELECT_MARKET_SUMMARY_SQL,
+                named_params! {
+                    ":pair": pair,
+                    ":begin": to_text(&begin),
+                    ":end": to_text(&end),
+                },
+                to_market_summary,
+            )
+            .optional()?;
+        summary.ok_or_else(|| {
+            Box::new(RecordNotFound {
+                table: "markets".to_owned(),
+                param: format!("pair:{}, offset_hour:{}", pair, offset_hour),
+            })
+            .into()
+        })
+    }
+
+    fn upsert_in_flight_action(&self, action: &InFlightAction) -> MyResult<()> {
+        let conn = self.get_conn()?;
+        conn.execute(
+            UPSERT_IN_FLIGHT_ACTION_SQL,
+            named_params! {
+                ":bot_name": action.bot_name,
+                ":pair": action.pair,
+                ":action_type": action.action_type,
+                ":state": action.state,
+                ":attempts": action.attempts,
+                ":updated_at": to_text(&action.updated_at),
+            },
+        )?;
+        Ok(())
+    }
+
+    fn select_in_flight_action(&self, bot_name: &str) -> MyResult<Option<InFlightAction>> {
+        let conn = self.get_conn()?;
+        let action = conn
+            .query_row(
+                SELECT_IN_FLIGHT_ACTION_SQL,
+                named_params! {
+                    ":bot_name": bot_name,
+                },
+                |row| {
+                    Ok(InFlightAction {
+                        bot_name: row.get(0)?,
+                        pair: row.get(1)?,
+                        action_type: row.get(2)?,
+                        state: row.get(3)?,
+                        attempts: row.get(4)?,
+                        updated_at: row.get(5)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(action)
+    }
+
+    fn delete_in_flight_action(&self, bot_name: &str) -> MyResult<()> {
+        let conn = self.get_conn()?;
+        conn.execute(
+            DELETE_IN_FLIGHT_ACTION_SQL,
+            named_params! {
+                ":bot_name": bot_name,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn upsert_report(&self, report: &Report) -> MyResult<()> {
+        let conn = self.get_conn()?;
+        conn.execute(
+            UPSERT_REPORT_SQL,
+            named_params! {
+                ":bot_name": report.bot_name,
+                ":period": report.period,
+                ":pair": report.pair,
+                ":begin_at": to_text(&report.begin_at),
+                ":end_at": to_text(&report.end_at),
+                ":trade_count": report.trade_count,
+                ":realized_pnl": report.realized_pnl,
+                ":fee": report.fee,
+                ":win_count": report.win_count,
+                ":loss_count": report.loss_count,
+                ":open_amount": report.open_amount,
+                ":unrealized_pnl": report.unrealized_pnl,
+                ":total_jpy": report.total_jpy,
+                ":total_jpy_change": report.total_jpy_change,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn select_latest_report(
+        &self,
+        bot_name: &str,
+        period: &str,
+        pair: &str,
+        before: &NaiveDateTime,
+    ) -> MyResult<Option<Report>> {
+        let conn = self.get_conn()?;
+        let report = conn
+            .query_row(
+                SELECT_LATEST_REPORT_SQL,
+                named_params! {
+                    ":bot_name": bot_name,
+                    ":period": period,
+                    ":pair": pair,
+                    ":before": to_text(before),
+                },
+                to_report,
+            )
+            .optional()?;
+        Ok(report)
+    }
+}
+
+fn to_text(v: &NaiveDateTime) -> String {
+    v.format(DATETIME_FORMAT).to_string()
+}
+
+fn to_market(row: &Row) -> rusqlite::Result<Market> {
+    Ok(Market {
+        pair: row.get(0)?,
+        store_rate_avg: row.get(1)?,
+        ex_rate_sell: row.get(2)?,
+        ex_rate_buy: row.get(3)?,
+        ex_volume_sell: row.get(4)?,
+        ex_volume_buy: row.get(5)?,
+        recorded_at: row.get(6)?,
+    })
+}
+
+fn to_report(row: &Row) -> rusqlite::Result<Report> {
+    Ok(Report {
+        bot_name: row.get(0)?,
+        period: row.get(1)?,
+        pair: row.get(2)?,
+        begin_at: row.get(3)?,
+        end_at: row.get(4)?,
+        trade_count: row.get(5)?,
+        realized_pnl: row.get(6)?,
+        fee: row.get(7)?,
+        win_count: row.get(8)?,
+        loss_count: row.get(9)?,
+        open_amount: row.get(10)?,
+        unrealized_pnl: row.get(11)?,
+        total_jpy: row.get(12)?,
+        total_jpy_change: row.get(13)?,
+    })
+}
+
+fn to_market_summary(row: &Row) -> rusqlite::Result<MarketSummary> {
+    Ok(MarketSummary {
+        count: row.get(0)?,
+        recorded_at_begin: row.get(1)?,
+        recorded_at_end: row.get(2)?,
+        ex_rate_sell_max: row.get(3)?,
+        ex_rate_sell_min: row.get(4)?,
+        ex_rate_buy_max: row.get(5)?,
+        ex_rate_buy_min: row.get(6)?,
+        ex_volume_sell_total: row.get(7)?,
+        ex_volume_buy_total: row.get(8)?,
+        trade_frequency_ratio: row.get(9)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coincheck::model::Pair;
+    use crate::mysql::model::EventType;
+
+    use chrono::Duration;
+
+    fn make_client() -> DefaultClient {
+        let client = DefaultClient::new_in_memory().unwrap();
+        client.migrate().unwrap();
+        client
+    }
+
+    fn make_market(pair: &str, rate: f64, volume: f64, recorded_at: DateTime<Utc>) -> Market {
+        Market {
+            pair: pair.to_owned(),
+            store_rate_avg: rate,
+            ex_rate_sell: rate,
+            ex_rate_buy: rate + 1.0,
+            ex_volume_sell: volume,
+            ex_volume_buy: volume,
+            recorded_at: recorded_at.naive_utc(),
+        }
+    }
+
+    #[test]
+    fn test_migrate() {
+        let client = make_client();
+        assert_eq!(client.migrate().unwrap(), Vec::<u32>::new());
+        assert_eq!(
+            client.schema_version().unwrap(),
+            MIGRATIONS.last().unwrap().version
+        );
+    }
+
+    #[test]
+    fn test_select_markets() {
+        let client = make_client();
+        let now = Utc::now();
+        client
+            .insert_market(&make_market(
+                "btc_jpy",
+                100.0,
+                1.0,
+                now - Duration::minutes(3),
+            ))
+            .unwrap();
+        client
+            .insert_market(&make_market(
+                "btc_jpy",
+                110.0,
+                1.0,
+                now - Duration::minutes(1),
+            ))
+            .unwrap();
+        client
+            .insert_market(&make_market(
+                "mona_jpy",
+                200.0,
+                1.0,
+                now - Duration::minutes(1),
+            ))
+            .unwrap();
+
+        let got = client
+            .select_markets("btc_jpy", now - Duration::minutes(2))
+            .unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].ex_rate_sell, 110.0);
+
+        let got = client
+            .select_markets("btc_jpy' OR '1' = '1", now - Duration::minutes(10))
+            .unwrap();
+        assert!(got.is_empty());
+    }
+
+    #[test]
+    fn test_bot_status() {
+        let client = make_client();
+        let mut status = BotStatus {
+            bot_name: "bot".to_owned(),
+            pair: "all".to_owned(),
+            r#type: "total_jpy".to_owned(),
+            value: 1000.0,
+            memo: "it's \"quoted\"".to_owned(),
+        };
+        client.upsert_bot_status(&status).unwrap();
+        status.value = 2000.0;
+        client.upsert_bot_status(&status).unwrap();
+
+        let got = client.select_bot_status("bot", "all", "total_jpy").unwrap();
+        assert_eq!(got.value, 2000.0);
+        assert_eq!(got.memo, status.memo);
+
+        assert!(client.select_bot_status("bot", "all", "unknown").is_err());
+    }
+
+    #[test]
+    fn test_in_flight_action() {
+        let client = make_client();
+        assert_eq!(client.select_in_flight_action("bot").unwrap(), None);
+
+        let mut action = InFlightAction {
+            bot_name: "bot".to_owned(),
+            pair: "btc_jpy".to_owned(),
+            action_type: "loss_cut".to_owned(),
+            state: "{\"type\":\"loss_cut\",\"canceled\":false}".to_owned(),
+            attempts: 0,
+            updated_at: Utc::now().naive_utc(),
+        };
+        client.upsert_in_flight_action(&action).unwrap();
+        action.state = "{\"type\":\"loss_cut\",\"canceled\":true}".to_owned();
+        action.attempts = 1;
+        client.upsert_in_flight_action(&action).unwrap();
+
+        let got = client.select_in_flight_action("bot").unwrap().unwrap();
+        assert_eq!(got.state, action.state);
+        assert_eq!(got.attempts, 1);
+
+        client.delete_in_flight_action("bot").unwrap();
+        assert_eq!(client.select_in_flight_action("bot").unwrap(), None);
+    }
+
+    #[test]
+    fn test_report() {
+        let client = make_client();
+        let end_at = NaiveDateTime::parse_from_str("2021-05-16 12:00:00", DATETIME_FORMAT).unwrap();
+        let mut report = Report {
+            bot_name: "bot".to_owned(),
+            period: "daily".to_owned(),
+            pair: "all".to_owned(),
+            begin_at: end_at - Duration::days(1),
+            end_at,
+            trade_count: 3,
+            realized_pnl: 120.5,
+            fee: 1.5,
+            win_count: 2,
+            loss_count: 1,
+            open_amount: 0.01,
+            unrealized_pnl: -30.0,
+            total_jpy: 10120.5,
+            total_jpy_change: 120.5,
+        };
+        client.upsert_report(&report).unwrap();
+        // 同じ期間は上書きする
+        report.trade_count = 4;
+        client.upsert_report(&report).unwrap();
+        let mut next = report.clone();
+        next.begin_at = end_at;
+        next.end_at = end_at + Duration::days(1);
+        client.upsert_report(&next).unwrap();
+
+        let got = client
+            .select_latest_report("bot", "daily", "all", &next.end_at)
+            .unwrap();
+        assert_eq!(got, Some(report));
+        let got = client
+            .select_latest_report("bot", "daily", "all", &(next.end_at + Duration::seconds(1)))
+            .unwrap();
+        assert_eq!(got, Some(next));
+        let got = client
+            .select_latest_report("bot", "weekly", "all", &end_at)
+            .unwrap();
+        assert_eq!(got, None);
+    }
+
+    #[test]
+    fn test_insert_event() {
+        let client = make_client();
+        let event = Event {
+            pair: Pair::new("btc_jpy").unwrap(),
+            event_type: EventType::Sell,
+            memo: "sell completed! `it's 'quoted'`".to_owned(),
+            recorded_at: Utc::now().naive_utc(),
+        };
+        client.insert_event(&event).unwrap();
+
+        let conn = client.get_conn().unwrap();
+        let (event_type, memo): (u8, String) = conn
+            .query_row("SELECT event_type, memo FROM events", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(event_type, 1);
+        assert_eq!(memo, event.memo);
+    }
+
+    #[test]
+    fn test_select_market_summary() {
+        let client = make_client();
+        assert!(client.select_market_summary("btc_jpy", 1).is_err());
+
+        let now = Utc::now();
+        // 集計期間外（直近1時間以内）
+        client
+            .insert_market(&make_market(
+                "btc_jpy",
+                500.0,
+                1.0,
+                now - Duration::minutes(30),
+            ))
+            .unwrap();
+        client
+            .insert_market(&make_market(
+                "btc_jpy",
+                100.0,
+                0.0,
+                now - Duration::hours(3),
+            ))
+            .unwrap();
+        client
+            .insert_market(&make_market(
+                "btc_jpy",
+                120.0,
+                2.0,
+                now - Duration::hours(2),
+            ))
+            .unwrap();
+
+        let got = client.select_market_summary("btc_jpy", 1).unwrap();
+        assert_eq!(got.count, 2);
+        assert_eq!(got.ex_rate_sell_max, 120.0);
+        assert_eq!(got.ex_rate_sell_min, 100.0);
+        assert_eq!(got.ex_volume_sell_total, 2.0);
+        assert_eq!(got.trade_frequency_ratio, 0.5);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::error::MyResult;
+use crate::mysql::migration::{pending, split_statements, Migration};
+
+use indoc::indoc;
+use rusqlite::{named_params, Connection};
+
+pub const MIGRATIONS: [Migration; 5] = [
+    Migration {
+        version: 1,
+        name: "create_markets",
+        sql: include_str!("../../migrations/sqlite/V001__create_markets.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "create_bot_statuses",
+        sql: include_str!("../../migrations/sqlite/V002__create_bot_statuses.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "create_events",
+        sql: include_str!("../../migrations/sqlite/V003__create_events.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "create_in_flight_actions",
+        sql: include_str!("../../migrations/sqlite/V004__create_in_flight_actions.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "create_reports",
+        sql: include_str!("../../migrations/sqlite/V005__create_reports.sql"),
+    },
+];
+
+const CREATE_SCHEMA_MIGRATIONS_SQL: &str = indoc!(
+    "
+    CREATE TABLE IF NOT EXISTS schema_migrations (
+        version INTEGER NOT NULL PRIMARY KEY,
+        name TEXT NOT NULL,
+        applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+    )
+    "
+);
+
+const SELECT_APPLIED_VERSIONS_SQL: &str = "SELECT version FROM schema_migrations ORDER BY version";
+
+const INSERT_APPLIED_VERSION_SQL: &str =
+    "INSERT INTO schema_migrations (version, name) VALUES (:version, :name)";
+
+// 未適用のマイグレーションを適用し、適用したバージョンを返す
+// SQLiteはDDLもトランザクションに含められるため、マイグレーション単位で全体が反映されるか何も反映されないかになる
+pub fn migrate(conn: &mut Connection, migrations: &[Migration]) -> MyResult<Vec<u32>> {
+    conn.execute(CREATE_SCHEMA_MIGRATIONS_SQL, [])?;
+    let applied = applied_versions(conn)?;
+
+    let mut versions = vec![];
+    for m in pending(migrations, &applied) {
+        let tx = conn.transaction()?;
+        for statement in split_statements(m.sql) {
+            tx.execute(&statement, [])?;
+        }
+        tx.execute(
+            INSERT_APPLIED_VERSION_SQL,
+            named_params! {
+                ":version": m.version,
+                ":name": m.name,
+            },
+        )?;
+        tx.commit()?;
+        versions.push(m.version);
+    }
+    Ok(versions)
+}
+
+// 適用済みの最新バージョン（未適用なら0）
+pub fn current_version(conn: &Connection) -> MyResult<u32> {
+    conn.execute(CREATE_SCHEMA_MIGRATIONS_SQL, [])?;
+    let applied = applied_versions(conn)?;
+    Ok(applied.into_iter().max().unwrap_or(0))
+}
+
+fn applied_versions(conn: &Connection) -> MyResult<Vec<u32>> {
+    let mut stmt = conn.prepare(SELECT_APPLIED_VERSIONS_SQL)?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    let mut versions = vec![];
+    for v in rows {
+        versions.push(v?);
+    }
+    Ok(versions)
+}
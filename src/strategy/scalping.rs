@@ -1,11 +1,11 @@
 use crate::bot::model::{
-    ActionType, AvgDownParam, EntryParam, LineMethod, LossCutParam, NotifyParam, SetProfitParam,
-    TradeInfo,
+    ActionType, AvgDownParam, EntryParam, LineMethod, LossCutParam, NotifyParam, RecoverParam,
+    SellParam, SetProfitParam, TradeInfo,
 };
 use crate::coincheck;
 use crate::coincheck::model::{OpenOrder, OrderType, Pair};
 use crate::error::MyResult;
-use crate::slack::client::TextMessage;
+use crate::notifier::model::{Event, Level, Notification};
 use crate::strategy::base::Strategy;
 use crate::util;
 use async_trait::async_trait;
@@ -31,10 +31,21 @@ impl Strategy for ScalpingStrategy<'_> {
     {
         let mut actions: Vec<ActionType> = Vec::new();
 
+        if self.config.recover_orphaned_coin {
+            debug!("========== check orphaned coin ==========");
+            if let Some(action_type) = self
+                .check_orphaned_coin(info, buy_jpy_per_lot, coincheck_cli)
+                .await?
+            {
+                actions.push(action_type);
+                return Ok(actions);
+            }
+        }
+
         debug!("========== check unused coin ==========");
-        if let Some(action_type) = self.check_unused_coin(now, info, buy_jpy_per_lot)? {
+        // 未使用コインがあっても、未決済注文の損切りなどは止めない
+        if let Some(action_type) = self.check_unused_coin(info, buy_jpy_per_lot)? {
             actions.push(action_type);
-            return Ok(actions);
         }
 
         debug!("========== check open orders ==========");
@@ -64,18 +75,12 @@ impl Strategy for ScalpingStrategy<'_> {
 
 impl ScalpingStrategy<'_> {
     // 未使用コインが一定以上なら通知
+    // 同じ通知の繰り返しは通知側でまとめるため、毎回判定する
     fn check_unused_coin(
         &self,
-        now: &DateTime<Utc>,
         info: &TradeInfo,
         buy_jpy_per_lot: f64,
     ) -> MyResult<Option<ActionType>> {
-        let (is_notification_timing, memo) = util::is_notification_timing(now);
-        if !is_notification_timing {
-            debug!("{}", format!("NONE <= {}", memo).blue());
-            return Ok(None);
-        }
-
         let border = buy_jpy_per_lot / info.buy_rate;
         let (has_unused_coin, memo) = util::has_unused_coin(info.get_balance_key()?, border);
         if has_unused_coin {
@@ -91,9 +96,72 @@ impl ScalpingStrategy<'_> {
         );
         let action = ActionType::Notify(NotifyParam {
             log_message: message.to_string(),
-            slack_message: TextMessage {
-                text: message.to_string(),
-            },
+            notification: Notification::new(Event::UnusedCoin, "unused coin exist", Level::Warning)
+                .field("coin", &self.config.key_currency())
+                .field("amount", &info.get_balance_key()?.amount.to_string()),
+        });
+        Ok(Some(action))
+    }
+
+    // 売注文のないコインがあれば、直近の買いの約定から求めた取得レートで売注文を出す
+    // 取得レートが分からなければ回収せず、未使用コインの通知に任せる
+    async fn check_orphaned_coin<T>(
+        &self,
+        info: &TradeInfo,
+        buy_jpy_per_lot: f64,
+        coincheck_cli: &T,
+    ) -> MyResult<Option<ActionType>>
+    where
+        T: coincheck::client::Client + std::marker::Sync,
+    {
+        let amount = info.get_balance_key()?.amount;
+        // 最小注文数量が分からない取引ペアは1ロット分を基準にする
+        let border = match info.pair.metadata() {
+            Some(m) => m.min_amount,
+            None => buy_jpy_per_lot / info.buy_rate,
+        };
+        if amount < border {
+            debug!(
+                "{}",
+                format!(
+                    "NONE <= has not orphaned coin, coin:{:.3} < border:{:.3}",
+                    amount, border
+                )
+                .blue()
+            );
+            return Ok(None);
+        }
+
+        let transactions = coincheck_cli.get_exchange_orders_transactions().await?;
+        let cost_rate = match util::calc_cost_basis_rate(&transactions, &info.pair, amount) {
+            Some(v) => v,
+            None => {
+                debug!(
+                    "{}",
+                    format!(
+                        "NONE <= cost rate of orphaned coin is unknown, coin:{:.3}",
+                        amount
+                    )
+                    .blue()
+                );
+                return Ok(None);
+            }
+        };
+        info!(
+            "{}",
+            format!(
+                "RECOVER <= orphaned coin exist, coin:{:.3}, cost_rate:{:.3}",
+                amount, cost_rate
+            )
+            .green()
+        );
+
+        let action = ActionType::Recover(RecoverParam {
+            pair: info.pair.clone(),
+            amount,
+            cost_rate,
+            profit_ratio: self.config.profit_ratio_per_order,
+            offset_sell_rate_ratio: self.config.offset_sell_rate_ratio,
         });
         Ok(Some(action))
     }
@@ -123,6 +191,46 @@ impl ScalpingStrategy<'_> {
                 .blue()
             );
             match open_order.order_type {
+                // 逆指値注文は損切りを取引所に任せ、利確のみ確認する
+                OrderType::Sell if open_order.stop_loss_rate.is_some() => {
+                    let position = match self.to_position_order(open_order) {
+                        Some(v) => v,
+                        None => {
+                            debug!(
+                                "{}",
+                                "NONE <= stop loss rate ratio is not set, skip stop loss order"
+                                    .blue()
+                            );
+                            continue;
+                        }
+                    };
+                    if let Some(a) = self
+                        .check_set_profit(info, &position, coincheck_cli)
+                        .await?
+                    {
+                        actions.push(a);
+                    }
+                }
+                // トレーリングする取引ペアは、利確の売注文を引き上げてから最高値からの下げで利確する
+                OrderType::Sell
+                    if self
+                        .config
+                        .trailing_take_profit_ratio(&open_order.pair)
+                        .is_some() =>
+                {
+                    if let Some(a) = self.check_trailing_take_profit(info, open_order)? {
+                        actions.push(a);
+                        continue;
+                    }
+                    if let Some(a) = self.check_avg_down(now, info, open_order, buy_jpy_per_lot)? {
+                        actions.push(a);
+                        continue;
+                    }
+                    if let Some(a) = self.check_loss_cut(info, open_order)? {
+                        actions.push(a);
+                        continue;
+                    }
+                }
                 OrderType::Sell => {
                     if let Some(a) = self.check_avg_down(now, info, open_order, buy_jpy_per_lot)? {
                         actions.push(a);
@@ -146,6 +254,26 @@ impl ScalpingStrategy<'_> {
         Ok(actions)
     }
 
+    // 逆指値注文を、エントリー時の利確の指値注文（目標レート）に読み替える
+    // 逆指値注文でなければそのまま返し、逆指値の比率が未設定で読み替えられなければ None を返す
+    fn to_position_order(&self, open_order: &OpenOrder) -> Option<OpenOrder> {
+        let stop_loss_rate = match open_order.stop_loss_rate {
+            Some(v) => v,
+            None => return Some(open_order.clone()),
+        };
+        let ratio = self.config.stop_loss_rate_ratio?;
+        let mut position = open_order.clone();
+        position.rate = util::calc_take_profit_rate(
+            stop_loss_rate,
+            ratio,
+            self.config.profit_ratio_per_order,
+            self.config.offset_sell_rate_ratio,
+            &self.config.fee_rate(&open_order.pair),
+        );
+        position.stop_loss_rate = None;
+        Some(position)
+    }
+
     // ロスカット？
     fn check_loss_cut(
         &self,
@@ -163,6 +291,7 @@ impl ScalpingStrategy<'_> {
             let action = ActionType::LossCut(LossCutParam {
                 pair: Pair::new(&self.config.target_pair)?,
                 open_order_id: open_order.id,
+                open_order_rate: open_order.rate,
                 amount: open_order.pending_amount,
             });
             Ok(Some(action))
@@ -271,8 +400,12 @@ impl ScalpingStrategy<'_> {
             )
             .await?;
 
-        let (should_set_profit, memo) =
-            util::should_set_profit(rate, open_order, self.config.offset_sell_rate_ratio);
+        let (should_set_profit, memo) = util::should_set_profit(
+            rate,
+            open_order,
+            self.config.offset_sell_rate_ratio,
+            &self.config.fee_rate(&open_order.pair),
+        );
         if should_set_profit {
             info!("{} <= {}", "Set Profit".green(), memo);
             let action = ActionType::SetProfit(SetProfitParam {
@@ -287,6 +420,82 @@ impl ScalpingStrategy<'_> {
         }
     }
 
+    // 利確（トレーリング）？
+    fn check_trailing_take_profit(
+        &self,
+        info: &TradeInfo,
+        open_order: &OpenOrder,
+    ) -> MyResult<Option<ActionType>> {
+        let trailing_take_profit_ratio =
+            match self.config.trailing_take_profit_ratio(&open_order.pair) {
+                Some(v) => v,
+                None => return Ok(None),
+            };
+        let sell_rate = info.get_sell_rate()?;
+
+        // 目標レートを超えたら、売注文を現在のレートまで引き上げて最高値を更新する
+        // 引き上げ幅が最小幅に満たなければ、注文を出し直さない
+        let (should_raise, memo) = util::should_set_profit(
+            sell_rate,
+            open_order,
+            self.config.offset_sell_rate_ratio,
+            &self.config.fee_rate(&open_order.pair),
+        );
+        let new_rate = sell_rate * (1.0 + self.config.offset_sell_rate_ratio);
+        let min_rate = open_order.rate * (1.0 + self.config.trailing_step_ratio);
+        if should_raise && new_rate >= min_rate {
+            info!("{} <= {}", "Trail Profit".green(), memo);
+            let action = ActionType::Sell(SellParam {
+                open_order_ids: vec![open_order.id],
+                open_order_rate: Some(open_order.rate),
+                pair: Pair::new(&self.config.target_pair)?,
+                rate: new_rate,
+                amount: open_order.pending_amount,
+                trailing_high_rate: Some(sell_rate),
+            });
+            return Ok(Some(action));
+        }
+        if should_raise {
+            debug!(
+                "{}",
+                format!(
+                    "NONE <= raise is too small, new_rate:{:.3} < min_rate:{:.3}",
+                    new_rate, min_rate
+                )
+                .blue()
+            );
+        } else {
+            debug!("{}", format!("NONE <= {}", memo).blue());
+        }
+
+        let trailing_high_rate = match info.trailing_high_rate {
+            Some(v) => v,
+            None => {
+                debug!("{}", "NONE <= trailing high rate is not recorded".blue());
+                return Ok(None);
+            }
+        };
+        let (should, memo) = util::should_take_trailing_profit(
+            sell_rate,
+            trailing_high_rate,
+            open_order,
+            self.config.offset_sell_rate_ratio,
+            trailing_take_profit_ratio,
+        );
+        if should {
+            info!("{} <= {}", "Set Profit".green(), memo);
+            let action = ActionType::SetProfit(SetProfitParam {
+                pair: Pair::new(&self.config.target_pair)?,
+                open_order_id: open_order.id,
+                amount: open_order.pending_amount,
+            });
+            Ok(Some(action))
+        } else {
+            debug!("{}", format!("NONE <= {}", memo).blue());
+            Ok(None)
+        }
+    }
+
     fn should_check_entry(&self, info: &TradeInfo, buy_jpy_per_lot: f64) -> MyResult<bool> {
         let mut should = true;
 
@@ -320,10 +529,15 @@ impl ScalpingStrategy<'_> {
         let profit_ratio = (1.0 + self.config.profit_ratio_per_order)
             * (1.0 + self.config.offset_sell_rate_ratio)
             - 1.0;
+        let open_orders: Vec<OpenOrder> = info
+            .open_orders
+            .iter()
+            .filter_map(|o| self.to_position_order(o))
+            .collect();
         let (has_near_rate_order, memo) = util::has_near_rate_order(
             info.buy_rate,
             profit_ratio,
-            &info.open_orders,
+            &open_orders,
             self.config.entry_skip_rate_ratio,
         );
         if has_near_rate_order {
@@ -562,10 +776,10 @@ mod tests {
     use crate::bot::model::LossCutParam;
     use crate::bot::model::NotifyParam;
     use crate::coincheck::client::MockClient;
-    use crate::coincheck::model::{Balance, OrderBooks, Pair};
-    use crate::config::Config;
+    use crate::coincheck::model::{Balance, FeeRate, Liquidity, OrderBooks, Pair, Transaction};
+    use crate::config::{Config, DbType, OverrunPolicy, SmtpSecurity};
     use crate::mysql::model::MarketSummary;
-    use crate::slack::client::TextMessage;
+    use crate::notifier::model::{Event, Level, Notification};
     use crate::strategy::scalping::ActionType::LossCut;
 
     use std::collections::HashMap;
@@ -583,7 +797,6 @@ mod tests {
             key_reserved: f64,
             buy_jpy_per_lot: f64,
             buy_rate: f64,
-            now: String,
             want: Option<ActionType>,
         }
         let mut params = HashMap::new();
@@ -594,34 +807,19 @@ mod tests {
                 key_reserved: 1.0,
                 buy_jpy_per_lot: 2.0,
                 buy_rate: 2.0,
-                now: "2018-12-07T19:30:20+09:00".to_string(),
-                want: None,
-            },
-        );
-        params.insert(
-            "when now is not notification time",
-            Param {
-                key_amount: 1.0,
-                key_reserved: 0.0,
-                buy_jpy_per_lot: 2.0,
-                buy_rate: 2.0,
-                now: "2018-12-07T19:31:28+09:00".to_string(),
                 want: None,
             },
         );
         params.insert(
-            "when balance is enough, now is notification time",
+            "when balance is enough",
             Param {
                 key_amount: 1.0,
                 key_reserved: 0.0,
                 buy_jpy_per_lot: 2.0,
                 buy_rate: 2.0,
-                now: "2018-12-07T19:30:20+09:00".to_string(),
                 want: Some(ActionType::Notify(NotifyParam {
                     log_message: "".to_string(),
-                    slack_message: TextMessage {
-                        text: "".to_string(),
-                    },
+                    notification: Notification::new(Event::UnusedCoin, "", Level::Warning),
                 })),
             },
         );
@@ -629,9 +827,6 @@ mod tests {
         for (name, p) in params.iter() {
             let config = make_config();
             let strategy = ScalpingStrategy { config: &config };
-            let now = DateTime::parse_from_rfc3339(&p.now)
-                .unwrap()
-                .with_timezone(&Utc);
             let mut info = make_info();
             info.balances.insert(
                 info.pair.key.clone(),
@@ -642,7 +837,7 @@ mod tests {
             );
             info.buy_rate = p.buy_rate;
 
-            let got = strategy.check_unused_coin(&now, &info, p.buy_jpy_per_lot);
+            let got = strategy.check_unused_coin(&info, p.buy_jpy_per_lot);
             assert!(got.is_ok(), "{}, failure: want: ok, got: err", name);
 
             let got = got.unwrap();
@@ -665,6 +860,171 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_check_orphaned_coin() {
+        let pair = Pair::new(&format!("{}_{}", COIN_KEY, COIN_SETTLEMENT)).unwrap();
+        let make_transaction = |id: u64, side: OrderType, rate: f64, amount: f64| Transaction {
+            id,
+            order_id: id,
+            pair: pair.clone(),
+            side,
+            rate,
+            funds: vec![(COIN_KEY.to_string(), amount)].into_iter().collect(),
+            fee: 0.0,
+            fee_currency: None,
+            liquidity: Liquidity::Taker,
+            created_at: DateTime::parse_from_rfc3339("2018-12-07T19:31:28+09:00").unwrap(),
+        };
+        struct Param {
+            key_amount: f64,
+            transactions: Vec<Transaction>,
+            want: Option<ActionType>,
+        }
+        let mut params = HashMap::new();
+        params.insert(
+            "when balance is less than minimum amount",
+            Param {
+                key_amount: 0.001,
+                transactions: vec![],
+                want: None,
+            },
+        );
+        params.insert(
+            "when cost rate is unknown",
+            Param {
+                key_amount: 0.01,
+                transactions: vec![make_transaction(1, OrderType::Buy, 4_000_000.0, 0.005)],
+                want: None,
+            },
+        );
+        // 新しい順に数量分の買いの約定を加重平均する（売りの約定は除く）
+        params.insert(
+            "when cost rate is known",
+            Param {
+                key_amount: 0.01,
+                transactions: vec![
+                    make_transaction(3, OrderType::Sell, 5_000_000.0, -0.01),
+                    make_transaction(2, OrderType::Buy, 4_000_000.0, 0.006),
+                    make_transaction(1, OrderType::Buy, 3_000_000.0, 0.01),
+                ],
+                want: Some(ActionType::Recover(RecoverParam {
+                    pair: pair.clone(),
+                    amount: 0.01,
+                    cost_rate: 3_600_000.0,
+                    profit_ratio: 0.0015,
+                    offset_sell_rate_ratio: 0.01,
+                })),
+            },
+        );
+
+        for (name, p) in params.into_iter() {
+            let mut config = make_config();
+            config.recover_orphaned_coin = true;
+            let strategy = ScalpingStrategy { config: &config };
+            let mut info = make_info();
+            info.balances.insert(
+                COIN_KEY.to_string(),
+                Balance {
+                    amount: p.key_amount,
+                    reserved: 0.0,
+                },
+            );
+
+            let mut coincheck_cli = MockClient::new();
+            let transactions = p.transactions;
+            coincheck_cli
+                .expect_get_exchange_orders_transactions()
+                .returning(move || {
+                    let v = transactions.clone();
+                    Box::pin(async move { Ok(v) })
+                });
+
+            let got = strategy
+                .check_orphaned_coin(&info, 1000.0, &coincheck_cli)
+                .await
+                .unwrap();
+            match (&got, &p.want) {
+                (Some(ActionType::Recover(got_param)), Some(ActionType::Recover(want_param))) => {
+                    assert!(
+                        (got_param.cost_rate - want_param.cost_rate).abs() < 1e-6,
+                        "{}, failure: got: {:?}",
+                        name,
+                        got
+                    );
+                    assert_eq!(
+                        RecoverParam {
+                            cost_rate: want_param.cost_rate,
+                            pair: got_param.pair.clone(),
+                            ..*got_param
+                        },
+                        *want_param,
+                        "{}, failure",
+                        name
+                    );
+                }
+                _ => assert_eq!(got, p.want, "{}, failure", name),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_judge_with_unused_coin() {
+        let mut config = make_config();
+        config.loss_cut_rate_ratio = 0.97;
+        config.offset_sell_rate_ratio = 0.01;
+        let strategy = ScalpingStrategy { config: &config };
+
+        let mut info = make_info();
+        info.balances.insert(
+            COIN_KEY.to_string(),
+            Balance {
+                amount: 1.0,
+                reserved: 1.0,
+            },
+        );
+        info.buy_rate = 2.0;
+        info.sell_rates
+            .insert(format!("{}_{}", COIN_KEY, COIN_SETTLEMENT), 96.9);
+        info.sell_rate_histories = vec![96.9; 10];
+        info.support_lines_long = vec![96.9; 10];
+        info.support_lines_short = vec![96.9; 10];
+        info.resistance_lines = vec![96.9; 10];
+        info.open_orders = vec![OpenOrder {
+            id: 100,
+            rate: 101.0,
+            pending_amount: 1.0,
+            pending_market_buy_amount: None,
+            order_type: OrderType::Sell,
+            stop_loss_rate: None,
+            pair: format!("{}_{}", COIN_KEY, COIN_SETTLEMENT),
+            created_at: DateTime::parse_from_rfc3339("2018-12-07T19:31:28+09:00").unwrap(),
+        }];
+
+        let coincheck_cli = MockClient::new();
+        let now = Utc::now();
+        let got = strategy
+            .judge(&now, &info, 2.0, &coincheck_cli)
+            .await
+            .unwrap();
+
+        // 未使用コインの通知と損切りの両方を行う
+        assert!(
+            got.iter().any(|a| matches!(a, ActionType::Notify(_))),
+            "got: {:?}",
+            got
+        );
+        assert!(
+            got.contains(&LossCut(LossCutParam {
+                pair: Pair::new(&format!("{}_{}", COIN_KEY, COIN_SETTLEMENT)).unwrap(),
+                open_order_id: 100,
+                open_order_rate: 101.0,
+                amount: 1.0,
+            })),
+            "got: {:?}",
+            got
+        );
+    }
+
     #[test]
     fn test_check_loss_cut() {
         struct Param {
@@ -687,6 +1047,7 @@ mod tests {
                     pending_amount: 1.0,
                     pending_market_buy_amount: None,
                     order_type: OrderType::Sell,
+                    stop_loss_rate: None,
                     pair: format!("{}_{}", COIN_KEY, COIN_SETTLEMENT),
                     created_at: DateTime::parse_from_rfc3339("2018-12-07T19:31:28+09:00").unwrap(),
                 },
@@ -705,6 +1066,7 @@ mod tests {
                     pending_amount: 1.0,
                     pending_market_buy_amount: None,
                     order_type: OrderType::Sell,
+                    stop_loss_rate: None,
                     pair: format!("{}_{}", COIN_KEY, COIN_SETTLEMENT),
                     created_at: DateTime::parse_from_rfc3339("2018-12-07T19:31:28+09:00").unwrap(),
                 },
@@ -714,6 +1076,7 @@ mod tests {
                         settlement: COIN_SETTLEMENT.to_string(),
                     },
                     open_order_id: 100,
+                    open_order_rate: 101.0,
                     amount: 1.0,
                 })),
             },
@@ -752,6 +1115,342 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_check_open_orders_with_stop_loss_order() {
+        struct Param {
+            sell_rate: f64,
+            stop_loss_rate_ratio: Option<f64>,
+            want: Option<ActionType>,
+        }
+        let mut params = HashMap::new();
+        params.insert(
+            "sell_rate is greater than take profit rate",
+            Param {
+                sell_rate: 101.0,
+                stop_loss_rate_ratio: Some(0.95),
+                want: Some(ActionType::SetProfit(SetProfitParam {
+                    pair: Pair::new(&format!("{}_{}", COIN_KEY, COIN_SETTLEMENT)).unwrap(),
+                    open_order_id: 100,
+                    amount: 1.0,
+                })),
+            },
+        );
+        params.insert(
+            "sell_rate is less than take profit rate",
+            Param {
+                sell_rate: 100.0,
+                stop_loss_rate_ratio: Some(0.95),
+                want: None,
+            },
+        );
+        // 損切りは取引所の逆指値注文に任せる
+        params.insert(
+            "sell_rate is less than loss cut rate",
+            Param {
+                sell_rate: 50.0,
+                stop_loss_rate_ratio: Some(0.95),
+                want: None,
+            },
+        );
+        params.insert(
+            "stop_loss_rate_ratio is not set",
+            Param {
+                sell_rate: 101.0,
+                stop_loss_rate_ratio: None,
+                want: None,
+            },
+        );
+
+        for (name, p) in params.iter() {
+            let mut config = make_config();
+            config.profit_ratio_per_order = 0.0015;
+            config.offset_sell_rate_ratio = 0.01;
+            config.stop_loss_rate_ratio = p.stop_loss_rate_ratio;
+
+            let strategy = ScalpingStrategy { config: &config };
+            let mut info = make_info();
+            info.sell_rates
+                .insert(format!("{}_{}", COIN_KEY, COIN_SETTLEMENT), p.sell_rate);
+            info.sell_rate_histories = vec![p.sell_rate; 5];
+            // 建値 100.0 の逆指値注文（利確の目標レートは 100.0 * 1.0015 * 1.01）
+            info.open_orders = vec![OpenOrder {
+                id: 100,
+                rate: 95.0,
+                pending_amount: 1.0,
+                pending_market_buy_amount: None,
+                order_type: OrderType::Sell,
+                stop_loss_rate: Some(95.0),
+                pair: format!("{}_{}", COIN_KEY, COIN_SETTLEMENT),
+                created_at: DateTime::parse_from_rfc3339("2018-12-07T19:31:28+09:00").unwrap(),
+            }];
+
+            let mut coincheck_cli = MockClient::new();
+            let sell_rate = p.sell_rate;
+            coincheck_cli
+                .expect_get_exchange_orders_rate()
+                .returning(move |_, _, _| Box::pin(async move { Ok(sell_rate) }));
+
+            let now = Utc::now();
+            let got = strategy
+                .check_open_orders(&now, &info, 1000.0, &coincheck_cli)
+                .await
+                .unwrap();
+            match &p.want {
+                Some(want) => assert_eq!(
+                    got.iter().collect::<Vec<_>>(),
+                    vec![want],
+                    "{}, failure",
+                    name
+                ),
+                None => assert!(got.is_empty(), "{}, failure: got: {:?}", name, got),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_set_profit_with_fee_rates() {
+        let pair = format!("{}_{}", COIN_KEY, COIN_SETTLEMENT);
+        struct Param {
+            sell_rate: f64,
+            fee_rate: Option<FeeRate>,
+            want: bool,
+        }
+        let mut params = HashMap::new();
+        params.insert(
+            "without fee",
+            Param {
+                sell_rate: 100.5,
+                fee_rate: None,
+                want: true,
+            },
+        );
+        // 成行のテイカー手数料を差し引くと目標利益に届かない
+        params.insert(
+            "taker fee is greater than maker fee",
+            Param {
+                sell_rate: 100.5,
+                fee_rate: Some(FeeRate {
+                    maker: 0.0,
+                    taker: 0.01,
+                }),
+                want: false,
+            },
+        );
+        params.insert(
+            "sell_rate covers taker fee",
+            Param {
+                sell_rate: 101.1,
+                fee_rate: Some(FeeRate {
+                    maker: 0.0,
+                    taker: 0.01,
+                }),
+                want: true,
+            },
+        );
+
+        for (name, p) in params.iter() {
+            let mut config = make_config();
+            config.offset_sell_rate_ratio = 0.01;
+            if let Some(fee_rate) = p.fee_rate {
+                config.fee_rates.insert(pair.clone(), fee_rate);
+            }
+
+            let strategy = ScalpingStrategy { config: &config };
+            let mut info = make_info();
+            info.sell_rates.insert(pair.clone(), p.sell_rate);
+            info.sell_rate_histories = vec![p.sell_rate; 5];
+            // 目標レート 100.0 の売注文
+            let open_order = OpenOrder {
+                id: 100,
+                rate: 101.0,
+                pending_amount: 1.0,
+                pending_market_buy_amount: None,
+                order_type: OrderType::Sell,
+                stop_loss_rate: None,
+                pair: pair.clone(),
+                created_at: Utc::now().into(),
+            };
+
+            let mut coincheck_cli = MockClient::new();
+            let sell_rate = p.sell_rate;
+            coincheck_cli
+                .expect_get_exchange_orders_rate()
+                .returning(move |_, _, _| Box::pin(async move { Ok(sell_rate) }));
+
+            let got = strategy
+                .check_set_profit(&info, &open_order, &coincheck_cli)
+                .await
+                .unwrap();
+            assert_eq!(got.is_some(), p.want, "{}, failure: got: {:?}", name, got);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_open_orders_with_trailing_take_profit() {
+        let pair = format!("{}_{}", COIN_KEY, COIN_SETTLEMENT);
+        struct Param {
+            sell_rate: f64,
+            open_order_rate: f64,
+            trailing_high_rate: Option<f64>,
+            trailing_take_profit_ratio: Option<f64>,
+            want: Option<ActionType>,
+        }
+        let mut params = HashMap::new();
+        params.insert(
+            "sell_rate is greater than target rate",
+            Param {
+                sell_rate: 102.0,
+                open_order_rate: 101.0,
+                trailing_high_rate: None,
+                trailing_take_profit_ratio: Some(0.01),
+                want: Some(ActionType::Sell(SellParam {
+                    open_order_ids: vec![100],
+                    open_order_rate: Some(101.0),
+                    pair: Pair::new(&pair).unwrap(),
+                    rate: 102.0 * 1.01,
+                    amount: 1.0,
+                    trailing_high_rate: Some(102.0),
+                })),
+            },
+        );
+        params.insert(
+            "sell_rate is less than target rate",
+            Param {
+                sell_rate: 99.5,
+                open_order_rate: 101.0,
+                trailing_high_rate: None,
+                trailing_take_profit_ratio: Some(0.01),
+                want: None,
+            },
+        );
+        params.insert(
+            "sell_rate is greater than trailing high rate",
+            Param {
+                sell_rate: 103.0,
+                open_order_rate: 102.0 * 1.01,
+                trailing_high_rate: Some(102.0),
+                trailing_take_profit_ratio: Some(0.01),
+                want: Some(ActionType::Sell(SellParam {
+                    open_order_ids: vec![100],
+                    open_order_rate: Some(102.0 * 1.01),
+                    pair: Pair::new(&pair).unwrap(),
+                    rate: 103.0 * 1.01,
+                    amount: 1.0,
+                    trailing_high_rate: Some(103.0),
+                })),
+            },
+        );
+        // 刻み程度の上昇では、売注文を出し直さない
+        params.insert(
+            "sell_rate rises by a tick",
+            Param {
+                sell_rate: 102.05,
+                open_order_rate: 102.0 * 1.01,
+                trailing_high_rate: Some(102.0),
+                trailing_take_profit_ratio: Some(0.01),
+                want: None,
+            },
+        );
+        params.insert(
+            "sell_rate is within trailing distance",
+            Param {
+                sell_rate: 101.5,
+                open_order_rate: 102.0 * 1.01,
+                trailing_high_rate: Some(102.0),
+                trailing_take_profit_ratio: Some(0.01),
+                want: None,
+            },
+        );
+        params.insert(
+            "sell_rate falls below trailing distance",
+            Param {
+                sell_rate: 100.9,
+                open_order_rate: 102.0 * 1.01,
+                trailing_high_rate: Some(102.0),
+                trailing_take_profit_ratio: Some(0.01),
+                want: Some(ActionType::SetProfit(SetProfitParam {
+                    pair: Pair::new(&pair).unwrap(),
+                    open_order_id: 100,
+                    amount: 1.0,
+                })),
+            },
+        );
+        // 引き上げていない売注文は、最高値からの下げで利確しない
+        params.insert(
+            "open order is not trailed",
+            Param {
+                sell_rate: 100.9,
+                open_order_rate: 104.0,
+                trailing_high_rate: Some(102.0),
+                trailing_take_profit_ratio: Some(0.01),
+                want: None,
+            },
+        );
+        params.insert(
+            "trailing is not enabled",
+            Param {
+                sell_rate: 102.0,
+                open_order_rate: 101.0,
+                trailing_high_rate: None,
+                trailing_take_profit_ratio: None,
+                want: Some(ActionType::SetProfit(SetProfitParam {
+                    pair: Pair::new(&pair).unwrap(),
+                    open_order_id: 100,
+                    amount: 1.0,
+                })),
+            },
+        );
+
+        for (name, p) in params.iter() {
+            let mut config = make_config();
+            config.offset_sell_rate_ratio = 0.01;
+            if let Some(ratio) = p.trailing_take_profit_ratio {
+                config
+                    .trailing_take_profit_ratios
+                    .insert(pair.clone(), ratio);
+            }
+
+            let strategy = ScalpingStrategy { config: &config };
+            let mut info = make_info();
+            info.sell_rates.insert(pair.clone(), p.sell_rate);
+            info.sell_rate_histories = vec![p.sell_rate; 5];
+            // サポートラインが下降中のためナンピンしない
+            info.support_lines_short = vec![100.0, 99.0];
+            info.trailing_high_rate = p.trailing_high_rate;
+            info.open_orders = vec![OpenOrder {
+                id: 100,
+                rate: p.open_order_rate,
+                pending_amount: 1.0,
+                pending_market_buy_amount: None,
+                order_type: OrderType::Sell,
+                stop_loss_rate: None,
+                pair: pair.clone(),
+                created_at: Utc::now().into(),
+            }];
+
+            let mut coincheck_cli = MockClient::new();
+            let sell_rate = p.sell_rate;
+            coincheck_cli
+                .expect_get_exchange_orders_rate()
+                .returning(move |_, _, _| Box::pin(async move { Ok(sell_rate) }));
+
+            let now = Utc::now();
+            let got = strategy
+                .check_open_orders(&now, &info, 1000.0, &coincheck_cli)
+                .await
+                .unwrap();
+            match &p.want {
+                Some(want) => assert_eq!(
+                    got.iter().collect::<Vec<_>>(),
+                    vec![want],
+                    "{}, failure",
+                    name
+                ),
+                None => assert!(got.is_empty(), "{}, failure: got: {:?}", name, got),
+            }
+        }
+    }
+
     fn make_config() -> Config {
         Config {
             bot_name: "dummy_bot_name".to_string(),
@@ -760,6 +1459,9 @@ mod tests {
             rate_period_minutes: 0,
             external_service_wait_interval_sec: 0,
             demo_mode: false,
+            overrun_policy: OverrunPolicy::Skip,
+            schedule_utc_offset_hours: 0,
+            cancel_open_orders_on_shutdown: false,
             wma_period_short: 5,
             wma_period_long: 10,
             resistance_line_period: 5,
@@ -777,22 +1479,55 @@ mod tests {
             funds_ratio_per_order: 0.1,
             profit_ratio_per_order: 0.0015,
             offset_sell_rate_ratio: 0.01,
+            fee_rates: HashMap::new(),
             hold_limit_minutes: 10,
             avg_down_rate_ratio: 0.97,
             avg_down_rate_ratio_on_holding_expired: 0.98,
             loss_cut_rate_ratio: 0.80,
+            stop_loss_rate_ratio: None,
+            stop_loss_slippage_ratio: 0.01,
+            trailing_take_profit_ratios: HashMap::new(),
+            trailing_step_ratio: 0.001,
             entry_skip_rate_ratio: 0.960,
             over_sell_volume_ratio: 0.022,
             required_trade_frequency_ratio: 0.2,
             keep_lot: 1.0,
+            recover_orphaned_coin: false,
             exchange_access_key: "dummy_access_key".to_string(),
             exchange_secret_key: "dummy_secret_key".to_string(),
+            exchange_base_url: "https://coincheck.com".to_string(),
+            exchange_timeout_sec: None,
+            exchange_connect_timeout_sec: None,
+            exchange_proxy: None,
+            exchange_user_agent: None,
+            exchange_nonce_path: None,
+            db_type: DbType::Mysql,
+            db_path: "dummy_db_path".to_string(),
             db_host: "dummy_db_host".to_string(),
             db_port: 100,
             db_name: "dummy_db_name".to_string(),
             db_user_name: "dummy_db_user_name".to_string(),
             db_password: "dummy_db_password".to_string(),
             slack_url: "dummy_slack_url".to_string(),
+            slack_link_url: None,
+            slack_command_addr: None,
+            slack_signing_secret: None,
+            monitor_addr: None,
+            discord_webhook_url: None,
+            line_notify_token: None,
+            smtp_host: None,
+            smtp_port: 587,
+            smtp_security: SmtpSecurity::Starttls,
+            smtp_username: None,
+            smtp_password: None,
+            smtp_from: "".to_string(),
+            smtp_to: vec![],
+            notify_routes: HashMap::new(),
+            notify_dedup_window_sec: 3600,
+            notify_digest_events: vec![],
+            notify_digest_interval_sec: 3600,
+            report_daily_cron: None,
+            report_weekly_cron: None,
         }
     }
 
@@ -859,6 +1594,7 @@ mod tests {
                 bids: vec![],
             },
             market_summary: market_summary,
+            trailing_high_rate: None,
         }
     }
 }
@@ -1,10 +1,15 @@
 use crate::bot::model::StraightLine;
 use crate::coincheck::model::Balance;
+use crate::coincheck::model::FeeRate;
+use crate::coincheck::model::Liquidity;
 use crate::coincheck::model::OpenOrder;
 use crate::coincheck::model::OrderBook;
+use crate::coincheck::model::OrderType;
+use crate::coincheck::model::Pair;
+use crate::coincheck::model::Transaction;
 use crate::error::MyError::TooShort;
 use crate::error::MyResult;
-use chrono::{DateTime, Duration, Timelike, Utc};
+use chrono::{DateTime, Duration, Utc};
 
 // coincheckの仕様に合わせて加工する
 pub fn to_request_string(v: f64) -> String {
@@ -43,24 +48,6 @@ pub fn has_unused_coin(balance: &Balance, amount_per_lot: f64) -> (bool, String)
     }
 }
 
-pub fn is_notification_timing(now: &DateTime<Utc>) -> (bool, String) {
-    let minute = now.minute();
-    if minute % 5 == 0 {
-        (
-            true,
-            format!("it is notification timing now, minute:{} % 5 == 0", minute),
-        )
-    } else {
-        (
-            false,
-            format!(
-                "it is not notification timing now, minute:{} % 5 != 0",
-                minute
-            ),
-        )
-    }
-}
-
 pub fn should_loss_cut(
     sell_rate: f64,
     open_order_rate: f64,
@@ -87,6 +74,73 @@ pub fn should_loss_cut(
     }
 }
 
+// 逆指値注文のレート（建値 * 比率）
+pub fn calc_stop_loss_rate(used_jpy: f64, amount_coin: f64, stop_loss_rate_ratio: f64) -> f64 {
+    used_jpy / amount_coin * stop_loss_rate_ratio
+}
+
+// 売買の手数料を差し引いて建値を回収するための、売りレートの倍率
+// 買いは成行（テイカー）で約定し、売りは sell_liquidity で約定する想定
+pub fn calc_fee_markup(fee_rate: &FeeRate, sell_liquidity: &Liquidity) -> f64 {
+    (1.0 + fee_rate.taker) / (1.0 - fee_rate.ratio(sell_liquidity))
+}
+
+// エントリー時に出す利確の指値注文のレート
+// 手数料を差し引いた利益が目標利益率になるレートに、上方補正率をかける
+pub fn calc_sell_rate(
+    used_jpy: f64,
+    amount_coin: f64,
+    profit_ratio: f64,
+    offset_sell_rate_ratio: f64,
+    fee_rate: &FeeRate,
+) -> f64 {
+    used_jpy / amount_coin
+        * (1.0 + profit_ratio)
+        * calc_fee_markup(fee_rate, &Liquidity::Maker)
+        * (1.0 + offset_sell_rate_ratio)
+}
+
+// 直近の買いの約定から、コイン数量分の取得レート（加重平均）を求める
+// transactions は新しい順, 買いの約定が数量に満たなければ None
+pub fn calc_cost_basis_rate(
+    transactions: &[Transaction],
+    pair: &Pair,
+    amount_coin: f64,
+) -> Option<f64> {
+    let mut total_amount = 0.0;
+    let mut total_jpy = 0.0;
+    for t in transactions
+        .iter()
+        .filter(|t| t.pair == *pair && t.side == OrderType::Buy)
+    {
+        let amount = t.amount().min(amount_coin - total_amount);
+        total_amount += amount;
+        total_jpy += t.rate * amount;
+        if total_amount >= amount_coin {
+            return Some(total_jpy / total_amount);
+        }
+    }
+    None
+}
+
+// 逆指値注文のレートから利確の目標レートを求める
+// エントリー時に出す利確の指値注文と同じレートになる（再起動後も逆指値注文から復元できるようにするため）
+pub fn calc_take_profit_rate(
+    stop_loss_rate: f64,
+    stop_loss_rate_ratio: f64,
+    profit_ratio: f64,
+    offset_sell_rate_ratio: f64,
+    fee_rate: &FeeRate,
+) -> f64 {
+    calc_sell_rate(
+        stop_loss_rate / stop_loss_rate_ratio,
+        1.0,
+        profit_ratio,
+        offset_sell_rate_ratio,
+        fee_rate,
+    )
+}
+
 pub fn should_avg_down(
     now: &DateTime<Utc>,
     buy_rate: f64,
@@ -125,12 +179,15 @@ pub fn should_avg_down(
     }
 }
 
+// 成行（テイカー）で売っても、指値（メイカー）で約定した場合の利益以上になるか
 pub fn should_set_profit(
     sell_rate: f64,
     open_order: &OpenOrder,
     offset_sell_rate_ratio: f64,
+    fee_rate: &FeeRate,
 ) -> (bool, String) {
-    let border = open_order.rate / (1.0 + offset_sell_rate_ratio);
+    let border = open_order.rate / (1.0 + offset_sell_rate_ratio) * (1.0 - fee_rate.maker)
+        / (1.0 - fee_rate.taker);
     if sell_rate > border {
         (
             true,
@@ -150,6 +207,45 @@ pub fn should_set_profit(
     }
 }
 
+// トレーリング中の売注文が、最高値から下げ幅以上に下がったか
+// 売注文のレートが最高値に引き上げ済みでなければ、トレーリング中ではない
+pub fn should_take_trailing_profit(
+    sell_rate: f64,
+    trailing_high_rate: f64,
+    open_order: &OpenOrder,
+    offset_sell_rate_ratio: f64,
+    trailing_take_profit_ratio: f64,
+) -> (bool, String) {
+    let border = open_order.rate / (1.0 + offset_sell_rate_ratio);
+    if (border / trailing_high_rate - 1.0).abs() > 1e-4 {
+        return (
+            false,
+            format!(
+                "should not take trailing profit, not trailing, border:{:.3} != high:{:.3}",
+                border, trailing_high_rate,
+            ),
+        );
+    }
+    let lower = trailing_high_rate * (1.0 - trailing_take_profit_ratio);
+    if sell_rate <= lower {
+        (
+            true,
+            format!(
+                "should take trailing profit, sell_rate:{:.3} <= lower:{:.3}, high:{:.3}",
+                sell_rate, lower, trailing_high_rate,
+            ),
+        )
+    } else {
+        (
+            false,
+            format!(
+                "should not take trailing profit, sell_rate:{:.3} > lower:{:.3}, high:{:.3}",
+                sell_rate, lower, trailing_high_rate,
+            ),
+        )
+    }
+}
+
 pub fn calc_avg_down_buy_amount(
     buy_jpy_per_lot: f64,
     open_order: &OpenOrder,
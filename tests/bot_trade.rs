@@ -0,0 +1,1174 @@
+mod common;
+
+use common::coincheck_stub::{CoincheckStub, RecordedRequest, ACCESS_KEY};
+use common::{add_flat_markets, make_config, RecordingSlackClient, PAIR};
+
+use chrono::{DateTime, Utc};
+use hyper::{Method, StatusCode};
+use trading_bot_rust::bot::action::ActionBehavior;
+use trading_bot_rust::bot::base::Bot;
+use trading_bot_rust::bot::model::{ActionType, EntryParam, LossCutParam, SellParam};
+use trading_bot_rust::bot::reconcile::OrderSnapshot;
+use trading_bot_rust::bot::report::{ReportPeriod, Reporter};
+use trading_bot_rust::bot::saga::{
+    AvgDownState, LossCutState, SagaState, MAX_COMPENSATE_ATTEMPTS, MAX_RESUME_ATTEMPTS,
+};
+use trading_bot_rust::coincheck;
+use trading_bot_rust::coincheck::client::Client as _;
+use trading_bot_rust::coincheck::client::ClientOptions;
+use trading_bot_rust::coincheck::model::{Balance, FeeRate, NewOrder, Pair};
+use trading_bot_rust::config::Config;
+use trading_bot_rust::error::MyResult;
+use trading_bot_rust::monitor::metrics;
+use trading_bot_rust::mysql::client::Client;
+use trading_bot_rust::mysql::memory::MemoryClient;
+use trading_bot_rust::mysql::model::{BotStatus, EventType};
+use trading_bot_rust::strategy::scalping::ScalpingStrategy;
+
+const SELL_RATE: f64 = 4_000_000.0;
+const BUY_RATE: f64 = 4_001_000.0;
+
+// 通知タイミング（5分毎）を避けた時刻
+fn make_now() -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339("2021-05-16T12:03:00Z")
+        .unwrap()
+        .with_timezone(&Utc)
+}
+
+async fn setup(config: &Config, now: DateTime<Utc>) -> (CoincheckStub, MemoryClient) {
+    let stub = CoincheckStub::start().await;
+    stub.set_balance("jpy", 100000.0, 0.0);
+    stub.set_rate(PAIR, SELL_RATE, BUY_RATE);
+    stub.set_order_books(vec![(BUY_RATE, 1.0)], vec![(SELL_RATE, 1.0)]);
+
+    let storage = MemoryClient::new();
+    storage.set_now(now).unwrap();
+    add_flat_markets(&storage, now, 30, SELL_RATE, BUY_RATE);
+    storage
+        .upsert_bot_status(&BotStatus {
+            bot_name: config.bot_name.to_owned(),
+            pair: "all".to_owned(),
+            r#type: "total_jpy".to_owned(),
+            value: 100000.0,
+            memo: "残高（JPY）".to_owned(),
+        })
+        .unwrap();
+    (stub, storage)
+}
+
+async fn trade(
+    config: &Config,
+    stub: &CoincheckStub,
+    storage: &MemoryClient,
+    slack: &RecordingSlackClient,
+    now: &DateTime<Utc>,
+) -> MyResult<()> {
+    let options = ClientOptions {
+        base_url: stub.url.clone(),
+        ..ClientOptions::from_config(config)
+    };
+    let coincheck_cli = coincheck::client::DefaultClient::with_options(
+        &config.exchange_access_key,
+        &config.exchange_secret_key,
+        &options,
+    )?;
+    let strategy = ScalpingStrategy { config };
+    let action_behavior = ActionBehavior {
+        config,
+        notifier: slack,
+        mysql_client: storage,
+        coincheck_client: &coincheck_cli,
+    };
+    let bot = Bot {
+        config,
+        coincheck_client: &coincheck_cli,
+        mysql_client: storage,
+        notifier: slack,
+        strategy: &strategy,
+        action_behavior: &action_behavior,
+    };
+    bot.trade(now).await
+}
+
+#[tokio::test]
+async fn test_trade_without_position() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+
+    trade(&config, &stub, &storage, &slack, &now).await.unwrap();
+
+    // 取引頻度が足りないためエントリーしない
+    assert!(stub
+        .requests_to(Method::POST, "/api/exchange/orders")
+        .is_empty());
+    assert!(stub
+        .requests()
+        .iter()
+        .all(|r| r.error.is_none() || r.error == Some("Nonce must be incremented".to_owned())));
+    assert!(slack.messages().is_empty());
+
+    let sell_rate = storage
+        .select_bot_status(&config.bot_name, PAIR, "sell_rate")
+        .unwrap();
+    assert_eq!(sell_rate.value, -1.0);
+    let total_jpy = storage
+        .select_bot_status(&config.bot_name, "all", "total_jpy")
+        .unwrap();
+    assert_eq!(total_jpy.value, 100000.0);
+}
+
+#[tokio::test]
+async fn test_trade_loss_cut() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+    stub.set_balance("btc", 0.01, 0.0);
+    let id = stub.add_open_order(PAIR, "sell", 6_000_000.0, 0.01);
+
+    trade(&config, &stub, &storage, &slack, &now).await.unwrap();
+
+    // 同一ミリ秒内のリクエストは nonce エラーで再送されるため、成功したリクエストのみ確認する
+    let cancels: Vec<RecordedRequest> = stub
+        .requests_to(Method::DELETE, &format!("/api/exchange/orders/{}", id))
+        .into_iter()
+        .filter(|r| r.error.is_none())
+        .collect();
+    assert_eq!(cancels.len(), 1);
+    let orders: Vec<RecordedRequest> = stub
+        .requests_to(Method::POST, "/api/exchange/orders")
+        .into_iter()
+        .filter(|r| r.error.is_none())
+        .collect();
+    assert_eq!(orders.len(), 1);
+    assert!(orders[0].body.contains("\"order_type\":\"market_sell\""));
+
+    assert!(stub.open_orders().is_empty());
+    assert_eq!(stub.balance("btc"), (0.0, 0.0));
+    assert_eq!(stub.balance("jpy"), (140000.0, 0.0));
+
+    let events = storage.events().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].event_type, EventType::Sell);
+    let messages = slack.messages();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].starts_with("losscut completed!"));
+}
+
+#[tokio::test]
+async fn test_trade_retry_on_nonce_error() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+    stub.fail_next(
+        Method::GET,
+        "/api/accounts/balance",
+        "Nonce must be incremented",
+        2,
+    );
+
+    trade(&config, &stub, &storage, &slack, &now).await.unwrap();
+
+    let requests = stub.requests_to(Method::GET, "/api/accounts/balance");
+    let first_success = requests.iter().position(|r| r.error.is_none()).unwrap();
+    assert!(first_success >= 2);
+    assert!(requests[..first_success]
+        .iter()
+        .all(|r| r.error == Some("Nonce must be incremented".to_owned())));
+}
+
+#[tokio::test]
+async fn test_trade_order_error() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+    stub.set_balance("btc", 0.01, 0.0);
+    stub.add_open_order(PAIR, "sell", 6_000_000.0, 0.01);
+    stub.fail_next(
+        Method::POST,
+        "/api/exchange/orders",
+        "Amount 0.001 is less than minimum",
+        1,
+    );
+
+    // 注文の失敗はSlackに通知され、取引処理自体はエラーにならない
+    trade(&config, &stub, &storage, &slack, &now).await.unwrap();
+
+    assert!(storage.events().unwrap().is_empty());
+    let messages = slack.messages();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].contains("loss cut"));
+    assert!(messages[0].contains("Amount 0.001 is less than minimum"));
+
+    // 他のテストと共有するため、記録されたことのみ確認する
+    let state = metrics::shared().snapshot();
+    assert!(state
+        .api_errors
+        .contains_key(&("/api/exchange/orders".to_owned(), "response".to_owned())));
+    assert!(state
+        .actions
+        .contains_key(&("loss_cut".to_owned(), "failure".to_owned())));
+    assert!(state.status.is_some());
+}
+
+// 注文が処理された上でエラー応答が返った場合は、再注文しない
+#[tokio::test]
+async fn test_trade_ambiguous_order_error_processed() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+    stub.set_balance("btc", 0.01, 0.0);
+    stub.add_open_order(PAIR, "sell", 6_000_000.0, 0.01);
+    stub.fail_next_after_processing(
+        Method::POST,
+        "/api/exchange/orders",
+        StatusCode::BAD_GATEWAY,
+        "bad gateway",
+        1,
+    );
+
+    trade(&config, &stub, &storage, &slack, &now).await.unwrap();
+
+    let orders: Vec<RecordedRequest> = stub
+        .requests_to(Method::POST, "/api/exchange/orders")
+        .into_iter()
+        .filter(|r| r.error != Some("Nonce must be incremented".to_owned()))
+        .collect();
+    assert_eq!(orders.len(), 1);
+    assert_eq!(stub.balance("btc"), (0.0, 0.0));
+    assert_eq!(stub.balance("jpy"), (140000.0, 0.0));
+
+    let events = storage.events().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].event_type, EventType::Sell);
+    let messages = slack.messages();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].starts_with("losscut completed!"));
+}
+
+// 注文が処理されずにエラー応答が返った場合は、照合できなくても再注文せず、次回の取引で照合してから注文する
+#[tokio::test]
+async fn test_trade_ambiguous_order_error_not_processed() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+    stub.set_balance("btc", 0.01, 0.0);
+    stub.add_open_order(PAIR, "sell", 6_000_000.0, 0.01);
+    stub.fail_next_with_status(
+        Method::POST,
+        "/api/exchange/orders",
+        StatusCode::BAD_GATEWAY,
+        "bad gateway",
+        1,
+    );
+
+    trade(&config, &stub, &storage, &slack, &now).await.unwrap();
+
+    let orders = stub.requests_to(Method::POST, "/api/exchange/orders");
+    assert_eq!(orders.len(), 1);
+    assert_eq!(stub.balance("btc"), (0.01, 0.0));
+    assert!(storage.events().unwrap().is_empty());
+    assert!(storage
+        .select_in_flight_action(&config.bot_name)
+        .unwrap()
+        .is_some());
+    let messages = slack.messages();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].contains("order result is unresolved"));
+
+    trade(&config, &stub, &storage, &slack, &now).await.unwrap();
+
+    let orders: Vec<RecordedRequest> = stub
+        .requests_to(Method::POST, "/api/exchange/orders")
+        .into_iter()
+        .filter(|r| r.error.is_none())
+        .collect();
+    assert_eq!(orders.len(), 1);
+    assert_eq!(stub.balance("btc"), (0.0, 0.0));
+    assert_eq!(stub.balance("jpy"), (140000.0, 0.0));
+    assert_eq!(storage.events().unwrap().len(), 1);
+    assert!(storage
+        .select_in_flight_action(&config.bot_name)
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_trade_authentication_error() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+
+    let coincheck_cli =
+        coincheck::client::DefaultClient::with_base_url(ACCESS_KEY, "wrong_secret", &stub.url)
+            .unwrap();
+    let strategy = ScalpingStrategy { config: &config };
+    let action_behavior = ActionBehavior {
+        config: &config,
+        notifier: &slack,
+        mysql_client: &storage,
+        coincheck_client: &coincheck_cli,
+    };
+    let bot = Bot {
+        config: &config,
+        coincheck_client: &coincheck_cli,
+        mysql_client: &storage,
+        notifier: &slack,
+        strategy: &strategy,
+        action_behavior: &action_behavior,
+    };
+
+    let err = bot.trade(&now).await.unwrap_err();
+    assert!(err.to_string().contains("invalid authentication"));
+}
+
+#[tokio::test]
+async fn test_trade_with_user_agent() {
+    let mut config = make_config();
+    config.exchange_user_agent = Some("trading-bot-test/1.0".to_owned());
+    config.exchange_timeout_sec = Some(5);
+    config.exchange_connect_timeout_sec = Some(1);
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+
+    trade(&config, &stub, &storage, &slack, &now).await.unwrap();
+
+    let requests = stub.requests();
+    assert!(!requests.is_empty());
+    assert!(requests
+        .iter()
+        .all(|r| r.user_agent == Some("trading-bot-test/1.0".to_owned())));
+}
+
+// 逆指値の比率を指定すると、エントリー時に取引所側の逆指値注文を出す
+#[tokio::test]
+async fn test_entry_with_stop_loss_order() {
+    let mut config = make_config();
+    config.stop_loss_rate_ratio = Some(0.95);
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+
+    let options = ClientOptions {
+        base_url: stub.url.clone(),
+        ..ClientOptions::from_config(&config)
+    };
+    let coincheck_cli = coincheck::client::DefaultClient::with_options(
+        &config.exchange_access_key,
+        &config.exchange_secret_key,
+        &options,
+    )
+    .unwrap();
+    let action_behavior = ActionBehavior {
+        config: &config,
+        notifier: &slack,
+        mysql_client: &storage,
+        coincheck_client: &coincheck_cli,
+    };
+    let entry = ActionType::Entry(EntryParam {
+        pair: Pair::new(PAIR).unwrap(),
+        amount: 40000.0,
+        profit_ratio: config.profit_ratio_per_order,
+        offset_sell_rate_ratio: config.offset_sell_rate_ratio,
+    });
+    let balance = Balance {
+        amount: 100000.0,
+        reserved: 0.0,
+    };
+    action_behavior.action(&entry, &balance).await.unwrap();
+
+    let amount_coin = 40000.0 / BUY_RATE;
+    let open_orders = stub.open_orders();
+    assert_eq!(open_orders.len(), 1);
+    assert_eq!(open_orders[0].order_type, "sell");
+    assert_eq!(open_orders[0].stop_loss_rate, Some(3_800_950.0));
+    // 指値レートは逆指値レートから滑り幅だけ下げる
+    assert_eq!(open_orders[0].rate, 3_762_940.0);
+    assert!((open_orders[0].pending_amount - amount_coin).abs() < 1e-4);
+    let messages = slack.messages();
+    assert!(messages[0].starts_with("entry completed!"));
+    assert!(messages[0].contains("3800950.000"));
+
+    // Bot が停止していても、レートが下がれば取引所側で損切りされる
+    stub.set_rate(PAIR, 3_700_000.0, 3_701_000.0);
+    assert!(stub.open_orders().is_empty());
+    let (btc, btc_reserved) = stub.balance("btc");
+    assert!(btc.abs() < 1e-4);
+    assert!(btc_reserved.abs() < 1e-9);
+}
+
+#[tokio::test]
+async fn test_entry_with_fee_rates() {
+    let mut config = make_config();
+    config.fee_rates.insert(
+        PAIR.to_owned(),
+        FeeRate {
+            maker: 0.0005,
+            taker: 0.001,
+        },
+    );
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+
+    let options = ClientOptions {
+        base_url: stub.url.clone(),
+        ..ClientOptions::from_config(&config)
+    };
+    let coincheck_cli = coincheck::client::DefaultClient::with_options(
+        &config.exchange_access_key,
+        &config.exchange_secret_key,
+        &options,
+    )
+    .unwrap();
+    let action_behavior = ActionBehavior {
+        config: &config,
+        notifier: &slack,
+        mysql_client: &storage,
+        coincheck_client: &coincheck_cli,
+    };
+    let entry = ActionType::Entry(EntryParam {
+        pair: Pair::new(PAIR).unwrap(),
+        amount: 40000.0,
+        profit_ratio: config.profit_ratio_per_order,
+        offset_sell_rate_ratio: config.offset_sell_rate_ratio,
+    });
+    let balance = Balance {
+        amount: 100000.0,
+        reserved: 0.0,
+    };
+    action_behavior.action(&entry, &balance).await.unwrap();
+
+    // 買いのテイカー手数料と売りのメイカー手数料を差し引いて目標利益になるレート
+    let want = BUY_RATE * (1.0 + config.profit_ratio_per_order) * 1.001 / (1.0 - 0.0005)
+        * (1.0 + config.offset_sell_rate_ratio);
+    let open_orders = stub.open_orders();
+    assert_eq!(open_orders.len(), 1);
+    assert_eq!(open_orders[0].order_type, "sell");
+    assert!(
+        (open_orders[0].rate - want).abs() < 1.0,
+        "rate: {}, want: {}",
+        open_orders[0].rate,
+        want
+    );
+}
+
+#[tokio::test]
+async fn test_entry_skipped_below_minimum_amount() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+
+    let options = ClientOptions {
+        base_url: stub.url.clone(),
+        ..ClientOptions::from_config(&config)
+    };
+    let coincheck_cli = coincheck::client::DefaultClient::with_options(
+        &config.exchange_access_key,
+        &config.exchange_secret_key,
+        &options,
+    )
+    .unwrap();
+    let action_behavior = ActionBehavior {
+        config: &config,
+        notifier: &slack,
+        mysql_client: &storage,
+        coincheck_client: &coincheck_cli,
+    };
+    // 0.0025 BTC 相当（最小注文数量 0.005 BTC 未満）
+    let entry = ActionType::Entry(EntryParam {
+        pair: Pair::new(PAIR).unwrap(),
+        amount: 10000.0,
+        profit_ratio: config.profit_ratio_per_order,
+        offset_sell_rate_ratio: config.offset_sell_rate_ratio,
+    });
+    let balance = Balance {
+        amount: 100000.0,
+        reserved: 0.0,
+    };
+    action_behavior.action(&entry, &balance).await.unwrap();
+
+    // 成行買いする前にスキップする
+    assert!(stub
+        .requests_to(Method::POST, "/api/exchange/orders")
+        .is_empty());
+    assert!(stub.open_orders().is_empty());
+    assert!(slack.messages().is_empty());
+}
+
+fn save_loss_cut_in_flight(config: &Config, storage: &MemoryClient, id: u64, attempts: u32) {
+    let state = SagaState::LossCut(LossCutState {
+        pair: PAIR.to_owned(),
+        open_order_id: id,
+        open_order_rate: 6_000_000.0,
+        amount: 0.01,
+        canceled: true,
+    });
+    storage
+        .upsert_in_flight_action(
+            &state
+                .to_in_flight_action(&config.bot_name, attempts)
+                .unwrap(),
+        )
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_resume_in_flight_loss_cut() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+    // 売注文のキャンセル後, 成行売りの前に停止した
+    stub.set_balance("btc", 0.01, 0.0);
+    save_loss_cut_in_flight(&config, &storage, 100, 0);
+
+    let options = ClientOptions {
+        base_url: stub.url.clone(),
+        ..ClientOptions::from_config(&config)
+    };
+    let coincheck_cli = coincheck::client::DefaultClient::with_options(
+        &config.exchange_access_key,
+        &config.exchange_secret_key,
+        &options,
+    )
+    .unwrap();
+    let action_behavior = ActionBehavior {
+        config: &config,
+        notifier: &slack,
+        mysql_client: &storage,
+        coincheck_client: &coincheck_cli,
+    };
+    action_behavior.resume_in_flight_action().await.unwrap();
+
+    // キャンセル済みのため、成行売りのみ実行する
+    assert!(stub
+        .requests_to(Method::DELETE, "/api/exchange/orders/100")
+        .is_empty());
+    let orders: Vec<RecordedRequest> = stub
+        .requests_to(Method::POST, "/api/exchange/orders")
+        .into_iter()
+        .filter(|r| r.error.is_none())
+        .collect();
+    assert_eq!(orders.len(), 1);
+    assert!(orders[0].body.contains("\"order_type\":\"market_sell\""));
+    assert_eq!(stub.balance("btc"), (0.0, 0.0));
+
+    assert_eq!(
+        storage.select_in_flight_action(&config.bot_name).unwrap(),
+        None
+    );
+    let messages = slack.messages();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].starts_with("in flight action resumed!"));
+}
+
+#[tokio::test]
+async fn test_compensate_in_flight_loss_cut() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+    stub.set_balance("btc", 0.01, 0.0);
+    save_loss_cut_in_flight(&config, &storage, 100, MAX_RESUME_ATTEMPTS);
+
+    let options = ClientOptions {
+        base_url: stub.url.clone(),
+        ..ClientOptions::from_config(&config)
+    };
+    let coincheck_cli = coincheck::client::DefaultClient::with_options(
+        &config.exchange_access_key,
+        &config.exchange_secret_key,
+        &options,
+    )
+    .unwrap();
+    let action_behavior = ActionBehavior {
+        config: &config,
+        notifier: &slack,
+        mysql_client: &storage,
+        coincheck_client: &coincheck_cli,
+    };
+    action_behavior.resume_in_flight_action().await.unwrap();
+
+    // 再開を諦めて、キャンセルした売注文を出し直す
+    let open_orders = stub.open_orders();
+    assert_eq!(open_orders.len(), 1);
+    assert_eq!(open_orders[0].order_type, "sell");
+    assert_eq!(open_orders[0].rate, 6_000_000.0);
+    assert_eq!(open_orders[0].pending_amount, 0.01);
+
+    assert_eq!(
+        storage.select_in_flight_action(&config.bot_name).unwrap(),
+        None
+    );
+    let messages = slack.messages();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].starts_with("in flight action compensated!"));
+}
+
+fn save_avg_down_in_flight(
+    config: &Config,
+    storage: &MemoryClient,
+    id: u64,
+    before_buy: Option<OrderSnapshot>,
+    attempts: u32,
+) {
+    let state = SagaState::AvgDown(AvgDownState {
+        pair: PAIR.to_owned(),
+        open_order_id: id,
+        open_order_rate: 6_000_000.0,
+        open_order_amount: 0.01,
+        market_buy_amount: 10000.0,
+        offset_sell_rate_ratio: config.offset_sell_rate_ratio,
+        before_buy,
+        amount_new_coin: None,
+        canceled: false,
+        sold_count: 0,
+    });
+    storage
+        .upsert_in_flight_action(
+            &state
+                .to_in_flight_action(&config.bot_name, attempts)
+                .unwrap(),
+        )
+        .unwrap();
+}
+
+// 成行買いの後に停止した場合は、成行買い前の状態と照合して残りのステップを再開する
+#[tokio::test]
+async fn test_resume_in_flight_avg_down_after_market_buy() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+    stub.set_balance("btc", 0.01, 0.0);
+    let id = stub.add_open_order(PAIR, "sell", 6_000_000.0, 0.01);
+
+    let options = ClientOptions {
+        base_url: stub.url.clone(),
+        ..ClientOptions::from_config(&config)
+    };
+    let coincheck_cli = coincheck::client::DefaultClient::with_options(
+        &config.exchange_access_key,
+        &config.exchange_secret_key,
+        &options,
+    )
+    .unwrap();
+    let before_buy = OrderSnapshot::new(
+        coincheck_cli.get_accounts_balance().await.unwrap(),
+        &coincheck_cli.get_exchange_orders_opens().await.unwrap(),
+        &coincheck_cli
+            .get_exchange_orders_transactions()
+            .await
+            .unwrap(),
+    );
+    coincheck_cli
+        .post_exchange_orders(&NewOrder::new_market_buy_order(
+            &Pair::new(PAIR).unwrap(),
+            10000.0,
+        ))
+        .await
+        .unwrap();
+    save_avg_down_in_flight(&config, &storage, id, Some(before_buy), 0);
+
+    let action_behavior = ActionBehavior {
+        config: &config,
+        notifier: &slack,
+        mysql_client: &storage,
+        coincheck_client: &coincheck_cli,
+    };
+    action_behavior.resume_in_flight_action().await.unwrap();
+
+    // 買ったコインを含めて売注文を出し直す
+    let open_orders = stub.open_orders();
+    assert_eq!(open_orders.len(), 2);
+    assert!(open_orders.iter().all(|o| o.id != id));
+    let total: f64 = open_orders.iter().map(|o| o.pending_amount).sum();
+    assert!(total > 0.01, "total: {}", total);
+
+    assert_eq!(
+        storage.select_in_flight_action(&config.bot_name).unwrap(),
+        None
+    );
+    let messages = slack.messages();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].starts_with("in flight action resumed!"));
+}
+
+// 成行買いが約定したか分からない場合は、記録を残して通知する
+#[tokio::test]
+async fn test_resume_in_flight_avg_down_unresolved() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+    stub.set_balance("btc", 0.01, 0.0);
+    let id = stub.add_open_order(PAIR, "sell", 6_000_000.0, 0.01);
+    save_avg_down_in_flight(&config, &storage, id, None, MAX_RESUME_ATTEMPTS - 1);
+
+    let options = ClientOptions {
+        base_url: stub.url.clone(),
+        ..ClientOptions::from_config(&config)
+    };
+    let coincheck_cli = coincheck::client::DefaultClient::with_options(
+        &config.exchange_access_key,
+        &config.exchange_secret_key,
+        &options,
+    )
+    .unwrap();
+    let action_behavior = ActionBehavior {
+        config: &config,
+        notifier: &slack,
+        mysql_client: &storage,
+        coincheck_client: &coincheck_cli,
+    };
+    assert!(action_behavior.resume_in_flight_action().await.is_err());
+
+    // 補償ステップでも照合できなければ、売注文に触れずに記録を残す
+    action_behavior.resume_in_flight_action().await.unwrap();
+    let open_orders = stub.open_orders();
+    assert_eq!(open_orders.len(), 1);
+    assert_eq!(open_orders[0].id, id);
+    let action = storage
+        .select_in_flight_action(&config.bot_name)
+        .unwrap()
+        .unwrap();
+    assert_eq!(action.attempts, MAX_RESUME_ATTEMPTS + 1);
+
+    let messages = slack.messages();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].starts_with("in flight action compensation failed!"));
+    assert!(messages[0].contains("market buy of avg down is not recorded"));
+}
+
+// 補償ステップが失敗し続けても、記録を残して取引を続ける
+#[tokio::test]
+async fn test_trade_compensate_in_flight_failed() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+    stub.set_balance("btc", 0.01, 0.0);
+    let attempts = MAX_RESUME_ATTEMPTS + MAX_COMPENSATE_ATTEMPTS;
+    save_loss_cut_in_flight(&config, &storage, 100, attempts - 2);
+    stub.fail_next(Method::POST, "/api/exchange/orders", "maintenance", 2);
+
+    trade(&config, &stub, &storage, &slack, &now).await.unwrap();
+    let action = storage
+        .select_in_flight_action(&config.bot_name)
+        .unwrap()
+        .unwrap();
+    assert_eq!(action.attempts, attempts - 1);
+
+    // 最後の補償ステップも失敗したら諦める
+    trade(&config, &stub, &storage, &slack, &now).await.unwrap();
+    let action = storage
+        .select_in_flight_action(&config.bot_name)
+        .unwrap()
+        .unwrap();
+    assert_eq!(action.attempts, attempts);
+
+    // 諦めた後は補償ステップを実行せず、記録はオペレーターのために残す
+    trade(&config, &stub, &storage, &slack, &now).await.unwrap();
+    assert!(stub.open_orders().is_empty());
+    assert!(storage
+        .select_in_flight_action(&config.bot_name)
+        .unwrap()
+        .is_some());
+
+    // 取引処理自体は続けている（残ったコインの通知が出る）
+    let messages = slack.messages();
+    assert!(messages.iter().any(|m| m.starts_with("unused coin exist")));
+    let messages: Vec<String> = messages
+        .into_iter()
+        .filter(|m| m.starts_with("in flight"))
+        .collect();
+    assert_eq!(messages.len(), 2);
+    assert!(messages[0].starts_with("in flight action compensation failed!"));
+    assert!(messages[0].contains("maintenance"));
+    assert!(messages[1].starts_with("in flight action abandoned!"));
+}
+
+// 諦めたアクションの記録が残っている間は、新しいアクションで上書きしない
+#[tokio::test]
+async fn test_loss_cut_blocked_by_abandoned_in_flight() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+    stub.set_balance("btc", 0.01, 0.0);
+    let id = stub.add_open_order(PAIR, "sell", 6_000_000.0, 0.01);
+    save_loss_cut_in_flight(
+        &config,
+        &storage,
+        100,
+        MAX_RESUME_ATTEMPTS + MAX_COMPENSATE_ATTEMPTS,
+    );
+    let abandoned = storage
+        .select_in_flight_action(&config.bot_name)
+        .unwrap()
+        .unwrap();
+
+    let options = ClientOptions {
+        base_url: stub.url.clone(),
+        ..ClientOptions::from_config(&config)
+    };
+    let coincheck_cli = coincheck::client::DefaultClient::with_options(
+        &config.exchange_access_key,
+        &config.exchange_secret_key,
+        &options,
+    )
+    .unwrap();
+    let action_behavior = ActionBehavior {
+        config: &config,
+        notifier: &slack,
+        mysql_client: &storage,
+        coincheck_client: &coincheck_cli,
+    };
+    action_behavior.resume_in_flight_action().await.unwrap();
+    let action = ActionType::LossCut(LossCutParam {
+        pair: Pair::new(PAIR).unwrap(),
+        open_order_id: id,
+        open_order_rate: 6_000_000.0,
+        amount: 0.01,
+    });
+    let balance = Balance {
+        amount: 100_000.0,
+        reserved: 0.0,
+    };
+    action_behavior.action(&action, &balance).await.unwrap();
+
+    // 損切りせず、記録もそのまま残す
+    assert!(stub
+        .requests_to(Method::DELETE, &format!("/api/exchange/orders/{}", id))
+        .is_empty());
+    assert_eq!(stub.open_orders().len(), 1);
+    assert_eq!(
+        storage.select_in_flight_action(&config.bot_name).unwrap(),
+        Some(abandoned)
+    );
+    let messages = slack.messages();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].starts_with("loss cut failed!"));
+    assert!(messages[0].contains("in flight action remains"));
+}
+
+#[tokio::test]
+async fn test_trade_recover_orphaned_coin() {
+    let mut config = make_config();
+    config.recover_orphaned_coin = true;
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+
+    // 成行買いの後, 売注文を出す前に停止した
+    let options = ClientOptions {
+        base_url: stub.url.clone(),
+        ..ClientOptions::from_config(&config)
+    };
+    let coincheck_cli = coincheck::client::DefaultClient::with_options(
+        &config.exchange_access_key,
+        &config.exchange_secret_key,
+        &options,
+    )
+    .unwrap();
+    coincheck_cli
+        .post_exchange_orders(&NewOrder::new_market_buy_order(
+            &Pair::new(PAIR).unwrap(),
+            40000.0,
+        ))
+        .await
+        .unwrap();
+    let (amount_coin, _) = stub.balance("btc");
+    assert!(amount_coin > 0.0);
+
+    trade(&config, &stub, &storage, &slack, &now).await.unwrap();
+
+    // 取得レートに目標利益率, 上方補正率をかけたレートで売注文を出す
+    let amount_sell = Pair::new(PAIR)
+        .unwrap()
+        .metadata()
+        .unwrap()
+        .round_amount(amount_coin);
+    let open_orders = stub.open_orders();
+    assert_eq!(open_orders.len(), 1);
+    assert_eq!(open_orders[0].order_type, "sell");
+    assert_eq!(open_orders[0].pending_amount, amount_sell);
+    let want =
+        (BUY_RATE * (1.0 + config.profit_ratio_per_order) * (1.0 + config.offset_sell_rate_ratio))
+            .ceil();
+    assert_eq!(open_orders[0].rate, want);
+    assert_eq!(stub.balance("btc").1, amount_sell);
+
+    let messages = slack.messages();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].starts_with("orphaned coin recovered!"));
+}
+
+#[tokio::test]
+async fn test_shutdown() {
+    struct Param {
+        cancel_open_orders_on_shutdown: bool,
+        want_open_orders: usize,
+        want_sell_rate: f64,
+        want_message: &'static str,
+    }
+    let params = vec![
+        (
+            "when cancel open orders",
+            Param {
+                cancel_open_orders_on_shutdown: true,
+                want_open_orders: 0,
+                want_sell_rate: -1.0,
+                want_message: "open orders: canceled [",
+            },
+        ),
+        (
+            "when keep open orders",
+            Param {
+                cancel_open_orders_on_shutdown: false,
+                want_open_orders: 1,
+                want_sell_rate: 6_000_000.0,
+                want_message: "open orders: kept [",
+            },
+        ),
+    ];
+
+    for (name, p) in params {
+        let mut config = make_config();
+        config.cancel_open_orders_on_shutdown = p.cancel_open_orders_on_shutdown;
+        let now = make_now();
+        let (stub, storage) = setup(&config, now).await;
+        let slack = RecordingSlackClient::default();
+        stub.set_balance("btc", 0.01, 0.0);
+        stub.add_open_order(PAIR, "sell", 6_000_000.0, 0.01);
+
+        let options = ClientOptions {
+            base_url: stub.url.clone(),
+            ..ClientOptions::from_config(&config)
+        };
+        let coincheck_cli = coincheck::client::DefaultClient::with_options(
+            &config.exchange_access_key,
+            &config.exchange_secret_key,
+            &options,
+        )
+        .unwrap();
+        let strategy = ScalpingStrategy { config: &config };
+        let action_behavior = ActionBehavior {
+            config: &config,
+            notifier: &slack,
+            mysql_client: &storage,
+            coincheck_client: &coincheck_cli,
+        };
+        let bot = Bot {
+            config: &config,
+            coincheck_client: &coincheck_cli,
+            mysql_client: &storage,
+            notifier: &slack,
+            strategy: &strategy,
+            action_behavior: &action_behavior,
+        };
+        bot.shutdown(&now).await.unwrap();
+
+        assert_eq!(
+            stub.open_orders().len(),
+            p.want_open_orders,
+            "{}, failure",
+            name
+        );
+        // 終了時の状態を記録する
+        let sell_rate = storage
+            .select_bot_status(&config.bot_name, PAIR, "sell_rate")
+            .unwrap();
+        assert_eq!(sell_rate.value, p.want_sell_rate, "{}, failure", name);
+        let messages = slack.messages();
+        assert_eq!(messages.len(), 1, "{}, failure", name);
+        assert!(
+            messages[0].starts_with("bot shutdown!"),
+            "{}, failure",
+            name
+        );
+        assert!(
+            messages[0].contains(p.want_message),
+            "{}, failure: {}",
+            name,
+            messages[0]
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_loss_cut_order() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+    stub.set_balance("btc", 0.01, 0.0);
+    let id = stub.add_open_order(PAIR, "sell", 6_000_000.0, 0.01);
+
+    let options = ClientOptions {
+        base_url: stub.url.clone(),
+        ..ClientOptions::from_config(&config)
+    };
+    let coincheck_cli = coincheck::client::DefaultClient::with_options(
+        &config.exchange_access_key,
+        &config.exchange_secret_key,
+        &options,
+    )
+    .unwrap();
+    let strategy = ScalpingStrategy { config: &config };
+    let action_behavior = ActionBehavior {
+        config: &config,
+        notifier: &slack,
+        mysql_client: &storage,
+        coincheck_client: &coincheck_cli,
+    };
+    let bot = Bot {
+        config: &config,
+        coincheck_client: &coincheck_cli,
+        mysql_client: &storage,
+        notifier: &slack,
+        strategy: &strategy,
+        action_behavior: &action_behavior,
+    };
+
+    // 存在しない注文
+    assert!(bot.loss_cut_order(id + 1).await.is_err());
+
+    bot.loss_cut_order(id).await.unwrap();
+    assert!(stub.open_orders().is_empty());
+    assert_eq!(stub.balance("btc"), (0.0, 0.0));
+    let messages = slack.messages();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].starts_with("losscut completed!"));
+}
+
+// トレーリングの売注文に失敗したら、キャンセルした売注文を元のレートで出し直す
+#[tokio::test]
+async fn test_trailing_sell_error() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+    stub.set_balance("btc", 0.01, 0.0);
+    let id = stub.add_open_order(PAIR, "sell", 6_000_000.0, 0.01);
+    stub.fail_next(Method::POST, "/api/exchange/orders", "maintenance", 1);
+
+    let options = ClientOptions {
+        base_url: stub.url.clone(),
+        ..ClientOptions::from_config(&config)
+    };
+    let coincheck_cli = coincheck::client::DefaultClient::with_options(
+        &config.exchange_access_key,
+        &config.exchange_secret_key,
+        &options,
+    )
+    .unwrap();
+    let action_behavior = ActionBehavior {
+        config: &config,
+        notifier: &slack,
+        mysql_client: &storage,
+        coincheck_client: &coincheck_cli,
+    };
+    let action = ActionType::Sell(SellParam {
+        open_order_ids: vec![id],
+        open_order_rate: Some(6_000_000.0),
+        pair: Pair::new(PAIR).unwrap(),
+        rate: 6_600_000.0,
+        amount: 0.01,
+        trailing_high_rate: Some(6_500_000.0),
+    });
+    let balance = Balance {
+        amount: 100_000.0,
+        reserved: 0.0,
+    };
+    action_behavior.action(&action, &balance).await.unwrap();
+
+    let open_orders = stub.open_orders();
+    assert_eq!(open_orders.len(), 1);
+    assert_ne!(open_orders[0].id, id);
+    assert_eq!(open_orders[0].order_type, "sell");
+    assert_eq!(open_orders[0].rate, 6_000_000.0);
+    assert_eq!(open_orders[0].pending_amount, 0.01);
+
+    // 売注文に失敗したため、最高値は更新しない
+    assert!(storage
+        .select_bot_status(&config.bot_name, PAIR, "trailing_high_rate")
+        .is_err());
+    let messages = slack.messages();
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].starts_with("sell failed!"));
+    assert!(messages[0].contains("maintenance"));
+}
+
+#[tokio::test]
+async fn test_daily_report() {
+    let config = make_config();
+    let now = make_now();
+    let (stub, storage) = setup(&config, now).await;
+    let slack = RecordingSlackClient::default();
+
+    // 3,900,000 で 0.01 買い、0.005 を 4,100,000 で売る
+    let buy = stub.add_open_order(PAIR, "buy", 3_900_000.0, 0.01);
+    stub.fill_open_order(buy);
+    let sell = stub.add_open_order(PAIR, "sell", 4_100_000.0, 0.005);
+    stub.fill_open_order(sell);
+
+    let options = ClientOptions {
+        base_url: stub.url.clone(),
+        ..ClientOptions::from_config(&config)
+    };
+    let coincheck_cli = coincheck::client::DefaultClient::with_options(
+        &config.exchange_access_key,
+        &config.exchange_secret_key,
+        &options,
+    )
+    .unwrap();
+    let reporter = Reporter {
+        config: &config,
+        notifier: &slack,
+        mysql_client: &storage,
+        coincheck_client: &coincheck_cli,
+    };
+    let end = DateTime::parse_from_rfc3339("2021-05-17T00:00:00Z")
+        .unwrap()
+        .with_timezone(&Utc);
+    let reports = reporter.report(ReportPeriod::Daily, &end).await.unwrap();
+
+    assert_eq!(reports.len(), 2);
+    let report = &reports[0];
+    assert_eq!(report.pair, PAIR);
+    assert_eq!(report.trade_count, 2);
+    assert_eq!((report.win_count, report.loss_count), (1, 0));
+    assert!((report.realized_pnl - 1000.0).abs() < 1e-6);
+    assert!((report.open_amount - 0.005).abs() < 1e-9);
+    assert!((report.unrealized_pnl - 500.0).abs() < 1e-6);
+    let all = &reports[1];
+    assert_eq!(all.pair, "all");
+    assert!((all.total_jpy - 101500.0).abs() < 1e-6);
+    assert_eq!(all.total_jpy_change, 0.0);
+    let saved = storage.reports().unwrap();
+    assert_eq!(saved.len(), 2);
+    assert!(reports.iter().all(|r| saved.contains(r)));
+
+    // 前回のレポートからの増減
+    stub.set_balance("jpy", 83000.0, 0.0);
+    let end = end + chrono::Duration::days(1);
+    let reports = reporter.report(ReportPeriod::Daily, &end).await.unwrap();
+    assert_eq!(reports[0].trade_count, 0);
+    assert!((reports[1].total_jpy_change - 1500.0).abs() < 1e-6);
+
+    let messages = slack.messages();
+    assert_eq!(messages.len(), 2);
+    assert!(messages[0].starts_with("daily report"));
+    assert!(messages[0].contains("total jpy: 101500 (+0)"));
+}
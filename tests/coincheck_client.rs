@@ -0,0 +1,226 @@
+mod common;
+
+use common::coincheck_stub::{CoincheckStub, ACCESS_KEY, SECRET_KEY};
+use common::PAIR;
+
+use std::time::{Duration, Instant};
+
+use hyper::{Method, StatusCode};
+use trading_bot_rust::coincheck::client::{Client, ClientOptions, DefaultClient};
+use trading_bot_rust::coincheck::model::{
+    Liquidity, NewOrder, OrderType, Pagination, Pair, SortOrder,
+};
+use trading_bot_rust::coincheck::retry::RetryPolicy;
+
+fn make_client(stub: &CoincheckStub, rate_limit: Option<(u32, f64)>) -> DefaultClient {
+    let options = ClientOptions {
+        base_url: stub.url.clone(),
+        retry_policy: RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+        },
+        rate_limit,
+        ..Default::default()
+    };
+    DefaultClient::with_options(ACCESS_KEY, SECRET_KEY, &options).unwrap()
+}
+
+#[tokio::test]
+async fn test_retry_on_server_error() {
+    let stub = CoincheckStub::start().await;
+    stub.set_balance("jpy", 1000.0, 0.0);
+    stub.fail_next_with_status(
+        Method::GET,
+        "/api/accounts/balance",
+        StatusCode::SERVICE_UNAVAILABLE,
+        "service unavailable",
+        2,
+    );
+    let client = make_client(&stub, None);
+
+    let balances = client.get_accounts_balance().await.unwrap();
+    assert_eq!(balances.get("jpy").unwrap().amount, 1000.0);
+}
+
+#[tokio::test]
+async fn test_retry_exhausted() {
+    let stub = CoincheckStub::start().await;
+    stub.fail_next_with_status(
+        Method::GET,
+        "/api/order_books",
+        StatusCode::BAD_GATEWAY,
+        "bad gateway",
+        10,
+    );
+    let client = make_client(&stub, None);
+
+    assert!(client.get_order_books(PAIR).await.is_err());
+    // 初回 + 再試行3回
+    assert_eq!(stub.requests_to(Method::GET, "/api/order_books").len(), 4);
+}
+
+#[tokio::test]
+async fn test_no_retry_order_on_server_error() {
+    let stub = CoincheckStub::start().await;
+    stub.set_balance("jpy", 1000.0, 0.0);
+    stub.fail_next_with_status(
+        Method::POST,
+        "/api/exchange/orders",
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "internal server error",
+        1,
+    );
+    let client = make_client(&stub, None);
+
+    // 注文が処理されたか不明なため再試行しない
+    let req = NewOrder::new_market_buy_order(&Pair::new(PAIR).unwrap(), 500.0);
+    assert!(client.post_exchange_orders(&req).await.is_err());
+    let requests: Vec<_> = stub
+        .requests_to(Method::POST, "/api/exchange/orders")
+        .into_iter()
+        .filter(|r| r.error != Some("Nonce must be incremented".to_owned()))
+        .collect();
+    assert_eq!(requests.len(), 1);
+}
+
+#[tokio::test]
+async fn test_retry_order_on_too_many_requests() {
+    let stub = CoincheckStub::start().await;
+    stub.set_balance("jpy", 1000.0, 0.0);
+    stub.set_rate(PAIR, 100.0, 100.0);
+    stub.fail_next_with_status(
+        Method::POST,
+        "/api/exchange/orders",
+        StatusCode::TOO_MANY_REQUESTS,
+        "too many requests",
+        1,
+    );
+    let client = make_client(&stub, None);
+
+    let req = NewOrder::new_market_buy_order(&Pair::new(PAIR).unwrap(), 500.0);
+    client.post_exchange_orders(&req).await.unwrap();
+    assert_eq!(stub.balance("btc"), (5.0, 0.0));
+}
+
+#[tokio::test]
+async fn test_no_retry_on_order_error() {
+    let stub = CoincheckStub::start().await;
+    stub.fail_next(
+        Method::POST,
+        "/api/exchange/orders",
+        "Amount 0.001 is less than minimum",
+        1,
+    );
+    let client = make_client(&stub, None);
+
+    let req = NewOrder::new_market_sell_order(&Pair::new(PAIR).unwrap(), 0.01);
+    let err = client.post_exchange_orders(&req).await.unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("Amount 0.001 is less than minimum"));
+}
+
+#[tokio::test]
+async fn test_rate_limit() {
+    let stub = CoincheckStub::start().await;
+    let client = make_client(&stub, Some((1, 10.0)));
+
+    let begin = Instant::now();
+    for _ in 0..3 {
+        client.get_order_books(PAIR).await.unwrap();
+    }
+    // 1件目は即時, 以降は100ms毎
+    assert!(begin.elapsed() >= Duration::from_millis(190));
+}
+
+#[tokio::test]
+async fn test_nonce_shared_between_clients() {
+    let stub = CoincheckStub::start().await;
+    let client_a = make_client(&stub, None);
+    let client_b = make_client(&stub, None);
+
+    // 同一ミリ秒内に別々のクライアントからリクエストしても nonce は重複しない
+    for _ in 0..20 {
+        client_a.get_accounts_balance().await.unwrap();
+        client_b.get_exchange_orders_opens().await.unwrap();
+    }
+    assert!(stub.requests().iter().all(|r| r.error.is_none()));
+}
+
+#[tokio::test]
+async fn test_get_exchange_orders_transactions() {
+    let stub = CoincheckStub::start().await;
+    stub.set_balance("jpy", 1000.0, 0.0);
+    stub.set_balance("btc", 10.0, 0.0);
+    stub.set_rate(PAIR, 100.0, 100.0);
+    let client = make_client(&stub, None);
+    let pair = Pair::new(PAIR).unwrap();
+
+    let buy = client
+        .post_exchange_orders(&NewOrder::new_market_buy_order(&pair, 500.0))
+        .await
+        .unwrap();
+    let sell = client
+        .post_exchange_orders(&NewOrder::new_sell_order(&pair, 120.0, 2.0))
+        .await
+        .unwrap();
+    stub.fill_open_order(sell.id);
+
+    // 新しい順
+    let transactions = client.get_exchange_orders_transactions().await.unwrap();
+    assert_eq!(transactions.len(), 2);
+    assert_eq!(transactions[0].order_id, sell.id);
+    assert_eq!(transactions[0].side, OrderType::Sell);
+    assert_eq!(transactions[0].rate, 120.0);
+    assert_eq!(transactions[0].amount(), 2.0);
+    assert_eq!(transactions[0].funds.get("jpy"), Some(&240.0));
+    assert_eq!(transactions[0].liquidity, Liquidity::Maker);
+    assert_eq!(transactions[1].order_id, buy.id);
+    assert_eq!(transactions[1].side, OrderType::Buy);
+    assert_eq!(transactions[1].amount(), 5.0);
+    assert_eq!(transactions[1].liquidity, Liquidity::Taker);
+
+    let page = client
+        .get_exchange_orders_transactions_pagination(&Pagination {
+            limit: Some(1),
+            order: Some(SortOrder::Desc),
+            starting_after: Some(transactions[0].id),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].id, transactions[1].id);
+    let request = stub
+        .requests_to(Method::GET, "/api/exchange/orders/transactions_pagination")
+        .pop()
+        .unwrap();
+    assert_eq!(
+        request.query,
+        format!("limit=1&order=desc&starting_after={}", transactions[0].id)
+    );
+}
+
+#[tokio::test]
+async fn test_get_trades_and_ticker() {
+    let stub = CoincheckStub::start().await;
+    stub.set_rate(PAIR, 4_000_000.0, 4_001_000.0);
+    stub.add_trade(PAIR, "buy", 4_001_000.0, 0.01);
+    stub.add_trade(PAIR, "sell", 4_000_000.0, 0.02);
+    stub.add_trade("mona_jpy", "sell", 100.0, 1.0);
+    let client = make_client(&stub, None);
+
+    let trades = client
+        .get_trades(PAIR, &Pagination::default())
+        .await
+        .unwrap();
+    assert_eq!(trades.len(), 2);
+    assert_eq!(trades[0].order_type, OrderType::Sell);
+    assert_eq!(trades[0].amount, 0.02);
+    assert_eq!(trades[1].rate, 4_001_000.0);
+
+    let ticker = client.get_ticker(PAIR).await.unwrap();
+    assert_eq!(ticker.bid, 4_000_000.0);
+    assert_eq!(ticker.ask, 4_001_000.0);
+}
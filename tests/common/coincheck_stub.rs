@@ -0,0 +1,709 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::oneshot;
+
+pub const ACCESS_KEY: &str = "stub_access_key";
+pub const SECRET_KEY: &str = "stub_secret_key";
+
+const CREATED_AT: &str = "2021-05-16T12:00:00.000Z";
+const CURRENCIES: [&str; 6] = ["jpy", "btc", "etc", "fct", "mona", "plt"];
+
+// Coincheck API のスタブサーバー
+// 注文は即時に処理し（成行注文は即約定, 指値注文は未決済のまま）、残高に反映する
+// 逆指値注文は売りレートが逆指値レート以下になったら約定する
+pub struct CoincheckStub {
+    pub url: String,
+    state: Arc<Mutex<StubState>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StubOrder {
+    pub id: u64,
+    pub pair: String,
+    pub order_type: String,
+    pub rate: f64,
+    pub pending_amount: f64,
+    pub stop_loss_rate: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StubTransaction {
+    pub id: u64,
+    pub order_id: u64,
+    pub pair: String,
+    pub side: String,
+    pub rate: f64,
+    pub amount: f64,
+    // M: メイカー, T: テイカー
+    pub liquidity: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: Method,
+    pub path: String,
+    pub query: String,
+    pub body: String,
+    pub user_agent: Option<String>,
+    // エラー応答を返したかどうか
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct StubState {
+    base_url: String,
+    // 通貨 => (利用可能, 注文中)
+    balances: HashMap<String, (f64, f64)>,
+    // 取引ペア => (売りレート, 買いレート)
+    rates: HashMap<String, (f64, f64)>,
+    asks: Vec<(f64, f64)>,
+    bids: Vec<(f64, f64)>,
+    open_orders: Vec<StubOrder>,
+    transactions: Vec<StubTransaction>,
+    // (ID, 取引ペア, 売買種別, レート, 数量)
+    trades: Vec<(u64, String, String, f64, f64)>,
+    next_id: u64,
+    last_nonce: u128,
+    // (メソッド, パス) => 返すエラー（ステータス, メッセージ）
+    errors: HashMap<(Method, String), VecDeque<(StatusCode, String)>>,
+    // (メソッド, パス) => 処理した上で返すエラー（ステータス, メッセージ）
+    errors_after_processing: HashMap<(Method, String), VecDeque<(StatusCode, String)>>,
+    requests: Vec<RecordedRequest>,
+}
+
+#[derive(Deserialize, Debug)]
+struct NewOrderBody {
+    pair: String,
+    order_type: String,
+    rate: Option<String>,
+    amount: Option<String>,
+    market_buy_amount: Option<String>,
+    stop_loss_rate: Option<String>,
+}
+
+impl CoincheckStub {
+    pub async fn start() -> CoincheckStub {
+        let state = Arc::new(Mutex::new(StubState {
+            next_id: 1,
+            ..Default::default()
+        }));
+        for c in CURRENCIES.iter() {
+            state
+                .lock()
+                .unwrap()
+                .balances
+                .insert(c.to_string(), (0.0, 0.0));
+        }
+
+        let service_state = Arc::clone(&state);
+        let make_svc = make_service_fn(move |_conn| {
+            let state = Arc::clone(&service_state);
+            async move { Ok::<_, Infallible>(service_fn(move |req| handle(Arc::clone(&state), req))) }
+        });
+        let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
+        let server = Server::bind(&addr).serve(make_svc);
+        let url = format!("http://{}", server.local_addr());
+        state.lock().unwrap().base_url = url.clone();
+
+        let (tx, rx) = oneshot::channel::<()>();
+        tokio::spawn(server.with_graceful_shutdown(async {
+            rx.await.ok();
+        }));
+
+        CoincheckStub {
+            url,
+            state,
+            shutdown: Some(tx),
+        }
+    }
+
+    pub fn set_balance(&self, currency: &str, amount: f64, reserved: f64) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .balances
+            .insert(currency.to_owned(), (amount, reserved));
+    }
+
+    pub fn balance(&self, currency: &str) -> (f64, f64) {
+        let state = self.state.lock().unwrap();
+        *state.balances.get(currency).unwrap()
+    }
+
+    pub fn set_rate(&self, pair: &str, sell_rate: f64, buy_rate: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.rates.insert(pair.to_owned(), (sell_rate, buy_rate));
+        let triggered: Vec<u64> = state
+            .open_orders
+            .iter()
+            .filter(|o| o.pair == pair && o.stop_loss_rate.is_some_and(|r| sell_rate <= r))
+            .map(|o| o.id)
+            .collect();
+        for id in triggered {
+            state.fill_order(id);
+        }
+    }
+
+    pub fn set_order_books(&self, asks: Vec<(f64, f64)>, bids: Vec<(f64, f64)>) {
+        let mut state = self.state.lock().unwrap();
+        state.asks = asks;
+        state.bids = bids;
+    }
+
+    // 指値注文を未決済注文として登録する（注文分の残高は注文中に移す）
+    pub fn add_open_order(&self, pair: &str, order_type: &str, rate: f64, amount: f64) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.place_limit_order(pair, order_type, rate, amount, None)
+    }
+
+    pub fn open_orders(&self) -> Vec<StubOrder> {
+        let state = self.state.lock().unwrap();
+        state.open_orders.clone()
+    }
+
+    // 未決済の指値注文を約定させる
+    pub fn fill_open_order(&self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.fill_order(id);
+    }
+
+    pub fn transactions(&self) -> Vec<StubTransaction> {
+        let state = self.state.lock().unwrap();
+        state.transactions.clone()
+    }
+
+    // 取引所全体の取引履歴を追加する
+    pub fn add_trade(&self, pair: &str, order_type: &str, rate: f64, amount: f64) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state
+            .trades
+            .push((id, pair.to_owned(), order_type.to_owned(), rate, amount));
+        id
+    }
+
+    // 指定したAPIの次回以降の呼び出しで、指定回数だけエラーを返す
+    pub fn fail_next(&self, method: Method, path: &str, message: &str, times: usize) {
+        self.fail_next_with_status(method, path, StatusCode::OK, message, times);
+    }
+
+    pub fn fail_next_with_status(
+        &self,
+        method: Method,
+        path: &str,
+        status: StatusCode,
+        message: &str,
+        times: usize,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let queue = state.errors.entry((method, path.to_owned())).or_default();
+        for _ in 0..times {
+            queue.push_back((status, message.to_owned()));
+        }
+    }
+
+    // 指定したAPIの次回以降の呼び出しで、リクエストを処理した上で指定回数だけエラーを返す
+    // （注文が受け付けられたがタイムアウトした場合など、結果が不明な失敗の再現用）
+    pub fn fail_next_after_processing(
+        &self,
+        method: Method,
+        path: &str,
+        status: StatusCode,
+        message: &str,
+        times: usize,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let queue = state
+            .errors_after_processing
+            .entry((method, path.to_owned()))
+            .or_default();
+        for _ in 0..times {
+            queue.push_back((status, message.to_owned()));
+        }
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        let state = self.state.lock().unwrap();
+        state.requests.clone()
+    }
+
+    pub fn requests_to(&self, method: Method, path: &str) -> Vec<RecordedRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|r| r.method == method && r.path == path)
+            .collect()
+    }
+}
+
+impl Drop for CoincheckStub {
+    fn drop(&mut self) {
+        if let Some(tx) = self.shutdown.take() {
+            tx.send(()).ok();
+        }
+    }
+}
+
+impl StubState {
+    fn place_limit_order(
+        &mut self,
+        pair: &str,
+        order_type: &str,
+        rate: f64,
+        amount: f64,
+        stop_loss_rate: Option<f64>,
+    ) -> u64 {
+        let (key, settlement) = split_pair(pair);
+        let (currency, reserve) = if order_type == "sell" {
+            (key, amount)
+        } else {
+            (settlement, rate * amount)
+        };
+        let balance = self.balances.entry(currency).or_insert((0.0, 0.0));
+        balance.0 -= reserve;
+        balance.1 += reserve;
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.open_orders.push(StubOrder {
+            id,
+            pair: pair.to_owned(),
+            order_type: order_type.to_owned(),
+            rate,
+            pending_amount: amount,
+            stop_loss_rate,
+        });
+        id
+    }
+
+    fn fill_order(&mut self, id: u64) {
+        let idx = self
+            .open_orders
+            .iter()
+            .position(|o| o.id == id)
+            .expect("open order is not found");
+        let o = self.open_orders.remove(idx);
+        let (key, settlement) = split_pair(&o.pair);
+        let price = o.rate * o.pending_amount;
+        if o.order_type == "sell" {
+            self.balances.entry(key).or_insert((0.0, 0.0)).1 -= o.pending_amount;
+            self.balances.entry(settlement).or_insert((0.0, 0.0)).0 += price;
+        } else {
+            self.balances.entry(settlement).or_insert((0.0, 0.0)).1 -= price;
+            self.balances.entry(key).or_insert((0.0, 0.0)).0 += o.pending_amount;
+        }
+        self.add_transaction(o.id, &o.pair, &o.order_type, o.rate, o.pending_amount, "M");
+    }
+
+    fn add_transaction(
+        &mut self,
+        order_id: u64,
+        pair: &str,
+        side: &str,
+        rate: f64,
+        amount: f64,
+        liquidity: &str,
+    ) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.transactions.push(StubTransaction {
+            id,
+            order_id,
+            pair: pair.to_owned(),
+            side: side.to_owned(),
+            rate,
+            amount,
+            liquidity: liquidity.to_owned(),
+        });
+    }
+
+    // 約定履歴（新しい順）
+    fn transactions_json(&self, pagination: &HashMap<String, String>) -> Vec<Value> {
+        let limit: usize = pagination
+            .get("limit")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(usize::MAX);
+        let starting_after: Option<u64> = pagination
+            .get("starting_after")
+            .and_then(|v| v.parse().ok());
+        let mut transactions: Vec<&StubTransaction> = self
+            .transactions
+            .iter()
+            .rev()
+            .filter(|t| starting_after.map(|id| t.id < id).unwrap_or(true))
+            .take(limit)
+            .collect();
+        if pagination.get("order").map(|v| v.as_str()) == Some("asc") {
+            transactions.reverse();
+        }
+        transactions
+            .into_iter()
+            .map(|t| {
+                let (key, settlement) = split_pair(&t.pair);
+                let sign = if t.side == "buy" { 1.0 } else { -1.0 };
+                let mut funds = serde_json::Map::new();
+                funds.insert(key, json!((sign * t.amount).to_string()));
+                funds.insert(settlement, json!((-sign * t.amount * t.rate).to_string()));
+                json!({
+                    "id": t.id,
+                    "order_id": t.order_id,
+                    "created_at": CREATED_AT,
+                    "funds": funds,
+                    "pair": t.pair,
+                    "rate": t.rate.to_string(),
+                    "fee_currency": null,
+                    "fee": "0.0",
+                    "liquidity": t.liquidity,
+                    "side": t.side,
+                })
+            })
+            .collect()
+    }
+
+    fn rate(&self, pair: &str) -> (f64, f64) {
+        *self.rates.get(pair).unwrap_or(&(1.0, 1.0))
+    }
+
+    // 認証が必要なAPIのヘッダーを検証する
+    fn authenticate(&mut self, req: &Request<Body>, body: &str) -> Result<(), String> {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_owned())
+        };
+        let key = header("ACCESS-KEY").ok_or("invalid authentication")?;
+        let nonce = header("ACCESS-NONCE").ok_or("invalid authentication")?;
+        let signature = header("ACCESS-SIGNATURE").ok_or("invalid authentication")?;
+        if key != ACCESS_KEY {
+            return Err("invalid authentication".to_owned());
+        }
+
+        let url = format!("{}{}", self.base_url, req.uri());
+        if signature != sign(&format!("{}{}{}", nonce, url, body)) {
+            return Err("invalid authentication".to_owned());
+        }
+
+        let nonce: u128 = nonce.parse().map_err(|_| "invalid authentication")?;
+        if nonce <= self.last_nonce {
+            return Err("Nonce must be incremented".to_owned());
+        }
+        self.last_nonce = nonce;
+        Ok(())
+    }
+
+    fn route(
+        &mut self,
+        method: &Method,
+        path: &str,
+        query: &HashMap<String, String>,
+        body: &str,
+    ) -> Value {
+        match (method, path) {
+            (&Method::GET, "/api/order_books") => json!({
+                "asks": to_books(&self.asks),
+                "bids": to_books(&self.bids),
+            }),
+            (&Method::GET, "/api/exchange/orders/rate") => {
+                let pair = query.get("pair").cloned().unwrap_or_default();
+                let amount: f64 = query
+                    .get("amount")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0.0);
+                let (sell, buy) = self.rate(&pair);
+                let rate = if query.get("order_type").map(|v| v.as_str()) == Some("sell") {
+                    sell
+                } else {
+                    buy
+                };
+                json!({
+                    "success": true,
+                    "rate": rate.to_string(),
+                    "price": (rate * amount).to_string(),
+                    "amount": amount.to_string(),
+                })
+            }
+            (&Method::POST, "/api/exchange/orders") => self.post_order(body),
+            (&Method::GET, "/api/exchange/orders/opens") => {
+                let orders: Vec<Value> = self
+                    .open_orders
+                    .iter()
+                    .map(|o| {
+                        json!({
+                            "id": o.id,
+                            "order_type": o.order_type,
+                            "rate": o.rate.to_string(),
+                            "pair": o.pair,
+                            "pending_amount": o.pending_amount.to_string(),
+                            "pending_market_buy_amount": null,
+                            "stop_loss_rate": o.stop_loss_rate.map(|v| v.to_string()),
+                            "created_at": CREATED_AT,
+                        })
+                    })
+                    .collect();
+                json!({"success": true, "orders": orders})
+            }
+            (&Method::GET, "/api/exchange/orders/cancel_status") => {
+                let id: u64 = query.get("id").and_then(|v| v.parse().ok()).unwrap_or(0);
+                let cancel = !self.open_orders.iter().any(|o| o.id == id);
+                json!({
+                    "success": true,
+                    "id": id,
+                    "cancel": cancel,
+                    "created_at": CREATED_AT,
+                })
+            }
+            (&Method::DELETE, p) if p.starts_with("/api/exchange/orders/") => {
+                let id: u64 = p
+                    .trim_start_matches("/api/exchange/orders/")
+                    .parse()
+                    .unwrap_or(0);
+                self.cancel_order(id)
+            }
+            (&Method::GET, "/api/accounts/balance") => {
+                let mut res = json!({"success": true});
+                for c in CURRENCIES.iter() {
+                    let (amount, reserved) = *self.balances.get(*c).unwrap_or(&(0.0, 0.0));
+                    res[c.to_string()] = json!(amount.to_string());
+                    res[format!("{}_reserved", c)] = json!(reserved.to_string());
+                }
+                res
+            }
+            (&Method::GET, "/api/exchange/orders/transactions") => {
+                json!({"success": true, "transactions": self.transactions_json(&HashMap::new())})
+            }
+            (&Method::GET, "/api/exchange/orders/transactions_pagination") => {
+                json!({"success": true, "data": self.transactions_json(query)})
+            }
+            (&Method::GET, "/api/trades") => {
+                let pair = query.get("pair").cloned().unwrap_or_default();
+                let data: Vec<Value> = self
+                    .trades
+                    .iter()
+                    .rev()
+                    .filter(|t| t.1 == pair)
+                    .map(|(id, pair, order_type, rate, amount)| {
+                        json!({
+                            "id": id,
+                            "amount": amount.to_string(),
+                            "rate": rate.to_string(),
+                            "pair": pair,
+                            "order_type": order_type,
+                            "created_at": CREATED_AT,
+                        })
+                    })
+                    .collect();
+                json!({"success": true, "data": data})
+            }
+            (&Method::GET, "/api/ticker") => {
+                let pair = query.get("pair").cloned().unwrap_or_default();
+                let (sell, buy) = self.rate(&pair);
+                json!({
+                    "last": sell,
+                    "bid": sell,
+                    "ask": buy,
+                    "high": buy,
+                    "low": sell,
+                    "volume": "0.0",
+                    "timestamp": 1621166400,
+                })
+            }
+            _ => error_body("not found"),
+        }
+    }
+
+    fn post_order(&mut self, body: &str) -> Value {
+        let order: NewOrderBody = match serde_json::from_str(body) {
+            Ok(v) => v,
+            Err(e) => return error_body(&e.to_string()),
+        };
+        let parse = |v: &Option<String>| v.as_ref().and_then(|s| s.parse::<f64>().ok());
+        let (key, settlement) = split_pair(&order.pair);
+        let (sell_rate, buy_rate) = self.rate(&order.pair);
+
+        let (id, rate, amount) = match order.order_type.as_str() {
+            "market_buy" => {
+                let jpy = match parse(&order.market_buy_amount) {
+                    Some(v) => v,
+                    None => return error_body("market_buy_amount is required"),
+                };
+                self.balances.entry(settlement).or_insert((0.0, 0.0)).0 -= jpy;
+                self.balances.entry(key).or_insert((0.0, 0.0)).0 += jpy / buy_rate;
+                let id = self.next_id;
+                self.next_id += 1;
+                self.add_transaction(id, &order.pair, "buy", buy_rate, jpy / buy_rate, "T");
+                (id, None, None)
+            }
+            "market_sell" => {
+                let amount = match parse(&order.amount) {
+                    Some(v) => v,
+                    None => return error_body("amount is required"),
+                };
+                self.balances.entry(key).or_insert((0.0, 0.0)).0 -= amount;
+                self.balances.entry(settlement).or_insert((0.0, 0.0)).0 += amount * sell_rate;
+                let id = self.next_id;
+                self.next_id += 1;
+                self.add_transaction(id, &order.pair, "sell", sell_rate, amount, "T");
+                (id, None, Some(amount))
+            }
+            "buy" | "sell" => {
+                let (rate, amount) = match (parse(&order.rate), parse(&order.amount)) {
+                    (Some(r), Some(a)) => (r, a),
+                    _ => return error_body("rate and amount are required"),
+                };
+                let id = self.place_limit_order(
+                    &order.pair,
+                    &order.order_type,
+                    rate,
+                    amount,
+                    parse(&order.stop_loss_rate),
+                );
+                (id, Some(rate), Some(amount))
+            }
+            _ => return error_body("order_type is invalid"),
+        };
+
+        json!({
+            "success": true,
+            "id": id,
+            "rate": rate.map(|v| v.to_string()),
+            "amount": amount.map(|v| v.to_string()),
+            "order_type": order.order_type,
+            "stop_loss_rate": order.stop_loss_rate,
+            "pair": order.pair,
+            "created_at": CREATED_AT,
+        })
+    }
+
+    fn cancel_order(&mut self, id: u64) -> Value {
+        let idx = match self.open_orders.iter().position(|o| o.id == id) {
+            Some(v) => v,
+            None => return error_body("The order doesn't exist."),
+        };
+        let o = self.open_orders.remove(idx);
+        let (key, settlement) = split_pair(&o.pair);
+        let (currency, reserved) = if o.order_type == "sell" {
+            (key, o.pending_amount)
+        } else {
+            (settlement, o.rate * o.pending_amount)
+        };
+        let balance = self.balances.entry(currency).or_insert((0.0, 0.0));
+        balance.0 += reserved;
+        balance.1 -= reserved;
+        json!({"success": true, "id": id})
+    }
+}
+
+async fn handle(
+    state: Arc<Mutex<StubState>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_owned();
+    let query_str = req.uri().query().unwrap_or("").to_owned();
+    let query: HashMap<String, String> = query_str
+        .split('&')
+        .filter_map(|kv| {
+            let mut it = kv.splitn(2, '=');
+            Some((it.next()?.to_owned(), it.next()?.to_owned()))
+        })
+        .collect();
+    let user_agent = req
+        .headers()
+        .get("User-Agent")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_owned());
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+    let body = String::from_utf8_lossy(&body).to_string();
+    let req = Request::from_parts(parts, Body::empty());
+
+    let mut state = state.lock().unwrap();
+    let is_public = path == "/api/order_books"
+        || path == "/api/exchange/orders/rate"
+        || path == "/api/trades"
+        || path == "/api/ticker";
+    let mut error = None;
+    let mut status = StatusCode::OK;
+    if !is_public {
+        if let Err(e) = state.authenticate(&req, &body) {
+            error = Some(e);
+        }
+    }
+    if error.is_none() {
+        if let Some(queue) = state.errors.get_mut(&(method.clone(), path.clone())) {
+            if let Some((s, e)) = queue.pop_front() {
+                status = s;
+                error = Some(e);
+            }
+        }
+    }
+
+    let mut res = match &error {
+        Some(e) => error_body(e),
+        None => state.route(&method, &path, &query, &body),
+    };
+    if error.is_none() {
+        let key = (method.clone(), path.clone());
+        if let Some((s, e)) = state
+            .errors_after_processing
+            .get_mut(&key)
+            .and_then(|q| q.pop_front())
+        {
+            status = s;
+            res = error_body(&e);
+            error = Some(e);
+        }
+    }
+    state.requests.push(RecordedRequest {
+        method,
+        path,
+        query: query_str,
+        body,
+        user_agent,
+        error,
+    });
+    let mut response = Response::new(Body::from(res.to_string()));
+    *response.status_mut() = status;
+    Ok(response)
+}
+
+fn error_body(message: &str) -> Value {
+    json!({"success": false, "error": message})
+}
+
+fn to_books(books: &[(f64, f64)]) -> Vec<Vec<String>> {
+    books
+        .iter()
+        .map(|(rate, amount)| vec![rate.to_string(), amount.to_string()])
+        .collect()
+}
+
+fn split_pair(pair: &str) -> (String, String) {
+    let mut it = pair.splitn(2, '_');
+    let key = it.next().unwrap_or("").to_owned();
+    let settlement = it.next().unwrap_or("").to_owned();
+    (key, settlement)
+}
+
+fn sign(v: &str) -> String {
+    let key = PKey::hmac(SECRET_KEY.as_bytes()).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    signer.update(v.as_bytes()).unwrap();
+    signer
+        .sign_to_vec()
+        .unwrap()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
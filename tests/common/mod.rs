@@ -0,0 +1,147 @@
+#![allow(dead_code)]
+
+pub mod coincheck_stub;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use trading_bot_rust::config::{Config, DbType, OverrunPolicy, SmtpSecurity};
+use trading_bot_rust::error::MyResult;
+use trading_bot_rust::mysql::memory::MemoryClient;
+use trading_bot_rust::mysql::model::Market;
+use trading_bot_rust::slack;
+use trading_bot_rust::slack::client::TextMessage;
+
+pub const PAIR: &str = "btc_jpy";
+
+pub fn make_config() -> Config {
+    Config {
+        bot_name: "test_bot".to_string(),
+        target_pair: PAIR.to_string(),
+        interval_sec: 0,
+        rate_period_minutes: 60,
+        external_service_wait_interval_sec: 0,
+        demo_mode: false,
+        overrun_policy: OverrunPolicy::Skip,
+        schedule_utc_offset_hours: 0,
+        cancel_open_orders_on_shutdown: false,
+        wma_period_short: 5,
+        wma_period_long: 10,
+        resistance_line_period: 5,
+        resistance_line_offset: 1,
+        resistance_line_width_ratio_upper: 0.005,
+        resistance_line_width_ratio_lower: 0.000,
+        support_line_period_long: 5,
+        support_line_period_short: 1,
+        support_line_offset: 1,
+        support_line_width_ratio_upper: 0.003,
+        support_line_width_ratio_lower: 0.005,
+        volume_period_short: 5,
+        order_books_size_ratio: 5.0,
+        rebound_check_period: 15,
+        funds_ratio_per_order: 0.1,
+        profit_ratio_per_order: 0.0015,
+        offset_sell_rate_ratio: 0.01,
+        fee_rates: HashMap::new(),
+        hold_limit_minutes: 10,
+        avg_down_rate_ratio: 0.97,
+        avg_down_rate_ratio_on_holding_expired: 0.98,
+        loss_cut_rate_ratio: 0.80,
+        stop_loss_rate_ratio: None,
+        stop_loss_slippage_ratio: 0.01,
+        trailing_take_profit_ratios: HashMap::new(),
+        trailing_step_ratio: 0.001,
+        entry_skip_rate_ratio: 0.960,
+        over_sell_volume_ratio: 0.022,
+        required_trade_frequency_ratio: 0.2,
+        keep_lot: 1.0,
+        recover_orphaned_coin: false,
+        exchange_access_key: coincheck_stub::ACCESS_KEY.to_string(),
+        exchange_secret_key: coincheck_stub::SECRET_KEY.to_string(),
+        exchange_base_url: "https://coincheck.com".to_string(),
+        exchange_timeout_sec: None,
+        exchange_connect_timeout_sec: None,
+        exchange_proxy: None,
+        exchange_user_agent: None,
+        exchange_nonce_path: None,
+        db_type: DbType::Sqlite,
+        db_path: ":memory:".to_string(),
+        db_host: "".to_string(),
+        db_port: 0,
+        db_name: "".to_string(),
+        db_user_name: "".to_string(),
+        db_password: "".to_string(),
+        slack_url: "".to_string(),
+        slack_link_url: None,
+        slack_command_addr: None,
+        slack_signing_secret: None,
+        monitor_addr: None,
+        discord_webhook_url: None,
+        line_notify_token: None,
+        smtp_host: None,
+        smtp_port: 587,
+        smtp_security: SmtpSecurity::Starttls,
+        smtp_username: None,
+        smtp_password: None,
+        smtp_from: "".to_string(),
+        smtp_to: vec![],
+        notify_routes: HashMap::new(),
+        notify_dedup_window_sec: 3600,
+        notify_digest_events: vec![],
+        notify_digest_interval_sec: 3600,
+        report_daily_cron: None,
+        report_weekly_cron: None,
+    }
+}
+
+// 送信したメッセージを記録するだけのSlackクライアント
+#[derive(Debug, Default)]
+pub struct RecordingSlackClient {
+    messages: Mutex<Vec<String>>,
+}
+
+impl RecordingSlackClient {
+    pub fn messages(&self) -> Vec<String> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl slack::client::Client for RecordingSlackClient {
+    async fn post_message(&self, message: &TextMessage) -> MyResult<()> {
+        // 添付があれば、添付の全体を記録する
+        let text = match message.attachments.first() {
+            Some(a) => a.fallback.clone(),
+            None => message.text.clone(),
+        };
+        self.messages.lock().unwrap().push(text);
+        Ok(())
+    }
+}
+
+// 直近 minutes 分の市場データ（1分間隔, 一定レート）と集計期間分の市場データを登録する
+pub fn add_flat_markets(
+    client: &MemoryClient,
+    now: DateTime<Utc>,
+    minutes: i64,
+    sell_rate: f64,
+    buy_rate: f64,
+) {
+    let mut times: Vec<DateTime<Utc>> = (1..=minutes).map(|i| now - Duration::minutes(i)).collect();
+    times.extend((2..=24).map(|i| now - Duration::hours(i)));
+    for t in times {
+        client
+            .add_market(&Market {
+                pair: PAIR.to_owned(),
+                store_rate_avg: sell_rate,
+                ex_rate_sell: sell_rate,
+                ex_rate_buy: buy_rate,
+                ex_volume_sell: 0.0,
+                ex_volume_buy: 0.0,
+                recorded_at: t.naive_utc(),
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,160 @@
+mod common;
+
+use common::make_config;
+
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use hyper::{Body, Method, Request, StatusCode};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use trading_bot_rust::bot::control::BotControl;
+use trading_bot_rust::coincheck::model::{Balance, OpenOrder, OrderType};
+use trading_bot_rust::monitor::metrics::{Metrics, TradeStatus};
+use trading_bot_rust::monitor::server::{MonitorServer, HEALTH_PATH, METRICS_PATH, STATUS_PATH};
+
+fn make_now() -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339("2021-05-16T12:00:00Z")
+        .unwrap()
+        .with_timezone(&Utc)
+}
+
+async fn get(server: &MonitorServer, path: &str, now: &DateTime<Utc>) -> (StatusCode, String) {
+    let req = Request::builder()
+        .method(Method::GET)
+        .uri(path)
+        .body(Body::empty())
+        .unwrap();
+    let res = server.handle(req, now);
+    let status = res.status();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+fn make_status(now: &DateTime<Utc>) -> TradeStatus {
+    let mut balances = HashMap::new();
+    balances.insert(
+        "jpy".to_owned(),
+        Balance {
+            amount: 90000.0,
+            reserved: 0.0,
+        },
+    );
+    balances.insert(
+        "btc".to_owned(),
+        Balance {
+            amount: 0.0,
+            reserved: 0.01,
+        },
+    );
+    TradeStatus {
+        pair: "btc_jpy".to_owned(),
+        sell_rate: 4_000_000.0,
+        buy_rate: 4_001_000.0,
+        balances,
+        open_orders: vec![OpenOrder {
+            id: 100,
+            rate: 4_100_000.0,
+            pending_amount: 0.01,
+            pending_market_buy_amount: None,
+            order_type: OrderType::Sell,
+            stop_loss_rate: None,
+            pair: "btc_jpy".to_owned(),
+            created_at: now.with_timezone(&FixedOffset::east_opt(0).unwrap()),
+        }],
+        support_line_long: Some(3_900_000.0),
+        support_line_short: Some(3_950_000.0),
+        resistance_line: None,
+        long_trend: "up".to_owned(),
+        total_balance_jpy: 130000.0,
+        updated_at: *now,
+    }
+}
+
+#[tokio::test]
+async fn test_health() {
+    let config = make_config();
+    let now = make_now();
+    let control = Arc::new(BotControl::new(false));
+    let metrics = Arc::new(Metrics::new(&now));
+    let server = MonitorServer::new(&config, control.clone(), metrics.clone());
+
+    // 起動直後は取引していなくても正常
+    let (status, body) = get(&server, HEALTH_PATH, &now).await;
+    assert_eq!(status, StatusCode::OK);
+    let v: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(v["status"], "ok");
+    assert_eq!(v["last_cycle_at"], Value::Null);
+
+    // 一定時間取引していなければ異常
+    let later = now + Duration::seconds(61);
+    let (status, body) = get(&server, HEALTH_PATH, &later).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(body.contains("stale"), "{}", body);
+
+    // 一時停止中は正常
+    control.set_paused(true);
+    let (status, body) = get(&server, HEALTH_PATH, &later).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("paused"), "{}", body);
+    control.set_paused(false);
+
+    metrics.record_cycle(&(later - Duration::seconds(1)), 1.5, true);
+    let (status, _) = get(&server, HEALTH_PATH, &later).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_status_and_metrics() {
+    let config = make_config();
+    let now = make_now();
+    let control = Arc::new(BotControl::new(true));
+    let metrics = Arc::new(Metrics::new(&now));
+    let server = MonitorServer::new(&config, control.clone(), metrics.clone());
+
+    // 取引の前は取引の状態なし
+    let (status, body) = get(&server, STATUS_PATH, &now).await;
+    assert_eq!(status, StatusCode::OK);
+    let v: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(v["config"]["bot_name"], config.bot_name.as_str());
+    assert_eq!(v["config"]["demo_mode"], true);
+    assert_eq!(v["trade"], Value::Null);
+
+    metrics.record_cycle(&now, 0.7, false);
+    control.record_trade(&now, Some("timeout".to_owned()));
+    metrics.record_status(make_status(&now));
+
+    let (_, body) = get(&server, STATUS_PATH, &now).await;
+    let v: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(v["last_cycle"]["duration_sec"], 0.7);
+    assert_eq!(v["last_cycle"]["error"], "timeout");
+    assert_eq!(v["trade"]["long_trend"], "up");
+    assert_eq!(v["trade"]["balances"]["btc"]["reserved"], 0.01);
+    assert_eq!(v["trade"]["open_orders"][0]["id"], 100);
+    assert_eq!(v["trade"]["open_orders"][0]["order_type"], "sell");
+    assert_eq!(v["trade"]["support_line_short"], 3_950_000.0);
+    assert_eq!(v["trade"]["resistance_line"], Value::Null);
+
+    let (status, body) = get(&server, METRICS_PATH, &now).await;
+    assert_eq!(status, StatusCode::OK);
+    let bot = format!("bot=\"{}\"", config.bot_name);
+    for want in [
+        format!("trading_bot_demo_mode{{{}}} 1", bot),
+        format!("trading_bot_cycles_total{{{},result=\"failure\"}} 1", bot),
+        format!(
+            "trading_bot_balance{{{},currency=\"btc\",kind=\"reserved\"}} 0.01",
+            bot
+        ),
+        format!("trading_bot_total_balance_jpy{{{}}} 130000", bot),
+        format!("trading_bot_open_orders{{{},pair=\"btc_jpy\"}} 1", bot),
+    ] {
+        assert!(
+            body.lines().any(|l| l == want),
+            "{} not found in\n{}",
+            want,
+            body
+        );
+    }
+
+    let (status, _) = get(&server, "/unknown", &now).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
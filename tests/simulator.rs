@@ -0,0 +1,60 @@
+mod common;
+
+use common::{make_config, PAIR};
+
+use chrono::{Duration, NaiveDateTime};
+use trading_bot_rust::coincheck::model::FeeRate;
+use trading_bot_rust::mysql::model::Market;
+use trading_bot_rust::simulator::base::Simulator;
+
+// 30時間分の1分足（上下に振れ続ける市場）
+fn make_markets() -> Vec<Market> {
+    let begin = NaiveDateTime::parse_from_str("2021-05-16 00:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+    (0..60 * 30)
+        .map(|i| {
+            let x = i as f64;
+            let rate = 4_000_000.0 * (1.0 + 0.01 * (x / 17.0).sin() + 0.004 * (x / 3.0).sin());
+            Market {
+                pair: PAIR.to_owned(),
+                store_rate_avg: rate,
+                ex_rate_sell: rate,
+                ex_rate_buy: rate * 1.0002,
+                ex_volume_sell: 1.0,
+                ex_volume_buy: 1.0,
+                recorded_at: begin + Duration::minutes(i),
+            }
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_run_markets_fees_reduce_total_jpy() {
+    // 1回の注文が最小注文数量を超えるようにする
+    let mut config = make_config();
+    config.funds_ratio_per_order = 0.3;
+    let without_fee = Simulator::new(&config)
+        .unwrap()
+        .run_markets(make_markets())
+        .await
+        .unwrap();
+    // 取引が行われ、手数料は掛かっていない
+    assert_ne!(without_fee.total_jpy, 100000.0);
+    assert_eq!(without_fee.total_fees, vec![("jpy".to_owned(), 0.0)]);
+
+    config.fee_rates.insert(
+        PAIR.to_owned(),
+        FeeRate {
+            maker: 0.001,
+            taker: 0.002,
+        },
+    );
+    let with_fee = Simulator::new(&config)
+        .unwrap()
+        .run_markets(make_markets())
+        .await
+        .unwrap();
+    assert_eq!(with_fee.total_fees.len(), 1);
+    assert!(with_fee.total_fees[0].1 > 0.0);
+    assert!(with_fee.total_jpy < without_fee.total_jpy);
+    assert!(with_fee.total_balance_jpy < without_fee.total_balance_jpy);
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use common::make_config;
+
+use chrono::Utc;
+use hyper::{Body, Method, Request, StatusCode};
+use serde_json::Value;
+use std::sync::Arc;
+use trading_bot_rust::bot::control::BotControl;
+use trading_bot_rust::slack::command::make_signature;
+use trading_bot_rust::slack::server::{CommandServer, COMMAND_PATH};
+
+const SIGNING_SECRET: &str = "stub_signing_secret";
+
+fn make_request(body: &str, secret: &str, timestamp: i64) -> Request<Body> {
+    let timestamp = timestamp.to_string();
+    let signature = make_signature(secret, &timestamp, body).unwrap();
+    Request::builder()
+        .method(Method::POST)
+        .uri(COMMAND_PATH)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Slack-Request-Timestamp", timestamp)
+        .header("X-Slack-Signature", signature)
+        .body(Body::from(body.to_owned()))
+        .unwrap()
+}
+
+async fn send(server: &CommandServer, req: Request<Body>) -> (StatusCode, String) {
+    let res = server.handle(req).await;
+    let status = res.status();
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+async fn send_command(server: &CommandServer, text: &str) -> String {
+    let body =
+        serde_urlencoded::to_string([("command", "/bot"), ("text", text), ("user_name", "alice")])
+            .unwrap();
+    let (status, body) = send(
+        server,
+        make_request(&body, SIGNING_SECRET, Utc::now().timestamp()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let v: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(v["response_type"], "ephemeral");
+    v["text"].as_str().unwrap().to_owned()
+}
+
+#[tokio::test]
+async fn test_commands() {
+    let config = make_config();
+    let control = Arc::new(BotControl::new(false));
+    let server = CommandServer::new(&config, SIGNING_SECRET, control.clone());
+
+    let text = send_command(&server, "pause").await;
+    assert!(text.starts_with("paused"));
+    assert!(control.is_paused());
+
+    let text = send_command(&server, "set demo_mode on").await;
+    assert_eq!(text, "demo_mode is set to true");
+    assert!(control.demo_mode());
+
+    send_command(&server, "losscut 100").await;
+    let text = send_command(&server, "status").await;
+    assert!(text.contains("paused:`true`"), "{}", text);
+    assert!(text.contains("demo_mode:`true`"), "{}", text);
+    assert!(text.contains("loss cut requests:`[100]`"), "{}", text);
+    assert_eq!(control.take_loss_cut_requests(), vec![100]);
+
+    send_command(&server, "resume").await;
+    assert!(!control.is_paused());
+
+    // 不明なコマンドは使い方を返信する
+    let text = send_command(&server, "unknown").await;
+    assert!(text.contains("usage: /bot"), "{}", text);
+}
+
+#[tokio::test]
+async fn test_reject_invalid_request() {
+    let config = make_config();
+    let control = Arc::new(BotControl::new(false));
+    let server = CommandServer::new(&config, SIGNING_SECRET, control.clone());
+    let body = "command=%2Fbot&text=pause";
+
+    // 署名の鍵が異なる
+    let req = make_request(body, "other_secret", Utc::now().timestamp());
+    assert_eq!(send(&server, req).await.0, StatusCode::UNAUTHORIZED);
+
+    // 古いリクエスト（リプレイ）
+    let req = make_request(body, SIGNING_SECRET, Utc::now().timestamp() - 60 * 10);
+    assert_eq!(send(&server, req).await.0, StatusCode::UNAUTHORIZED);
+
+    // 署名がない
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(COMMAND_PATH)
+        .body(Body::from(body))
+        .unwrap();
+    assert_eq!(send(&server, req).await.0, StatusCode::UNAUTHORIZED);
+
+    let req = Request::builder()
+        .method(Method::GET)
+        .uri("/")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&server, req).await.0, StatusCode::NOT_FOUND);
+
+    assert!(!control.is_paused());
+}